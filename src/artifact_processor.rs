@@ -0,0 +1,137 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::process::Command;
+use crate::report::{artifact_info, collect_artifacts, ArtifactInfo};
+
+/// A file a build produced, as handed to an `ArtifactProcessor`
+#[derive(Debug, Clone, Copy)]
+pub struct Artifact<'a> {
+    /// Target the artifact was built for
+    pub target: &'a str,
+    /// Absolute path of the file in the artifact directory
+    pub path: &'a Path,
+    /// Path, size and checksum of the file as the build produced it
+    pub metadata: &'a ArtifactInfo,
+}
+
+/// Hook run on every artifact once it has been copied out of the Nix store
+///
+/// Processors run in the order they are given, so one that rewrites files (such as
+/// `StripProcessor`) should come before one that records them (such as `ChecksumProcessor`).
+/// An error fails the build.
+pub trait ArtifactProcessor: Send + Sync {
+    /// Short name used in progress and error messages
+    fn name(&self) -> &str;
+
+    /// Process one artifact
+    fn process(&self, artifact: &Artifact) -> Result<()>;
+
+    /// Process the build report once it is written; most processors only care about artifacts
+    fn process_manifest(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes `<file>.sha256`, in `sha256sum` format, next to each artifact
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChecksumProcessor;
+
+impl ArtifactProcessor for ChecksumProcessor {
+    fn name(&self) -> &str {
+        "checksum"
+    }
+
+    fn process(&self, artifact: &Artifact) -> Result<()> {
+        // Hashed afresh, since an earlier processor may have changed the file
+        let sha256 = format!("{:x}", Sha256::digest(std::fs::read(artifact.path)?));
+        let file_name = artifact.path.file_name().unwrap_or_default().to_string_lossy();
+        let mut checksum_path = artifact.path.as_os_str().to_owned();
+        checksum_path.push(".sha256");
+        std::fs::write(checksum_path, format!("{}  {}\n", sha256, file_name))?;
+        Ok(())
+    }
+}
+
+/// Strips symbols from ELF executables and libraries with `strip` (or another program)
+///
+/// Other files are left alone. The program must understand the target's architecture;
+/// use `llvm-strip` for cross-compiled targets.
+#[derive(Debug, Clone)]
+pub struct StripProcessor {
+    pub program: String,
+}
+
+impl Default for StripProcessor {
+    fn default() -> Self {
+        Self { program: "strip".to_string() }
+    }
+}
+
+impl ArtifactProcessor for StripProcessor {
+    fn name(&self) -> &str {
+        "strip"
+    }
+
+    fn process(&self, artifact: &Artifact) -> Result<()> {
+        let mut magic = [0u8; 4];
+        let is_elf = std::io::Read::read_exact(&mut std::fs::File::open(artifact.path)?, &mut magic).is_ok()
+            && magic == *b"\x7fELF";
+        if !is_elf {
+            return Ok(());
+        }
+        // Files copied out of the Nix store are read-only
+        let mut permissions = std::fs::metadata(artifact.path)?.permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        std::fs::set_permissions(artifact.path, permissions)?;
+
+        let status = Command::new(&self.program)
+            .arg("--strip-unneeded")
+            .arg(artifact.path)
+            .status()
+            .with_context(|| format!("Could not run {}", self.program))?;
+        if !status.success() {
+            anyhow::bail!("{} exited with {}", self.program, status);
+        }
+        Ok(())
+    }
+}
+
+/// Run `processors` on every file in a target's artifact directory and return the final metadata
+///
+/// Files the processors add (such as checksum files) aren't part of the result.
+pub fn process_artifacts(dir: &Path, target: &str, processors: &[Box<dyn ArtifactProcessor>]) -> Result<Vec<ArtifactInfo>> {
+    let artifacts = collect_artifacts(dir)?;
+    if processors.is_empty() {
+        return Ok(artifacts);
+    }
+    for processor in processors {
+        for metadata in &artifacts {
+            let path = dir.join(&metadata.path);
+            processor
+                .process(&Artifact { target, path: &path, metadata })
+                .with_context(|| format!("Artifact processor {} failed on {}", processor.name(), path.display()))?;
+        }
+    }
+    artifacts.iter().map(|a| artifact_info(&dir.join(&a.path), dir)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum_processor_writes_sums_without_recording_them() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("bin")).unwrap();
+        std::fs::write(dir.path().join("bin/app"), "binary").unwrap();
+
+        let processors: Vec<Box<dyn ArtifactProcessor>> = vec![Box::new(ChecksumProcessor)];
+        let artifacts = process_artifacts(dir.path(), "x86_64-linux-gnu", &processors).unwrap();
+        assert_eq!(artifacts.len(), 1);
+
+        let checksum = std::fs::read_to_string(dir.path().join("bin/app.sha256")).unwrap();
+        assert_eq!(checksum, format!("{}  app\n", artifacts[0].sha256));
+    }
+}
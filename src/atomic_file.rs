@@ -0,0 +1,110 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// Suffix of the temp files `write_atomic` writes before renaming them into place
+const TEMP_SUFFIX: &str = ".repx-tmp";
+
+/// Temp files older than this are left over from a build that died; younger ones may belong to one still running
+pub const STALE_TEMP_AGE: Duration = Duration::from_secs(3600);
+
+/// A unique temp file next to `path`, so the rename stays on one filesystem
+///
+/// Also used for backups, which `remove_stale_temp_files` then cleans up after a crash.
+pub fn temp_path_for(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let unique = Uuid::new_v4().simple().to_string();
+    path.with_file_name(format!(".{}.{}{}", name, &unique[..8], TEMP_SUFFIX))
+}
+
+/// Replace `path` with `contents` so readers see either the old file or the new one, never a partial write
+///
+/// The contents go to a uniquely named temp file in the same directory, are synced, and
+/// the temp file is renamed over `path`. If anything fails the temp file is removed.
+pub async fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let temp_path = temp_path_for(path);
+    let result = async {
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        file.write_all(contents.as_ref()).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&temp_path, path).await?;
+        Ok(())
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+/// Remove temp files in `dir` that an interrupted build left behind, returning their paths
+///
+/// Covers the temp files of `write_atomic` and the `*.new` files of earlier versions, if
+/// they weren't modified within `older_than`.
+pub async fn remove_stale_temp_files(dir: &Path, older_than: Duration) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let is_temp = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(TEMP_SUFFIX) || name.ends_with(".new"));
+        let metadata = entry.metadata().await?;
+        if !is_temp || !metadata.is_file() {
+            continue;
+        }
+        let age = metadata.modified().ok().and_then(|modified| SystemTime::now().duration_since(modified).ok());
+        if age.is_some_and(|age| age >= older_than) {
+            tokio::fs::remove_file(&path).await?;
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_write_atomic_replaces_whole_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("flake.nix");
+        tokio::fs::write(&path, "old").await.unwrap();
+
+        write_atomic(&path, "new contents").await.unwrap();
+        assert_eq!(tokio::fs::read_to_string(&path).await.unwrap(), "new contents");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // A failed rename, here onto a directory, leaves neither a temp file nor a changed target
+        let target = dir.path().join("flake.lock");
+        std::fs::create_dir(&target).unwrap();
+        assert!(write_atomic(&target, "{}").await.is_err());
+        assert!(target.is_dir());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_write_leaves_original_and_is_cleaned_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("flake.nix");
+        tokio::fs::write(&path, "{ outputs = _: { }; }\n").await.unwrap();
+
+        // A build that died after writing part of the new file but before renaming it
+        tokio::fs::write(temp_path_for(&path), "{ outputs =").await.unwrap();
+        tokio::fs::write(dir.path().join("flake.lock.new"), "{\"nodes\":").await.unwrap();
+        assert_eq!(tokio::fs::read_to_string(&path).await.unwrap(), "{ outputs = _: { }; }\n");
+
+        // Recent temp files may belong to a build that is still running
+        assert!(remove_stale_temp_files(dir.path(), STALE_TEMP_AGE).await.unwrap().is_empty());
+
+        let removed = remove_stale_temp_files(dir.path(), Duration::ZERO).await.unwrap();
+        assert_eq!(removed.len(), 2);
+        let remaining: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(remaining, ["flake.nix"]);
+    }
+}
@@ -0,0 +1,256 @@
+use anyhow::Result;
+use bollard::Docker;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use crate::execute_command::{execute_command, CommandError, ExecSettings};
+use crate::{RepxError, RESET, BOLD, GREEN, RED, YELLOW};
+
+/// Runs cargo-audit from the nixpkgs pinned in the generated flake's lock
+const AUDIT_COMMAND: &str = "nix --extra-experimental-features 'nix-command flakes' run --inputs-from ./.repx nixpkgs#cargo-audit -- audit --json --file Cargo.lock";
+
+/// Severity of an advisory, derived from its CVSS score
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    None,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// Qualitative rating of a CVSS base score
+    fn from_score(score: f64) -> Self {
+        match score {
+            s if s >= 9.0 => Severity::Critical,
+            s if s >= 7.0 => Severity::High,
+            s if s >= 4.0 => Severity::Medium,
+            s if s > 0.0 => Severity::Low,
+            _ => Severity::None,
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = RepxError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "low" => Ok(Severity::Low),
+            "medium" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            other => Err(RepxError::ConfigInvalid(format!(
+                "Unknown audit severity '{}'; expected low, medium, high or critical",
+                other
+            ))),
+        }
+    }
+}
+
+/// A vulnerable dependency reported by cargo-audit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditFinding {
+    /// RustSec advisory ID, e.g. `RUSTSEC-2023-0001`
+    pub id: String,
+    pub package: String,
+    pub version: String,
+    pub title: String,
+    /// CVSS base score, if the advisory has one
+    pub score: Option<f64>,
+    pub severity: Option<Severity>,
+}
+
+/// Result of auditing `Cargo.lock`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditReport {
+    /// Severity at or above which findings fail the build, if any
+    pub deny: Option<Severity>,
+    pub findings: Vec<AuditFinding>,
+}
+
+impl AuditReport {
+    /// Findings that fail the build; advisories without a score always count
+    pub fn denied(&self) -> Vec<&AuditFinding> {
+        match self.deny {
+            Some(deny) => self
+                .findings
+                .iter()
+                .filter(|f| f.severity.is_none_or(|severity| severity >= deny))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AuditOutput {
+    vulnerabilities: Vulnerabilities,
+}
+
+#[derive(Deserialize)]
+struct Vulnerabilities {
+    list: Vec<Vulnerability>,
+}
+
+#[derive(Deserialize)]
+struct Vulnerability {
+    advisory: Advisory,
+    package: Package,
+}
+
+#[derive(Deserialize)]
+struct Advisory {
+    id: String,
+    title: String,
+    cvss: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    version: String,
+}
+
+/// CVSS v3 base score of a vector such as `CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H`
+pub fn cvss_base_score(vector: &str) -> Option<f64> {
+    if !vector.starts_with("CVSS:3") {
+        return None;
+    }
+    let metric = |name: &str| vector.split('/').find_map(|m| m.strip_prefix(name)?.strip_prefix(':'));
+
+    let changed = match metric("S")? {
+        "U" => false,
+        "C" => true,
+        _ => return None,
+    };
+    let av = match metric("AV")? { "N" => 0.85, "A" => 0.62, "L" => 0.55, "P" => 0.2, _ => return None };
+    let ac = match metric("AC")? { "L" => 0.77, "H" => 0.44, _ => return None };
+    let pr = match (metric("PR")?, changed) {
+        ("N", _) => 0.85,
+        ("L", false) => 0.62,
+        ("L", true) => 0.68,
+        ("H", false) => 0.27,
+        ("H", true) => 0.5,
+        _ => return None,
+    };
+    let ui = match metric("UI")? { "N" => 0.85, "R" => 0.62, _ => return None };
+    let cia = |name: &str| match metric(name)? { "H" => Some(0.56), "L" => Some(0.22), "N" => Some(0.0), _ => None };
+    let (c, i, a) = (cia("C")?, cia("I")?, cia("A")?);
+
+    let iss = 1.0 - (1.0 - c) * (1.0 - i) * (1.0 - a);
+    let impact = if changed {
+        7.52 * (iss - 0.029) - 3.25 * (iss - 0.02_f64).powi(15)
+    } else {
+        6.42 * iss
+    };
+    if impact <= 0.0 {
+        return Some(0.0);
+    }
+    let exploitability = 8.22 * av * ac * pr * ui;
+    let total = if changed { 1.08 * (impact + exploitability) } else { impact + exploitability };
+    Some(round_up(total.min(10.0)))
+}
+
+/// Round up to one decimal, as defined by the CVSS v3.1 specification
+fn round_up(value: f64) -> f64 {
+    let scaled = (value * 100_000.0).round() as i64;
+    if scaled % 10_000 == 0 {
+        scaled as f64 / 100_000.0
+    } else {
+        (scaled / 10_000 + 1) as f64 / 10.0
+    }
+}
+
+/// Parse the JSON printed by `cargo audit --json`
+pub fn parse_audit_output(json: &str, deny: Option<Severity>) -> Result<AuditReport> {
+    let output: AuditOutput = serde_json::from_str(json)?;
+    let findings = output
+        .vulnerabilities
+        .list
+        .into_iter()
+        .map(|v| {
+            let score = v.advisory.cvss.as_deref().and_then(cvss_base_score);
+            AuditFinding {
+                id: v.advisory.id,
+                package: v.package.name,
+                version: v.package.version,
+                title: v.advisory.title,
+                score,
+                severity: score.map(Severity::from_score),
+            }
+        })
+        .collect();
+    Ok(AuditReport { deny, findings })
+}
+
+/// Audit the project's `Cargo.lock` inside the container and print the findings
+///
+/// cargo-audit exits non-zero when it finds vulnerabilities, so its output is read
+/// from the failed command too; whether they fail the build is decided by `deny`.
+pub async fn run_audit(docker: &Docker, container_id: &str, settings: &ExecSettings, deny: Option<Severity>) -> Result<AuditReport> {
+    println!("{}{}Auditing Cargo.lock with cargo-audit...{}", BOLD, YELLOW, RESET);
+    let stdout = match execute_command(docker, container_id, AUDIT_COMMAND, settings).await {
+        Ok(output) => output.stdout,
+        Err(e) => match e.downcast::<CommandError>() {
+            Ok(command_error) if command_error.exit_code == 1 => command_error.output.stdout,
+            Ok(command_error) => return Err(command_error.into()),
+            Err(e) => return Err(e),
+        },
+    };
+    let report = parse_audit_output(&stdout, deny)?;
+
+    if report.findings.is_empty() {
+        println!("{}{}No known vulnerabilities in Cargo.lock{}", BOLD, GREEN, RESET);
+    }
+    for finding in &report.findings {
+        let severity = match (finding.severity, finding.score) {
+            (Some(severity), Some(score)) => format!("{:?} {:.1}", severity, score).to_lowercase(),
+            _ => "unscored".to_string(),
+        };
+        println!(
+            "{}{}WARNING:{} {} {}: {} ({}, {})",
+            BOLD, YELLOW, RESET, finding.package, finding.version, finding.title, finding.id, severity
+        );
+    }
+    let denied = report.denied();
+    if !denied.is_empty() {
+        println!(
+            "{}{}{} vulnerabilit(y/ies) at or above the --audit-deny threshold{}",
+            BOLD, RED, denied.len(), RESET
+        );
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cvss_base_score() {
+        assert_eq!(cvss_base_score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"), Some(9.8));
+        assert_eq!(cvss_base_score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:C/C:H/I:H/A:H"), Some(10.0));
+        assert_eq!(cvss_base_score("CVSS:3.1/AV:N/AC:H/PR:N/UI:N/S:U/C:N/I:N/A:H"), Some(5.9));
+        assert_eq!(cvss_base_score("CVSS:3.1/AV:L/AC:L/PR:L/UI:N/S:U/C:N/I:N/A:N"), Some(0.0));
+        assert_eq!(cvss_base_score("AV:N/AC:L"), None);
+    }
+
+    #[test]
+    fn test_audit_deny_threshold() {
+        let json = r#"{"vulnerabilities": {"found": true, "count": 3, "list": [
+            {"advisory": {"id": "RUSTSEC-0000-0001", "title": "Remote code execution", "cvss": "CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"}, "package": {"name": "a", "version": "1.0.0"}},
+            {"advisory": {"id": "RUSTSEC-0000-0002", "title": "Denial of service", "cvss": "CVSS:3.1/AV:N/AC:H/PR:N/UI:N/S:U/C:N/I:N/A:H"}, "package": {"name": "b", "version": "2.0.0"}},
+            {"advisory": {"id": "RUSTSEC-0000-0003", "title": "Unscored", "cvss": null}, "package": {"name": "c", "version": "3.0.0"}}
+        ]}}"#;
+        let report = parse_audit_output(json, Some(Severity::High)).unwrap();
+        assert_eq!(report.findings[0].severity, Some(Severity::Critical));
+        assert_eq!(report.findings[1].severity, Some(Severity::Medium));
+        let denied: Vec<&str> = report.denied().iter().map(|f| f.id.as_str()).collect();
+        assert_eq!(denied, ["RUSTSEC-0000-0001", "RUSTSEC-0000-0003"]);
+
+        assert!(parse_audit_output(json, None).unwrap().denied().is_empty());
+        assert!("severe".parse::<Severity>().is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+use crate::container_utils::{remove_volume, volume_exists};
+use crate::{build_with_nix_using, connect_docker, BuildReport, RepxConfig, RepxError, BOLD, MAGENTA, RESET};
+
+/// Store cache volume `repx bench` builds with when none is given
+pub const DEFAULT_BENCH_VOLUME: &str = "repx-bench-store";
+
+/// Timings of a cold and a warm build of the same project
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    /// Build starting from an empty Nix store cache
+    pub cold: BuildReport,
+    /// The same build again, with the store cache the cold build filled
+    pub warm: BuildReport,
+    /// Wall-clock seconds of each build, cold then warm
+    pub total_seconds: (f64, f64),
+}
+
+impl BenchResult {
+    /// Seconds each phase took in the cold and warm build, in the order the phases first ran
+    pub fn rows(&self) -> Vec<(String, Option<f64>, Option<f64>)> {
+        fn seconds(report: &BuildReport) -> BTreeMap<&str, f64> {
+            report.phases.iter().map(|p| (p.name.as_str(), p.seconds)).collect()
+        }
+        let (cold, warm) = (seconds(&self.cold), seconds(&self.warm));
+        let mut names: Vec<&str> = Vec::new();
+        for phase in self.cold.phases.iter().chain(&self.warm.phases) {
+            if !names.contains(&phase.name.as_str()) {
+                names.push(&phase.name);
+            }
+        }
+        names
+            .into_iter()
+            .map(|name| (name.to_string(), cold.get(name).copied(), warm.get(name).copied()))
+            .collect()
+    }
+
+    /// Plain-text table of the phase timings, with a total row
+    pub fn table(&self) -> String {
+        let mut rows = self.rows();
+        rows.push(("total".to_string(), Some(self.total_seconds.0), Some(self.total_seconds.1)));
+        let width = rows.iter().map(|(name, _, _)| name.len()).max().unwrap_or(0).max("Phase".len());
+        let cell = |seconds: Option<f64>| seconds.map_or("-".to_string(), |s| format!("{:.1}s", s));
+
+        let mut table = format!("{:<width$}  {:>8}  {:>8}  {:>8}\n", "Phase", "Cold", "Warm", "Change", width = width);
+        for (name, cold, warm) in rows {
+            let change = match (cold, warm) {
+                (Some(cold), Some(warm)) => format!("{:+.1}s", warm - cold),
+                _ => "-".to_string(),
+            };
+            table.push_str(&format!("{:<width$}  {:>8}  {:>8}  {:>8}\n", name, cell(cold), cell(warm), change, width = width));
+        }
+        table
+    }
+}
+
+/// Build twice with a dedicated Nix store cache: once from an empty cache, then again warm
+///
+/// The cache volume is removed before the cold build and after the warm one, so runs
+/// don't influence each other.
+pub async fn run_bench(config: &RepxConfig, targets: &[&str], volume: &str) -> Result<BenchResult, RepxError> {
+    let docker = connect_docker(config).await?;
+    if volume_exists(&docker, volume).await? {
+        remove_volume(&docker, volume).await?;
+    }
+    let config = RepxConfig {
+        nix_store_cache: Some(volume.to_string()),
+        summary_json: None,
+        junit: None,
+        sarif: None,
+        fail_on_warning: false,
+        ..config.clone()
+    };
+
+    let mut runs = Vec::new();
+    for kind in ["cold", "warm"] {
+        println!("\n{}{}Benchmark: {} build{}", BOLD, MAGENTA, kind, RESET);
+        let started = Instant::now();
+        let report = build_with_nix_using(&config, targets, &[]).await;
+        let seconds = (started.elapsed().as_secs_f64() * 10.0).round() / 10.0;
+        match report {
+            Ok(report) => runs.push((report, seconds)),
+            Err(e) => {
+                // The volume may not exist yet if the build failed early
+                let _ = remove_volume(&docker, volume).await;
+                return Err(e);
+            }
+        }
+    }
+    remove_volume(&docker, volume).await?;
+
+    let (warm, warm_seconds) = runs.pop().expect("warm run");
+    let (cold, cold_seconds) = runs.pop().expect("cold run");
+    Ok(BenchResult { cold, warm, total_seconds: (cold_seconds, warm_seconds) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PhaseTiming;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_bench_table() {
+        let report = |phases: &[(&str, f64)]| BuildReport {
+            phases: phases.iter().map(|(name, seconds)| PhaseTiming { name: name.to_string(), seconds: *seconds }).collect(),
+            ..BuildReport::new("abc", PathBuf::from("build-abc.log"), &["x86_64-linux-gnu"])
+        };
+        let result = BenchResult {
+            cold: report(&[("image_pull", 20.0), ("build:x86_64-linux-gnu", 300.0)]),
+            warm: report(&[("image_pull", 0.5), ("build:x86_64-linux-gnu", 40.0), ("artifact_copy", 1.0)]),
+            total_seconds: (330.0, 45.0),
+        };
+        assert_eq!(result.rows()[2], ("artifact_copy".to_string(), None, Some(1.0)));
+
+        let table = result.table();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("Phase"));
+        assert!(lines[2].starts_with("build:x86_64-linux-gnu") && lines[2].ends_with("-260.0s"));
+        assert!(lines[3].ends_with("-"));
+        assert!(lines[4].starts_with("total") && lines[4].contains("330.0s"));
+    }
+}
@@ -0,0 +1,71 @@
+use bollard::Docker;
+use std::path::Path;
+use crate::{RESET, BOLD, YELLOW};
+
+/// Directory where the kernel lists registered binfmt_misc handlers
+const BINFMT_MISC_DIR: &str = "/proc/sys/fs/binfmt_misc";
+
+/// binfmt_misc handler needed to run a target's binaries during the build, if the
+/// Docker host can't run them natively
+///
+/// aarch64 Linux builds execute target binaries (tests, build-time tools) under qemu,
+/// which only works on a foreign host when the handler is registered in the kernel.
+pub fn required_binfmt_handler(target: &str, host_arch: &str) -> Option<&'static str> {
+    let native = matches!(host_arch, "aarch64" | "arm64");
+    (target.starts_with("aarch64-linux-") && !native).then_some("qemu-aarch64")
+}
+
+/// Whether the handler is registered and enabled on this machine
+fn handler_enabled(handler: &str) -> bool {
+    std::fs::read_to_string(Path::new(BINFMT_MISC_DIR).join(handler))
+        .map(|status| status.lines().next() == Some("enabled"))
+        .unwrap_or(false)
+}
+
+/// Architecture of the Docker host, falling back to the machine repx runs on
+async fn docker_host_arch(docker: &Docker) -> String {
+    docker
+        .info()
+        .await
+        .ok()
+        .and_then(|info| info.architecture)
+        .unwrap_or_else(|| std::env::consts::ARCH.to_string())
+}
+
+/// Warn about targets that need binfmt emulation the host doesn't provide, returning them
+///
+/// Without the handler the build fails deep inside Nix with "exec format error".
+pub async fn check_binfmt_support(docker: &Docker, targets: &[&str]) -> Vec<String> {
+    let host_arch = docker_host_arch(docker).await;
+    let missing: Vec<String> = targets
+        .iter()
+        .filter(|target| {
+            required_binfmt_handler(target, &host_arch).is_some_and(|handler| !handler_enabled(handler))
+        })
+        .map(|target| target.to_string())
+        .collect();
+
+    if !missing.is_empty() {
+        println!(
+            "{}{}WARNING:{} {} need(s) to run aarch64 binaries, but qemu-aarch64 isn't registered with binfmt_misc on this {} host.",
+            BOLD, YELLOW, RESET, missing.join(", "), host_arch
+        );
+        println!("   Register it with: docker run --privileged --rm tonistiigi/binfmt --install arm64");
+        println!("   Otherwise the build is likely to fail with \"exec format error\".");
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_binfmt_handler() {
+        assert_eq!(required_binfmt_handler("aarch64-linux-gnu", "x86_64"), Some("qemu-aarch64"));
+        assert_eq!(required_binfmt_handler("aarch64-linux-musl", "x86_64"), Some("qemu-aarch64"));
+        assert_eq!(required_binfmt_handler("aarch64-linux-gnu", "aarch64"), None);
+        assert_eq!(required_binfmt_handler("x86_64-linux-gnu", "x86_64"), None);
+        assert_eq!(required_binfmt_handler("aarch64-apple-darwin", "x86_64"), None);
+    }
+}
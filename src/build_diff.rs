@@ -0,0 +1,162 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use crate::report::{collect_artifacts, ArtifactInfo, BuildReport, ReferenceComparison, ReferenceStatus};
+
+/// Config keys that differ between every pair of builds, so comparing them says nothing
+const IGNORED_CONFIG_KEYS: &[&str] = &["Build ID"];
+
+/// A build setting that changed between two builds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChange {
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// An artifact that was added, removed or changed between two builds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactChange {
+    pub target: String,
+    pub path: String,
+    pub old: Option<ArtifactInfo>,
+    pub new: Option<ArtifactInfo>,
+}
+
+impl ArtifactChange {
+    /// Size difference in bytes, counting a missing artifact as empty
+    pub fn size_delta(&self) -> i64 {
+        let size = |info: &Option<ArtifactInfo>| info.as_ref().map_or(0, |i| i.size as i64);
+        size(&self.new) - size(&self.old)
+    }
+}
+
+/// Differences between two past builds
+#[derive(Debug, Clone, Default)]
+pub struct BuildDiff {
+    pub config: Vec<ConfigChange>,
+    /// Targets only the old build had
+    pub removed_targets: Vec<String>,
+    /// Targets only the new build had
+    pub added_targets: Vec<String>,
+    /// Artifacts of targets both builds copied, where the checksum differs
+    pub artifacts: Vec<ArtifactChange>,
+}
+
+/// Compare the reports of two builds
+pub fn diff_builds(old: &BuildReport, new: &BuildReport) -> BuildDiff {
+    let keys: BTreeSet<&String> = old.config.keys().chain(new.config.keys()).collect();
+    let config = keys
+        .into_iter()
+        .filter(|key| !IGNORED_CONFIG_KEYS.contains(&key.as_str()))
+        .filter(|key| old.config.get(*key) != new.config.get(*key))
+        .map(|key| ConfigChange { key: key.clone(), old: old.config.get(key).cloned(), new: new.config.get(key).cloned() })
+        .collect();
+
+    let removed_targets = old.targets.iter().filter(|t| !new.targets.contains(t)).cloned().collect();
+    let added_targets = new.targets.iter().filter(|t| !old.targets.contains(t)).cloned().collect();
+
+    let mut artifacts = Vec::new();
+    for (target, old_files) in &old.artifacts {
+        let Some(new_files) = new.artifacts.get(target) else {
+            continue;
+        };
+        let by_path = |files: &[ArtifactInfo]| -> BTreeMap<String, ArtifactInfo> {
+            files.iter().map(|f| (f.path.clone(), f.clone())).collect()
+        };
+        let (mut old_by_path, mut new_by_path) = (by_path(old_files), by_path(new_files));
+        let paths: BTreeSet<String> = old_by_path.keys().chain(new_by_path.keys()).cloned().collect();
+        for path in paths {
+            let (old_file, new_file) = (old_by_path.remove(&path), new_by_path.remove(&path));
+            let changed = match (&old_file, &new_file) {
+                (Some(a), Some(b)) => a.sha256 != b.sha256,
+                _ => true,
+            };
+            if changed {
+                artifacts.push(ArtifactChange { target: target.clone(), path, old: old_file, new: new_file });
+            }
+        }
+    }
+
+    BuildDiff { config, removed_targets, added_targets, artifacts }
+}
+
+/// Compare a target's artifacts by SHA-256 with the reference files in `reference_dir`
+///
+/// Files are matched by their path relative to the artifact directory; a missing
+/// `reference_dir` counts every artifact as missing from the reference.
+pub fn compare_with_reference(target: &str, artifacts: &[ArtifactInfo], reference_dir: &Path) -> Result<Vec<ReferenceComparison>> {
+    let reference = if reference_dir.is_dir() { collect_artifacts(reference_dir)? } else { Vec::new() };
+    let mut reference_by_path: BTreeMap<&str, &ArtifactInfo> = reference.iter().map(|f| (f.path.as_str(), f)).collect();
+
+    let mut comparisons = Vec::new();
+    for artifact in artifacts {
+        let status = match reference_by_path.remove(artifact.path.as_str()) {
+            Some(file) if file.sha256 == artifact.sha256 => ReferenceStatus::Match,
+            Some(_) => ReferenceStatus::Mismatch,
+            None => ReferenceStatus::MissingFromReference,
+        };
+        comparisons.push(ReferenceComparison { target: target.to_string(), path: artifact.path.clone(), status });
+    }
+    for path in reference_by_path.into_keys() {
+        comparisons.push(ReferenceComparison { target: target.to_string(), path: path.to_string(), status: ReferenceStatus::MissingFromBuild });
+    }
+    comparisons.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(comparisons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn artifact(path: &str, size: u64, sha256: &str) -> ArtifactInfo {
+        ArtifactInfo { path: path.to_string(), size, sha256: sha256.to_string() }
+    }
+
+    #[test]
+    fn test_diff_builds() {
+        let mut old = BuildReport::new("a", PathBuf::from("build-a.log"), &["x86_64-linux-gnu", "aarch64-linux-gnu"]);
+        old.config.insert("Build ID".to_string(), "a".to_string());
+        old.config.insert("Rust Version".to_string(), "1.80.0".to_string());
+        old.config.insert("Cargo Profile".to_string(), "release".to_string());
+        old.artifacts.insert("x86_64-linux-gnu".to_string(), vec![artifact("bin/app", 1000, "aa"), artifact("bin/tool", 10, "cc")]);
+
+        let mut new = BuildReport::new("b", PathBuf::from("build-b.log"), &["x86_64-linux-gnu", "x86_64-linux-musl"]);
+        new.config.insert("Build ID".to_string(), "b".to_string());
+        new.config.insert("Rust Version".to_string(), "1.81.0".to_string());
+        new.config.insert("Cargo Profile".to_string(), "release".to_string());
+        new.artifacts.insert("x86_64-linux-gnu".to_string(), vec![artifact("bin/app", 1500, "bb"), artifact("bin/tool", 10, "cc")]);
+
+        let diff = diff_builds(&old, &new);
+        assert_eq!(diff.config, [ConfigChange { key: "Rust Version".to_string(), old: Some("1.80.0".to_string()), new: Some("1.81.0".to_string()) }]);
+        assert_eq!(diff.removed_targets, ["aarch64-linux-gnu"]);
+        assert_eq!(diff.added_targets, ["x86_64-linux-musl"]);
+        assert_eq!(diff.artifacts.len(), 1);
+        assert_eq!(diff.artifacts[0].path, "bin/app");
+        assert_eq!(diff.artifacts[0].size_delta(), 500);
+    }
+
+    #[test]
+    fn test_compare_with_reference() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("bin")).unwrap();
+        std::fs::write(dir.path().join("bin/app"), "binary").unwrap();
+        std::fs::write(dir.path().join("bin/tool"), "old tool").unwrap();
+        std::fs::write(dir.path().join("bin/extra"), "extra").unwrap();
+        let app = crate::report::artifact_info(&dir.path().join("bin/app"), dir.path()).unwrap();
+
+        let built = [app, artifact("bin/tool", 8, "ff"), artifact("bin/new", 3, "ee")];
+        let comparisons = compare_with_reference("x86_64-linux-gnu", &built, dir.path()).unwrap();
+        let statuses: Vec<(&str, ReferenceStatus)> = comparisons.iter().map(|c| (c.path.as_str(), c.status)).collect();
+        assert_eq!(statuses, [
+            ("bin/app", ReferenceStatus::Match),
+            ("bin/extra", ReferenceStatus::MissingFromBuild),
+            ("bin/new", ReferenceStatus::MissingFromReference),
+            ("bin/tool", ReferenceStatus::Mismatch),
+        ]);
+
+        let missing = compare_with_reference("x86_64-linux-gnu", &built, &dir.path().join("missing")).unwrap();
+        assert!(missing.iter().all(|c| c.status == ReferenceStatus::MissingFromReference));
+    }
+}
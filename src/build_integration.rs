@@ -1,59 +1,56 @@
 use anyhow::Result;
 use std::env;
+use crate::{BuildReport, PartialRepxConfig, RepxBuilder, RepxConfig, RepxError};
 use std::path::{Path, PathBuf};
 
 /// Helper function to be called from a build.rs script to perform a reproducible build
 ///
+/// Returns the build's report, so the script can serialize it or inspect the artifacts.
+///
 /// # Example
 /// ```no_run
 /// // In build.rs
 /// #[tokio::main]
 /// async fn main() {
-///     if let Err(e) = repx_lib::build_script::run_build().await {
-///         eprintln!("Reproducible build failed: {}", e);
-///         std::process::exit(1);
+///     match repx_lib::build_script::run_build().await {
+///         Ok(report) => {
+///             let out_dir = std::env::var("OUT_DIR").unwrap();
+///             let json = serde_json::to_string_pretty(&report).unwrap();
+///             std::fs::write(format!("{}/repx-report.json", out_dir), json).unwrap();
+///         }
+///         Err(e) => {
+///             eprintln!("Reproducible build failed: {}", e);
+///             std::process::exit(1);
+///         }
 ///     }
 /// }
 /// ```
-pub async fn run_build() -> Result<()> {
+pub async fn run_build() -> Result<BuildReport, RepxError> {
     // Get environment variables that Cargo sets for build scripts
     let out_dir = env::var("OUT_DIR").expect("OUT_DIR not set");
     let cargo_manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set");
     let _package_name = env::var("CARGO_PKG_NAME").expect("CARGO_PKG_NAME not set");
 
     // Use defaults that can be overridden via environment variables
-    let nix_image = env::var("REPRO_BUILD_IMAGE").unwrap_or_else(|_| "nixos/nix:latest".to_string());
-    let targets = env::var("REPRO_BUILD_TARGETS").unwrap_or_else(|_| "x86_64-linux-gnu".to_string());
-    let rust_channel = env::var("REPRO_BUILD_RUST_CHANNEL").unwrap_or_else(|_| "stable".to_string());
-    let rust_version = env::var("REPRO_BUILD_RUST_VERSION").unwrap_or_else(|_| "latest".to_string());
-    let nixpkgs_url = env::var("REPRO_BUILD_NIXPKGS_URL").unwrap_or_else(|_| "github:NixOS/nixpkgs/nixos-unstable".to_string());
-
-    // Parse extra packages from environment variables
-    let extra_packages = parse_extra_packages_from_env();
-
-    // Parse targets into a vector
-    let targets_vec: Vec<&str> = targets.split(',').collect();
+    let config = RepxConfig {
+        project: cargo_manifest_dir.clone(),
+        targets: Some("x86_64-linux-gnu".to_string()),
+        ..RepxConfig::default()
+    }
+    .merge(env_overrides());
 
     // Run the build
     println!("cargo:warning=Starting reproducible build with Nix inside Docker...");
     println!("cargo:warning=Project: {}", cargo_manifest_dir);
-    println!("cargo:warning=Docker Image: {}", nix_image);
-    println!("cargo:warning=Targets: {:?}", targets_vec);
+    println!("cargo:warning=Docker Image: {}", config.image);
+    println!("cargo:warning=Targets: {:?}", config.targets.as_deref().unwrap_or_default().split(',').collect::<Vec<_>>());
 
     // Call the main build function
-    let result = crate::build_with_nix(
-        &nix_image,
-        &cargo_manifest_dir,
-        &targets_vec,
-        extra_packages,
-        &rust_channel,
-        &rust_version,
-        &nixpkgs_url,
-    ).await;
+    let result = RepxBuilder::from_config(config).build().await;
 
     // Handle the result
     match result {
-        Ok(_) => {
+        Ok(report) => {
             let target_path = Path::new(&cargo_manifest_dir).join("target/repx");
             println!("cargo:warning=Build completed successfully!");
 
@@ -66,7 +63,7 @@ pub async fn run_build() -> Result<()> {
                     println!("cargo:warning=Artifacts copied to OUT_DIR");
                 }
             }
-            Ok(())
+            Ok(report)
         },
         Err(e) => {
             println!("cargo:warning=Build failed: {}", e);
@@ -75,6 +72,22 @@ pub async fn run_build() -> Result<()> {
     }
 }
 
+/// Settings given as REPRO_BUILD_* environment variables, over the REPX_* ones the CLI also reads
+fn env_overrides() -> PartialRepxConfig {
+    let extra_packages = parse_extra_packages_from_env();
+    let repx = crate::env_overrides();
+    PartialRepxConfig {
+        image: env::var("REPRO_BUILD_IMAGE").ok(),
+        targets: env::var("REPRO_BUILD_TARGETS").ok(),
+        rust_channel: env::var("REPRO_BUILD_RUST_CHANNEL").ok(),
+        rust_version: env::var("REPRO_BUILD_RUST_VERSION").ok(),
+        nixpkgs_url: env::var("REPRO_BUILD_NIXPKGS_URL").ok(),
+        progress: env::var("REPRO_BUILD_PROGRESS").ok().or(repx.progress),
+        extra: (!extra_packages.is_empty()).then_some(extra_packages),
+        ..repx
+    }
+}
+
 /// Parse extra packages from environment variables
 /// Format: REPRO_BUILD_EXTRA_PACKAGE_1=openssl, REPRO_BUILD_EXTRA_PACKAGE_2=pkg-config, etc.
 /// Or: REPRO_BUILD_EXTRA_PACKAGES=openssl,pkg-config,curl
@@ -0,0 +1,153 @@
+use bollard::Docker;
+use crate::{run_build, ArtifactProcessor, BuildReport, BuildState, RepxConfig, RepxError};
+
+/// Called with `building` when a build starts and `idle` or `failed` when it ends,
+/// mirroring `.repx/status.json`
+pub type ProgressSink = Box<dyn Fn(BuildState) + Send + Sync>;
+
+/// Fluent builder for library users, as an alternative to `build_with_nix`
+///
+/// ```no_run
+/// # async fn example() -> Result<(), repx_lib::RepxError> {
+/// let report = repx_lib::RepxBuilder::new("path/to/project")
+///     .targets(["x86_64-linux-musl", "aarch64-linux-musl"])
+///     .rust_version("1.80.0")
+///     .extra_package("openssl")
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RepxBuilder {
+    config: RepxConfig,
+    targets: Vec<String>,
+    processors: Vec<Box<dyn ArtifactProcessor>>,
+    docker: Option<Docker>,
+    progress_sink: Option<ProgressSink>,
+}
+
+impl RepxBuilder {
+    /// Start a build of the project at `project`, with the default configuration
+    pub fn new(project: impl Into<String>) -> Self {
+        Self::from_config(RepxConfig { project: project.into(), ..RepxConfig::default() })
+    }
+
+    /// Start from a full configuration, e.g. one read from `repx.toml`
+    ///
+    /// Its `targets` are used unless targets are set on the builder.
+    pub fn from_config(config: RepxConfig) -> Self {
+        let targets = config.targets.as_deref().map(|t| t.split(',').map(|t| t.trim().to_string()).collect()).unwrap_or_default();
+        Self { config, targets, processors: Vec::new(), docker: None, progress_sink: None }
+    }
+
+    /// Nix Docker image to build in
+    pub fn image(mut self, image: impl Into<String>) -> Self {
+        self.config.image = image.into();
+        self
+    }
+
+    /// Targets to build, replacing any set before
+    pub fn targets<I, S>(mut self, targets: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.targets = targets.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Add a target to build
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.targets.push(target.into());
+        self
+    }
+
+    /// Rust channel, `stable` or `nightly`
+    pub fn rust_channel(mut self, channel: impl Into<String>) -> Self {
+        self.config.rust_channel = channel.into();
+        self
+    }
+
+    /// Rust version, e.g. `1.75.0` or `latest`
+    pub fn rust_version(mut self, version: impl Into<String>) -> Self {
+        self.config.rust_version = version.into();
+        self
+    }
+
+    /// nixpkgs flake URL
+    pub fn nixpkgs_url(mut self, url: impl Into<String>) -> Self {
+        self.config.nixpkgs_url = url.into();
+        self
+    }
+
+    /// Add a nixpkgs package to install in the build environment
+    pub fn extra_package(mut self, package: impl Into<String>) -> Self {
+        self.config.extra.push(package.into());
+        self
+    }
+
+    /// Cargo profile to build with
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.config.profile = profile.into();
+        self
+    }
+
+    /// Add a processor to run on every artifact of a successful build
+    pub fn processor(mut self, processor: Box<dyn ArtifactProcessor>) -> Self {
+        self.processors.push(processor);
+        self
+    }
+
+    /// Docker client to use instead of connecting to the local daemon
+    pub fn docker(mut self, docker: Docker) -> Self {
+        self.docker = Some(docker);
+        self
+    }
+
+    /// Callback told when the build starts and ends
+    pub fn progress_sink(mut self, sink: impl Fn(BuildState) + Send + Sync + 'static) -> Self {
+        self.progress_sink = Some(Box::new(sink));
+        self
+    }
+
+    /// The configuration the build will run with
+    pub fn config(&self) -> &RepxConfig {
+        &self.config
+    }
+
+    /// Run the build, returning its report
+    pub async fn build(mut self) -> Result<BuildReport, RepxError> {
+        if self.targets.is_empty() {
+            return Err(RepxError::ConfigInvalid("No targets to build".to_string()));
+        }
+        self.config.targets = Some(self.targets.join(","));
+        let targets: Vec<&str> = self.targets.iter().map(String::as_str).collect();
+        run_build(&self.config, &targets, &self.processors, self.docker, self.progress_sink.as_deref(), false).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_builder_sets_config() {
+        let builder = RepxBuilder::new("project")
+            .image("nixos/nix:2.24.0")
+            .target("x86_64-linux-musl")
+            .rust_channel("nightly")
+            .extra_package("openssl")
+            .extra_package("pkg-config");
+        assert_eq!(builder.config().project, "project");
+        assert_eq!(builder.config().image, "nixos/nix:2.24.0");
+        assert_eq!(builder.config().rust_channel, "nightly");
+        assert_eq!(builder.config().extra, ["openssl", "pkg-config"]);
+        assert_eq!(builder.targets, ["x86_64-linux-musl"]);
+
+        let config = RepxConfig { targets: Some("x86_64-linux-gnu, aarch64-linux-gnu".to_string()), ..RepxConfig::default() };
+        assert_eq!(RepxBuilder::from_config(config).targets, ["x86_64-linux-gnu", "aarch64-linux-gnu"]);
+
+        let no_targets = RepxBuilder::new("project").targets(Vec::<String>::new()).build().await;
+        assert!(matches!(no_targets, Err(RepxError::ConfigInvalid(_))));
+    }
+}
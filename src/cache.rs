@@ -0,0 +1,128 @@
+use anyhow::Result;
+use crate::{connect_docker, RepxConfig, RepxError};
+use crate::container_utils::{cleanup_container, is_store_cache_dir, remove_store_cache_dir, remove_volume, setup_store_container, volume_exists};
+use crate::execute_command::{execute_command, CommandOutput, ExecSettings};
+
+/// Volume used for the Nix store cache when none is configured
+pub const DEFAULT_STORE_VOLUME: &str = "repx-nix-store";
+
+/// Size and path count of a Nix store cache volume
+#[derive(Debug, Clone)]
+pub struct CacheStats {
+    pub volume: String,
+    pub store_paths: usize,
+    pub total_bytes: u64,
+}
+
+/// Parse `nix path-info --all -s` output into (path count, total bytes)
+///
+/// Each path's own NAR size is summed; closure sizes (`-S`) would count shared
+/// dependencies once per path that depends on them.
+fn parse_path_info(output: &str) -> (usize, u64) {
+    let mut store_paths = 0;
+    let mut total_bytes = 0;
+    for line in output.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(path), Some(size)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        if !path.starts_with("/nix/store/") {
+            continue;
+        }
+        if let Ok(size) = size.parse::<u64>() {
+            store_paths += 1;
+            total_bytes += size;
+        }
+    }
+    (store_paths, total_bytes)
+}
+
+/// Run a command against the store volume in a throwaway container
+async fn run_in_store_container(config: &RepxConfig, volume: &str, cmd: &str) -> Result<CommandOutput> {
+    let docker = connect_docker(config).await?;
+    if is_store_cache_dir(volume) {
+        if !std::path::Path::new(volume).exists() {
+            return Err(RepxError::ConfigInvalid(format!("No Nix store cache directory {} exists", volume)).into());
+        }
+    } else if !volume_exists(&docker, volume).await? {
+        return Err(RepxError::ConfigInvalid(format!("No Nix store cache volume named '{}' exists", volume)).into());
+    }
+
+    let container = setup_store_container(&docker, &config.image, volume).await?;
+    let result = execute_command(&docker, &container.id, cmd, &ExecSettings::default()).await;
+    cleanup_container(&docker, &container.id).await?;
+    result
+}
+
+/// Report the size and number of store paths in a Nix store cache volume
+///
+/// The container runtime, daemon and Nix image are taken from `config`.
+pub async fn cache_stats(config: &RepxConfig, volume: &str) -> Result<CacheStats, RepxError> {
+    let cmd = "nix --extra-experimental-features nix-command path-info --all -s";
+    let output = run_in_store_container(config, volume, cmd).await?;
+    let (store_paths, total_bytes) = parse_path_info(&output.stdout);
+
+    Ok(CacheStats {
+        volume: volume.to_string(),
+        store_paths,
+        total_bytes,
+    })
+}
+
+/// Garbage-collect a Nix store cache volume
+///
+/// With `keep_days`, profile generations newer than that many days are kept, mirroring
+/// `nix-collect-garbage --delete-older-than`.
+pub async fn cache_clean(config: &RepxConfig, volume: &str, keep_days: Option<u32>) -> Result<String, RepxError> {
+    let cmd = match keep_days {
+        Some(days) => format!("nix-collect-garbage --delete-older-than {}d", days),
+        None => "nix-collect-garbage".to_string(),
+    };
+    // The summary line goes to stdout or stderr depending on the Nix version
+    let output = run_in_store_container(config, volume, &cmd).await?;
+    Ok(format!("{}{}", output.stdout, output.stderr))
+}
+
+/// Drop a Nix store cache, the volume or the host directory, returning whether there was one
+///
+/// A volume still mounted by a running build can't be removed.
+pub async fn remove_store_cache(config: &RepxConfig, cache: &str) -> Result<bool, RepxError> {
+    Ok(drop_store_cache(config, cache).await?)
+}
+
+async fn drop_store_cache(config: &RepxConfig, cache: &str) -> Result<bool> {
+    let docker = connect_docker(config).await?;
+    if is_store_cache_dir(cache) {
+        if !std::path::Path::new(cache).exists() {
+            return Ok(false);
+        }
+        remove_store_cache_dir(&docker, &config.image, cache).await?;
+    } else {
+        if !volume_exists(&docker, cache).await? {
+            return Ok(false);
+        }
+        remove_volume(&docker, cache).await?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_path_info_sums_nar_sizes() {
+        let output = "/nix/store/aaa-glibc-2.39\t 31457280\n\
+                      /nix/store/bbb-bash-5.2   1572864\n\
+                      warning: ignoring something\n";
+        assert_eq!(parse_path_info(output), (2, 31457280 + 1572864));
+    }
+
+    #[test]
+    fn test_store_cache_dir_or_volume() {
+        assert!(!is_store_cache_dir(DEFAULT_STORE_VOLUME));
+        assert!(is_store_cache_dir("/var/cache/repx-nix"));
+        assert!(is_store_cache_dir("./.nix-cache"));
+        assert!(is_store_cache_dir(r"C:\cache\nix"));
+    }
+}
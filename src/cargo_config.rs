@@ -0,0 +1,104 @@
+use anyhow::Result;
+use std::path::Path;
+
+/// Cargo config files in the project, newest name first; the build sees these as part of the source
+const CARGO_CONFIG_FILES: &[&str] = &[".cargo/config.toml", ".cargo/config"];
+
+/// Settings in a Cargo config that clash with how the flake builds each target, one message each
+pub fn conflicting_settings(file: &str, contents: &str) -> Result<Vec<String>> {
+    let config: toml::Table = toml::from_str(contents)?;
+    let mut conflicts = Vec::new();
+
+    if let Some(build) = config.get("build").and_then(|b| b.as_table()) {
+        if let Some(target) = build.get("target") {
+            conflicts.push(format!(
+                "[build] target = {} in {} is overridden by the target repx builds; pick targets with --targets instead",
+                target, file
+            ));
+        }
+        if build.contains_key("rustflags") {
+            conflicts.push(format!(
+                "[build] rustflags in {} are ignored in the container, where the flake sets RUSTFLAGS; export them in the pre_build_hook template variable instead",
+                file
+            ));
+        }
+    }
+
+    if let Some(targets) = config.get("target").and_then(|t| t.as_table()) {
+        for (triple, settings) in targets {
+            let Some(settings) = settings.as_table() else {
+                continue;
+            };
+            if let Some(linker) = settings.get("linker") {
+                conflicts.push(format!(
+                    "[target.{}] linker = {} in {} points at a host tool the Nix build can't see; the flake provides the cross linker, so remove it or scope it to local builds",
+                    triple, linker, file
+                ));
+            }
+            if let Some(runner) = settings.get("runner") {
+                conflicts.push(format!(
+                    "[target.{}] runner = {} in {} is used for the tests run during the Nix build and must exist in the build environment; add it with --extra or remove it",
+                    triple, runner, file
+                ));
+            }
+            if settings.contains_key("rustflags") {
+                conflicts.push(format!(
+                    "[target.{}] rustflags in {} are ignored in the container, where the flake sets RUSTFLAGS; export them in the pre_build_hook template variable instead",
+                    triple, file
+                ));
+            }
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Check the project's Cargo config for settings that conflict with repx's target handling
+///
+/// A config that can't be parsed is reported as a conflict too, since Cargo will reject it in the build.
+pub async fn check_cargo_config(project_path: &Path) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    for file in CARGO_CONFIG_FILES {
+        let path = project_path.join(file);
+        let Ok(contents) = tokio::fs::read_to_string(&path).await else {
+            continue;
+        };
+        match conflicting_settings(file, &contents) {
+            Ok(found) => conflicts.extend(found),
+            Err(e) => conflicts.push(format!("{} can't be parsed: {}", file, e)),
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conflicting_settings() {
+        let contents = r#"
+[build]
+target = "x86_64-unknown-linux-musl"
+jobs = 4
+
+[target.aarch64-unknown-linux-gnu]
+linker = "aarch64-linux-gnu-gcc"
+runner = "qemu-aarch64"
+
+[target.'cfg(target_os = "linux")']
+rustflags = ["-C", "target-cpu=native"]
+
+[alias]
+xtask = "run --package xtask --"
+"#;
+        let conflicts = conflicting_settings(".cargo/config.toml", contents).unwrap();
+        assert_eq!(conflicts.len(), 4);
+        assert!(conflicts[0].starts_with("[build] target = \"x86_64-unknown-linux-musl\""));
+        assert!(conflicts[1].starts_with("[target.aarch64-unknown-linux-gnu] linker = \"aarch64-linux-gnu-gcc\""));
+        assert!(conflicts[2].contains("runner"));
+        assert!(conflicts[3].starts_with("[target.cfg(target_os = \"linux\")] rustflags"));
+
+        assert!(conflicting_settings(".cargo/config.toml", "[alias]\nb = \"build\"\n").unwrap().is_empty());
+        assert!(conflicting_settings(".cargo/config.toml", "[build\n").is_err());
+    }
+}
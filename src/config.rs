@@ -1,13 +1,36 @@
-use anyhow::Result;
+use crate::container_utils::{ContainerRuntime, DockerEndpoint, RUNTIME_ENV};
+use crate::execute_command::ProgressRate;
+use crate::RepxError;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs;
 
+/// Format version of repx.toml written by this version of repx
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Migrations between config versions; entry `n` upgrades a version `n` config to `n + 1`
+const MIGRATIONS: &[fn(&mut toml_edit::DocumentMut)] = &[
+    // 0 -> 1: configs written before versioning; the fields are unchanged
+    |_| {},
+];
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RepxConfig {
+    /// Format version of this file; missing in configs written before versioning
+    #[serde(default)]
+    pub version: u32,
+
     /// Path location to your Cargo.toml or project root
     #[serde(default = "default_project")]
     pub project: String,
+
+    /// Path to the project's Cargo manifest, if not `<project>/Cargo.toml`
+    pub manifest_path: Option<String>,
+
+    /// Workspace member to build, by package name; required in a workspace with several members
+    pub package: Option<String>,
     
     /// Pin nix docker image to a specific version
     #[serde(default = "default_image")]
@@ -27,10 +50,561 @@ pub struct RepxConfig {
     /// Rust version, e.g. '1.75.0' or 'latest'
     #[serde(default = "default_rust_version")]
     pub rust_version: String,
+
+    /// Toolchain matrix: build every target with each of these instead of `rust_version`,
+    /// e.g. `1.75.0`, `stable`, `beta`, `nightly` or `nightly-2024-05-01`
+    #[serde(default)]
+    pub rust_versions: Vec<String>,
+
+    /// Per-target toolchains instead of `rust_channel`/`rust_version`,
+    /// e.g. `"x86_64-linux-musl" = { channel = "nightly", version = "2024-05-01" }`
+    #[serde(default)]
+    pub target_toolchains: BTreeMap<String, ToolchainOverride>,
     
     /// nixpkgs URL/commit to use for reproducible builds
     #[serde(default = "default_nixpkgs_url")]
     pub nixpkgs_url: String,
+
+    /// rust-overlay flake URL; pin to a rev for use with `no_flake_lock`
+    #[serde(default = "default_rust_overlay_url")]
+    pub rust_overlay_url: String,
+
+    /// flake-utils flake URL; pin to a rev for use with `no_flake_lock`
+    #[serde(default = "default_flake_utils_url")]
+    pub flake_utils_url: String,
+
+    /// Skip generating flake.lock and rely on fully pinned input URLs instead
+    #[serde(default)]
+    pub no_flake_lock: bool,
+
+    /// Never add /app to git's safe.directory list in the container, even when the flake reads git
+    #[serde(default)]
+    pub no_git_config: bool,
+
+    /// Skip parsing the generated flake.nix before the build; unrelated to `flake_check`, which runs `nix flake check`
+    #[serde(default)]
+    pub no_flake_check: bool,
+
+    /// Fail the build if the committed flake.lock is stale instead of updating it
+    #[serde(default)]
+    pub verify_lock: bool,
+
+    /// Cargo profile to build with: release, dev (or debug), or a custom profile from Cargo.toml
+    #[serde(default = "default_profile")]
+    pub profile: String,
+
+    /// Fixed-output hash of the vendored dependencies, used instead of vendoring from Cargo.lock
+    pub cargo_hash: Option<String>,
+
+    /// Megabytes of each command's output kept in memory; the log file always gets all of it
+    #[serde(default = "default_max_output_mb")]
+    pub max_output_mb: usize,
+
+    /// Lines of each command's output kept in memory
+    #[serde(default = "default_max_output_lines")]
+    pub max_output_lines: usize,
+
+    /// Megabytes the build log may grow to before command output is left out of it
+    pub max_log_size_mb: Option<u64>,
+
+    /// How often the progress of a running command is redrawn: `fast`, `default` or `slow`
+    #[serde(default = "default_progress")]
+    pub progress: String,
+
+    /// Milliseconds between progress redraws, overriding the `progress` preset
+    pub progress_interval_ms: Option<u64>,
+
+    /// Copied store paths between redraws of the copied-paths counter, overriding the `progress` preset
+    pub progress_path_step: Option<usize>,
+
+    /// File holding the registry credentials (`username:token`) used to pull the Nix image
+    pub registry_token_file: Option<String>,
+
+    /// File holding Nix access tokens (`github.com=<token>`) for private flake inputs
+    pub flake_token_file: Option<String>,
+
+    /// Flake attribute `nix build` builds for a target, with `{target}` standing for the
+    /// attribute the built-in template defines, e.g. `packages.x86_64-linux.default`
+    #[serde(default = "default_output_attr_template")]
+    pub output_attr_template: String,
+
+    /// What to run for the targets: `build` builds each one, `check` runs `nix flake check` instead
+    #[serde(default = "default_build_command")]
+    pub build_command: String,
+
+    /// Also run `nix flake check` after building the targets
+    #[serde(default)]
+    pub flake_check: bool,
+
+    /// Cargo target directory inside the container, relative to the project; artifacts go to `<target_dir>/repx`
+    #[serde(default = "default_target_dir")]
+    pub target_dir: String,
+
+    /// How artifacts are laid out: `nested` keeps each target's Nix output tree, `flat` also
+    /// copies the executables to `<target_dir>/repx/flat/<name>-<target>`
+    #[serde(default = "default_layout")]
+    pub layout: String,
+
+    /// What the generated .repx/.gitignore ignores: ignore-logs-only, ignore-all or ignore-nothing
+    #[serde(default = "default_metadata_gitignore")]
+    pub metadata_gitignore: String,
+
+    /// Extra arguments appended to each `nix build` of a target, e.g. `--keep-going`
+    #[serde(default)]
+    pub nix_args: Vec<String>,
+
+    /// Extra `docker run` options for the build container, e.g. `--device=/dev/fuse`; only the options in the README are supported
+    #[serde(default)]
+    pub container_runtime_args: Vec<String>,
+
+    /// Container runtime to build with, `docker` or `podman`; Docker if unset
+    #[serde(default)]
+    pub runtime: Option<String>,
+
+    /// Docker API of the runtime instead of its local default: `unix://` or `npipe://` socket,
+    /// `tcp://` or `http://` address, or a bare socket path
+    #[serde(default)]
+    pub docker_host: Option<String>,
+
+    /// Run the build container privileged, which Nix's sandbox needs; rootless runtimes may not allow it
+    #[serde(default = "default_privileged")]
+    pub privileged: bool,
+
+    /// Report flake.nix drift on any text change, including comments and whitespace
+    #[serde(default)]
+    pub exact_flake_compare: bool,
+
+    /// Print a diff when flake.nix or flake.lock drifted from the existing files
+    #[serde(default = "default_show_diff")]
+    pub show_diff: bool,
+
+    /// Print the variables passed to the flake template as JSON before rendering it
+    #[serde(default)]
+    pub dump_context: bool,
+
+    /// Link every target statically; glibc targets use crt-static
+    #[serde(default)]
+    pub static_link: bool,
+
+    /// `SOURCE_DATE_EPOCH` for timestamps baked into artifacts, instead of the commit time of git HEAD
+    pub source_date_epoch: Option<u64>,
+
+    /// Leave `SOURCE_DATE_EPOCH` at Nix's default (1980-01-01) rather than the commit time
+    #[serde(default)]
+    pub no_source_date_epoch: bool,
+
+    /// Record the runtime closure (all store paths) of each built target in the build report
+    #[serde(default)]
+    pub record_closure: bool,
+
+    /// Owner (`UID:GID`, or `host` for the invoking user) given to files the container writes into the project
+    pub user: Option<String>,
+
+    /// Strip symbols from ELF artifacts on the host once they are copied
+    #[serde(default)]
+    pub strip: bool,
+
+    /// Write a `<file>.sha256` checksum next to each artifact
+    #[serde(default)]
+    pub checksums: bool,
+
+    /// Sign artifacts and the build report with `minisign` or `cosign`
+    pub sign: Option<String>,
+
+    /// Secret key used for signing; required for minisign, cosign signs keyless without one
+    pub sign_key_file: Option<String>,
+
+    /// Require an up-to-date Cargo.lock instead of generating one when it is missing
+    #[serde(default)]
+    pub locked: bool,
+
+    /// Audit Cargo.lock with cargo-audit before building
+    #[serde(default)]
+    pub audit: bool,
+
+    /// Fail the build on vulnerabilities of this severity or worse (low, medium, high, critical); implies `audit`
+    pub audit_deny: Option<String>,
+
+    /// Mount the project read-only and build from a copy inside the container
+    #[serde(default)]
+    pub readonly_source: bool,
+
+    /// With `readonly_source`, write a generated Cargo.lock back to the project
+    #[serde(default)]
+    pub write_cargo_lock: bool,
+
+    /// Seconds a single command in the build container may run before it is aborted
+    pub command_timeout_secs: Option<u64>,
+
+    /// Also build an OCI image of the result, tagged with this reference (name[:tag])
+    pub image_output: Option<String>,
+
+    /// Write the build report as JSON to this path, whether or not the build succeeds
+    pub summary_json: Option<String>,
+
+    /// Also write a JUnit XML report here, with each target as a test case
+    pub junit: Option<String>,
+
+    /// Also write the Rust compiler errors of a failed build here as SARIF, for code scanning
+    pub sarif: Option<String>,
+
+    /// Number of targets whose artifacts are copied out of the Nix store concurrently
+    #[serde(default = "default_copy_jobs")]
+    pub copy_jobs: usize,
+
+    /// How many targets are built with `nix build` at the same time
+    #[serde(default = "default_jobs")]
+    pub jobs: usize,
+
+    /// How many times an interrupted image pull is retried; completed layers aren't downloaded again
+    #[serde(default = "default_pull_retries")]
+    pub pull_retries: u32,
+
+    /// How many times a failed artifact copy is retried before the target is given up on
+    #[serde(default = "default_copy_retries")]
+    pub copy_retries: u32,
+
+    /// How many more times targets whose nix build failed are built again after the others,
+    /// to get past transient failures; off by default so real errors aren't masked
+    #[serde(default)]
+    pub retry_failed_targets: u32,
+
+    /// Directory of reference artifacts laid out like `<target_dir>/repx`, e.g. an unpacked release;
+    /// every artifact must match its reference file by SHA-256
+    pub compare_with: Option<String>,
+
+    /// Refuse to build with less free disk space than this, in GB, on the host or in the
+    /// container's /nix; 0 disables the check
+    #[serde(default = "default_min_free_space_gb")]
+    pub min_free_space_gb: u64,
+
+    /// Build even when the project has uncommitted changes, without warning
+    #[serde(default)]
+    pub allow_dirty: bool,
+
+    /// Refuse to build when reproducibility can't be guaranteed, e.g. from a dirty tree
+    #[serde(default)]
+    pub strict: bool,
+
+    /// Fail the build if a target builds but produces no artifacts, instead of warning
+    #[serde(default)]
+    pub strict_targets: bool,
+
+    /// Fail the build if it emitted any warning, such as flake drift or a dirty tree
+    #[serde(default)]
+    pub fail_on_warning: bool,
+
+    /// Vendor crate sources into `.repx/vendor` and build against them instead of fetching crates
+    #[serde(default)]
+    pub vendor: bool,
+
+    /// Run the build container without network access
+    #[serde(default)]
+    pub offline: bool,
+
+    /// Run the Nix daemon in the container and send Nix commands through it (multi-user mode)
+    #[serde(default)]
+    pub nix_daemon: bool,
+
+    /// Named Docker volume, or host directory if it is a path, mounted at /nix so the Nix store persists between builds
+    pub nix_store_cache: Option<String>,
+
+    /// Name the container after the project path and target set instead of a timestamp
+    #[serde(default)]
+    pub deterministic_name: bool,
+
+    /// Exact name for the build container, e.g. to `docker exec` into it mid-build
+    pub container_name: Option<String>,
+
+    /// Record every command run in the container to .repx/build-script.sh
+    #[serde(default)]
+    pub trace_commands: bool,
+
+    /// Match failed command output against known failure signatures and suggest fixes
+    #[serde(default)]
+    pub explain_failure: bool,
+
+    /// Print the end of the build log to stderr when a build fails; on by default in CI
+    #[serde(default = "default_dump_logs_on_failure")]
+    pub dump_logs_on_failure: bool,
+
+    /// Named target groups, e.g. `release = ["x86_64-linux-musl", "aarch64-linux-musl"]`;
+    /// the `default` group is built when no targets are given
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+
+    /// Hosts a target is built on when targets come from `repx.toml` rather than the command line,
+    /// e.g. `"aarch64-apple-darwin" = ["macos"]`; each entry is an OS, an arch, `os-arch` or `host-only`
+    #[serde(default)]
+    pub target_hosts: BTreeMap<String, Vec<String>>,
+
+    /// Extra variables for the flake template; the built-in template renders the
+    /// `extra_overlays`, `extra_native_build_inputs` and `pre_build_hook` slots
+    #[serde(default)]
+    pub template_vars: BTreeMap<String, String>,
+
+    /// Key/value metadata recorded with the build, e.g. the CI run ID or release version;
+    /// `--meta key=value` adds to or overrides these
+    #[serde(default)]
+    pub meta: BTreeMap<String, String>,
+}
+
+/// Overrides for a `RepxConfig`, e.g. from the command line or environment variables
+///
+/// Each field replaces the field of the same name when set, and leaves it alone otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartialRepxConfig {
+    pub project: Option<String>,
+    pub manifest_path: Option<String>,
+    pub package: Option<String>,
+    pub image: Option<String>,
+    pub targets: Option<String>,
+    pub extra: Option<Vec<String>>,
+    pub rust_channel: Option<String>,
+    pub rust_version: Option<String>,
+    pub rust_versions: Option<Vec<String>>,
+    pub nixpkgs_url: Option<String>,
+    pub rust_overlay_url: Option<String>,
+    pub flake_utils_url: Option<String>,
+    pub no_flake_lock: Option<bool>,
+    pub no_git_config: Option<bool>,
+    pub no_flake_check: Option<bool>,
+    pub verify_lock: Option<bool>,
+    pub profile: Option<String>,
+    pub cargo_hash: Option<String>,
+    pub max_output_mb: Option<usize>,
+    pub max_output_lines: Option<usize>,
+    pub max_log_size_mb: Option<u64>,
+    pub progress: Option<String>,
+    pub progress_interval_ms: Option<u64>,
+    pub progress_path_step: Option<usize>,
+    pub registry_token_file: Option<String>,
+    pub flake_token_file: Option<String>,
+    pub output_attr_template: Option<String>,
+    pub build_command: Option<String>,
+    pub flake_check: Option<bool>,
+    pub target_dir: Option<String>,
+    pub layout: Option<String>,
+    pub metadata_gitignore: Option<String>,
+    pub nix_args: Option<Vec<String>>,
+    pub container_runtime_args: Option<Vec<String>>,
+    pub runtime: Option<String>,
+    pub docker_host: Option<String>,
+    pub privileged: Option<bool>,
+    pub exact_flake_compare: Option<bool>,
+    pub show_diff: Option<bool>,
+    pub dump_context: Option<bool>,
+    pub static_link: Option<bool>,
+    pub source_date_epoch: Option<u64>,
+    pub no_source_date_epoch: Option<bool>,
+    pub record_closure: Option<bool>,
+    pub user: Option<String>,
+    pub strip: Option<bool>,
+    pub checksums: Option<bool>,
+    pub sign: Option<String>,
+    pub sign_key_file: Option<String>,
+    pub locked: Option<bool>,
+    pub audit: Option<bool>,
+    pub audit_deny: Option<String>,
+    pub readonly_source: Option<bool>,
+    pub write_cargo_lock: Option<bool>,
+    pub command_timeout_secs: Option<u64>,
+    pub image_output: Option<String>,
+    pub summary_json: Option<String>,
+    pub junit: Option<String>,
+    pub sarif: Option<String>,
+    pub copy_jobs: Option<usize>,
+    pub jobs: Option<usize>,
+    pub pull_retries: Option<u32>,
+    pub copy_retries: Option<u32>,
+    pub retry_failed_targets: Option<u32>,
+    pub compare_with: Option<String>,
+    pub min_free_space_gb: Option<u64>,
+    pub allow_dirty: Option<bool>,
+    pub strict: Option<bool>,
+    pub strict_targets: Option<bool>,
+    pub fail_on_warning: Option<bool>,
+    pub vendor: Option<bool>,
+    pub offline: Option<bool>,
+    pub nix_daemon: Option<bool>,
+    pub nix_store_cache: Option<String>,
+    pub deterministic_name: Option<bool>,
+    pub container_name: Option<String>,
+    pub trace_commands: Option<bool>,
+    pub explain_failure: Option<bool>,
+    pub dump_logs_on_failure: Option<bool>,
+    pub meta: Option<BTreeMap<String, String>>,
+}
+
+/// Targets left out for a host, each with the reason
+pub type SkippedTargets<'a> = Vec<(&'a str, String)>;
+
+/// A toolchain of the `rust_versions` matrix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RustToolchain {
+    /// As configured, e.g. `1.75.0` or `stable`; artifacts go to a directory of this name
+    pub name: String,
+    /// rust-overlay channel, e.g. `stable`
+    pub channel: String,
+    /// rust-overlay version of the channel, e.g. `1.75.0` or `latest`
+    pub version: String,
+}
+
+impl RustToolchain {
+    /// Parse a matrix entry: a release such as `1.75.0`, a channel, or `nightly-YYYY-MM-DD`
+    pub fn parse(name: &str) -> Result<Self, RepxError> {
+        let (channel, version) = match name {
+            "stable" | "beta" | "nightly" => (name, "latest"),
+            _ if is_release(name) => ("stable", name),
+            _ => match name.strip_prefix("nightly-").filter(|date| is_date(date)) {
+                Some(date) => ("nightly", date),
+                None => {
+                    return Err(RepxError::ConfigInvalid(format!(
+                        "Invalid Rust version '{}' in rust_versions; expected e.g. 1.75.0, stable, beta, nightly or nightly-2024-05-01",
+                        name
+                    )))
+                }
+            },
+        };
+        Ok(Self { name: name.to_string(), channel: channel.to_string(), version: version.to_string() })
+    }
+
+    /// Suffix of the toolchain's package attributes in the generated flake, e.g. `1_75_0`
+    pub fn attr_suffix(&self) -> String {
+        self.name.replace('.', "_")
+    }
+}
+
+/// A Rust release such as `1.75.0`
+fn is_release(version: &str) -> bool {
+    let parts: Vec<&str> = version.split('.').collect();
+    parts.len() == 3 && parts.iter().all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// A `YYYY-MM-DD` date, as nightly and beta toolchains are versioned
+fn is_date(version: &str) -> bool {
+    version.len() == 10 && version.chars().enumerate().all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() })
+}
+
+/// Toolchain of one target in `target_toolchains`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolchainOverride {
+    /// `stable`, `beta` or `nightly`; `rust_channel` if unset
+    pub channel: Option<String>,
+    /// `latest`, a release for stable or a date for beta and nightly; `latest` if unset
+    /// and the channel is overridden, otherwise `rust_version`
+    pub version: Option<String>,
+}
+
+impl ToolchainOverride {
+    /// Resolve against the configured channel and version, checking both are well-formed
+    fn resolve(&self, target: &str, rust_channel: &str, rust_version: &str) -> Result<RustToolchain, RepxError> {
+        let channel = self.channel.as_deref().unwrap_or(rust_channel);
+        let version = match (&self.version, &self.channel) {
+            (Some(version), _) => version.as_str(),
+            (None, Some(_)) => "latest",
+            (None, None) => rust_version,
+        };
+        if !["stable", "beta", "nightly"].contains(&channel) {
+            return Err(RepxError::ConfigInvalid(format!(
+                "Invalid Rust channel '{}' for {} in target_toolchains; expected stable, beta or nightly",
+                channel, target
+            )));
+        }
+        let valid_version = version == "latest" || if channel == "stable" { is_release(version) } else { is_date(version) };
+        if !valid_version {
+            return Err(RepxError::ConfigInvalid(format!(
+                "Invalid Rust version '{}' for {} in target_toolchains; expected latest or {}",
+                version,
+                target,
+                if channel == "stable" { "a release such as 1.75.0" } else { "a date such as 2024-05-01" }
+            )));
+        }
+        let name = match (channel, version) {
+            (_, "latest") => channel.to_string(),
+            ("stable", _) => version.to_string(),
+            _ => format!("{}-{}", channel, version),
+        };
+        Ok(RustToolchain { name, channel: channel.to_string(), version: version.to_string() })
+    }
+}
+
+/// Host operating systems and architectures `target_hosts` conditions can name
+const HOST_OSES: &[&str] = &["linux", "macos", "windows"];
+const HOST_ARCHES: &[&str] = &["x86_64", "aarch64", "arm", "riscv64", "powerpc64"];
+
+/// Parse a `--meta key=value` entry
+pub fn parse_meta(entry: &str) -> Result<(String, String), RepxError> {
+    let (key, value) = entry
+        .split_once('=')
+        .ok_or_else(|| RepxError::ConfigInvalid(format!("Metadata '{}' must be given as key=value", entry)))?;
+    validate_meta_entry(key, value)?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Keys are plain identifiers such as `ci_run` or `git.commit`; values are anything on one line
+fn validate_meta_entry(key: &str, value: &str) -> Result<(), RepxError> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+        return Err(RepxError::ConfigInvalid(format!(
+            "Metadata key '{}' may only contain letters, digits, '-', '_' and '.'",
+            key
+        )));
+    }
+    if value.contains(['\n', '\r']) {
+        return Err(RepxError::ConfigInvalid(format!("Metadata value of '{}' must be a single line", key)));
+    }
+    Ok(())
+}
+
+fn validate_host_condition(condition: &str) -> Result<(), RepxError> {
+    let valid = condition == "host-only"
+        || HOST_OSES.contains(&condition)
+        || HOST_ARCHES.contains(&condition)
+        || condition
+            .split_once('-')
+            .is_some_and(|(os, arch)| HOST_OSES.contains(&os) && HOST_ARCHES.contains(&arch));
+    if valid {
+        Ok(())
+    } else {
+        Err(RepxError::ConfigInvalid(format!(
+            "Unknown host condition '{}' in target_hosts; use host-only, an OS ({}), an arch ({}) or os-arch",
+            condition,
+            HOST_OSES.join(", "),
+            HOST_ARCHES.join(", ")
+        )))
+    }
+}
+
+/// OS and architecture a target's binaries run on, in the terms of `std::env::consts`
+fn target_platform(target: &str) -> Option<(&'static str, &'static str)> {
+    let os = if target.contains("-linux-") {
+        "linux"
+    } else if target.ends_with("-apple-darwin") {
+        "macos"
+    } else if target.contains("-windows-") || target.contains("-mingw32") {
+        "windows"
+    } else {
+        return None;
+    };
+    let arch = match target.split('-').next()? {
+        "x86_64" => "x86_64",
+        "aarch64" => "aarch64",
+        "armv7" => "arm",
+        "riscv64" => "riscv64",
+        "powerpc64le" => "powerpc64",
+        _ => return None,
+    };
+    Some((os, arch))
+}
+
+fn host_condition_matches(condition: &str, target: &str, host_os: &str, host_arch: &str) -> bool {
+    match condition {
+        "host-only" => target_platform(target) == Some((host_os, host_arch)),
+        _ => match condition.split_once('-') {
+            Some((os, arch)) => os == host_os && arch == host_arch,
+            None => condition == host_os || condition == host_arch,
+        },
+    }
 }
 
 fn default_project() -> String {
@@ -53,35 +627,506 @@ fn default_nixpkgs_url() -> String {
     "github:NixOS/nixpkgs/nixos-unstable".to_string()
 }
 
+/// On in CI, going by the `CI` variable most CI services set, so failed builds show their log
+fn default_dump_logs_on_failure() -> bool {
+    std::env::var("CI").is_ok_and(|ci| !ci.is_empty() && ci != "0" && !ci.eq_ignore_ascii_case("false"))
+}
+
+fn default_output_attr_template() -> String {
+    "{target}".to_string()
+}
+
+fn default_build_command() -> String {
+    "build".to_string()
+}
+
+fn default_copy_jobs() -> usize {
+    1
+}
+
+fn default_jobs() -> usize {
+    1
+}
+
+fn default_privileged() -> bool {
+    true
+}
+
+fn default_show_diff() -> bool {
+    true
+}
+
+fn default_pull_retries() -> u32 {
+    3
+}
+
+fn default_copy_retries() -> u32 {
+    2
+}
+
+fn default_min_free_space_gb() -> u64 {
+    5
+}
+
+fn default_metadata_gitignore() -> String {
+    "ignore-logs-only".to_string()
+}
+
+fn default_target_dir() -> String {
+    "target".to_string()
+}
+
+fn default_layout() -> String {
+    "nested".to_string()
+}
+
+fn default_max_output_mb() -> usize {
+    16
+}
+
+fn default_max_output_lines() -> usize {
+    100_000
+}
+
+fn default_progress() -> String {
+    "default".to_string()
+}
+
+fn default_rust_overlay_url() -> String {
+    "github:oxalica/rust-overlay".to_string()
+}
+
+fn default_flake_utils_url() -> String {
+    "github:numtide/flake-utils".to_string()
+}
+
+fn default_profile() -> String {
+    "release".to_string()
+}
+
 impl Default for RepxConfig {
     fn default() -> Self {
         Self {
+            version: CONFIG_VERSION,
             project: default_project(),
+            manifest_path: None,
+            package: None,
             image: default_image(),
             targets: None,
             extra: Vec::new(),
             rust_channel: default_rust_channel(),
             rust_version: default_rust_version(),
+            rust_versions: Vec::new(),
+            target_toolchains: BTreeMap::new(),
             nixpkgs_url: default_nixpkgs_url(),
+            rust_overlay_url: default_rust_overlay_url(),
+            flake_utils_url: default_flake_utils_url(),
+            no_flake_lock: false,
+            no_git_config: false,
+            no_flake_check: false,
+            verify_lock: false,
+            profile: default_profile(),
+            cargo_hash: None,
+            image_output: None,
+            max_output_mb: default_max_output_mb(),
+            max_output_lines: default_max_output_lines(),
+            max_log_size_mb: None,
+            progress: default_progress(),
+            progress_interval_ms: None,
+            progress_path_step: None,
+            registry_token_file: None,
+            flake_token_file: None,
+            output_attr_template: default_output_attr_template(),
+            build_command: default_build_command(),
+            flake_check: false,
+            target_dir: default_target_dir(),
+            layout: default_layout(),
+            metadata_gitignore: default_metadata_gitignore(),
+            nix_args: Vec::new(),
+            container_runtime_args: Vec::new(),
+            runtime: None,
+            docker_host: None,
+            privileged: default_privileged(),
+            exact_flake_compare: false,
+            show_diff: default_show_diff(),
+            dump_context: false,
+            static_link: false,
+            source_date_epoch: None,
+            no_source_date_epoch: false,
+            record_closure: false,
+            user: None,
+            strip: false,
+            checksums: false,
+            sign: None,
+            sign_key_file: None,
+            locked: false,
+            audit: false,
+            audit_deny: None,
+            readonly_source: false,
+            write_cargo_lock: false,
+            command_timeout_secs: None,
+            summary_json: None,
+            junit: None,
+            sarif: None,
+            copy_jobs: default_copy_jobs(),
+            jobs: default_jobs(),
+            pull_retries: default_pull_retries(),
+            copy_retries: default_copy_retries(),
+            retry_failed_targets: 0,
+            compare_with: None,
+            min_free_space_gb: default_min_free_space_gb(),
+            allow_dirty: false,
+            strict: false,
+            strict_targets: false,
+            fail_on_warning: false,
+            vendor: false,
+            offline: false,
+            nix_daemon: false,
+            nix_store_cache: None,
+            deterministic_name: false,
+            container_name: None,
+            trace_commands: false,
+            explain_failure: false,
+            dump_logs_on_failure: default_dump_logs_on_failure(),
+            groups: BTreeMap::new(),
+            target_hosts: BTreeMap::new(),
+            template_vars: BTreeMap::new(),
+            meta: BTreeMap::new(),
         }
     }
 }
 
+/// Environment variable with the default progress preset
+pub const PROGRESS_ENV: &str = "REPX_PROGRESS";
+
+/// Settings from the environment, layered over repx.toml and under the command line
+///
+/// Reads `REPX_PROGRESS` and `REPX_RUNTIME`; empty variables count as unset.
+pub fn env_overrides() -> PartialRepxConfig {
+    env_overrides_from(|key| std::env::var(key).ok())
+}
+
+fn env_overrides_from(var: impl Fn(&str) -> Option<String>) -> PartialRepxConfig {
+    let var = |key: &str| var(key).filter(|value| !value.is_empty());
+    PartialRepxConfig {
+        progress: var(PROGRESS_ENV),
+        runtime: var(RUNTIME_ENV),
+        ..PartialRepxConfig::default()
+    }
+}
+
 impl RepxConfig {
+    /// Layer `other` over this configuration, overriding only the fields it sets
+    pub fn merge(mut self, other: PartialRepxConfig) -> RepxConfig {
+        self.project = other.project.unwrap_or(self.project);
+        self.manifest_path = other.manifest_path.or(self.manifest_path);
+        self.package = other.package.or(self.package);
+        self.image = other.image.unwrap_or(self.image);
+        self.targets = other.targets.or(self.targets);
+        self.extra = other.extra.unwrap_or(self.extra);
+        self.rust_channel = other.rust_channel.unwrap_or(self.rust_channel);
+        self.rust_version = other.rust_version.unwrap_or(self.rust_version);
+        self.rust_versions = other.rust_versions.unwrap_or(self.rust_versions);
+        self.nixpkgs_url = other.nixpkgs_url.unwrap_or(self.nixpkgs_url);
+        self.rust_overlay_url = other.rust_overlay_url.unwrap_or(self.rust_overlay_url);
+        self.flake_utils_url = other.flake_utils_url.unwrap_or(self.flake_utils_url);
+        self.no_flake_lock = other.no_flake_lock.unwrap_or(self.no_flake_lock);
+        self.no_git_config = other.no_git_config.unwrap_or(self.no_git_config);
+        self.no_flake_check = other.no_flake_check.unwrap_or(self.no_flake_check);
+        self.verify_lock = other.verify_lock.unwrap_or(self.verify_lock);
+        self.profile = other.profile.unwrap_or(self.profile);
+        self.cargo_hash = other.cargo_hash.or(self.cargo_hash);
+        self.max_output_mb = other.max_output_mb.unwrap_or(self.max_output_mb);
+        self.max_output_lines = other.max_output_lines.unwrap_or(self.max_output_lines);
+        self.max_log_size_mb = other.max_log_size_mb.or(self.max_log_size_mb);
+        self.progress = other.progress.unwrap_or(self.progress);
+        self.progress_interval_ms = other.progress_interval_ms.or(self.progress_interval_ms);
+        self.progress_path_step = other.progress_path_step.or(self.progress_path_step);
+        self.registry_token_file = other.registry_token_file.or(self.registry_token_file);
+        self.flake_token_file = other.flake_token_file.or(self.flake_token_file);
+        self.output_attr_template = other.output_attr_template.unwrap_or(self.output_attr_template);
+        self.build_command = other.build_command.unwrap_or(self.build_command);
+        self.flake_check = other.flake_check.unwrap_or(self.flake_check);
+        self.target_dir = other.target_dir.unwrap_or(self.target_dir);
+        self.layout = other.layout.unwrap_or(self.layout);
+        self.metadata_gitignore = other.metadata_gitignore.unwrap_or(self.metadata_gitignore);
+        self.nix_args = other.nix_args.unwrap_or(self.nix_args);
+        self.container_runtime_args = other.container_runtime_args.unwrap_or(self.container_runtime_args);
+        self.runtime = other.runtime.or(self.runtime);
+        self.docker_host = other.docker_host.or(self.docker_host);
+        self.privileged = other.privileged.unwrap_or(self.privileged);
+        self.exact_flake_compare = other.exact_flake_compare.unwrap_or(self.exact_flake_compare);
+        self.show_diff = other.show_diff.unwrap_or(self.show_diff);
+        self.dump_context = other.dump_context.unwrap_or(self.dump_context);
+        self.static_link = other.static_link.unwrap_or(self.static_link);
+        self.source_date_epoch = other.source_date_epoch.or(self.source_date_epoch);
+        self.no_source_date_epoch = other.no_source_date_epoch.unwrap_or(self.no_source_date_epoch);
+        self.record_closure = other.record_closure.unwrap_or(self.record_closure);
+        self.user = other.user.or(self.user);
+        self.strip = other.strip.unwrap_or(self.strip);
+        self.checksums = other.checksums.unwrap_or(self.checksums);
+        self.sign = other.sign.or(self.sign);
+        self.sign_key_file = other.sign_key_file.or(self.sign_key_file);
+        self.locked = other.locked.unwrap_or(self.locked);
+        self.audit = other.audit.unwrap_or(self.audit);
+        self.audit_deny = other.audit_deny.or(self.audit_deny);
+        self.readonly_source = other.readonly_source.unwrap_or(self.readonly_source);
+        self.write_cargo_lock = other.write_cargo_lock.unwrap_or(self.write_cargo_lock);
+        self.command_timeout_secs = other.command_timeout_secs.or(self.command_timeout_secs);
+        self.image_output = other.image_output.or(self.image_output);
+        self.summary_json = other.summary_json.or(self.summary_json);
+        self.junit = other.junit.or(self.junit);
+        self.sarif = other.sarif.or(self.sarif);
+        self.copy_jobs = other.copy_jobs.unwrap_or(self.copy_jobs);
+        self.jobs = other.jobs.unwrap_or(self.jobs);
+        self.pull_retries = other.pull_retries.unwrap_or(self.pull_retries);
+        self.copy_retries = other.copy_retries.unwrap_or(self.copy_retries);
+        self.retry_failed_targets = other.retry_failed_targets.unwrap_or(self.retry_failed_targets);
+        self.compare_with = other.compare_with.or(self.compare_with);
+        self.min_free_space_gb = other.min_free_space_gb.unwrap_or(self.min_free_space_gb);
+        self.allow_dirty = other.allow_dirty.unwrap_or(self.allow_dirty);
+        self.strict = other.strict.unwrap_or(self.strict);
+        self.strict_targets = other.strict_targets.unwrap_or(self.strict_targets);
+        self.fail_on_warning = other.fail_on_warning.unwrap_or(self.fail_on_warning);
+        self.vendor = other.vendor.unwrap_or(self.vendor);
+        self.offline = other.offline.unwrap_or(self.offline);
+        self.nix_daemon = other.nix_daemon.unwrap_or(self.nix_daemon);
+        self.nix_store_cache = other.nix_store_cache.or(self.nix_store_cache);
+        self.deterministic_name = other.deterministic_name.unwrap_or(self.deterministic_name);
+        self.container_name = other.container_name.or(self.container_name);
+        self.trace_commands = other.trace_commands.unwrap_or(self.trace_commands);
+        self.explain_failure = other.explain_failure.unwrap_or(self.explain_failure);
+        self.dump_logs_on_failure = other.dump_logs_on_failure.unwrap_or(self.dump_logs_on_failure);
+        if let Some(meta) = other.meta {
+            self.meta.extend(meta);
+        }
+        self
+    }
+
     /// Load configuration from a TOML file
-    pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+    pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, RepxError> {
+        let path = path.as_ref();
         let content = fs::read_to_string(path).await?;
-        let config: RepxConfig = toml::from_str(&content)?;
-        Ok(config)
+        toml::from_str(&content)
+            .map_err(|e| RepxError::ConfigInvalid(format!("{}: {}", path.display(), e)))
     }
     
     /// Save configuration to a TOML file
-    pub async fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let content = toml::to_string_pretty(self)?;
+    pub async fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), RepxError> {
+        let content = toml::to_string_pretty(self).map_err(|e| RepxError::ConfigInvalid(e.to_string()))?;
         fs::write(path, content).await?;
         Ok(())
     }
     
+    /// Whether the file was written by a newer repx that may use settings this one ignores
+    pub fn is_newer_than_supported(&self) -> bool {
+        self.version > CONFIG_VERSION
+    }
+
+    /// Targets of a named group, as a comma-separated list
+    pub fn target_group(&self, name: &str) -> Result<String, RepxError> {
+        match self.groups.get(name) {
+            Some(targets) if !targets.is_empty() => Ok(targets.join(",")),
+            Some(_) => Err(RepxError::ConfigInvalid(format!("Target group '{}' is empty", name))),
+            None => {
+                let known: Vec<&str> = self.groups.keys().map(String::as_str).collect();
+                Err(RepxError::ConfigInvalid(format!(
+                    "Unknown target group '{}'; defined groups: {}",
+                    name,
+                    if known.is_empty() { "none".to_string() } else { known.join(", ") }
+                )))
+            }
+        }
+    }
+
+    /// Toolchains of the `rust_versions` matrix, empty when every target is built with `rust_version` only
+    pub fn rust_matrix(&self) -> Result<Vec<RustToolchain>, RepxError> {
+        let mut matrix: Vec<RustToolchain> = Vec::new();
+        for name in &self.rust_versions {
+            let toolchain = RustToolchain::parse(name.trim())?;
+            if matrix.contains(&toolchain) {
+                return Err(RepxError::ConfigInvalid(format!("Rust version '{}' is listed twice in rust_versions", name)));
+            }
+            matrix.push(toolchain);
+        }
+        Ok(matrix)
+    }
+
+    /// Toolchains of the targets in `target_toolchains`, by target
+    pub fn target_toolchains(&self) -> Result<BTreeMap<&str, RustToolchain>, RepxError> {
+        self.target_toolchains
+            .iter()
+            .map(|(target, toolchain)| Ok((target.as_str(), toolchain.resolve(target, &self.rust_channel, &self.rust_version)?)))
+            .collect()
+    }
+
+    /// Split `targets` into the ones `target_hosts` allows on this host and the skipped ones, with why
+    ///
+    /// Targets without an entry are always kept.
+    pub fn targets_for_host<'a>(&self, targets: &[&'a str], host_os: &str, host_arch: &str) -> Result<(Vec<&'a str>, SkippedTargets<'a>), RepxError> {
+        let mut kept = Vec::new();
+        let mut skipped = Vec::new();
+        for &target in targets {
+            let Some(conditions) = self.target_hosts.get(target) else {
+                kept.push(target);
+                continue;
+            };
+            for condition in conditions {
+                validate_host_condition(condition)?;
+            }
+            if conditions.iter().any(|c| host_condition_matches(c, target, host_os, host_arch)) {
+                kept.push(target);
+            } else {
+                skipped.push((target, format!("only built on {}", conditions.join(" or "))));
+            }
+        }
+        Ok((kept, skipped))
+    }
+
+    /// Path to the Cargo manifest of the project being built
+    pub fn manifest_path(&self) -> PathBuf {
+        match &self.manifest_path {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(&self.project).join("Cargo.toml"),
+        }
+    }
+
+    /// Directory the artifacts are copied to, relative to the project
+    pub fn artifacts_dir(&self) -> String {
+        format!("{}/repx", self.target_dir.trim_end_matches('/'))
+    }
+
+    /// Check that `target_dir` stays inside the project
+    pub fn validate_target_dir(&self) -> Result<(), RepxError> {
+        let path = Path::new(&self.target_dir);
+        let escapes = path.is_absolute()
+            || path.components().any(|c| !matches!(c, std::path::Component::Normal(_)));
+        if self.target_dir.is_empty() || escapes {
+            return Err(RepxError::ConfigInvalid(format!(
+                "target_dir '{}' must be a relative path inside the project",
+                self.target_dir
+            )));
+        }
+        Ok(())
+    }
+
+    /// Flake attribute to build for `target`, from `output_attr_template`
+    pub fn output_attr(&self, target: &str) -> String {
+        self.output_attr_template.replace("{target}", target)
+    }
+
+    /// This configuration as a `repx.toml` that reproduces a build of `targets` with `-c`
+    ///
+    /// The token file settings are left out: they name per-user credentials, not build inputs.
+    pub fn effective_toml(&self, targets: &[&str]) -> Result<String, RepxError> {
+        let effective = RepxConfig {
+            version: CONFIG_VERSION,
+            targets: Some(targets.join(",")),
+            registry_token_file: None,
+            flake_token_file: None,
+            ..self.clone()
+        };
+        toml::to_string_pretty(&effective)
+            .map_err(|e| RepxError::ConfigInvalid(format!("Could not serialize the effective configuration: {}", e)))
+    }
+
+    /// Whether the targets are built, rather than only checked with `nix flake check`
+    pub fn builds_targets(&self) -> bool {
+        self.build_command == "build"
+    }
+
+    /// Whether `nix flake check` runs, with `--check` or as the build command
+    pub fn runs_flake_check(&self) -> bool {
+        self.flake_check || self.build_command == "check"
+    }
+
+    /// The progress redraw rate: the `progress` preset with any explicit interval or step applied
+    pub fn progress_rate(&self) -> Result<ProgressRate, RepxError> {
+        let preset = ProgressRate::preset(&self.progress)?;
+        Ok(ProgressRate {
+            interval: self.progress_interval_ms.map_or(preset.interval, Duration::from_millis),
+            path_step: self.progress_path_step.unwrap_or(preset.path_step).max(1),
+        })
+    }
+
+    /// The container runtime to use: `runtime`, else Docker
+    ///
+    /// `REPX_RUNTIME` reaches `runtime` through `env_overrides`.
+    pub fn container_runtime(&self) -> Result<ContainerRuntime, RepxError> {
+        match &self.runtime {
+            Some(name) => ContainerRuntime::parse(name),
+            None => Ok(ContainerRuntime::Docker),
+        }
+    }
+
+    /// Check that `docker_host` is an address repx can connect to
+    pub fn validate_docker_host(&self) -> Result<(), RepxError> {
+        match &self.docker_host {
+            Some(host) => DockerEndpoint::parse(host).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that `manifest_path` names a `Cargo.toml`
+    ///
+    /// Only the host's `cargo metadata` is given the path; cargo in the container and the
+    /// flake's builders look for `Cargo.toml` in the project root, so no other name works.
+    pub fn validate_manifest_path(&self) -> Result<(), RepxError> {
+        let Some(path) = &self.manifest_path else {
+            return Ok(());
+        };
+        if Path::new(path).file_name().is_none_or(|name| name != "Cargo.toml") {
+            return Err(RepxError::ConfigInvalid(format!(
+                "--manifest-path '{}' must name a Cargo.toml; repx builds the manifest in the project root, so point it at the directory's Cargo.toml",
+                path
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that `layout` is one repx knows
+    pub fn validate_layout(&self) -> Result<(), RepxError> {
+        if !["nested", "flat"].contains(&self.layout.as_str()) {
+            return Err(RepxError::ConfigInvalid(format!("Unknown layout '{}'; use nested or flat", self.layout)));
+        }
+        Ok(())
+    }
+
+    /// Check that `build_command` is one repx knows how to run
+    pub fn validate_build_command(&self) -> Result<(), RepxError> {
+        if !["build", "check"].contains(&self.build_command.as_str()) {
+            return Err(RepxError::ConfigInvalid(format!(
+                "Unknown build_command '{}'; use build or check",
+                self.build_command
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that `output_attr_template` is a plain attribute path
+    ///
+    /// It is quoted in the build command either way; this catches typos such as spaces.
+    pub fn validate_output_attr_template(&self) -> Result<(), RepxError> {
+        let valid = self.output_attr_template.chars().all(|c| c.is_ascii_alphanumeric() || "-_.{}\"".contains(c));
+        if self.output_attr_template.is_empty() || !valid {
+            return Err(RepxError::ConfigInvalid(format!(
+                "output_attr_template '{}' must be a flake attribute path such as packages.x86_64-linux.default or {{target}}",
+                self.output_attr_template
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that every `meta` entry fits on one line of the log header
+    pub fn validate_meta(&self) -> Result<(), RepxError> {
+        self.meta.iter().try_for_each(|(key, value)| validate_meta_entry(key, value))
+    }
+
+    /// Top-level project directory holding the target dir, left out of the Nix source
+    pub fn target_dir_root(&self) -> &str {
+        self.target_dir.split('/').next().unwrap_or(&self.target_dir)
+    }
+
     /// Get the default config file path (repx.toml in current directory)
     pub fn default_config_path() -> &'static str {
         "repx.toml"
@@ -91,4 +1136,354 @@ impl RepxConfig {
     pub async fn config_exists() -> bool {
         fs::metadata(Self::default_config_path()).await.is_ok()
     }
-} 
\ No newline at end of file
+
+    /// Find the repx.toml that applies to `start`, the way Cargo finds Cargo.toml
+    ///
+    /// Looks in `start` and then each parent directory. The search ends at the workspace
+    /// root, a directory with a `[workspace]` Cargo.toml or a `.git`, or at the filesystem root.
+    pub fn discover_config_path(start: &Path) -> Option<PathBuf> {
+        let start = start.canonicalize().unwrap_or_else(|_| start.to_path_buf());
+        for dir in start.ancestors() {
+            let candidate = dir.join(Self::default_config_path());
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+            if is_workspace_root(dir) {
+                break;
+            }
+        }
+        None
+    }
+}
+
+/// Whether `dir` is the root of a Cargo workspace or a git repository
+fn is_workspace_root(dir: &Path) -> bool {
+    let declares_workspace = std::fs::read_to_string(dir.join("Cargo.toml")).is_ok_and(|manifest| {
+        manifest.lines().map(str::trim).any(|line| line == "[workspace]" || line.starts_with("[workspace."))
+    });
+    declares_workspace || dir.join(".git").exists()
+}
+
+/// Upgrade the text of a repx.toml to `CONFIG_VERSION`, keeping comments and formatting
+///
+/// Returns the upgraded text and the version it was upgraded from.
+pub fn upgrade_config_text(text: &str) -> Result<(String, u32), RepxError> {
+    let mut document: toml_edit::DocumentMut = text
+        .parse()
+        .map_err(|e| RepxError::ConfigInvalid(format!("Could not parse config: {}", e)))?;
+    let from = match document.get("version") {
+        None => 0,
+        Some(item) => item
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| RepxError::ConfigInvalid("version must be a non-negative integer".to_string()))?,
+    };
+    if from > CONFIG_VERSION {
+        return Err(RepxError::ConfigInvalid(format!(
+            "Config version {} is newer than this repx supports ({}); upgrade repx instead",
+            from, CONFIG_VERSION
+        )));
+    }
+
+    for migration in &MIGRATIONS[from as usize..] {
+        migration(&mut document);
+    }
+    document.insert("version", toml_edit::value(i64::from(CONFIG_VERSION)));
+    // Keep the version at the top, where it is easiest to find
+    document.sort_values_by(|a, _, b, _| (b.get() == "version").cmp(&(a.get() == "version")));
+    Ok((document.to_string(), from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_for_host() {
+        let mut config = RepxConfig::default();
+        config.target_hosts.insert("aarch64-apple-darwin".to_string(), vec!["macos".to_string()]);
+        config.target_hosts.insert("x86_64-linux-gnu".to_string(), vec!["host-only".to_string()]);
+        config.target_hosts.insert("x86_64-pc-windows-msvc".to_string(), vec!["linux-x86_64".to_string(), "windows".to_string()]);
+        let targets = ["aarch64-apple-darwin", "x86_64-linux-gnu", "x86_64-pc-windows-msvc", "wasm32-wasi"];
+
+        let (kept, skipped) = config.targets_for_host(&targets, "linux", "x86_64").unwrap();
+        assert_eq!(kept, ["x86_64-linux-gnu", "x86_64-pc-windows-msvc", "wasm32-wasi"]);
+        assert_eq!(skipped, [("aarch64-apple-darwin", "only built on macos".to_string())]);
+
+        let (kept, _) = config.targets_for_host(&targets, "linux", "aarch64").unwrap();
+        assert_eq!(kept, ["wasm32-wasi"]);
+
+        config.target_hosts.insert("wasm32-wasi".to_string(), vec!["linuxx".to_string()]);
+        assert!(config.targets_for_host(&targets, "linux", "x86_64").is_err());
+    }
+
+    #[test]
+    fn test_target_groups_round_trip() {
+        let mut config: RepxConfig = toml::from_str(
+            "[groups]\nrelease = [\"x86_64-linux-musl\", \"aarch64-linux-musl\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.target_group("release").unwrap(), "x86_64-linux-musl,aarch64-linux-musl");
+        assert!(config.target_group("nightly").is_err());
+
+        config.targets = Some("x86_64-linux-gnu".to_string());
+        let reparsed: RepxConfig = toml::from_str(&toml::to_string_pretty(&config).unwrap()).unwrap();
+        assert_eq!(reparsed.groups, config.groups);
+    }
+
+    #[test]
+    fn test_rust_matrix() {
+        let mut config = RepxConfig::default();
+        assert!(config.rust_matrix().unwrap().is_empty());
+
+        config.rust_versions = ["1.75.0", "stable", "nightly-2024-05-01"].iter().map(|v| v.to_string()).collect();
+        let matrix = config.rust_matrix().unwrap();
+        assert_eq!((matrix[0].channel.as_str(), matrix[0].version.as_str()), ("stable", "1.75.0"));
+        assert_eq!(matrix[0].attr_suffix(), "1_75_0");
+        assert_eq!((matrix[1].channel.as_str(), matrix[1].version.as_str()), ("stable", "latest"));
+        assert_eq!((matrix[2].channel.as_str(), matrix[2].version.as_str()), ("nightly", "2024-05-01"));
+
+        for invalid in ["1.75", "latest", "nightly-24-05-01", "1.75.0\"; rm"] {
+            config.rust_versions = vec![invalid.to_string()];
+            assert!(config.rust_matrix().is_err(), "{}", invalid);
+        }
+        config.rust_versions = vec!["stable".to_string(), "stable".to_string()];
+        assert!(config.rust_matrix().is_err());
+    }
+
+    #[test]
+    fn test_merge_overrides_only_set_fields() {
+        let file = RepxConfig {
+            image: "nixos/nix:2.24.0".to_string(),
+            rust_version: "1.75.0".to_string(),
+            cargo_hash: Some("sha256-file".to_string()),
+            offline: true,
+            extra: vec!["openssl".to_string()],
+            ..RepxConfig::default()
+        };
+
+        // Nothing set: the file's values stand, including ones that differ from the defaults
+        let merged = file.clone().merge(PartialRepxConfig::default());
+        assert_eq!(merged.image, "nixos/nix:2.24.0");
+        assert_eq!(merged.cargo_hash.as_deref(), Some("sha256-file"));
+        assert!(merged.offline);
+
+        // Set fields win over the file, even when set back to a default value
+        let overrides = PartialRepxConfig {
+            rust_version: Some("latest".to_string()),
+            cargo_hash: Some("sha256-cli".to_string()),
+            offline: Some(false),
+            extra: Some(Vec::new()),
+            ..PartialRepxConfig::default()
+        };
+        let merged = file.merge(overrides);
+        assert_eq!(merged.image, "nixos/nix:2.24.0");
+        assert_eq!(merged.rust_version, "latest");
+        assert_eq!(merged.cargo_hash.as_deref(), Some("sha256-cli"));
+        assert!(!merged.offline);
+        assert!(merged.extra.is_empty());
+
+        // Later layers take precedence over earlier ones
+        let env = PartialRepxConfig { profile: Some("dist".to_string()), nix_store_cache: Some("env".to_string()), ..PartialRepxConfig::default() };
+        let cli = PartialRepxConfig { nix_store_cache: Some("cli".to_string()), ..PartialRepxConfig::default() };
+        let merged = RepxConfig::default().merge(env).merge(cli);
+        assert_eq!(merged.profile, "dist");
+        assert_eq!(merged.nix_store_cache.as_deref(), Some("cli"));
+    }
+
+    #[test]
+    fn test_env_overrides_between_file_and_cli() {
+        let env_vars = BTreeMap::from([(PROGRESS_ENV, "slow"), (RUNTIME_ENV, "podman"), ("REPX_UNRELATED", "x")]);
+        let env = env_overrides_from(|key| env_vars.get(key).map(|value| value.to_string()));
+        let file = RepxConfig { progress: "fast".to_string(), runtime: Some("docker".to_string()), ..RepxConfig::default() };
+        let cli = PartialRepxConfig { progress: Some("default".to_string()), ..PartialRepxConfig::default() };
+
+        let merged = file.clone().merge(env.clone());
+        assert_eq!(merged.progress, "slow");
+        assert_eq!(merged.container_runtime().unwrap(), ContainerRuntime::Podman);
+        let merged = file.clone().merge(env).merge(cli);
+        assert_eq!(merged.progress, "default");
+        assert_eq!(merged.runtime.as_deref(), Some("podman"));
+
+        // Empty variables leave the file's settings alone
+        let merged = file.merge(env_overrides_from(|_| Some(String::new())));
+        assert_eq!(merged.progress, "fast");
+        assert_eq!(merged.runtime.as_deref(), Some("docker"));
+    }
+
+    #[test]
+    fn test_target_toolchains() {
+        let mut config = RepxConfig { rust_version: "1.75.0".to_string(), ..RepxConfig::default() };
+        let set = |config: &mut RepxConfig, target: &str, channel: Option<&str>, version: Option<&str>| {
+            config.target_toolchains.insert(
+                target.to_string(),
+                ToolchainOverride { channel: channel.map(str::to_string), version: version.map(str::to_string) },
+            );
+        };
+        set(&mut config, "x86_64-linux-musl", Some("nightly"), Some("2024-05-01"));
+        set(&mut config, "aarch64-linux-musl", Some("beta"), None);
+        set(&mut config, "x86_64-linux-gnu", None, Some("1.80.0"));
+        let toolchains = config.target_toolchains().unwrap();
+        assert_eq!(toolchains["x86_64-linux-musl"].name, "nightly-2024-05-01");
+        assert_eq!((toolchains["aarch64-linux-musl"].channel.as_str(), toolchains["aarch64-linux-musl"].version.as_str()), ("beta", "latest"));
+        assert_eq!((toolchains["x86_64-linux-gnu"].channel.as_str(), toolchains["x86_64-linux-gnu"].version.as_str()), ("stable", "1.80.0"));
+
+        for (channel, version) in [(Some("dev"), None), (Some("nightly"), Some("1.80.0")), (None, Some("2024-05-01")), (Some("stable"), Some("1.80\""))] {
+            let mut invalid = RepxConfig::default();
+            set(&mut invalid, "x86_64-linux-gnu", channel, version);
+            assert!(invalid.target_toolchains().is_err(), "{:?} {:?}", channel, version);
+        }
+
+        let parsed: RepxConfig = toml::from_str("[target_toolchains]\n\"x86_64-linux-musl\" = { channel = \"nightly\" }\n").unwrap();
+        assert_eq!(parsed.target_toolchains().unwrap()["x86_64-linux-musl"].name, "nightly");
+    }
+
+    #[test]
+    fn test_target_dir() {
+        let config = RepxConfig { target_dir: "target-repx/".to_string(), ..RepxConfig::default() };
+        assert_eq!(config.artifacts_dir(), "target-repx/repx");
+        assert_eq!(config.target_dir_root(), "target-repx");
+        assert!(config.validate_target_dir().is_ok());
+
+        for invalid in ["", "/tmp/target", "../target"] {
+            let config = RepxConfig { target_dir: invalid.to_string(), ..RepxConfig::default() };
+            assert!(config.validate_target_dir().is_err(), "{} should be rejected", invalid);
+        }
+    }
+
+    #[test]
+    fn test_effective_toml() {
+        let config = RepxConfig {
+            rust_version: "1.80.0".to_string(),
+            registry_token_file: Some("/home/me/.repx-registry-token".to_string()),
+            flake_token_file: Some("/home/me/.repx-flake-token".to_string()),
+            ..RepxConfig::default()
+        };
+        let toml = config.effective_toml(&["x86_64-linux-gnu", "aarch64-linux-gnu"]).unwrap();
+        assert!(!toml.contains("token"));
+
+        let reparsed: RepxConfig = toml::from_str(&toml).unwrap();
+        assert_eq!(reparsed.version, CONFIG_VERSION);
+        assert_eq!(reparsed.targets.as_deref(), Some("x86_64-linux-gnu,aarch64-linux-gnu"));
+        assert_eq!(reparsed.rust_version, "1.80.0");
+        assert_eq!(reparsed.registry_token_file, None);
+    }
+
+    #[test]
+    fn test_build_command() {
+        let config = RepxConfig::default();
+        assert!(config.validate_build_command().is_ok());
+        assert!(config.builds_targets() && !config.runs_flake_check());
+
+        let config = RepxConfig { flake_check: true, ..RepxConfig::default() };
+        assert!(config.builds_targets() && config.runs_flake_check());
+
+        let config = RepxConfig { build_command: "check".to_string(), ..RepxConfig::default() };
+        assert!(config.validate_build_command().is_ok());
+        assert!(!config.builds_targets() && config.runs_flake_check());
+
+        let config = RepxConfig { build_command: "run".to_string(), ..RepxConfig::default() };
+        assert!(config.validate_build_command().is_err());
+    }
+
+    #[test]
+    fn test_progress_rate() {
+        assert_eq!(RepxConfig::default().progress_rate().unwrap(), ProgressRate::default());
+
+        let config = RepxConfig { progress: "fast".to_string(), ..RepxConfig::default() };
+        assert_eq!(config.progress_rate().unwrap(), ProgressRate { interval: Duration::from_millis(100), path_step: 1 });
+
+        let config = RepxConfig { progress_interval_ms: Some(250), progress_path_step: Some(0), ..config };
+        assert_eq!(config.progress_rate().unwrap(), ProgressRate { interval: Duration::from_millis(250), path_step: 1 });
+
+        let config = RepxConfig { progress: "instant".to_string(), ..RepxConfig::default() };
+        assert!(config.progress_rate().is_err());
+    }
+
+    #[test]
+    fn test_validate_manifest_path() {
+        let with_manifest = |path: &str| RepxConfig { manifest_path: Some(path.to_string()), ..RepxConfig::default() };
+        assert!(RepxConfig::default().validate_manifest_path().is_ok());
+        assert!(with_manifest("crates/demo/Cargo.toml").validate_manifest_path().is_ok());
+        assert!(with_manifest("Cargo.toml").validate_manifest_path().is_ok());
+        assert!(with_manifest("crates/demo/Other.toml").validate_manifest_path().is_err());
+        assert!(with_manifest("crates/demo").validate_manifest_path().is_err());
+    }
+
+    #[test]
+    fn test_meta() {
+        assert_eq!(parse_meta("ci_run=https://ci.example.com/runs/42").unwrap(), ("ci_run".to_string(), "https://ci.example.com/runs/42".to_string()));
+        assert_eq!(parse_meta("release=").unwrap().1, "");
+        for invalid in ["release", "=1.0", "ci run=42", "note=two\nlines"] {
+            assert!(parse_meta(invalid).is_err(), "{} should be rejected", invalid);
+        }
+
+        let mut config = RepxConfig::default();
+        config.meta.insert("release".to_string(), "1.0".to_string());
+        config.meta.insert("team".to_string(), "infra".to_string());
+        let overrides = PartialRepxConfig {
+            meta: Some(BTreeMap::from([("release".to_string(), "1.1".to_string())])),
+            ..PartialRepxConfig::default()
+        };
+        let config = config.merge(overrides);
+        assert_eq!(config.meta["release"], "1.1");
+        assert_eq!(config.meta["team"], "infra");
+        assert!(config.validate_meta().is_ok());
+    }
+
+    #[test]
+    fn test_output_attr_template() {
+        let config = RepxConfig::default();
+        assert_eq!(config.output_attr("x86_64-linux-gnu"), "x86_64-linux-gnu");
+
+        let config = RepxConfig { output_attr_template: "packages.x86_64-linux.{target}".to_string(), ..config };
+        assert_eq!(config.output_attr("x86_64-linux-musl"), "packages.x86_64-linux.x86_64-linux-musl");
+        assert!(config.validate_output_attr_template().is_ok());
+
+        for invalid in ["", "packages default", "default; rm -rf /"] {
+            let config = RepxConfig { output_attr_template: invalid.to_string(), ..RepxConfig::default() };
+            assert!(config.validate_output_attr_template().is_err(), "{} should be rejected", invalid);
+        }
+    }
+
+    #[test]
+    fn test_discover_config_path() {
+        let root = tempfile::tempdir().unwrap();
+        let member = root.path().join("crates/app/src");
+        std::fs::create_dir_all(&member).unwrap();
+        std::fs::write(root.path().join("Cargo.toml"), "[workspace]\nmembers = [\"crates/app\"]\n").unwrap();
+        std::fs::write(root.path().join("crates/app/Cargo.toml"), "[package]\nname = \"app\"\n").unwrap();
+        assert_eq!(RepxConfig::discover_config_path(&member), None);
+
+        // The nearest repx.toml wins, and the root one is found from any member
+        let root_config = root.path().join("repx.toml");
+        std::fs::write(&root_config, "").unwrap();
+        assert_eq!(RepxConfig::discover_config_path(&member), Some(root_config.canonicalize().unwrap()));
+        let member_config = root.path().join("crates/app/repx.toml");
+        std::fs::write(&member_config, "").unwrap();
+        assert_eq!(RepxConfig::discover_config_path(&member), Some(member_config.canonicalize().unwrap()));
+
+        // A repx.toml above the workspace root belongs to something else
+        let outer = tempfile::tempdir().unwrap();
+        std::fs::write(outer.path().join("repx.toml"), "").unwrap();
+        let repo = outer.path().join("repo");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        assert_eq!(RepxConfig::discover_config_path(&repo), None);
+    }
+
+    #[test]
+    fn test_upgrade_config_keeps_comments() {
+        let old = "# Release settings\nimage = \"nixos/nix:2.24.0\"\ntargets = \"x86_64-linux-gnu\" # ci\n";
+        let (upgraded, from) = upgrade_config_text(old).unwrap();
+        assert_eq!(from, 0);
+        assert!(upgraded.contains("# Release settings"));
+        assert!(upgraded.contains("# ci"));
+        assert!(upgraded.starts_with("version = 1"));
+        let config: RepxConfig = toml::from_str(&upgraded).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.image, "nixos/nix:2.24.0");
+
+        assert_eq!(upgrade_config_text(&upgraded).unwrap(), (upgraded.clone(), CONFIG_VERSION));
+        assert!(upgrade_config_text("version = 99\n").is_err());
+    }
+}
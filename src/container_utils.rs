@@ -1,29 +1,123 @@
 use anyhow::Result;
 use bollard::{
-    models::{ContainerCreateBody, HostConfig},
+    auth::DockerCredentials,
+    models::{ContainerCreateBody, ContainerSummary, ContainerSummaryStateEnum, DeviceMapping, HostConfig, ResourcesUlimits},
     query_parameters::{
-        CreateContainerOptions, CreateImageOptions, RemoveContainerOptions, StartContainerOptions,
+        CreateContainerOptions, CreateImageOptions, ImportImageOptions, ListContainersOptions,
+        RemoveContainerOptions, StartContainerOptions,
     },
     Docker,
 };
-use futures_util::stream::TryStreamExt;
-use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
+use futures_util::stream::StreamExt;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
 
 // Import color constants from lib.rs
-use crate::{BLUE, BOLD, CYAN, GREEN, RESET};
+use crate::execute_command::{execute_command, ExecSettings};
+use crate::secrets::{read_secret_file, registry_credentials};
+use crate::{connect_docker, RepxConfig, RepxError, BLUE, BOLD, CYAN, GREEN, RESET, YELLOW};
+
+/// Label holding the Unix time a repx container was last used, for `repx reap`
+const LAST_USED_LABEL: &str = "repx.last-used";
+
+/// File written into a host directory used as the Nix store cache once it holds the image's /nix
+const SEEDED_MARKER: &str = ".repx-seeded";
+
+/// Environment variable choosing the container runtime when the config doesn't
+pub const RUNTIME_ENV: &str = "REPX_RUNTIME";
+
+/// Where the runtime's Docker API is reached, from `docker_host`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DockerEndpoint {
+    /// Unix socket path, or named pipe on Windows
+    Socket(String),
+    /// Plain HTTP address, `host:port`
+    Http(String),
+}
+
+impl DockerEndpoint {
+    /// Parse a `docker_host` the way `DOCKER_HOST` is written, or a bare socket path
+    pub fn parse(host: &str) -> Result<Self, RepxError> {
+        let invalid = |reason: &str| RepxError::ConfigInvalid(format!("Invalid docker_host '{}': {}", host, reason));
+        let (scheme, rest) = match host.split_once("://") {
+            Some((scheme, rest)) => (scheme, rest),
+            None if host.starts_with('/') => ("unix", host),
+            None => return Err(invalid("use unix://, npipe://, tcp:// or http://, or an absolute socket path")),
+        };
+        if rest.is_empty() {
+            return Err(invalid("the address is empty"));
+        }
+        match scheme {
+            "unix" => Ok(DockerEndpoint::Socket(rest.to_string())),
+            "npipe" => Ok(DockerEndpoint::Socket(host.to_string())),
+            "tcp" | "http" => Ok(DockerEndpoint::Http(rest.trim_end_matches('/').to_string())),
+            "https" => Err(invalid("TLS isn't supported; reach the daemon over an SSH tunnel or a local socket")),
+            other => Err(invalid(&format!("unknown scheme '{}'; use unix://, npipe://, tcp:// or http://", other))),
+        }
+    }
+
+    /// The address as `DOCKER_HOST` expects it, for the runtime's CLI
+    pub fn url(&self) -> String {
+        match self {
+            DockerEndpoint::Socket(path) if path.starts_with("npipe://") => path.clone(),
+            DockerEndpoint::Socket(path) => format!("unix://{}", path),
+            DockerEndpoint::Http(address) => format!("tcp://{}", address),
+        }
+    }
+}
+
+/// Container engine that runs the build containers, through its Docker-compatible API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerRuntime {
+    Docker,
+    Podman,
+}
+
+impl ContainerRuntime {
+    /// Parse a runtime name as given to `--runtime`, `runtime` in repx.toml or `REPX_RUNTIME`
+    pub fn parse(name: &str) -> Result<Self, RepxError> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "docker" => Ok(ContainerRuntime::Docker),
+            "podman" => Ok(ContainerRuntime::Podman),
+            other => Err(RepxError::ConfigInvalid(format!("Unknown container runtime '{}'; use docker or podman", other))),
+        }
+    }
+
+    /// Name of the runtime's command-line tool
+    pub fn cli(self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+        }
+    }
+
+    /// API socket to use when none is configured; none leaves it to Docker's defaults and `DOCKER_HOST`
+    ///
+    /// Rootless Podman serves its API under `$XDG_RUNTIME_DIR`, rootful Podman under /run.
+    pub fn default_socket(self) -> Option<PathBuf> {
+        match self {
+            ContainerRuntime::Docker => None,
+            ContainerRuntime::Podman => {
+                let rootless = std::env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("podman/podman.sock"));
+                Some(rootless.filter(|socket| socket.exists()).unwrap_or_else(|| PathBuf::from("/run/podman/podman.sock")))
+            }
+        }
+    }
+}
 
 /// Convert a Windows path to a Docker-compatible format
-fn windows_path_to_docker(path: &Path) -> String {
+pub(crate) fn windows_path_to_docker(path: &Path) -> String {
     let path_str = path.display().to_string();
 
     // Handle Windows extended path format (\\?\)
-    if path_str.starts_with("\\\\?\\") {
-        let cleaned = &path_str[4..]; // Remove \\?\
-
+    if let Some(cleaned) = path_str.strip_prefix("\\\\?\\") {
         // Convert Windows drive letter to Unix-style path for Docker
         if cleaned.len() >= 3 && cleaned.chars().nth(1) == Some(':') {
-            let drive = cleaned.chars().nth(0).unwrap().to_ascii_lowercase();
+            let drive = cleaned.chars().next().unwrap().to_ascii_lowercase();
             let rest = &cleaned[2..].replace('\\', "/");
             format!("/{}{}", drive, rest)
         } else {
@@ -32,7 +126,7 @@ fn windows_path_to_docker(path: &Path) -> String {
     } else {
         // Handle regular Windows paths
         if cfg!(windows) && path_str.len() >= 3 && path_str.chars().nth(1) == Some(':') {
-            let drive = path_str.chars().nth(0).unwrap().to_ascii_lowercase();
+            let drive = path_str.chars().next().unwrap().to_ascii_lowercase();
             let rest = &path_str[2..].replace('\\', "/");
             format!("/{}{}", drive, rest)
         } else {
@@ -41,61 +135,458 @@ fn windows_path_to_docker(path: &Path) -> String {
     }
 }
 
+/// Pick a name for the build container
+///
+/// Deterministic names are derived from a hash of the absolute project path and the
+/// (sorted) target set, so the container for a given project can be found again.
+/// Otherwise the name is timestamp based.
+pub fn container_name_for(project_path: &Path, targets: &[&str], deterministic: bool) -> String {
+    if deterministic {
+        let mut sorted_targets = targets.to_vec();
+        sorted_targets.sort_unstable();
+        sorted_targets.dedup();
+
+        let mut hasher = Sha256::new();
+        hasher.update(project_path.display().to_string().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(sorted_targets.join(",").as_bytes());
+        let digest = format!("{:x}", hasher.finalize());
+        format!("repx-{}", &digest[..16])
+    } else {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        format!("repx-{}", timestamp)
+    }
+}
+
+/// Check a `container_name` against Docker's rules: a letter or digit, then letters, digits, `_`, `.` or `-`
+pub fn validate_container_name(name: &str) -> Result<(), RepxError> {
+    let mut chars = name.chars();
+    let valid = name.len() >= 2
+        && chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(RepxError::ConfigInvalid(format!(
+            "Invalid container name '{}'; it must start with a letter or digit, followed by at least one letter, digit, '_', '.' or '-'",
+            name
+        )))
+    }
+}
+
+/// Labels marking a container as created by repx and started now
+fn last_used_labels() -> HashMap<String, String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    HashMap::from([(LAST_USED_LABEL.to_string(), now.to_string())])
+}
+
+/// How long ago a container was started according to its labels, `None` if it has no valid `LAST_USED_LABEL`
+fn idle_time(labels: &HashMap<String, String>, now: SystemTime) -> Option<Duration> {
+    let last_used = labels.get(LAST_USED_LABEL)?.parse::<u64>().ok()?;
+    now.duration_since(UNIX_EPOCH + Duration::from_secs(last_used)).ok()
+}
+
+/// Snapshot of an in-progress image pull
+#[derive(Debug, Clone, Default)]
+pub struct PullProgress {
+    /// Number of layers the registry reported for the image
+    pub layers: usize,
+    /// Layers that are downloaded or already present locally
+    pub layers_complete: usize,
+    /// Bytes downloaded so far across all layers
+    pub current_bytes: u64,
+    /// Total bytes of the layers being downloaded, as far as known
+    pub total_bytes: u64,
+}
+
+/// Per-layer state tracked while pulling
+#[derive(Default)]
+struct LayerState {
+    current: u64,
+    total: u64,
+    complete: bool,
+}
+
+/// Pull an image, reporting aggregated layer progress to `on_progress`
+async fn pull_image<F: FnMut(&PullProgress)>(
+    docker: &Docker,
+    image: &str,
+    credentials: Option<DockerCredentials>,
+    mut on_progress: F,
+) -> Result<(), bollard::errors::Error> {
+    let mut stream = docker.create_image(
+        Some(CreateImageOptions {
+            from_image: Some(image.to_string()),
+            ..Default::default()
+        }),
+        None,
+        credentials,
+    );
+
+    let mut layers: HashMap<String, LayerState> = HashMap::new();
+    while let Some(info) = stream.next().await {
+        let info = info?;
+        let (Some(id), Some(status)) = (info.id, info.status) else {
+            continue;
+        };
+        // "Pulling from <repo>" is keyed by the tag rather than a layer
+        if status.starts_with("Pulling from") {
+            continue;
+        }
+
+        let layer = layers.entry(id).or_default();
+        match status.as_str() {
+            "Downloading" => {
+                if let Some(detail) = info.progress_detail {
+                    layer.current = detail.current.unwrap_or(0).max(0) as u64;
+                    layer.total = detail.total.unwrap_or(0).max(0) as u64;
+                }
+            }
+            "Download complete" | "Pull complete" | "Already exists" => {
+                layer.current = layer.total;
+                layer.complete = true;
+            }
+            _ => {}
+        }
+
+        on_progress(&PullProgress {
+            layers: layers.len(),
+            layers_complete: layers.values().filter(|l| l.complete).count(),
+            current_bytes: layers.values().map(|l| l.current).sum(),
+            total_bytes: layers.values().map(|l| l.total).sum(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Render pull progress as a single-line progress bar, similar to `docker pull`
+///
+/// On a retried pull, layers that finished before the failure show up as complete right away.
+fn render_pull_progress(image: &str, progress: &PullProgress, resumed: bool) {
+    const BAR_WIDTH: usize = 30;
+    let fraction = if progress.total_bytes > 0 {
+        progress.current_bytes as f64 / progress.total_bytes as f64
+    } else {
+        0.0
+    };
+    let filled = ((fraction * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+    let bar = format!("{}{}", "=".repeat(filled), " ".repeat(BAR_WIDTH - filled));
+    let mb = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+
+    print!(
+        "\r\x1B[K{}{}{} {}:{} [{}] {:.1}/{:.1} MB ({}/{} layers)",
+        BOLD, BLUE, if resumed { "Resuming" } else { "Pulling" }, image, RESET, bar,
+        mb(progress.current_bytes), mb(progress.total_bytes),
+        progress.layers_complete, progress.layers
+    );
+    let _ = stdout().flush();
+}
+
+/// Where the project is mounted read-only with `readonly_source`
+pub const SOURCE_MOUNT: &str = "/src";
+
+/// Socket the Nix daemon listens on in the standard Nix images
+const NIX_DAEMON_SOCKET: &str = "/nix/var/nix/daemon-socket/socket";
+
+/// Command keeping the build container alive, optionally with the Nix daemon in the background
+///
+/// Images without `nix-daemon` still start; `probe_nix_daemon` then reports it as unavailable.
+fn keepalive_command(nix_daemon: bool) -> Vec<String> {
+    if nix_daemon {
+        let script = "if command -v nix-daemon >/dev/null 2>&1; then nix-daemon >/tmp/nix-daemon.log 2>&1 & fi; exec sleep 3600";
+        vec!["sh".to_string(), "-c".to_string(), script.to_string()]
+    } else {
+        vec!["sleep".to_string(), "3600".to_string()]
+    }
+}
+
+/// Wait for the Nix daemon started by the keepalive command and check that clients can reach it
+pub async fn probe_nix_daemon(docker: &Docker, container_id: &str, settings: &ExecSettings) -> bool {
+    let cmd = format!(
+        "for i in $(seq 1 20); do [ -S {socket} ] && break; sleep 0.25; done; \
+         NIX_REMOTE=daemon nix --extra-experimental-features nix-command store ping",
+        socket = NIX_DAEMON_SOCKET
+    );
+    execute_command(docker, container_id, &cmd, settings).await.is_ok()
+}
+
+/// Command copying the read-only source into the writable working directory,
+/// leaving out the directories that are mounted from the host
+pub fn copy_source_command(config: &RepxConfig) -> String {
+    format!(
+        "tar -C {} --exclude=./.repx --exclude=./{} -cf - . | tar -C /app -xf -",
+        SOURCE_MOUNT,
+        config.target_dir_root()
+    )
+}
+
+/// `docker run` options accepted in `container_runtime_args`
+///
+/// Options repx sets itself, such as mounts, networking and `--privileged`, aren't among them.
+pub const SUPPORTED_RUNTIME_ARGS: &[&str] = &[
+    "--add-host", "--cap-add", "--cap-drop", "--device", "--dns", "--security-opt", "--shm-size", "--sysctl", "--tmpfs", "--ulimit",
+];
+
+/// Host settings for the build container from `docker run` style options, e.g. `--ulimit=nofile=4096`
+///
+/// Each option is `--flag=value` or `--flag value`. Unsupported options are an error
+/// rather than being ignored, since the build would otherwise run without them.
+pub fn runtime_host_config(args: &[String]) -> Result<HostConfig, RepxError> {
+    let mut host_config = HostConfig::default();
+    for arg in args {
+        let invalid = |reason: &str| RepxError::ConfigInvalid(format!("Invalid container runtime argument '{}': {}", arg, reason));
+        let (flag, value) = match arg.trim().split_once(['=', ' ']) {
+            Some((flag, value)) if !value.trim().is_empty() => (flag, value.trim()),
+            _ => return Err(invalid("expected --flag=value")),
+        };
+        match flag {
+            "--add-host" => host_config.extra_hosts.get_or_insert_with(Vec::new).push(value.to_string()),
+            "--cap-add" => host_config.cap_add.get_or_insert_with(Vec::new).push(value.to_string()),
+            "--cap-drop" => host_config.cap_drop.get_or_insert_with(Vec::new).push(value.to_string()),
+            "--dns" => host_config.dns.get_or_insert_with(Vec::new).push(value.to_string()),
+            "--security-opt" => host_config.security_opt.get_or_insert_with(Vec::new).push(value.to_string()),
+            "--device" => {
+                // HOST[:CONTAINER][:PERMISSIONS], as docker run parses it
+                let mut parts = value.splitn(3, ':');
+                let path_on_host = parts.next().unwrap_or_default().to_string();
+                let path_in_container = parts.next().map_or_else(|| path_on_host.clone(), str::to_string);
+                host_config.devices.get_or_insert_with(Vec::new).push(DeviceMapping {
+                    path_on_host: Some(path_on_host),
+                    path_in_container: Some(path_in_container),
+                    cgroup_permissions: Some(parts.next().unwrap_or("rwm").to_string()),
+                });
+            }
+            "--sysctl" => {
+                let (key, value) = value.split_once('=').ok_or_else(|| invalid("expected KEY=VALUE"))?;
+                host_config.sysctls.get_or_insert_with(HashMap::new).insert(key.to_string(), value.to_string());
+            }
+            "--tmpfs" => {
+                let (path, options) = value.split_once(':').unwrap_or((value, ""));
+                host_config.tmpfs.get_or_insert_with(HashMap::new).insert(path.to_string(), options.to_string());
+            }
+            "--ulimit" => {
+                let (name, limits) = value.split_once('=').ok_or_else(|| invalid("expected NAME=SOFT[:HARD]"))?;
+                let (soft, hard) = limits.split_once(':').unwrap_or((limits, limits));
+                let parse = |limit: &str| limit.parse::<i64>().map_err(|_| invalid("limits must be numbers"));
+                host_config.ulimits.get_or_insert_with(Vec::new).push(ResourcesUlimits {
+                    name: Some(name.to_string()),
+                    soft: Some(parse(soft)?),
+                    hard: Some(parse(hard)?),
+                });
+            }
+            "--shm-size" => {
+                let (number, unit) = value.split_at(value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len()));
+                let multiplier = match unit.to_ascii_lowercase().as_str() {
+                    "" | "b" => 1,
+                    "k" | "kb" => 1 << 10,
+                    "m" | "mb" => 1 << 20,
+                    "g" | "gb" => 1 << 30,
+                    _ => return Err(invalid("expected a size such as 512m or 2g")),
+                };
+                let number: i64 = number.parse().map_err(|_| invalid("expected a size such as 512m or 2g"))?;
+                host_config.shm_size = Some(number * multiplier);
+            }
+            _ => {
+                return Err(invalid(&format!("unsupported option; supported are {}", SUPPORTED_RUNTIME_ARGS.join(", "))));
+            }
+        }
+    }
+    Ok(host_config)
+}
+
+/// UID and GID that files the container writes into the project should belong to
+///
+/// `user` is `UID:GID`, or `host` for the user running repx.
+pub fn resolve_owner(user: Option<&str>) -> Result<Option<(u32, u32)>, RepxError> {
+    let Some(user) = user else {
+        return Ok(None);
+    };
+    if user == "host" {
+        return host_owner().map(Some);
+    }
+    let invalid = || RepxError::ConfigInvalid(format!("Invalid user '{}': expected UID:GID, e.g. 1000:1000", user));
+    let (uid, gid) = user.split_once(':').ok_or_else(invalid)?;
+    Ok(Some((uid.parse().map_err(|_| invalid())?, gid.parse().map_err(|_| invalid())?)))
+}
+
+#[cfg(unix)]
+fn host_owner() -> Result<(u32, u32), RepxError> {
+    Ok((nix::unistd::getuid().as_raw(), nix::unistd::getgid().as_raw()))
+}
+
+#[cfg(not(unix))]
+fn host_owner() -> Result<(u32, u32), RepxError> {
+    Err(RepxError::ConfigInvalid("The host user can only be detected on Unix; pass --user UID:GID".to_string()))
+}
+
+/// Command handing the files the container wrote into the project back to `owner`
+///
+/// Nix runs as root in the container, so without this they end up root-owned on the host.
+pub fn chown_command(config: &RepxConfig, (uid, gid): (u32, u32)) -> String {
+    format!(
+        "chown -R {uid}:{gid} ./.repx ./{artifacts} 2>/dev/null; \
+         chown -h {uid}:{gid} ./{target_dir} ./Cargo.lock ./result-* 2>/dev/null; true",
+        artifacts = config.artifacts_dir(),
+        target_dir = config.target_dir_root(),
+    )
+}
+
 /// Container info returned by setup_container
 #[derive(Debug)]
 pub struct ContainerInfo {
     pub id: String,
-    #[allow(dead_code)]
     pub name: String,
 }
 
+/// Whether a pull failed because the image or tag doesn't exist, rather than a network problem
+fn is_missing_image(error: &bollard::errors::Error) -> bool {
+    let message = match error {
+        bollard::errors::Error::DockerResponseServerError { status_code: 404, .. } => return true,
+        bollard::errors::Error::DockerResponseServerError { message, .. } => message,
+        bollard::errors::Error::DockerStreamError { error } => error,
+        _ => return false,
+    };
+    let message = message.to_ascii_lowercase();
+    ["manifest unknown", "not found", "repository does not exist", "pull access denied"]
+        .iter()
+        .any(|needle| message.contains(needle))
+}
+
+/// Pull the Nix image if needed, rendering download progress
+///
+/// A pull that fails partway is retried up to `retries` times with a growing backoff. Docker
+/// keeps the layers that completed, so a retry only downloads the ones that didn't. A missing
+/// image or tag isn't retried.
+async fn ensure_image(docker: &Docker, nix_image: &str, credentials: Option<DockerCredentials>, retries: u32) -> Result<()> {
+    println!(
+        "{}{}Ensuring Nix image is available:{} {}",
+        BOLD, BLUE, RESET, nix_image
+    );
+    let attempts = retries + 1;
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            let backoff = Duration::from_secs(1 << (attempt - 2).min(4));
+            println!(
+                "{}{}Retrying pull of {} in {}s (attempt {}/{}); completed layers are kept{}",
+                BOLD, YELLOW, nix_image, backoff.as_secs(), attempt, attempts, RESET
+            );
+            tokio::time::sleep(backoff).await;
+        }
+        let mut last_render = Instant::now();
+        let mut rendered = false;
+        let result = pull_image(docker, nix_image, credentials.clone(), |progress| {
+            // Throttle redraws so fast local pulls don't flood the terminal
+            if last_render.elapsed() >= Duration::from_millis(100) || progress.layers_complete == progress.layers {
+                render_pull_progress(nix_image, progress, attempt > 1);
+                last_render = Instant::now();
+                rendered = true;
+            }
+        })
+        .await;
+        if rendered {
+            print!("\r\x1B[K");
+        }
+        match result {
+            Ok(()) => {
+                println!("{}{}Image ready:{} {}", BOLD, GREEN, RESET, nix_image);
+                return Ok(());
+            }
+            Err(source) if is_missing_image(&source) => {
+                return Err(RepxError::ImageNotFound { image: nix_image.to_string(), source }.into());
+            }
+            Err(source) if attempt == attempts => {
+                return Err(RepxError::ImagePullFailed { image: nix_image.to_string(), attempts, source }.into());
+            }
+            Err(e) => {
+                println!("{}{}Pull of {} failed:{} {}", BOLD, YELLOW, nix_image, RESET, e);
+            }
+        }
+    }
+    unreachable!("the last attempt always returns")
+}
+
+/// Make sure the configured Nix image is available, pulling it with the registry credentials if needed
+pub async fn pull_build_image(docker: &Docker, config: &RepxConfig) -> Result<()> {
+    let credentials = match &config.registry_token_file {
+        Some(path) => Some(registry_credentials(&read_secret_file(path).await?)),
+        None => None,
+    };
+    ensure_image(docker, &config.image, credentials, config.pull_retries).await
+}
+
 /// Set up and start a Docker container for Nix operations
+///
+/// The image must already be available; see `pull_build_image`.
 pub async fn setup_container(
     docker: &Docker,
-    nix_image: &str,
+    config: &RepxConfig,
     project_path: &Path,
     metadata_dir: &Path,
+    container_name: &str,
 ) -> Result<ContainerInfo> {
-    println!(
-        "{}{}Ensuring Nix image is available:{} {}",
-        BOLD, BLUE, RESET, nix_image
-    );
-    docker
-        .create_image(
-            Some(CreateImageOptions {
-                from_image: Some(nix_image.to_string()),
-                ..Default::default()
-            }),
-            None,
-            None,
-        )
-        .try_collect::<Vec<_>>()
-        .await?;
+    let nix_image = config.image.as_str();
+
+    let mut binds = vec![
+        format!("{}:/flake-dir:rw", windows_path_to_docker(metadata_dir)), // Mount metadata dir as writable
+    ];
+    if config.readonly_source {
+        // Only the metadata and artifact directories are writable; the rest of /app is a copy
+        let artifacts_dir = project_path.join(config.artifacts_dir());
+        tokio::fs::create_dir_all(&artifacts_dir).await?;
+        binds.push(format!("{}:{}:ro", windows_path_to_docker(project_path), SOURCE_MOUNT));
+        binds.push(format!("{}:/app/.repx:rw", windows_path_to_docker(metadata_dir)));
+        binds.push(format!("{}:/app/{}:rw", windows_path_to_docker(&artifacts_dir), config.artifacts_dir()));
+    } else {
+        binds.push(format!("{}:/app:rw", windows_path_to_docker(project_path))); // Mount project as read-write
+    }
+    if let Some(cache) = &config.nix_store_cache {
+        // Docker seeds an empty named volume from the image's /nix on first use; a directory is seeded here
+        if is_store_cache_dir(cache) {
+            seed_store_cache_dir(docker, nix_image, cache).await?;
+        }
+        binds.push(store_cache_bind(cache)?);
+    }
     let host_cfg = HostConfig {
-        binds: Some(vec![
-            format!("{}:/app:rw", windows_path_to_docker(project_path)), // Mount project as read-write
-            format!("{}:/flake-dir:rw", windows_path_to_docker(metadata_dir)), // Mount metadata dir as writable
-        ]),
-        privileged: Some(true),
-        ..Default::default()
+        binds: Some(binds),
+        // Rootless runtimes can't grant it; Nix's sandbox then needs `--option sandbox false`
+        privileged: Some(config.privileged),
+        // Offline builds can only use what is already in the Nix store and .repx/vendor
+        network_mode: config.offline.then(|| "none".to_string()),
+        ..runtime_host_config(&config.container_runtime_args)?
     };
     let container_config = ContainerCreateBody {
         image: Some(nix_image.to_string()),
-        cmd: Some(vec!["sleep".to_string(), "3600".to_string()]), // Keep container running
+        cmd: Some(keepalive_command(config.nix_daemon)), // Keep container running
         working_dir: Some("/app".to_string()),                    // Set working directory to /app
         host_config: Some(host_cfg),
+        labels: Some(last_used_labels()),
         ..Default::default()
     };
-    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-    let container_name = format!("repx-{}", timestamp);
-    let options = CreateContainerOptions {
-        name: Some(container_name.clone()),
-        platform: String::new(),
-    };
     println!("{}{}Starting Nix container...{}", BOLD, BLUE, RESET);
-    let container = docker
-        .create_container(Some(options), container_config)
-        .await?;
+    let mut container_name = container_name.to_string();
+    let container = match create_named_container(docker, &container_name, container_config.clone()).await {
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 409, .. }) if config.container_name.is_some() => {
+            return Err(RepxError::ConfigInvalid(format!(
+                "A container named '{}' already exists; remove it with `docker rm -f {}` or pick another --container-name",
+                container_name, container_name
+            )).into());
+        }
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 409, .. }) => {
+            // The name is taken (e.g. a concurrent run of the same project), so disambiguate it
+            let suffix = Uuid::new_v4().simple().to_string();
+            container_name = format!("{}-{}", container_name, &suffix[..8]);
+            println!(
+                "{}{}Container name in use, falling back to:{} {}",
+                BOLD, YELLOW, RESET, container_name
+            );
+            create_named_container(docker, &container_name, container_config).await?
+        }
+        result => result?,
+    };
     docker
         .start_container(&container.id, None::<StartContainerOptions>)
         .await?;
@@ -109,6 +600,132 @@ pub async fn setup_container(
     })
 }
 
+/// Set up a throwaway container with only the Nix store cache volume mounted
+pub async fn setup_store_container(docker: &Docker, nix_image: &str, volume: &str) -> Result<ContainerInfo> {
+    start_helper_container(docker, nix_image, store_cache_bind(volume)?).await
+}
+
+/// Start a throwaway container from `nix_image` with a single bind mount
+async fn start_helper_container(docker: &Docker, nix_image: &str, bind: String) -> Result<ContainerInfo> {
+    ensure_image(docker, nix_image, None, RepxConfig::default().pull_retries).await?;
+
+    let container_name = format!("repx-cache-{}", Uuid::new_v4().simple());
+    let container_config = ContainerCreateBody {
+        image: Some(nix_image.to_string()),
+        cmd: Some(vec!["sleep".to_string(), "3600".to_string()]),
+        host_config: Some(HostConfig {
+            binds: Some(vec![bind]),
+            ..Default::default()
+        }),
+        labels: Some(last_used_labels()),
+        ..Default::default()
+    };
+    let container = create_named_container(docker, &container_name, container_config).await?;
+    docker
+        .start_container(&container.id, None::<StartContainerOptions>)
+        .await?;
+    Ok(ContainerInfo {
+        id: container.id,
+        name: container_name,
+    })
+}
+
+/// Whether a Nix store cache names a host directory rather than a Docker volume
+///
+/// Volume names can't contain path separators or start with a dot, so this is how
+/// `docker run -v` tells them apart too.
+pub fn is_store_cache_dir(cache: &str) -> bool {
+    cache.contains('/') || cache.contains('\\') || cache.starts_with('.')
+}
+
+/// The bind mounting the Nix store cache, a volume or an existing host directory, at /nix
+pub(crate) fn store_cache_bind(cache: &str) -> Result<String> {
+    if is_store_cache_dir(cache) {
+        let dir = Path::new(cache).canonicalize()?;
+        Ok(format!("{}:/nix", windows_path_to_docker(&dir)))
+    } else {
+        Ok(format!("{}:/nix", cache))
+    }
+}
+
+/// Copy the image's /nix into a host directory used as the Nix store cache, unless that was done before
+///
+/// Mounting an empty directory at /nix would hide Nix itself. The copy is marked complete
+/// only at the end, so one that was interrupted is simply made again.
+pub(crate) async fn seed_store_cache_dir(docker: &Docker, nix_image: &str, cache: &str) -> Result<()> {
+    let dir = Path::new(cache);
+    if dir.join(SEEDED_MARKER).exists() {
+        return Ok(());
+    }
+    tokio::fs::create_dir_all(dir).await?;
+    println!("{}{}Seeding Nix store cache {} from {}{}", BOLD, CYAN, dir.display(), nix_image, RESET);
+    let bind = format!("{}:/nix-cache", windows_path_to_docker(&dir.canonicalize()?));
+    let container = start_helper_container(docker, nix_image, bind).await?;
+    let seed_cmd = format!("cp -a /nix/. /nix-cache/ && touch /nix-cache/{}", SEEDED_MARKER);
+    let result = execute_command(docker, &container.id, &seed_cmd, &ExecSettings::default()).await;
+    cleanup_container(docker, &container.id).await?;
+    result.map(|_| ())
+}
+
+/// Empty and remove a host directory used as the Nix store cache
+///
+/// The store's files belong to root and are read-only, so they are removed from a container.
+pub(crate) async fn remove_store_cache_dir(docker: &Docker, nix_image: &str, cache: &str) -> Result<()> {
+    let dir = Path::new(cache).canonicalize()?;
+    let bind = format!("{}:/nix-cache", windows_path_to_docker(&dir));
+    let container = start_helper_container(docker, nix_image, bind).await?;
+    let result = execute_command(docker, &container.id, "rm -rf /nix-cache/..?* /nix-cache/.[!.]* /nix-cache/*", &ExecSettings::default()).await;
+    cleanup_container(docker, &container.id).await?;
+    result?;
+    tokio::fs::remove_dir(&dir).await?;
+    Ok(())
+}
+
+/// Check whether a Docker volume exists
+pub async fn volume_exists(docker: &Docker, volume: &str) -> Result<bool> {
+    match docker.inspect_volume(volume).await {
+        Ok(_) => Ok(true),
+        Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove a Docker volume, e.g. a Nix store cache
+#[allow(deprecated)] // bollard 0.19's remove_volume only takes the deprecated options type
+pub async fn remove_volume(docker: &Docker, volume: &str) -> Result<()> {
+    docker.remove_volume(volume, Some(bollard::volume::RemoveVolumeOptions { force: true })).await?;
+    Ok(())
+}
+
+/// Create a container with the given name
+async fn create_named_container(
+    docker: &Docker,
+    name: &str,
+    config: ContainerCreateBody,
+) -> Result<bollard::models::ContainerCreateResponse, bollard::errors::Error> {
+    let options = CreateContainerOptions {
+        name: Some(name.to_string()),
+        platform: String::new(),
+    };
+    docker.create_container(Some(options), config).await
+}
+
+/// Load an image tarball (as produced by `docker save` or dockerTools) into the daemon
+pub async fn load_image(docker: &Docker, tarball: &Path) -> Result<()> {
+    let data = tokio::fs::read(tarball).await?;
+    let mut stream = docker.import_image(
+        ImportImageOptions { quiet: true, ..Default::default() },
+        bollard::body_full(data.into()),
+        None,
+    );
+    while let Some(info) = stream.next().await {
+        if let Some(error) = info?.error {
+            return Err(anyhow::anyhow!("Failed to load {}: {}", tarball.display(), error));
+        }
+    }
+    Ok(())
+}
+
 /// Clean up a Docker container
 pub async fn cleanup_container(docker: &Docker, container_id: &str) -> Result<()> {
     println!("{}{}Cleaning up container...{}", BOLD, CYAN, RESET);
@@ -124,3 +741,189 @@ pub async fn cleanup_container(docker: &Docker, container_id: &str) -> Result<()
     println!("{}{}Container removed successfully{}", BOLD, GREEN, RESET);
     Ok(())
 }
+
+/// Whether the reaper may remove a container: it isn't running and was started over `idle_timeout` ago
+///
+/// Labels can't be changed after creation, so `LAST_USED_LABEL` only records when the
+/// container was started. A build keeps its container running however long it takes,
+/// so running (or paused) containers are never reaped.
+fn is_reapable(container: &ContainerSummary, now: SystemTime, idle_timeout: Duration) -> bool {
+    let in_use = matches!(
+        container.state,
+        Some(ContainerSummaryStateEnum::RUNNING | ContainerSummaryStateEnum::PAUSED | ContainerSummaryStateEnum::RESTARTING)
+    );
+    !in_use
+        && container
+            .labels
+            .as_ref()
+            .and_then(|labels| idle_time(labels, now))
+            .is_some_and(|idle| idle > idle_timeout)
+}
+
+/// Remove repx containers that have been idle for longer than `idle_timeout`, returning their names
+///
+/// Build containers are removed when the build ends, so this only finds the ones
+/// left behind by interrupted builds once they have stopped. Running containers are
+/// kept whatever their age. The runtime and daemon are taken from `config`.
+pub async fn reap_idle_containers(config: &RepxConfig, idle_timeout: Duration) -> Result<Vec<String>, RepxError> {
+    let docker = connect_docker(config).await?;
+    let filters = HashMap::from([("label".to_string(), vec![LAST_USED_LABEL.to_string()])]);
+    let containers = docker
+        .list_containers(Some(ListContainersOptions { all: true, filters: Some(filters), ..Default::default() }))
+        .await
+        .map_err(anyhow::Error::from)?;
+
+    let now = SystemTime::now();
+    let mut reaped = Vec::new();
+    for container in containers {
+        if !is_reapable(&container, now, idle_timeout) {
+            continue;
+        }
+        let Some(id) = container.id else {
+            continue;
+        };
+        let name = container
+            .names
+            .and_then(|names| names.first().map(|n| n.trim_start_matches('/').to_string()))
+            .unwrap_or_else(|| id.clone());
+        docker
+            .remove_container(&id, Some(RemoveContainerOptions { force: true, ..Default::default() }))
+            .await
+            .map_err(anyhow::Error::from)?;
+        reaped.push(name);
+    }
+    Ok(reaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_docker_endpoint() {
+        let socket = DockerEndpoint::parse("unix:///run/user/1000/docker.sock").unwrap();
+        assert_eq!(socket, DockerEndpoint::Socket("/run/user/1000/docker.sock".to_string()));
+        assert_eq!(DockerEndpoint::parse("/run/user/1000/docker.sock").unwrap(), socket);
+        assert_eq!(socket.url(), "unix:///run/user/1000/docker.sock");
+        let tcp = DockerEndpoint::parse("tcp://10.0.0.5:2375").unwrap();
+        assert_eq!(tcp, DockerEndpoint::Http("10.0.0.5:2375".to_string()));
+        assert_eq!(DockerEndpoint::parse("http://10.0.0.5:2375/").unwrap(), tcp);
+        assert_eq!(tcp.url(), "tcp://10.0.0.5:2375");
+        assert_eq!(DockerEndpoint::parse("npipe:////./pipe/docker_engine").unwrap().url(), "npipe:////./pipe/docker_engine");
+
+        for invalid in ["ssh://build@host", "https://10.0.0.5:2376", "tcp://", "localhost:2375", ""] {
+            let error = DockerEndpoint::parse(invalid).unwrap_err();
+            assert!(error.to_string().contains("Invalid docker_host"), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_container_runtime() {
+        assert_eq!(ContainerRuntime::parse("Podman").unwrap(), ContainerRuntime::Podman);
+        assert_eq!(ContainerRuntime::parse("docker").unwrap().cli(), "docker");
+        assert!(ContainerRuntime::parse("lxc").is_err());
+        assert_eq!(ContainerRuntime::Docker.default_socket(), None);
+        assert!(ContainerRuntime::Podman.default_socket().unwrap().ends_with("podman/podman.sock"));
+
+        let config = RepxConfig { runtime: Some("podman".to_string()), ..RepxConfig::default() };
+        assert_eq!(config.container_runtime().unwrap(), ContainerRuntime::Podman);
+        let config = RepxConfig { runtime: Some("rkt".to_string()), ..RepxConfig::default() };
+        assert!(config.container_runtime().is_err());
+    }
+
+    #[test]
+    fn test_is_missing_image() {
+        let stream_error = |error: &str| bollard::errors::Error::DockerStreamError { error: error.to_string() };
+        assert!(is_missing_image(&stream_error("manifest for nixos/nix:nope not found: manifest unknown")));
+        assert!(is_missing_image(&bollard::errors::Error::DockerResponseServerError {
+            status_code: 404,
+            message: "No such image".to_string(),
+        }));
+        assert!(!is_missing_image(&stream_error("read tcp 10.0.0.2:51234->104.18.121.25:443: read: connection reset by peer")));
+        assert!(!is_missing_image(&bollard::errors::Error::DockerResponseServerError {
+            status_code: 500,
+            message: "Get \"https://registry-1.docker.io/v2/\": dial tcp: lookup registry-1.docker.io: no such host".to_string(),
+        }));
+    }
+
+    #[test]
+    fn test_resolve_owner() {
+        assert_eq!(resolve_owner(None).unwrap(), None);
+        assert_eq!(resolve_owner(Some("1000:100")).unwrap(), Some((1000, 100)));
+        assert!(resolve_owner(Some("1000")).is_err());
+        assert!(resolve_owner(Some("me:staff")).is_err());
+        #[cfg(unix)]
+        assert!(resolve_owner(Some("host")).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_runtime_host_config() {
+        let args: Vec<String> = ["--device=/dev/fuse", "--sysctl net.ipv4.ip_forward=1", "--ulimit=nofile=1024:4096", "--add-host=cache:10.0.0.2", "--shm-size=2g"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let host_config = runtime_host_config(&args).unwrap();
+        let device = &host_config.devices.unwrap()[0];
+        assert_eq!(device.path_in_container.as_deref(), Some("/dev/fuse"));
+        assert_eq!(device.cgroup_permissions.as_deref(), Some("rwm"));
+        assert_eq!(host_config.sysctls.unwrap()["net.ipv4.ip_forward"], "1");
+        let ulimit = &host_config.ulimits.unwrap()[0];
+        assert_eq!((ulimit.soft, ulimit.hard), (Some(1024), Some(4096)));
+        assert_eq!(host_config.extra_hosts.unwrap(), ["cache:10.0.0.2"]);
+        assert_eq!(host_config.shm_size, Some(2 << 30));
+
+        assert!(runtime_host_config(&["--volume=/:/host".to_string()]).is_err());
+        assert!(runtime_host_config(&["--ulimit=nofile=lots".to_string()]).is_err());
+        assert!(runtime_host_config(&["--device".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_deterministic_container_name_ignores_target_order() {
+        let project = Path::new("/home/user/project");
+        let a = container_name_for(project, &["x86_64-linux-gnu", "aarch64-linux-gnu"], true);
+        let b = container_name_for(project, &["aarch64-linux-gnu", "x86_64-linux-gnu"], true);
+        let other = container_name_for(Path::new("/home/user/other"), &["x86_64-linux-gnu", "aarch64-linux-gnu"], true);
+
+        assert_eq!(a, b);
+        assert_ne!(a, other);
+        assert!(a.starts_with("repx-"));
+        assert_eq!(a.len(), "repx-".len() + 16);
+    }
+
+    #[test]
+    fn test_validate_container_name() {
+        for valid in ["repx-debug", "ci_build.42", "0x"] {
+            assert!(validate_container_name(valid).is_ok(), "{}", valid);
+        }
+        for invalid in ["", "a", "-repx", "_repx", "repx build", "repx/build", "repx:1"] {
+            assert!(validate_container_name(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_idle_time() {
+        let now = UNIX_EPOCH + Duration::from_secs(10_000);
+        let labels = |value: &str| HashMap::from([(LAST_USED_LABEL.to_string(), value.to_string())]);
+        assert_eq!(idle_time(&labels("6400"), now), Some(Duration::from_secs(3600)));
+        assert_eq!(idle_time(&labels("20000"), now), None);
+        assert_eq!(idle_time(&labels("yesterday"), now), None);
+        assert_eq!(idle_time(&HashMap::new(), now), None);
+        assert!(last_used_labels().contains_key(LAST_USED_LABEL));
+    }
+
+    #[test]
+    fn test_running_containers_are_never_reaped() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let container = |state| ContainerSummary {
+            labels: Some(HashMap::from([(LAST_USED_LABEL.to_string(), "0".to_string())])),
+            state: Some(state),
+            ..Default::default()
+        };
+        let timeout = Duration::from_secs(3600);
+        assert!(is_reapable(&container(ContainerSummaryStateEnum::EXITED), now, timeout));
+        assert!(is_reapable(&container(ContainerSummaryStateEnum::CREATED), now, timeout));
+        assert!(!is_reapable(&container(ContainerSummaryStateEnum::RUNNING), now, timeout));
+        assert!(!is_reapable(&container(ContainerSummaryStateEnum::PAUSED), now, timeout));
+        assert!(!is_reapable(&container(ContainerSummaryStateEnum::EXITED), now, Duration::from_secs(2_000_000)));
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+use crate::container_utils::{is_store_cache_dir, pull_build_image, seed_store_cache_dir, store_cache_bind, windows_path_to_docker, ContainerRuntime, DockerEndpoint};
+use crate::generate_flake::generate_flake_file;
+use crate::{connect_docker, generate_gitignore, RepxConfig, RepxError, BOLD, CYAN, RESET};
+
+/// The `nix develop` command entering `shell` of the generated flake, run from the project root
+fn develop_command(config: &RepxConfig, shell: &str) -> Vec<String> {
+    let mut command: Vec<String> = ["nix", "--extra-experimental-features", "nix-command flakes", "develop"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    if config.no_flake_lock {
+        command.push("--no-write-lock-file".to_string());
+    }
+    if config.offline {
+        command.push("--offline".to_string());
+    }
+    command.push(format!("./.repx#{}", shell));
+    command
+}
+
+/// `docker run` arguments for an interactive container with the project mounted at /app, as in a build
+fn docker_run_args(config: &RepxConfig, project_path: &Path, shell: &str) -> Result<Vec<String>> {
+    let mut args: Vec<String> = ["run", "--rm", "-it"].iter().map(|s| s.to_string()).collect();
+    if config.privileged {
+        args.push("--privileged".to_string());
+    }
+    args.extend(["-w", "/app", "-v"].map(String::from));
+    args.push(format!("{}:/app:rw", windows_path_to_docker(project_path)));
+    if let Some(cache) = &config.nix_store_cache {
+        args.push("-v".to_string());
+        args.push(store_cache_bind(cache)?);
+    }
+    if config.offline {
+        args.push("--network=none".to_string());
+    }
+    args.push(config.image.clone());
+    args.extend(develop_command(config, shell));
+    Ok(args)
+}
+
+/// Whether Nix is installed on the host
+async fn host_has_nix() -> bool {
+    Command::new("nix")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .is_ok_and(|status| status.success())
+}
+
+/// Open an interactive `nix develop` shell with the toolchain and extra packages of the build
+///
+/// Generates `.repx/flake.nix` from `config`, then enters its `shell` dev shell (`default`
+/// if none is given) in a container from the build image, or with the host's Nix if `host`
+/// is set. Needs the runtime's CLI (`docker` or `podman`) for the container, since the shell is attached to the
+/// terminal. Returns the shell's exit code.
+pub async fn dev_shell(config: &RepxConfig, shell: Option<&str>, host: bool) -> Result<i32, RepxError> {
+    Ok(run_dev_shell(config, shell.unwrap_or("default"), host).await?)
+}
+
+async fn run_dev_shell(config: &RepxConfig, shell: &str, host: bool) -> Result<i32> {
+    let project_path = PathBuf::from(&config.project).canonicalize()?;
+    let metadata_dir = project_path.join(".repx");
+    tokio::fs::create_dir_all(&metadata_dir).await?;
+    generate_gitignore(&metadata_dir, &config.metadata_gitignore).await?;
+    generate_flake_file(&metadata_dir.join("flake.nix"), config).await?;
+
+    let status = if host {
+        if !host_has_nix().await {
+            return Err(RepxError::ConfigInvalid("--host needs Nix installed on the host; drop it to use the build image".to_string()).into());
+        }
+        println!("{}{}Entering dev shell {} with the host's Nix{}", BOLD, CYAN, shell, RESET);
+        let command = develop_command(config, shell);
+        Command::new(&command[0]).args(&command[1..]).current_dir(&project_path).status().await?
+    } else {
+        let runtime = config.container_runtime()?;
+        let docker = connect_docker(config).await?;
+        pull_build_image(&docker, config).await?;
+        if let Some(cache) = config.nix_store_cache.as_deref().filter(|cache| is_store_cache_dir(cache)) {
+            seed_store_cache_dir(&docker, &config.image, cache).await?;
+        }
+        println!("{}{}Entering dev shell {} in {}{}", BOLD, CYAN, shell, config.image, RESET);
+        let mut command = Command::new(runtime.cli());
+        if let Some(host) = &config.docker_host {
+            // Point the CLI at the same API the build uses
+            let variable = if runtime == ContainerRuntime::Podman { "CONTAINER_HOST" } else { "DOCKER_HOST" };
+            command.env(variable, DockerEndpoint::parse(host)?.url());
+        }
+        command
+            .args(docker_run_args(config, &project_path, shell)?)
+            .status()
+            .await
+            .with_context(|| format!("Could not run {0}; the shell needs the {0} CLI", runtime.cli()))?
+    };
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_docker_run_args() {
+        let config = RepxConfig {
+            image: "nixos/nix:2.24.0".to_string(),
+            nix_store_cache: Some("repx-nix-store".to_string()),
+            offline: true,
+            ..RepxConfig::default()
+        };
+        let args = docker_run_args(&config, Path::new("/work/demo"), "x86_64-linux-musl").unwrap();
+        assert_eq!(args[..8], ["run", "--rm", "-it", "--privileged", "-w", "/app", "-v", "/work/demo:/app:rw"]);
+        assert!(args.windows(2).any(|w| w == ["-v", "repx-nix-store:/nix"]));
+        assert!(args.contains(&"--network=none".to_string()));
+        let image = args.iter().position(|a| a == "nixos/nix:2.24.0").unwrap();
+        assert_eq!(
+            args[image + 1..],
+            ["nix", "--extra-experimental-features", "nix-command flakes", "develop", "--offline", "./.repx#x86_64-linux-musl"]
+        );
+
+        let rootless = RepxConfig { privileged: false, ..RepxConfig::default() };
+        assert!(!docker_run_args(&rootless, Path::new("/work/demo"), "default").unwrap().contains(&"--privileged".to_string()));
+    }
+}
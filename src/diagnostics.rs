@@ -0,0 +1,124 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// A rustc error from a failed build, with the lines that belong to it grouped together
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    /// Target whose build printed the error
+    pub target: String,
+    /// Error code such as `E0308`, if rustc gave one
+    pub code: Option<String>,
+    pub message: String,
+    /// Source file relative to the project, from the `-->` line
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    /// The full error as rustc printed it
+    pub rendered: String,
+}
+
+/// Summary errors that cargo and rustc print after the real ones
+const SUMMARY_PREFIXES: &[&str] = &["aborting due to", "could not compile", "build failed", "builder for"];
+
+/// Drop the prefix Nix puts before each line of a builder's log, e.g. `demo> ` or `       > `
+fn strip_log_prefix(line: &str) -> &str {
+    let prefix = Regex::new(r"^\s*(?:[\w.+-]*\w> ?|> ?)").expect("log prefix pattern is valid");
+    match prefix.find(line) {
+        Some(m) => &line[m.end()..],
+        None => line,
+    }
+}
+
+/// Make a path from the Nix sandbox relative to the project, e.g. `/build/source/src/main.rs` to `src/main.rs`
+fn project_relative(path: &str) -> String {
+    let sandbox = Regex::new(r"^(?:/build|/tmp/nix-build-[^/]+)/[^/]+/").expect("sandbox path pattern is valid");
+    sandbox.replace(path, "").trim_start_matches("./").to_string()
+}
+
+/// Group the rustc errors in a failed build's output
+///
+/// Each error runs from its `error[E…]:` or `error:` header up to the next blank line
+/// or header; its location is taken from the first `-->` line. Errors without a message
+/// of their own, such as "aborting due to 2 previous errors", are left out.
+pub fn parse_diagnostics(target: &str, output: &str) -> Vec<Diagnostic> {
+    let header = Regex::new(r"^(error|warning)(?:\[(E\d{4})\])?: (.+)$").expect("header pattern is valid");
+    let location = Regex::new(r"^\s*--> (.+?):(\d+):(\d+)\s*$").expect("location pattern is valid");
+
+    let mut diagnostics = Vec::new();
+    let mut current: Option<Diagnostic> = None;
+    for raw in output.lines() {
+        let line = strip_log_prefix(raw).trim_end();
+        if let Some(captures) = header.captures(line) {
+            diagnostics.extend(current.take());
+            let message = captures[3].to_string();
+            if &captures[1] == "error" && !SUMMARY_PREFIXES.iter().any(|p| message.starts_with(p)) {
+                current = Some(Diagnostic {
+                    target: target.to_string(),
+                    code: captures.get(2).map(|c| c.as_str().to_string()),
+                    message,
+                    file: None,
+                    line: None,
+                    column: None,
+                    rendered: line.to_string(),
+                });
+            }
+            continue;
+        }
+        let Some(diagnostic) = current.as_mut() else {
+            continue;
+        };
+        if line.trim().is_empty() {
+            diagnostics.extend(current.take());
+            continue;
+        }
+        if diagnostic.file.is_none() {
+            if let Some(captures) = location.captures(line) {
+                diagnostic.file = Some(project_relative(&captures[1]));
+                diagnostic.line = captures[2].parse().ok();
+                diagnostic.column = captures[3].parse().ok();
+            }
+        }
+        diagnostic.rendered.push('\n');
+        diagnostic.rendered.push_str(line);
+    }
+    diagnostics.extend(current);
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let output = "\
+demo> error[E0308]: mismatched types
+demo>  --> /build/source/src/main.rs:4:18
+demo>   |
+demo> 4 |     let x: u32 = \"one\";
+demo>   |            ---   ^^^^^ expected `u32`, found `&str`
+demo>
+demo> warning: unused variable: `y`
+demo>  --> src/lib.rs:2:9
+demo>
+demo> error: cannot find macro `printn` in this scope
+demo>  --> src/main.rs:9:5
+demo>   |
+demo> 9 |     printn!(\"hi\");
+demo> error: aborting due to 2 previous errors
+error: builder for '/nix/store/abc-demo.drv' failed with exit code 101";
+        let diagnostics = parse_diagnostics("x86_64-linux-gnu", output);
+        assert_eq!(diagnostics.len(), 2);
+
+        assert_eq!(diagnostics[0].code.as_deref(), Some("E0308"));
+        assert_eq!(diagnostics[0].message, "mismatched types");
+        assert_eq!(diagnostics[0].file.as_deref(), Some("src/main.rs"));
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (Some(4), Some(18)));
+        assert_eq!(diagnostics[0].rendered.lines().count(), 5);
+
+        assert_eq!(diagnostics[1].code, None);
+        assert_eq!(diagnostics[1].file.as_deref(), Some("src/main.rs"));
+        assert_eq!(diagnostics[1].line, Some(9));
+        assert!(diagnostics[1].rendered.ends_with("printn!(\"hi\");"));
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use bollard::Docker;
+use std::path::Path;
+use crate::execute_command::{execute_command, ExecSettings};
+use crate::RepxError;
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+/// Available bytes reported by `df -Pk <path>`, from the last line's fourth column
+fn parse_df_available(output: &str) -> Option<u64> {
+    let line = output.lines().rev().find(|line| !line.trim().is_empty())?;
+    let available_kb = line.split_whitespace().nth(3)?.parse::<u64>().ok()?;
+    Some(available_kb * 1024)
+}
+
+/// Fail if `available` bytes on `location` are below `min_free_gb`
+fn require_free_space(location: &str, available: u64, min_free_gb: u64) -> Result<(), RepxError> {
+    if available < min_free_gb.saturating_mul(GB) {
+        return Err(RepxError::InsufficientDiskSpace {
+            location: location.to_string(),
+            available_gb: available as f64 / GB as f64,
+            required_gb: min_free_gb,
+        });
+    }
+    Ok(())
+}
+
+/// Check the free space of the filesystem holding `path` on the host
+///
+/// Only Unix hosts can be checked; elsewhere this always succeeds.
+pub fn check_host_space(path: &Path, min_free_gb: u64) -> Result<(), RepxError> {
+    #[cfg(unix)]
+    {
+        let stats = nix::sys::statvfs::statvfs(path).map_err(std::io::Error::from)?;
+        let available = stats.blocks_available() as u64 * stats.fragment_size() as u64;
+        require_free_space(&path.display().to_string(), available, min_free_gb)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, min_free_gb);
+        Ok(())
+    }
+}
+
+/// Check the free space of the container's Nix store, returning `false` if `df` gave nothing usable
+pub async fn check_store_space(docker: &Docker, container_id: &str, min_free_gb: u64, settings: &ExecSettings) -> Result<bool> {
+    let Ok(output) = execute_command(docker, container_id, "df -Pk /nix", settings).await else {
+        return Ok(false);
+    };
+    match parse_df_available(&output.stdout) {
+        Some(available) => {
+            require_free_space("/nix in the container", available, min_free_gb)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_df_available() {
+        let output = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n\
+                      overlay         102400000  90000000  12400000      88% /\n";
+        assert_eq!(parse_df_available(output), Some(12_400_000 * 1024));
+        assert_eq!(parse_df_available("df: /nix: No such file or directory\n"), None);
+        assert_eq!(parse_df_available(""), None);
+
+        assert!(require_free_space("/nix", 4 * GB, 5).is_err());
+        assert!(require_free_space("/nix", 5 * GB, 5).is_ok());
+        assert!(require_free_space("/nix", 0, 0).is_ok());
+    }
+}
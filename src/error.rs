@@ -0,0 +1,199 @@
+use std::time::Duration;
+use crate::execute_command::CommandError;
+use crate::shutdown::ShutdownSignal;
+
+/// Why the Docker daemon couldn't be reached, telling apart the usual first-run problems
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DockerProblem {
+    /// There is neither a Docker socket nor a docker CLI
+    NotInstalled,
+    /// Docker is installed, but its daemon isn't running
+    NotRunning,
+    /// The daemon's socket exists, but the user may not connect to it
+    PermissionDenied,
+    /// Anything else, e.g. a remote `DOCKER_HOST` that doesn't respond
+    Unreachable,
+}
+
+impl DockerProblem {
+    /// Tell from a failed connection or ping why the daemon is out of reach
+    ///
+    /// A missing socket or a refused connection means the daemon isn't running, or isn't
+    /// installed at all if there is no docker CLI either (`cli_installed`).
+    pub fn classify(error: &bollard::errors::Error, cli_installed: bool) -> Self {
+        let not_running = if cli_installed { DockerProblem::NotRunning } else { DockerProblem::NotInstalled };
+        if matches!(error, bollard::errors::Error::SocketNotFoundError(_)) {
+            return not_running;
+        }
+        // `IOError` is transparent, so its io::Error doesn't show up in the source chain
+        let mut cause: Option<&(dyn std::error::Error + 'static)> = match error {
+            bollard::errors::Error::IOError { err } => Some(err),
+            other => Some(other),
+        };
+        while let Some(current) = cause {
+            if let Some(io_error) = current.downcast_ref::<std::io::Error>() {
+                match io_error.kind() {
+                    std::io::ErrorKind::PermissionDenied => return DockerProblem::PermissionDenied,
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused => return not_running,
+                    _ => {}
+                }
+            }
+            cause = current.source();
+        }
+        // Some connectors only keep the OS error's text
+        let text = error.to_string().to_lowercase();
+        if text.contains("permission denied") {
+            DockerProblem::PermissionDenied
+        } else if text.contains("connection refused") || text.contains("no such file") {
+            not_running
+        } else {
+            DockerProblem::Unreachable
+        }
+    }
+}
+
+impl std::fmt::Display for DockerProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DockerProblem::NotInstalled => "Docker doesn't seem to be installed",
+            DockerProblem::NotRunning => "is the Docker daemon running?",
+            DockerProblem::PermissionDenied => "permission denied on the Docker socket",
+            DockerProblem::Unreachable => "the Docker daemon didn't respond",
+        })
+    }
+}
+
+/// Errors returned by the library, so embedders can tell failure kinds apart
+#[derive(Debug, thiserror::Error)]
+pub enum RepxError {
+    /// The Docker daemon couldn't be reached, with the likely reason
+    #[error("Docker daemon not reachable: {problem} ({source})")]
+    DockerUnavailable {
+        problem: DockerProblem,
+        #[source]
+        source: bollard::errors::Error,
+    },
+
+    /// The Nix image couldn't be pulled, e.g. because the registry was unreachable, after every retry
+    #[error("Failed to pull image {image} after {attempts} attempt(s): {source}")]
+    ImagePullFailed {
+        image: String,
+        attempts: u32,
+        #[source]
+        source: bollard::errors::Error,
+    },
+
+    /// The registry doesn't have the Nix image or tag
+    #[error("Image {image} doesn't exist or isn't accessible: {source}")]
+    ImageNotFound {
+        image: String,
+        #[source]
+        source: bollard::errors::Error,
+    },
+
+    /// One or more targets failed to build or produced no artifacts
+    #[error("Build failed for target(s): {}", targets.join(", "))]
+    BuildFailed { targets: Vec<String> },
+
+    /// Targets built successfully but produced no artifacts, with `strict_targets`
+    #[error("Target(s) produced no artifacts: {}", targets.join(", "))]
+    EmptyTargets { targets: Vec<String> },
+
+    /// The configuration or command-line options are invalid
+    #[error("Invalid configuration: {0}")]
+    ConfigInvalid(String),
+
+    /// The generated flake.nix isn't valid Nix, e.g. because a template variable broke its syntax
+    #[error("The generated flake.nix is not valid Nix: {message}")]
+    FlakeInvalid { line: Option<u32>, message: String },
+
+    /// The build succeeded, but emitted warnings and `fail_on_warning` is set
+    #[error("Failing the build on {} warning(s) (--fail-on-warning)", warnings.len())]
+    WarningsAsErrors { warnings: Vec<String> },
+
+    /// Too little disk space is free to start the build, see `min_free_space_gb`
+    #[error("Only {available_gb:.1} GB free on {location}, less than the required {required_gb} GB")]
+    InsufficientDiskSpace { location: String, available_gb: f64, required_gb: u64 },
+
+    /// Artifacts differ from the reference set given with `compare_with`
+    #[error("{} artifact(s) don't match the reference: {}", files.len(), files.join(", "))]
+    ReferenceMismatch { files: Vec<String> },
+
+    /// Two builds of the same sources produced different artifacts, with `repx verify`
+    #[error("{} artifact(s) differ between two builds of the same sources: {}", files.len(), files.join(", "))]
+    NotReproducible { files: Vec<String> },
+
+    /// A signal stopped the build; the container was cleaned up
+    #[error("Build interrupted by {signal}")]
+    Interrupted { signal: ShutdownSignal },
+
+    /// A command in the build container exceeded the command timeout
+    #[error("Command timed out after {}s: {command}", timeout.as_secs())]
+    CommandTimeout { command: String, timeout: Duration },
+
+    /// A command in the build container exited with a non-zero status
+    #[error(transparent)]
+    CommandFailed(CommandError),
+
+    /// Reading or writing project files failed
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// Any other failure
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for RepxError {
+    /// Recover the typed error from an internal `anyhow` error
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<RepxError>() {
+            Ok(repx_error) => return repx_error,
+            Err(error) => error,
+        };
+        let error = match error.downcast::<CommandError>() {
+            Ok(command_error) => return RepxError::CommandFailed(command_error),
+            Err(error) => error,
+        };
+        match error.downcast::<std::io::Error>() {
+            Ok(io_error) => RepxError::Io(io_error),
+            Err(error) => RepxError::Other(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_error_survives_anyhow_round_trip() {
+        let error: anyhow::Error = RepxError::BuildFailed { targets: vec!["x86_64-linux-gnu".to_string()] }.into();
+        assert!(matches!(RepxError::from(error), RepxError::BuildFailed { targets } if targets == ["x86_64-linux-gnu"]));
+
+        let error = anyhow::anyhow!("something else");
+        assert!(matches!(RepxError::from(error), RepxError::Other(_)));
+    }
+
+    #[test]
+    fn test_docker_problem_classify() {
+        use bollard::errors::Error;
+        use std::io::{Error as IoError, ErrorKind};
+
+        let denied = Error::IOError { err: IoError::new(ErrorKind::PermissionDenied, "connect") };
+        assert_eq!(DockerProblem::classify(&denied, true), DockerProblem::PermissionDenied);
+
+        let refused = Error::IOError { err: IoError::new(ErrorKind::ConnectionRefused, "connect") };
+        assert_eq!(DockerProblem::classify(&refused, true), DockerProblem::NotRunning);
+        assert_eq!(DockerProblem::classify(&refused, false), DockerProblem::NotInstalled);
+
+        let missing = Error::SocketNotFoundError("/var/run/docker.sock".to_string());
+        assert_eq!(DockerProblem::classify(&missing, true), DockerProblem::NotRunning);
+
+        let wrapped = Error::IOError { err: IoError::other("Permission denied (os error 13)") };
+        assert_eq!(DockerProblem::classify(&wrapped, true), DockerProblem::PermissionDenied);
+
+        let timeout = Error::RequestTimeoutError;
+        assert_eq!(DockerProblem::classify(&timeout, true), DockerProblem::Unreachable);
+    }
+}
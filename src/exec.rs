@@ -0,0 +1,82 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use crate::container_utils::{cleanup_container, container_name_for, pull_build_image, setup_container};
+use crate::execute_command::{execute_command, shell_quote, CommandError, ExecSettings};
+use crate::generate_flake::generate_flake_file;
+use crate::{connect_docker, generate_gitignore, RepxConfig, RepxError, BOLD, CYAN, RESET};
+
+/// The shell command for `command`: a single word is run as a shell command, several are quoted as arguments
+fn exec_command_line(command: &[String]) -> String {
+    match command {
+        [single] => single.clone(),
+        words => words.iter().map(|word| shell_quote(word)).collect::<Vec<_>>().join(" "),
+    }
+}
+
+/// Run `command` in /app of a container set up as for a build, with the generated flake in `.repx`
+///
+/// Generates `.repx/flake.nix` from `config`, then runs the command in a container from the
+/// build image with the same mounts and options as a build, e.g. `nix eval ./.repx#...`. Nix
+/// commands get flakes enabled. The output is printed once the command finishes and the
+/// container is removed. Returns the command's exit code.
+pub async fn exec_in_container(config: &RepxConfig, command: &[String]) -> Result<i32, RepxError> {
+    Ok(run_exec(config, command).await?)
+}
+
+async fn run_exec(config: &RepxConfig, command: &[String]) -> Result<i32> {
+    if command.is_empty() {
+        return Err(RepxError::ConfigInvalid("No command given; pass it after --".to_string()).into());
+    }
+    let project_path = PathBuf::from(&config.project).canonicalize()?;
+    let metadata_dir = project_path.join(".repx");
+    tokio::fs::create_dir_all(&metadata_dir).await?;
+    generate_gitignore(&metadata_dir, &config.metadata_gitignore).await?;
+    generate_flake_file(&metadata_dir.join("flake.nix"), config).await?;
+
+    let docker = connect_docker(config).await?;
+    pull_build_image(&docker, config).await?;
+    let container_name = container_name_for(&project_path, &["exec"], false);
+    let container = setup_container(&docker, config, &project_path, &metadata_dir, &container_name).await?;
+    let settings = ExecSettings {
+        env: vec![
+            "NIX_CONFIG=experimental-features = nix-command flakes".to_string(),
+            format!("CARGO_TARGET_DIR=/app/{}", config.target_dir),
+        ],
+        ..ExecSettings::default()
+    };
+    let result = async {
+        // The flake in /app/.repx is read through git when the project is a repository
+        if project_path.join(".git").exists() {
+            execute_command(&docker, &container.id, "git config --global --add safe.directory /app", &settings).await?;
+        }
+        println!("{}{}Running in {}:{} {}", BOLD, CYAN, config.image, RESET, exec_command_line(command));
+        let (output, code) = match execute_command(&docker, &container.id, &exec_command_line(command), &settings).await {
+            Ok(output) => (output, 0),
+            Err(e) => match e.downcast::<CommandError>() {
+                Ok(failed) => (failed.output, i32::try_from(failed.exit_code).unwrap_or(1)),
+                Err(e) => return Err(e),
+            },
+        };
+        print!("{}", output.stdout);
+        eprint!("{}", output.stderr);
+        Ok(code)
+    }
+    .await;
+    cleanup_container(&docker, &container.id).await?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec_command_line() {
+        let words = |list: &[&str]| list.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        assert_eq!(exec_command_line(&words(&["ls /nix/store | wc -l"])), "ls /nix/store | wc -l");
+        assert_eq!(
+            exec_command_line(&words(&["nix", "eval", "./.repx#packages.x86_64-linux", "--apply", "builtins.attrNames"])),
+            "'nix' 'eval' './.repx#packages.x86_64-linux' '--apply' 'builtins.attrNames'"
+        );
+    }
+}
@@ -1,152 +1,774 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use bollard::Docker;
-use crate::execute_command::execute_command;
-use crate::{RESET, BOLD, GREEN, RED, YELLOW, MAGENTA, BuildLogger};
+use futures_util::stream::{self, StreamExt};
+use regex::Regex;
+use std::time::{Duration, Instant};
+use crate::execute_command::{execute_command, shell_quote, CommandError, ExecSettings};
+use crate::explain_failure::print_failure_explanation;
+use crate::diagnostics::{parse_diagnostics, Diagnostic};
+use crate::report::{DryRunSummary, MatrixResult, PhaseTiming};
+use crate::{RESET, BOLD, GREEN, RED, YELLOW, MAGENTA, CYAN, BuildLogger, BuildReport, RepxConfig, RepxError};
+
+/// Store paths in the runtime closure of a target's build result
+async fn record_closure(docker: &Docker, container_id: &str, target: &str, settings: &ExecSettings) -> Result<Vec<String>> {
+    let cmd = format!("nix --extra-experimental-features 'nix-command flakes' path-info -r ./result-{}", target);
+    let output = execute_command(docker, container_id, &cmd, settings).await?;
+    Ok(output.stdout.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect())
+}
+
+/// File name of the image tarball written next to a target's artifacts
+pub const IMAGE_FILE_NAME: &str = "image.tar.gz";
+
+/// Whether the generated flake exposes a `<target>-image` output for the target
+pub fn supports_image(target: &str) -> bool {
+    target.contains("-linux-")
+}
+
+/// Build characteristics of a target
+#[derive(Debug, Clone)]
+struct TargetInfo {
+    /// Target as requested, e.g. `x86_64-linux-gnu`
+    target: String,
+    /// Package attribute of the target in the generated flake
+    flake_attr: String,
+    /// Name in messages and the report: the target, with `@<version>` for a matrix toolchain
+    name: String,
+    /// Toolchain of the `rust_versions` matrix the target is built with, if any
+    rust_version: Option<String>,
+    /// Directory the artifacts are copied to, relative to the project
+    artifact_dir: String,
+    is_windows_msvc: bool,
+    is_static_musl: bool,
+    /// Produces a `.wasm` module rather than a native executable
+    is_wasm: bool,
+}
 
 /// Parse target name and determine build characteristics
-fn parse_target(target: &str) -> (String, bool, bool) {
-    // Returns (actual_target_name_for_flake, is_windows_msvc, is_static_musl)
-    match target {
-        "x86_64-linux-gnu" => (target.to_string(), false, false),
-        "aarch64-linux-gnu" => (target.to_string(), false, false),
-        "x86_64-linux-musl" => (target.to_string(), false, true),
-        "aarch64-linux-musl" => (target.to_string(), false, true),
-        "x86_64-w64-mingw32" => (target.to_string(), false, false),      // Windows GNU
-        "x86_64-pc-windows-msvc" => (target.to_string(), true, false), // Windows MSVC
-        "aarch64-w64-mingw32" => (target.to_string(), false, false),     // Windows ARM GNU
-        "x86_64-apple-darwin" => (target.to_string(), false, false),     // macOS Intel
-        "aarch64-apple-darwin" => (target.to_string(), false, false),    // macOS Apple Silicon
-        _ => (target.to_string(), false, false), // Fallback, though should be caught by main.rs validation
+fn parse_target(target: &str, config: &RepxConfig) -> TargetInfo {
+    let (is_windows_msvc, is_static_musl, is_wasm) = match target {
+        "x86_64-linux-gnu" => (false, false, false),
+        "aarch64-linux-gnu" => (false, false, false),
+        "x86_64-linux-musl" => (false, true, false),
+        "aarch64-linux-musl" => (false, true, false),
+        "riscv64-linux-gnu" => (false, false, false),      // RISC-V 64 (experimental)
+        "armv7-linux-gnueabihf" => (false, false, false),  // ARMv7 hard-float (experimental)
+        "powerpc64le-linux-gnu" => (false, false, false),  // POWER little-endian (experimental)
+        "wasm32-wasi" => (false, false, true),             // WebAssembly (WASI)
+        "wasm32-unknown-unknown" => (false, false, true),  // WebAssembly (bare)
+        "x86_64-w64-mingw32" => (false, false, false),     // Windows GNU
+        "x86_64-pc-windows-msvc" => (true, false, false),  // Windows MSVC
+        "aarch64-w64-mingw32" => (false, false, false),    // Windows ARM GNU
+        "x86_64-apple-darwin" => (false, false, false),    // macOS Intel
+        "aarch64-apple-darwin" => (false, false, false),   // macOS Apple Silicon
+        _ => (false, false, false), // Fallback, though should be caught by main.rs validation
+    };
+
+    TargetInfo {
+        target: target.to_string(),
+        flake_attr: target.to_string(),
+        name: target.to_string(),
+        rust_version: None,
+        artifact_dir: format!("{}/{}", config.artifacts_dir(), target),
+        is_windows_msvc,
+        is_static_musl,
+        is_wasm,
     }
 }
 
-pub async fn execute_nix_build(
+/// Every build of the run: each target, or with `rust_versions` each target with each toolchain
+///
+/// Targets requested twice share a result link and artifact dir, so they are built once.
+fn build_plan(targets: &[&str], config: &RepxConfig) -> Result<Vec<TargetInfo>, RepxError> {
+    let matrix = config.rust_matrix()?;
+    let mut plan: Vec<TargetInfo> = Vec::new();
+    for target in targets {
+        let target_info = parse_target(target, config);
+        let builds = if matrix.is_empty() {
+            vec![target_info]
+        } else {
+            matrix
+                .iter()
+                .map(|toolchain| TargetInfo {
+                    flake_attr: format!("{}-rust-{}", target, toolchain.attr_suffix()),
+                    name: format!("{}@{}", target, toolchain.name),
+                    rust_version: Some(toolchain.name.clone()),
+                    artifact_dir: format!("{}/{}/{}", config.artifacts_dir(), toolchain.name, target),
+                    ..target_info.clone()
+                })
+                .collect()
+        };
+        for build in builds {
+            if !plan.iter().any(|b| b.flake_attr == build.flake_attr) {
+                plan.push(build);
+            }
+        }
+    }
+    Ok(plan)
+}
+
+/// Name and artifact dir (relative to the project) of every build of the run
+pub fn artifact_dirs(targets: &[&str], config: &RepxConfig) -> Result<Vec<(String, String)>, RepxError> {
+    Ok(build_plan(targets, config)?.into_iter().map(|b| (b.name, b.artifact_dir)).collect())
+}
+
+/// Check `nix_args` before they are appended to `nix build`
+///
+/// Arguments are quoted, so they can't inject shell syntax; this only rejects the
+/// ones that would break how repx collects the build result.
+pub fn validate_nix_args(args: &[String]) -> Result<(), RepxError> {
+    for arg in args {
+        if arg.is_empty() || arg.contains(['\0', '\n']) {
+            return Err(RepxError::ConfigInvalid(format!("Invalid nix argument {:?}", arg)));
+        }
+        let flag = arg.split('=').next().unwrap_or(arg);
+        if matches!(flag, "--out-link" | "-o" | "--no-link") {
+            return Err(RepxError::ConfigInvalid(format!(
+                "Nix argument {} is managed by repx and can't be overridden",
+                arg
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `static_link` can be honored for a target: musl targets are always static,
+/// and the x86_64/aarch64 glibc targets link statically with crt-static
+pub fn supports_static_linking(target: &str) -> bool {
+    matches!(
+        target,
+        "x86_64-linux-gnu" | "aarch64-linux-gnu" | "x86_64-linux-musl" | "aarch64-linux-musl"
+    )
+}
+
+/// Build the `<target>-image` flake output and save the tarball into the artifact dir
+///
+/// Returns whether the image was built; targets without an image output are skipped.
+async fn build_target_image(
+    docker: &Docker,
+    container_id: &str,
+    target: &str,
+    artifact_dir: &str,
+    build_options: &str,
+    logger: &BuildLogger,
+    settings: &ExecSettings,
+) -> Result<bool> {
+    if !supports_image(target) {
+        println!("{}{}Skipping container image for {}:{} images are only built for Linux targets", BOLD, YELLOW, target, RESET);
+        logger.log(&format!("Skipping container image for {}: not a Linux target", target)).await?;
+        return Ok(true);
+    }
+
+    let image_cmd = format!(
+        "nix --extra-experimental-features 'nix-command flakes' build {} ./.repx#{}-image --out-link ./result-{}-image \
+         && mkdir -p ./{} && cp -L ./result-{}-image ./{}/{} && rm -f ./result-{}-image",
+        build_options, target, target, artifact_dir, target, artifact_dir, IMAGE_FILE_NAME, target
+    );
+    match execute_command(docker, container_id, &image_cmd, settings).await {
+        Ok(_) => {
+            println!("{}{}Container image saved to:{} {}/{}", BOLD, GREEN, RESET, artifact_dir, IMAGE_FILE_NAME);
+            logger.log(&format!("Container image saved to {}/{}", artifact_dir, IMAGE_FILE_NAME)).await?;
+            Ok(true)
+        }
+        Err(e) => {
+            println!("{}{}Container image build failed for target {}:{} {}", BOLD, RED, target, RESET, e);
+            logger.log(&format!("Container image build failed for target {}: {}", target, e)).await?;
+            Ok(false)
+        }
+    }
+}
+
+/// Copy a built target's result out of the Nix store into its artifact dir
+///
+/// Returns whether artifacts were copied. Each target has its own artifact dir and
+/// result link, so copies for different targets can run concurrently.
+async fn copy_artifacts(
+    docker: &Docker,
+    container_id: &str,
+    target_info: &TargetInfo,
+    logger: &BuildLogger,
+    settings: &ExecSettings,
+    config: &RepxConfig,
+) -> Result<bool> {
+    let clean_target = target_info.flake_attr.as_str();
+    let artifact_dir = target_info.artifact_dir.as_str();
+    let copy_started = Instant::now();
+
+    // Check if the build produced any output; the command fails if the result link is missing or dangling
+    let check_output_cmd = format!("[ -L ./result-{0} ] && [ -e ./result-{0} ]", clean_target);
+
+    if execute_command(docker, container_id, &check_output_cmd, settings).await.is_ok() {
+        // Create target directory
+        let mkdir_cmd = format!("mkdir -p ./{}", artifact_dir);
+        if let Err(e) = execute_command(docker, container_id, &mkdir_cmd, settings).await {
+            println!("{}{}Failed to create target directory:{} {}", BOLD, YELLOW, RESET, e);
+            logger.log(&format!("Failed to create target directory: {}", e)).await?;
+            return Ok(false);
+        }
+
+        // Copy build artifacts using tar (handles Nix store permissions reliably)
+        let copy_cmd = format!(
+            "tar -C ./result-{} -cf - . | tar -C ./{} -xf -",
+            clean_target, artifact_dir
+        );
+        // Fallback: plain cp, in case tar itself is the problem
+        let fallback_cmd = format!("cp -r ./result-{}/. ./{}/", clean_target, artifact_dir);
+
+        let attempts = config.copy_retries + 1;
+        let mut copied = false;
+        for attempt in 1..=attempts {
+            if attempt > 1 {
+                // Transient failures (remote daemons, networked volumes) often clear up after a moment
+                let backoff = Duration::from_secs(1 << (attempt - 2).min(4));
+                println!("{}{}Retrying copy for {} in {}s (attempt {}/{}){}", BOLD, YELLOW, clean_target, backoff.as_secs(), attempt, attempts, RESET);
+                tokio::time::sleep(backoff).await;
+            }
+            match execute_command(docker, container_id, &copy_cmd, settings).await {
+                Ok(_) => {
+                    println!("{}{}Successfully copied build artifacts for {}{}", BOLD, GREEN, clean_target, RESET);
+                    copied = true;
+                    break;
+                },
+                Err(e) => {
+                    println!("{}{}Failed to copy build artifacts for {}:{} {}", BOLD, YELLOW, clean_target, RESET, e);
+                    logger.log(&format!("Failed to copy build artifacts (attempt {}/{}): {}", attempt, attempts, e)).await?;
+                }
+            }
+            match execute_command(docker, container_id, &fallback_cmd, settings).await {
+                Ok(_) => {
+                    println!("{}{}Successfully copied artifacts for {} using fallback method{}", BOLD, GREEN, clean_target, RESET);
+                    copied = true;
+                    break;
+                },
+                Err(e) => {
+                    logger.log(&format!("Fallback copy failed (attempt {}/{}): {}", attempt, attempts, e)).await?;
+                }
+            }
+        }
+
+        if !copied {
+            // Keep the result link so the artifacts can still be copied by hand
+            println!("{}{}Could not copy build artifacts for {} after {} attempt(s).{}", BOLD, RED, clean_target, attempts, RESET);
+            println!("   The result link ./result-{} was kept; with --nix-store-cache its store path stays available to copy by hand.", clean_target);
+            logger.log(&format!("Giving up copying artifacts for {} after {} attempt(s); kept ./result-{}", clean_target, attempts, clean_target)).await?;
+            return Ok(false);
+        }
+
+        // Cleanup result symlink
+        let cleanup_cmd = format!("rm -rf ./result-{}", clean_target);
+        if let Err(e) = execute_command(docker, container_id, &cleanup_cmd, settings).await {
+            println!("{}{}Failed to clean up symlink:{} {}", BOLD, YELLOW, RESET, e);
+            logger.log(&format!("Failed to clean up symlink: {}", e)).await?;
+        }
+
+        if target_info.is_wasm {
+            // WebAssembly targets produce modules rather than executables
+            println!("{}{}WebAssembly modules are in:{} {}/lib", BOLD, CYAN, RESET, artifact_dir);
+            logger.log(&format!("WebAssembly modules are in {}/lib", artifact_dir)).await?;
+        }
+
+        let linkage = if target_info.is_static_musl || config.static_link { "static" } else { "dynamic" };
+        println!("{}{}Build successful for target:{} {} ({} profile, {} linking, artifacts in {}, copied in {:.1}s)", BOLD, GREEN, RESET, clean_target, config.profile, linkage, artifact_dir, copy_started.elapsed().as_secs_f64());
+        logger.log(&format!("Build successful for target: {} ({} profile, {} linking, artifacts in {}, copied in {:.1}s)", clean_target, config.profile, linkage, artifact_dir, copy_started.elapsed().as_secs_f64())).await?;
+    } else {
+        println!("{}{}Build produced no output for target:{} {}", BOLD, YELLOW, RESET, clean_target);
+        logger.log(&format!("Build produced no output for target: {}", clean_target)).await?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Fetch the flake inputs and evaluate every target's build without building it
+///
+/// With a Nix store cache, a later build starts from the fetched inputs and derivations.
+pub async fn prefetch_nix_build(
     docker: &Docker,
     container_id: &str,
     targets: &[&str],
     logger: &BuildLogger,
+    settings: &ExecSettings,
+    config: &RepxConfig,
+    report: &mut BuildReport,
 ) -> Result<()> {
-    let create_target_dir = "mkdir -p ./target/repx";
-    let output = execute_command(docker, container_id, create_target_dir).await?;
-    logger.log_command(create_target_dir, &output).await?;
+    let lock_option = if config.no_flake_lock { "--no-write-lock-file" } else { "" };
+    let offline_option = if config.offline { "--offline" } else { "" };
 
-    println!("{}{}Starting build process for {} target(s)...{}", BOLD, MAGENTA, targets.len(), RESET);
-    logger.log(&format!("Starting build process for {} target(s)...", targets.len())).await?;
+    println!("{}{}Fetching flake inputs...{}", BOLD, MAGENTA, RESET);
+    logger.log("Fetching flake inputs").await?;
+    let archive_cmd = format!(
+        "nix --extra-experimental-features 'nix-command flakes' flake archive {} {} ./.repx",
+        lock_option, offline_option
+    );
+    execute_command(docker, container_id, &archive_cmd, settings).await?;
 
-    let mut all_builds_successful = true;
+    for target_info in build_plan(targets, config)? {
+        println!("{}{}Evaluating target:{} {}", BOLD, MAGENTA, RESET, target_info.name);
+        logger.log(&format!("Evaluating target: {}", target_info.name)).await?;
+        let dry_run_cmd = format!(
+            "nix --extra-experimental-features 'nix-command flakes' build --dry-run {} {} {}",
+            lock_option, offline_option, shell_quote(&format!("./.repx#{}", config.output_attr(&target_info.flake_attr)))
+        );
+        let output = execute_command(docker, container_id, &dry_run_cmd, settings).await?;
+        let summary = parse_dry_run(&target_info.name, &output.stderr);
+        logger.log(&format!(
+            "{}: {} derivation(s) to build, {} path(s) to fetch",
+            target_info.name, summary.to_build.len(), summary.to_fetch.len()
+        )).await?;
+        report.dry_run.push(summary);
+    }
 
-    for target in targets {
-        // Parse the target to get build characteristics
-        let (clean_target, is_windows_msvc, _is_static_musl) = parse_target(target);
+    println!("\n{}{}Evaluation completed; nothing was built.{}", BOLD, GREEN, RESET);
+    logger.log("Prefetch completed").await?;
+    Ok(())
+}
 
-        println!("\n{}{}Building for target:{} {}", BOLD, MAGENTA, RESET, clean_target);
-        logger.log(&format!("Building for target: {}", clean_target)).await?;
+/// Parse the "will be built" and "will be fetched" lists `nix build --dry-run` prints to stderr
+pub fn parse_dry_run(target: &str, output: &str) -> DryRunSummary {
+    let mut summary = DryRunSummary { target: target.to_string(), ..DryRunSummary::default() };
+    let mut section: Option<&mut Vec<String>> = None;
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("/nix/store/") {
+            if let Some(paths) = section.as_mut() {
+                paths.push(trimmed.to_string());
+            }
+        } else if trimmed.contains("will be built") {
+            section = Some(&mut summary.to_build);
+        } else if trimmed.contains("will be fetched") {
+            // e.g. "these 25 paths will be fetched (120.50 MiB download, 500.20 MiB unpacked):"
+            summary.download_mib = trimmed
+                .split_once('(')
+                .and_then(|(_, sizes)| sizes.split_once(" MiB download"))
+                .and_then(|(size, _)| size.trim().parse().ok());
+            section = Some(&mut summary.to_fetch);
+        } else {
+            section = None;
+        }
+    }
+    summary
+}
 
-        // Main build command with sandbox option for Windows MSVC
-        let sandbox_option = if is_windows_msvc { "--option sandbox false" } else { "" };
+/// Run `nix flake check` on the generated flake, recording whether it passed in the report
+pub async fn execute_flake_check(
+    docker: &Docker,
+    container_id: &str,
+    logger: &BuildLogger,
+    settings: &ExecSettings,
+    config: &RepxConfig,
+    report: &mut BuildReport,
+) -> Result<()> {
+    let lock_option = if config.no_flake_lock { "--no-write-lock-file" } else { "" };
+    let offline_option = if config.offline { "--offline" } else { "" };
 
-        // Run nix build
-        let nix_build_cmd = format!(
-            "nix --extra-experimental-features 'nix-command flakes' build {} ./.repx#{} --out-link ./result-{}",
-            sandbox_option, clean_target, clean_target
-        );
+    println!("\n{}{}Running nix flake check...{}", BOLD, MAGENTA, RESET);
+    logger.log("Running nix flake check").await?;
+    let check_cmd = format!(
+        "nix --extra-experimental-features 'nix-command flakes' flake check {} {} ./.repx",
+        lock_option, offline_option
+    );
+    let check_started = Instant::now();
+    let check_result = execute_command(docker, container_id, &check_cmd, settings).await;
+    report.record_phase("flake_check", check_started);
+    report.flake_check = Some(check_result.is_ok());
+    match &check_result {
+        Ok(_) => {
+            println!("{}{}nix flake check passed{}", BOLD, GREEN, RESET);
+            logger.log("nix flake check passed").await?;
+        }
+        Err(e) => {
+            println!("{}{}nix flake check failed:{} {}", BOLD, RED, RESET, e);
+            logger.log(&format!("nix flake check failed: {}", e)).await?;
+        }
+    }
+    check_result.map(|_| ())
+}
 
-        let build_result = execute_command(docker, container_id, &nix_build_cmd).await;
-        match build_result {
-            Ok(output) => {
-                logger.log_command(&nix_build_cmd, &output).await?;
-            },
-            Err(e) => {
-                println!("{}{}Build failed for target {}:{} {}", BOLD, RED, clean_target, RESET, e);
-                logger.log(&format!("Build failed for target {}: {}", clean_target, e)).await?;
+/// Describe the parse error Nix printed for the generated flake, quoting the offending line of `flake`
+///
+/// Handles both the `at <file>:<line>:<col>` line newer Nix prints below the error and
+/// the `, at <file>:<line>:<col>` suffix of older releases.
+fn describe_parse_error(output: &str, flake: &str) -> (Option<u32>, String) {
+    let location = Regex::new(r"flake\.nix:(\d+):(\d+)").expect("location pattern is valid");
+    let message = output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("error: "))
+        .map(|message| message.split(", at ").next().unwrap_or(message).trim().to_string())
+        .unwrap_or_else(|| "nix-instantiate could not parse it".to_string());
+    let Some(captures) = location.captures(output) else {
+        return (None, message);
+    };
+    let line: u32 = captures[1].parse().unwrap_or(0);
+    let source = flake.lines().nth(line.saturating_sub(1) as usize).unwrap_or("").trim_end();
+    (Some(line), format!("{} at line {}, column {}:\n    {} | {}", message, line, &captures[2], line, source))
+}
 
-                // Try to get more information about the build failure
-                if let Ok(flake_content) = execute_command(docker, container_id, "cat .repx/flake.nix").await {
-                    logger.log("Flake content for debugging:").await?;
-                    logger.log(&flake_content).await?;
-                }
+/// Parse the generated flake.nix in the container, so template mistakes fail before the slow steps
+///
+/// Only the syntax is checked: `nix-instantiate --parse` neither fetches inputs nor evaluates.
+pub async fn check_flake_syntax(
+    docker: &Docker,
+    container_id: &str,
+    logger: &BuildLogger,
+    settings: &ExecSettings,
+    flake: &str,
+) -> Result<()> {
+    logger.log("Parsing the generated flake.nix").await?;
+    let parse_cmd = "nix-instantiate --parse .repx/flake.nix > /dev/null";
+    match execute_command(docker, container_id, parse_cmd, settings).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let Some(command_error) = e.downcast_ref::<CommandError>() else {
+                return Err(e);
+            };
+            let (line, message) = describe_parse_error(&command_error.output.stderr, flake);
+            logger.log(&format!("The generated flake.nix is not valid Nix: {}", message)).await?;
+            Err(RepxError::FlakeInvalid { line, message }.into())
+        }
+    }
+}
 
-                all_builds_successful = false;
-                continue;
+/// How building one target ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TargetOutcome {
+    Built,
+    /// `nix build` failed, so there is nothing to copy
+    Failed,
+    /// The target built, but its container image didn't
+    ImageFailed,
+}
+
+/// What building one target produced, added to the report once the concurrent builds are done
+struct TargetBuild {
+    outcome: TargetOutcome,
+    /// How long `nix build` took
+    phase: PhaseTiming,
+    /// Rust compiler errors, if the build failed
+    diagnostics: Vec<Diagnostic>,
+    /// Store paths of the runtime closure, with `record_closure`
+    closure: Option<Vec<String>>,
+}
+
+impl TargetBuild {
+    /// Add the timing, diagnostics and closure to `report`
+    fn record(self, name: &str, report: &mut BuildReport) -> TargetOutcome {
+        report.phases.push(self.phase);
+        report.diagnostics.extend(self.diagnostics);
+        if let Some(paths) = self.closure {
+            report.closures.insert(name.to_string(), paths);
+        }
+        self.outcome
+    }
+}
+
+/// Run `nix build` for one target, then record its closure and build its image if configured
+async fn build_target(
+    docker: &Docker,
+    container_id: &str,
+    target_info: &TargetInfo,
+    logger: &BuildLogger,
+    settings: &ExecSettings,
+    config: &RepxConfig,
+) -> Result<TargetBuild> {
+    let clean_target = target_info.flake_attr.as_str();
+    let name = target_info.name.as_str();
+    let artifact_dir = target_info.artifact_dir.as_str();
+
+    println!("\n{}{}Building for target:{} {}", BOLD, MAGENTA, RESET, name);
+    logger.log(&format!("Building for target: {}", name)).await?;
+
+    // Main build command with sandbox option for Windows MSVC
+    let sandbox_option = if target_info.is_windows_msvc { "--option sandbox false" } else { "" };
+
+    // Without a generated flake.lock the pinned inputs are locked in memory only
+    let lock_option = if config.no_flake_lock { "--no-write-lock-file" } else { "" };
+    let offline_option = if config.offline { "--offline" } else { "" };
+
+    // Run nix build, with any user-supplied arguments appended as quoted words
+    let mut nix_build_cmd = format!(
+        "nix --extra-experimental-features 'nix-command flakes' build {} {} {} {} --out-link ./result-{}",
+        sandbox_option, lock_option, offline_option, shell_quote(&format!("./.repx#{}", config.output_attr(clean_target))), clean_target
+    );
+    for arg in &config.nix_args {
+        nix_build_cmd.push(' ');
+        nix_build_cmd.push_str(&shell_quote(arg));
+    }
+
+    let build_started = Instant::now();
+    let build_result = execute_command(docker, container_id, &nix_build_cmd, settings).await;
+    let mut build = TargetBuild {
+        outcome: TargetOutcome::Built,
+        phase: PhaseTiming::since(&format!("build:{}", name), build_started),
+        diagnostics: Vec::new(),
+        closure: None,
+    };
+    if let Err(e) = build_result {
+        println!("{}{}Build failed for target {}:{} {}", BOLD, RED, name, RESET, e);
+        logger.log(&format!("Build failed for target {}: {}", name, e)).await?;
+
+        if let Some(command_error) = e.downcast_ref::<CommandError>() {
+            for stream in [&command_error.output.stderr, &command_error.output.stdout] {
+                build.diagnostics.extend(parse_diagnostics(name, stream));
+            }
+            if config.explain_failure {
+                print_failure_explanation(&command_error.output.labeled());
             }
         }
 
-        // Check if the build produced any output
-        let check_output_cmd = format!("if [ -L ./result-{0} ] && [ -e ./result-{0} ]; then echo \"true\"; else echo \"false\"; fi", clean_target);
+        // Try to get more information about the build failure; the output lands in the log
+        logger.log("Flake content for debugging:").await?;
+        let _ = execute_command(docker, container_id, "cat .repx/flake.nix", settings).await;
 
-        if let Ok(output) = execute_command(docker, container_id, &check_output_cmd).await {
-            logger.log_command(&check_output_cmd, &output).await?;
+        build.outcome = TargetOutcome::Failed;
+        return Ok(build);
+    }
 
-            // Create target directory
-            let mkdir_cmd = format!("mkdir -p ./target/repx/{}", clean_target);
-            match execute_command(docker, container_id, &mkdir_cmd).await {
-                Ok(output) => {
-                    logger.log_command(&mkdir_cmd, &output).await?;
-                },
-                Err(e) => {
-                    println!("{}{}Failed to create target directory:{} {}", BOLD, YELLOW, RESET, e);
-                    logger.log(&format!("Failed to create target directory: {}", e)).await?;
-                    continue;
-                }
+    if config.record_closure {
+        match record_closure(docker, container_id, clean_target, settings).await {
+            Ok(paths) => {
+                println!("{}{}Recorded closure of {}:{} {} store paths", BOLD, CYAN, name, RESET, paths.len());
+                logger.log(&format!("Recorded closure of {}: {} store paths", name, paths.len())).await?;
+                build.closure = Some(paths);
             }
+            Err(e) => {
+                println!("{}{}Failed to record closure of {}:{} {}", BOLD, YELLOW, name, RESET, e);
+                logger.log(&format!("Failed to record closure of {}: {}", name, e)).await?;
+            }
+        }
+    }
 
-            // Copy build artifacts using tar (handles Nix store permissions reliably)
-            let copy_cmd = format!(
-                "tar -C ./result-{} -cf - . | tar -C ./target/repx/{} -xf -",
-                clean_target, clean_target
-            );
+    if config.image_output.is_some()
+        && !build_target_image(docker, container_id, clean_target, artifact_dir, &format!("{} {}", lock_option, offline_option), logger, settings).await?
+    {
+        build.outcome = TargetOutcome::ImageFailed;
+    }
+    Ok(build)
+}
 
-            match execute_command(docker, container_id, &copy_cmd).await {
-                Ok(output) => {
-                    logger.log_command(&copy_cmd, &output).await?;
-                    println!("{}{}Successfully copied build artifacts{}", BOLD, GREEN, RESET);
-                },
-                Err(e) => {
-                    println!("{}{}Failed to copy build artifacts:{} {}", BOLD, YELLOW, RESET, e);
-                    logger.log(&format!("Failed to copy build artifacts: {}", e)).await?;
-
-                    // Fallback: try simple cp as last resort
-                    let fallback_cmd = format!("cp -r ./result-{}/. ./target/repx/{}/", clean_target, clean_target);
-                    match execute_command(docker, container_id, &fallback_cmd).await {
-                        Ok(fallback_output) => {
-                            logger.log_command(&fallback_cmd, &fallback_output).await?;
-                            println!("{}{}Successfully copied using fallback method{}", BOLD, GREEN, RESET);
-                        },
-                        Err(_) => {
-                            println!("{}{}Warning: Could not copy build artifacts, but build was successful{}", BOLD, YELLOW, RESET);
-                        }
-                    }
-                }
+/// Build `targets`, up to `jobs` at a time, returning each target's result in the order given
+///
+/// Each target links its result to its own `result-<target>`, so the builds don't overlap,
+/// and a failed target doesn't stop the others.
+async fn build_targets<'a>(
+    docker: &Docker,
+    container_id: &str,
+    targets: &[&'a TargetInfo],
+    jobs: usize,
+    logger: &BuildLogger,
+    settings: &ExecSettings,
+    config: &RepxConfig,
+) -> Vec<(&'a TargetInfo, Result<TargetBuild>)> {
+    stream::iter(targets.iter().copied())
+        .map(|target_info| async move { (target_info, build_target(docker, container_id, target_info, logger, settings, config).await) })
+        .buffered(jobs.max(1))
+        .collect()
+        .await
+}
+
+pub async fn execute_nix_build(
+    docker: &Docker,
+    container_id: &str,
+    targets: &[&str],
+    logger: &BuildLogger,
+    settings: &ExecSettings,
+    config: &RepxConfig,
+    report: &mut BuildReport,
+) -> Result<()> {
+    let create_target_dir = format!("mkdir -p ./{}", config.artifacts_dir());
+    // Command output is streamed to the build log by execute_command
+    execute_command(docker, container_id, &create_target_dir, settings).await?;
+
+    let plan = build_plan(targets, config)?;
+    println!("{}{}Starting build process for {} target(s)...{}", BOLD, MAGENTA, plan.len(), RESET);
+    logger.log(&format!("Starting build process for {} target(s)...", plan.len())).await?;
+
+    let mut failed_targets: Vec<String> = Vec::new();
+    let mut built = Vec::new();
+    let mut build_failed = Vec::new();
+
+    let jobs = config.jobs.max(1);
+    if jobs > 1 {
+        logger.log(&format!("Building up to {} targets at a time", jobs)).await?;
+    }
+    let all_targets: Vec<&TargetInfo> = plan.iter().collect();
+    for (target_info, build) in build_targets(docker, container_id, &all_targets, jobs, logger, settings, config).await {
+        match build?.record(&target_info.name, report) {
+            TargetOutcome::Built => built.push(target_info),
+            TargetOutcome::Failed => {
+                failed_targets.push(target_info.name.clone());
+                build_failed.push(target_info);
+            }
+            TargetOutcome::ImageFailed => {
+                failed_targets.push(target_info.name.clone());
+                built.push(target_info);
             }
+        }
+    }
 
-            // Cleanup result symlink
-            let cleanup_cmd = format!("rm -rf ./result-{}", clean_target);
-            match execute_command(docker, container_id, &cleanup_cmd).await {
-                Ok(output) => {
-                    logger.log_command(&cleanup_cmd, &output).await?;
-                },
-                Err(e) => {
-                    println!("{}{}Failed to clean up symlink:{} {}", BOLD, YELLOW, RESET, e);
-                    logger.log(&format!("Failed to clean up symlink: {}", e)).await?;
-                }
+    // Re-attempt only the targets whose nix build failed, to get past transient failures
+    for attempt in 1..=config.retry_failed_targets {
+        if build_failed.is_empty() {
+            break;
+        }
+        println!(
+            "\n{}{}Retrying {} failed target(s) (attempt {}/{}){}",
+            BOLD, YELLOW, build_failed.len(), attempt, config.retry_failed_targets, RESET
+        );
+        logger.log(&format!("Retrying {} failed target(s) (attempt {}/{})", build_failed.len(), attempt, config.retry_failed_targets)).await?;
+        let retried = std::mem::take(&mut build_failed);
+        for (target_info, build) in build_targets(docker, container_id, &retried, jobs, logger, settings, config).await {
+            let name = target_info.name.as_str();
+            // Only the errors of the last attempt describe the result
+            report.diagnostics.retain(|d| d.target != name);
+            let outcome = build?.record(name, report);
+            if outcome == TargetOutcome::Failed {
+                build_failed.push(target_info);
+                continue;
+            }
+            println!("{}{}Target {} succeeded on retry{}", BOLD, GREEN, name, RESET);
+            logger.log(&format!("Target {} succeeded on retry", name)).await?;
+            report.retried_targets.push(name.to_string());
+            if outcome == TargetOutcome::Built {
+                failed_targets.retain(|t| t != name);
             }
+            built.push(target_info);
+        }
+    }
 
-            println!("{}{}Build successful for target:{} {}", BOLD, GREEN, RESET, clean_target);
-            logger.log(&format!("Build successful for target: {}", clean_target)).await?;
-        } else {
-            println!("{}{}Build produced no output for target:{} {}", BOLD, YELLOW, RESET, clean_target);
-            logger.log(&format!("Build produced no output for target: {}", clean_target)).await?;
-            all_builds_successful = false;
+    // Copy artifacts out of the store, up to `copy_jobs` targets at a time
+    let copy_jobs = config.copy_jobs.max(1);
+    let copy_phase_started = Instant::now();
+    logger.log(&format!("Copying artifacts for {} target(s) with {} job(s)", built.len(), copy_jobs)).await?;
+    let copy_results: Vec<(&str, Result<bool>)> = stream::iter(&built)
+        .map(|target_info| async move {
+            let copied = copy_artifacts(docker, container_id, target_info, logger, settings, config).await;
+            (target_info.name.as_str(), copied)
+        })
+        .buffer_unordered(copy_jobs)
+        .collect()
+        .await;
+    for (target, copied) in copy_results {
+        if !copied? && !failed_targets.iter().any(|t| t == target) {
+            failed_targets.push(target.to_string());
+        }
+    }
+    logger.log(&format!("Copied artifacts in {:.1}s", copy_phase_started.elapsed().as_secs_f64())).await?;
+    report.record_phase("artifact_copy", copy_phase_started);
+
+    // Summarize the toolchain matrix, including builds that failed before copying
+    report.matrix = plan
+        .iter()
+        .filter_map(|b| {
+            Some(MatrixResult {
+                rust_version: b.rust_version.clone()?,
+                target: b.target.clone(),
+                success: !failed_targets.contains(&b.name),
+            })
+        })
+        .collect();
+    if !report.matrix.is_empty() {
+        println!("\n{}{}Toolchain matrix:{}", BOLD, CYAN, RESET);
+        for result in &report.matrix {
+            let (color, status) = if result.success { (GREEN, "ok") } else { (RED, "FAILED") };
+            println!("   - {} {}: {}{}{}", result.rust_version, result.target, color, status, RESET);
         }
     }
 
-    if all_builds_successful {
+    if failed_targets.is_empty() {
         println!("\n{}{}All builds completed successfully!{}", BOLD, GREEN, RESET);
         logger.log("All builds completed successfully!").await?;
         Ok(())
     } else {
         println!("\n{}{}Some builds failed or produced no output{}", BOLD, YELLOW, RESET);
         logger.log("Some builds failed or produced no output").await?;
-        Err(anyhow!("Not all builds were successful"))
+        Err(RepxError::BuildFailed { targets: failed_targets }.into())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_build_recorded_in_report() {
+        let mut report = BuildReport::new("abc", std::path::PathBuf::from("build-abc.log"), &["x86_64-linux-gnu", "aarch64-linux-gnu"]);
+        let failed = TargetBuild {
+            outcome: TargetOutcome::Failed,
+            phase: PhaseTiming { name: "build:x86_64-linux-gnu".to_string(), seconds: 1.5 },
+            diagnostics: parse_diagnostics("x86_64-linux-gnu", "error[E0308]: mismatched types\n --> src/main.rs:2:5\n"),
+            closure: None,
+        };
+        let built = TargetBuild {
+            outcome: TargetOutcome::Built,
+            phase: PhaseTiming { name: "build:aarch64-linux-gnu".to_string(), seconds: 2.0 },
+            diagnostics: Vec::new(),
+            closure: Some(vec!["/nix/store/aaaa-demo".to_string()]),
+        };
+        assert_eq!(failed.record("x86_64-linux-gnu", &mut report), TargetOutcome::Failed);
+        assert_eq!(built.record("aarch64-linux-gnu", &mut report), TargetOutcome::Built);
+        assert_eq!(report.phases.len(), 2);
+        assert_eq!(report.diagnostics[0].target, "x86_64-linux-gnu");
+        assert_eq!(report.closures["aarch64-linux-gnu"], ["/nix/store/aaaa-demo"]);
+    }
+
+    #[test]
+    fn test_describe_parse_error() {
+        let flake = "{\n  outputs = { self }: {\n    x = 1\n  };\n}\n";
+        let current = "error: syntax error, unexpected '}', expecting ';'\n\n       at /app/.repx/flake.nix:4:3:\n\n            3|     x = 1\n";
+        let (line, message) = describe_parse_error(current, flake);
+        assert_eq!(line, Some(4));
+        assert_eq!(message, "syntax error, unexpected '}', expecting ';' at line 4, column 3:\n    4 |   };");
+
+        let older = "error: syntax error, unexpected '}', expecting ';', at /app/.repx/flake.nix:4:3\n";
+        assert_eq!(describe_parse_error(older, flake), (line, message));
+
+        assert_eq!(describe_parse_error("", flake), (None, "nix-instantiate could not parse it".to_string()));
+    }
+
+    #[test]
+    fn test_parse_dry_run() {
+        let output = "\
+these 2 derivations will be built:
+  /nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-demo-deps-0.1.0.drv
+  /nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-demo-0.1.0.drv
+this path will be fetched (12.34 MiB download, 56.78 MiB unpacked):
+  /nix/store/cccccccccccccccccccccccccccccccc-rust-default-1.80.0
+warning: Git tree '/app' is dirty
+";
+        let summary = parse_dry_run("x86_64-linux-gnu", output);
+        assert_eq!(summary.target, "x86_64-linux-gnu");
+        assert_eq!(summary.to_build.len(), 2);
+        assert!(summary.to_build[1].ends_with("-demo-0.1.0.drv"));
+        assert_eq!(summary.to_fetch, ["/nix/store/cccccccccccccccccccccccccccccccc-rust-default-1.80.0"]);
+        assert_eq!(summary.download_mib, Some(12.34));
+
+        let cached = parse_dry_run("x86_64-linux-gnu", "");
+        assert!(cached.to_build.is_empty() && cached.to_fetch.is_empty() && cached.download_mib.is_none());
+    }
+
+    #[test]
+    fn test_validate_nix_args() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert!(validate_nix_args(&args(&["--keep-going", "--option", "cores", "4"])).is_ok());
+        assert!(validate_nix_args(&args(&["--out-link", "x"])).is_err());
+        assert!(validate_nix_args(&args(&["--out-link=x"])).is_err());
+        assert!(validate_nix_args(&args(&[""])).is_err());
+        assert!(validate_nix_args(&args(&["--keep-going\nrm -rf /"])).is_err());
+        assert_eq!(shell_quote("$(touch x); echo"), "'$(touch x); echo'");
+    }
+
+    #[test]
+    fn test_build_plan_with_rust_matrix() {
+        let config = RepxConfig::default();
+        let plan = build_plan(&["x86_64-linux-gnu", "x86_64-linux-gnu"], &config).unwrap();
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].artifact_dir, "target/repx/x86_64-linux-gnu");
+
+        let config = RepxConfig { rust_versions: vec!["1.75.0".to_string(), "stable".to_string()], ..config };
+        let plan = build_plan(&["x86_64-linux-gnu", "aarch64-linux-musl"], &config).unwrap();
+        let names: Vec<&str> = plan.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["x86_64-linux-gnu@1.75.0", "x86_64-linux-gnu@stable", "aarch64-linux-musl@1.75.0", "aarch64-linux-musl@stable"]);
+        assert_eq!(plan[0].flake_attr, "x86_64-linux-gnu-rust-1_75_0");
+        assert_eq!(plan[0].artifact_dir, "target/repx/1.75.0/x86_64-linux-gnu");
+        assert!(plan[2].is_static_musl);
+    }
+}
@@ -0,0 +1,113 @@
+use regex::Regex;
+use crate::{BOLD, CYAN, RESET, YELLOW};
+
+/// A known failure signature and the fix to suggest for it
+struct FailureRule {
+    /// Regex matched against the failed command's output
+    pattern: &'static str,
+    /// Remediation text; `$1`-style references expand to the pattern's capture groups
+    remediation: &'static str,
+}
+
+/// Known failure signatures, checked in order
+const FAILURE_RULES: &[FailureRule] = &[
+    FailureRule {
+        pattern: r"(?i)could not find directory of openssl|openssl-sys",
+        remediation: "A crate needs OpenSSL. Add `pkg-config` and `openssl` to `extra` (e.g. `--extra pkg-config,openssl`), or switch the crate to rustls.",
+    },
+    FailureRule {
+        pattern: r#"(?i)could not run `"?pkg-config|pkg-config: (command )?not found|the pkg-config command could not be found"#,
+        remediation: "A crate uses pkg-config to locate a system library. Add `pkg-config` to `extra`.",
+    },
+    FailureRule {
+        pattern: r"(?i)(?:cannot|unable to) find (?:library )?-l([A-Za-z0-9_+\-]+)",
+        remediation: "The linker couldn't find lib$1. Add the nixpkgs package that provides it to `extra`.",
+    },
+    FailureRule {
+        pattern: r"(?i)undefined reference to `(__\w+_chk|gnu_get_libc_version|__libc_\w+)'",
+        remediation: "A dependency uses glibc-only symbols ($1) and won't link against musl. Build a -gnu target instead, or disable the feature that pulls in the glibc dependency.",
+    },
+    FailureRule {
+        pattern: r"(?i)sandbox.*(permission denied|operation not permitted)|(?:setting up|creating) .*sandbox",
+        remediation: "Nix's build sandbox couldn't be set up inside the container. Make sure the container runs privileged, or disable the sandbox with `--option sandbox false`.",
+    },
+    FailureRule {
+        pattern: r"(?i)hash mismatch in fixed-output derivation",
+        remediation: "A fixed-output hash doesn't match. Cargo.lock and the flake have drifted apart; regenerate Cargo.lock and the flake lock, then rebuild.",
+    },
+    FailureRule {
+        pattern: r"(?i)needs to be updated but --locked was passed",
+        remediation: "Cargo.lock is out of date. Run `cargo update` (or `cargo generate-lockfile`) on the host and commit the result.",
+    },
+    FailureRule {
+        pattern: r"(?i)could not resolve host|couldn't resolve host|unable to download",
+        remediation: "A download failed. Check the network connection and any proxy settings available to the Docker daemon.",
+    },
+    FailureRule {
+        pattern: r"(?i)no space left on device",
+        remediation: "The disk filled up. Free space on the Docker host or prune unused images and volumes with `docker system prune`.",
+    },
+    FailureRule {
+        pattern: r"(?i)exec format error",
+        remediation: "A binary for another architecture was executed. Register qemu binfmt handlers on the host, e.g. `docker run --privileged --rm tonistiigi/binfmt --install all`.",
+    },
+];
+
+/// Match failed command output against the known failure signatures
+///
+/// Returns one remediation per matching rule, in rule order.
+pub fn explain_failure(output: &str) -> Vec<String> {
+    FAILURE_RULES
+        .iter()
+        .filter_map(|rule| {
+            let regex = Regex::new(rule.pattern).expect("failure rule patterns are valid");
+            regex.captures(output).map(|captures| {
+                let mut remediation = String::new();
+                captures.expand(rule.remediation, &mut remediation);
+                remediation
+            })
+        })
+        .collect()
+}
+
+/// Print targeted fixes for a failed command's output, if any rule matches
+pub fn print_failure_explanation(output: &str) {
+    let suggestions = explain_failure(output);
+    if suggestions.is_empty() {
+        println!("{}{}No known failure signature matched; see the log file for the full output.{}", BOLD, YELLOW, RESET);
+        return;
+    }
+
+    println!("{}{}Possible fixes:{}", BOLD, CYAN, RESET);
+    for suggestion in suggestions {
+        println!("   - {}", suggestion);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain_failure_matches_missing_library() {
+        let output = "note: /nix/store/...-ld: cannot find -lsqlite3: No such file or directory";
+        let suggestions = explain_failure(output);
+        assert_eq!(suggestions.len(), 1);
+        assert!(suggestions[0].contains("libsqlite3"));
+    }
+
+    #[test]
+    fn test_explain_failure_matches_openssl_and_pkg_config() {
+        let output = "error: failed to run custom build command for `openssl-sys v0.9.102`\n\
+                      Could not run `\"pkg-config\" \"--libs\" \"openssl\"`";
+        let suggestions = explain_failure(output);
+        assert_eq!(suggestions.len(), 2);
+        assert!(suggestions[0].contains("openssl"));
+        assert!(suggestions[1].contains("pkg-config"));
+    }
+
+    #[test]
+    fn test_explain_failure_without_match() {
+        assert!(explain_failure("error[E0308]: mismatched types").is_empty());
+    }
+}
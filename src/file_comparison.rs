@@ -1,7 +1,25 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use similar::{ChangeTag, TextDiff};
+use std::collections::BTreeMap;
 use std::path::Path;
 use tokio::fs;
-use crate::{BOLD, YELLOW, RESET, GREEN};
+use crate::{BOLD, YELLOW, RESET, GREEN, RED, CYAN};
+
+/// Outcome of comparing a generated file with the one already on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DriftStatus {
+    /// The generated file matches the existing one
+    Unchanged,
+    /// The generated file differs from the existing one
+    Drifted,
+    /// There was no existing file to compare against
+    Created,
+    /// The file wasn't generated for this build
+    Skipped,
+}
 
 /// Compare two files and return whether they are different
 pub async fn files_differ<P1: AsRef<Path>, P2: AsRef<Path>>(path1: P1, path2: P2) -> Result<bool> {
@@ -15,53 +33,266 @@ pub async fn files_differ<P1: AsRef<Path>, P2: AsRef<Path>>(path1: P1, path2: P2
     Ok(normalized1 != normalized2)
 }
 
+/// A unified diff from `old` to `new` with three lines of context, colored like the rest of the output
+pub fn colored_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let (old, new) = (old.replace("\r\n", "\n"), new.replace("\r\n", "\n"));
+    let diff = TextDiff::from_lines(&old, &new);
+    let mut out = format!("{}--- {}{}\n{}+++ {}{}\n", BOLD, old_name, RESET, BOLD, new_name, RESET);
+    for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+        out.push_str(&format!("{}{}{}\n", CYAN, hunk.header(), RESET));
+        for change in hunk.iter_changes() {
+            let line = change.value().trim_end_matches('\n');
+            match change.tag() {
+                ChangeTag::Delete => out.push_str(&format!("{}-{}{}\n", RED, line, RESET)),
+                ChangeTag::Insert => out.push_str(&format!("{}+{}{}\n", GREEN, line, RESET)),
+                ChangeTag::Equal => out.push_str(&format!(" {}\n", line)),
+            }
+        }
+    }
+    out
+}
+
+/// One line per locked input of a flake.lock: its source, revision and hash
+///
+/// Diffing these instead of the JSON keeps a lock update to one line per input that moved.
+/// Returns `None` if `json` isn't a flake.lock.
+fn lock_revisions(json: &str) -> Option<String> {
+    let lock: Value = serde_json::from_str(json).ok()?;
+    let nodes: BTreeMap<&String, &Value> = lock.get("nodes")?.as_object()?.iter().collect();
+    let field = |locked: &Value, key: &str| locked.get(key).and_then(Value::as_str).unwrap_or("-").to_string();
+    let mut lines = String::new();
+    for (name, node) in nodes {
+        let Some(locked) = node.get("locked") else { continue };
+        let source = match (locked.get("owner"), locked.get("repo")) {
+            (Some(_), Some(_)) => format!("{}:{}/{}", field(locked, "type"), field(locked, "owner"), field(locked, "repo")),
+            _ => locked.get("url").map_or_else(|| field(locked, "type"), |_| field(locked, "url")),
+        };
+        lines.push_str(&format!("{}: {} rev={} narHash={}\n", name, source, field(locked, "rev"), field(locked, "narHash")));
+    }
+    Some(lines)
+}
+
+/// Diff of two flake.locks: of their inputs' revisions, or of the pretty-printed JSON if those match
+fn lock_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    if let (Some(old_revs), Some(new_revs)) = (lock_revisions(old), lock_revisions(new)) {
+        if old_revs != new_revs {
+            return colored_diff(&old_revs, &new_revs, old_name, new_name);
+        }
+    }
+    let pretty = |json: &str| {
+        serde_json::from_str::<Value>(json)
+            .and_then(|value| serde_json::to_string_pretty(&value))
+            .map_or_else(|_| json.to_string(), |pretty| pretty + "\n")
+    };
+    colored_diff(&pretty(old), &pretty(new), old_name, new_name)
+}
+
+/// Split Nix source into tokens, dropping comments and whitespace outside of strings
+///
+/// This is deliberately lightweight: strings are kept verbatim and runs of other
+/// characters are only split at punctuation, so two flakes compare equal when they
+/// differ in comments, indentation or spacing alone.
+fn nix_tokens(source: &str) -> Vec<String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '#' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '"' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i += 1;
+            tokens.push(chars[start..i.min(chars.len())].iter().collect());
+        } else if c == '\'' && next == Some('\'') {
+            // Indented string; '' followed by ', $ or \ is an escape, not the end
+            let start = i;
+            i += 2;
+            while i < chars.len() {
+                if chars[i] == '\'' && chars.get(i + 1) == Some(&'\'') {
+                    if matches!(chars.get(i + 2), Some('\'') | Some('$') | Some('\\')) {
+                        i += 3;
+                        continue;
+                    }
+                    i += 2;
+                    break;
+                }
+                i += 1;
+            }
+            tokens.push(chars[start..i.min(chars.len())].iter().collect());
+        } else if "{}[]();=,".contains(c) {
+            tokens.push(c.to_string());
+            i += 1;
+        } else {
+            let starts_other_token = |i: usize| {
+                let next = chars.get(i + 1).copied();
+                chars[i].is_whitespace()
+                    || "{}[]();=,#\"".contains(chars[i])
+                    || (chars[i] == '/' && next == Some('*'))
+                    || (chars[i] == '\'' && next == Some('\''))
+            };
+            let start = i;
+            while i < chars.len() && !starts_other_token(i) {
+                i += 1;
+            }
+            tokens.push(chars[start..i].iter().collect());
+        }
+    }
+    tokens
+}
+
+/// Whether two flakes differ, byte for byte (modulo line endings) or only in their tokens
+pub fn flakes_differ(existing: &str, generated: &str, exact: bool) -> bool {
+    if exact {
+        existing.replace("\r\n", "\n").replace("\r", "\n") != generated.replace("\r\n", "\n").replace("\r", "\n")
+    } else {
+        nix_tokens(existing) != nix_tokens(generated)
+    }
+}
+
 /// Compare generated flake.nix with existing one and warn if different
+///
+/// Unless `exact` is set, differences in comments and whitespace don't count as drift.
+/// With `show_diff`, drift is printed as a unified diff. Warnings are also added to `warnings`.
 pub async fn check_flake_changes(
-    generated_path: &Path,
     existing_path: &Path,
     generated_content: &str,
-) -> Result<()> {
+    exact: bool,
+    show_diff: bool,
+    warnings: &mut Vec<String>,
+) -> Result<DriftStatus> {
     if existing_path.exists() {
         let existing_content = fs::read_to_string(existing_path).await?;
-        let normalized_existing = existing_content.replace("\r\n", "\n").replace("\r", "\n");
-        let normalized_generated = generated_content.replace("\r\n", "\n").replace("\r", "\n");
         
-        if normalized_existing != normalized_generated {
+        if flakes_differ(&existing_content, generated_content, exact) {
             println!("\n{}{}WARNING:{} Generated flake.nix differs from existing {}", 
                      BOLD, YELLOW, RESET, existing_path.display());
             println!("{}{}Differences detected in flake configuration.{}", BOLD, YELLOW, RESET);
-            println!("Consider reviewing the changes and updating your flake.nix if needed.");
-            println!("Generated flake.nix is available at: {}", generated_path.display());
+            if show_diff {
+                print!("{}", colored_diff(&existing_content, generated_content, "flake.nix (existing)", "flake.nix (generated)"));
+            }
+            println!("The generated flake.nix replaces it; review the changes before committing it.");
+            warnings.push(format!("Generated flake.nix differs from existing {}", existing_path.display()));
+            Ok(DriftStatus::Drifted)
         } else {
             println!("{}{}Generated flake.nix matches existing configuration.{}", BOLD, GREEN, RESET);
+            Ok(DriftStatus::Unchanged)
         }
     } else {
         println!("{}{}No existing flake.nix found at {}, using generated one.{}", 
                  BOLD, GREEN, RESET, existing_path.display());
+        Ok(DriftStatus::Created)
     }
-    
-    Ok(())
 }
 
-/// Compare generated flake.lock with existing one and warn if different
-pub async fn check_lock_changes(existing_lock_path: &Path, temp_lock_path: &Path) -> Result<()> {
-    if existing_lock_path.exists() && temp_lock_path.exists() {
-        match files_differ(existing_lock_path, temp_lock_path).await {
-            Ok(true) => {
-                println!("\n{}{}WARNING:{} Generated flake.lock differs from existing {}", 
-                         BOLD, YELLOW, RESET, existing_lock_path.display());
-                println!("{}{}Lock file changes detected.{}", BOLD, YELLOW, RESET);
-                println!("This might indicate dependency updates or changes in flake inputs.");
-                println!("Consider reviewing the lock file changes.");
-            }
-            Ok(false) => {
-                println!("{}{}flake.lock is up to date.{}", BOLD, GREEN, RESET);
-            }
-            Err(e) => {
-                println!("{}{}Warning:{} Failed to compare lock files: {}", BOLD, YELLOW, RESET, e);
+/// Compare generated flake.lock with existing one and warn if different, adding warnings to `warnings`
+///
+/// With `show_diff`, the inputs whose locked revision changed are printed as a diff.
+pub async fn check_lock_changes(existing_lock_path: &Path, temp_lock_path: &Path, show_diff: bool, warnings: &mut Vec<String>) -> Result<DriftStatus> {
+    if !existing_lock_path.exists() || !temp_lock_path.exists() {
+        return Ok(DriftStatus::Created);
+    }
+
+    let status = match files_differ(existing_lock_path, temp_lock_path).await {
+        Ok(true) => {
+            println!("\n{}{}WARNING:{} Generated flake.lock differs from existing {}", 
+                     BOLD, YELLOW, RESET, existing_lock_path.display());
+            println!("{}{}Lock file changes detected.{}", BOLD, YELLOW, RESET);
+            if show_diff {
+                let existing = fs::read_to_string(existing_lock_path).await?;
+                let generated = fs::read_to_string(temp_lock_path).await?;
+                print!("{}", lock_diff(&existing, &generated, "flake.lock (before)", "flake.lock (after)"));
             }
+            println!("This might indicate dependency updates or changes in flake inputs.");
+            println!("Consider reviewing the lock file changes.");
+            warnings.push(format!("Generated flake.lock differs from existing {}", existing_lock_path.display()));
+            DriftStatus::Drifted
         }
-    }
+        Ok(false) => {
+            println!("{}{}flake.lock is up to date.{}", BOLD, GREEN, RESET);
+            DriftStatus::Unchanged
+        }
+        Err(e) => {
+            println!("{}{}Warning:{} Failed to compare lock files: {}", BOLD, YELLOW, RESET, e);
+            warnings.push(format!("Failed to compare lock files: {}", e));
+            // Without a comparison the lock can't be confirmed unchanged
+            DriftStatus::Drifted
+        }
+    };
     
-    Ok(())
-} 
\ No newline at end of file
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flake_comparison_ignores_cosmetic_changes() {
+        let generated = "{\n  inputs = { nixpkgs.url = \"github:NixOS/nixpkgs\"; };\n  x = ''\n    echo hi\n  '';\n}\n";
+        let reformatted = "# my notes\n{ inputs = {nixpkgs.url=\"github:NixOS/nixpkgs\";}; /* keep */\n  x = ''\n    echo hi\n  '';\n}";
+        assert!(!flakes_differ(reformatted, generated, false));
+        assert!(flakes_differ(reformatted, generated, true));
+
+        // Whitespace inside strings still matters
+        let changed_string = generated.replace("echo hi", "echo  hi");
+        assert!(flakes_differ(&changed_string, generated, false));
+        let changed_url = generated.replace("NixOS/nixpkgs", "NixOS/nixpkgs/nixos-24.05");
+        assert!(flakes_differ(&changed_url, generated, false));
+    }
+
+    #[test]
+    fn test_drift_diffs() {
+        let diff = colored_diff("a\nb\nc\n", "a\nB\nc\n", "old", "new");
+        assert!(diff.contains("@@ -1,3 +1,3 @@"));
+        assert!(diff.contains(&format!("{}-b{}", RED, RESET)));
+        assert!(diff.contains(&format!("{}+B{}", GREEN, RESET)));
+        assert!(diff.contains("\n a\n"));
+
+        let lock = |rev: &str| {
+            format!(
+                r#"{{"nodes": {{"nixpkgs": {{"locked": {{"owner": "NixOS", "repo": "nixpkgs", "rev": "{}", "type": "github", "narHash": "sha256-x"}}}}, "root": {{"inputs": {{"nixpkgs": "nixpkgs"}}}}}}, "version": 7}}"#,
+                rev
+            )
+        };
+        let diff = lock_diff(&lock("abc"), &lock("def"), "before", "after");
+        assert!(diff.contains("-nixpkgs: github:NixOS/nixpkgs rev=abc narHash=sha256-x"));
+        assert!(diff.contains("+nixpkgs: github:NixOS/nixpkgs rev=def narHash=sha256-x"));
+        assert!(!diff.contains("root"));
+
+        // Same revisions but a different file falls back to the pretty-printed JSON
+        let diff = lock_diff(&lock("abc"), &lock("abc").replace("7}", "8}"), "before", "after");
+        assert!(diff.contains("+  \"version\": 8"));
+    }
+
+    #[tokio::test]
+    async fn test_drift_is_collected_as_warning() {
+        let dir = tempfile::tempdir().unwrap();
+        let (existing, generated) = (dir.path().join("flake.lock"), dir.path().join("flake.lock.new"));
+        fs::write(&existing, "{\"version\": 7}").await.unwrap();
+        fs::write(&generated, "{\"version\": 7}").await.unwrap();
+
+        let mut warnings = Vec::new();
+        assert_eq!(check_lock_changes(&existing, &generated, true, &mut warnings).await.unwrap(), DriftStatus::Unchanged);
+        assert!(warnings.is_empty());
+
+        fs::write(&generated, "{\"version\": 8}").await.unwrap();
+        assert_eq!(check_lock_changes(&existing, &generated, true, &mut warnings).await.unwrap(), DriftStatus::Drifted);
+        assert_eq!(warnings.len(), 1);
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use crate::container_utils::{cleanup_container, container_name_for, pull_build_image, setup_container};
+use crate::execute_command::{execute_command, ExecSettings};
+use crate::{connect_docker, RepxConfig, RepxError};
+
+/// Package attributes per system in the output of `nix flake show --json`
+fn parse_flake_packages(json: &str) -> Result<BTreeMap<String, Vec<String>>> {
+    let outputs: Value = serde_json::from_str(json)?;
+    let Some(systems) = outputs.get("packages").and_then(Value::as_object) else {
+        return Ok(BTreeMap::new());
+    };
+    Ok(systems
+        .iter()
+        .map(|(system, packages)| {
+            let names = packages.as_object().map(|p| p.keys().cloned().collect()).unwrap_or_default();
+            (system.clone(), names)
+        })
+        .collect())
+}
+
+/// The `packages.<system>` attributes the project's generated flake actually exposes
+///
+/// Runs `nix flake show --json` on `.repx/flake.nix` in a container from the build image.
+/// The flake isn't regenerated and no lock file is written, so this shows what the flake
+/// as it is can build, including edits or pins made to it.
+pub async fn flake_packages(config: &RepxConfig) -> Result<BTreeMap<String, Vec<String>>, RepxError> {
+    Ok(show_flake_packages(config).await?)
+}
+
+async fn show_flake_packages(config: &RepxConfig) -> Result<BTreeMap<String, Vec<String>>> {
+    let project_path = PathBuf::from(&config.project).canonicalize()?;
+    let metadata_dir = project_path.join(".repx");
+    if !metadata_dir.join("flake.nix").exists() {
+        return Err(RepxError::ConfigInvalid(format!(
+            "No generated flake at {}; run repx build or repx shell first",
+            metadata_dir.join("flake.nix").display()
+        ))
+        .into());
+    }
+
+    let docker = connect_docker(config).await?;
+    pull_build_image(&docker, config).await?;
+    let container_name = container_name_for(&project_path, &["flake-show"], false);
+    let container = setup_container(&docker, config, &project_path, &metadata_dir, &container_name).await?;
+    let settings = ExecSettings::default();
+    let shown = async {
+        // The flake in /app/.repx is read through git when the project is a repository
+        if project_path.join(".git").exists() {
+            execute_command(&docker, &container.id, "git config --global --add safe.directory /app", &settings).await?;
+        }
+        let offline_option = if config.offline { " --offline" } else { "" };
+        let show_cmd = format!(
+            "nix --extra-experimental-features 'nix-command flakes' flake show --json --all-systems --no-write-lock-file{} ./.repx",
+            offline_option
+        );
+        let output = execute_command(&docker, &container.id, &show_cmd, &settings).await?;
+        parse_flake_packages(&output.stdout)
+    }
+    .await;
+    cleanup_container(&docker, &container.id).await?;
+    shown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_flake_packages() {
+        let json = r#"{
+            "devShells": {"x86_64-linux": {"default": {"type": "derivation"}}},
+            "packages": {
+                "aarch64-darwin": {},
+                "x86_64-linux": {
+                    "default": {"name": "demo-0.1.0", "type": "derivation"},
+                    "x86_64-linux-gnu": {"name": "demo-0.1.0", "type": "derivation"},
+                    "x86_64-linux-gnu-image": {"name": "demo-image", "type": "derivation"}
+                }
+            }
+        }"#;
+        let packages = parse_flake_packages(json).unwrap();
+        assert_eq!(packages.len(), 2);
+        assert!(packages["aarch64-darwin"].is_empty());
+        assert_eq!(packages["x86_64-linux"], ["default", "x86_64-linux-gnu", "x86_64-linux-gnu-image"]);
+        assert!(parse_flake_packages("{}").unwrap().is_empty());
+        assert!(parse_flake_packages("not json").is_err());
+    }
+}
@@ -1,25 +1,242 @@
 use std::path::Path;
-use cargo_metadata::MetadataCommand;
+use cargo_metadata::{Metadata, MetadataCommand, Package};
 use anyhow::Result;
 use tera::Tera;
-use crate::FLAKE_TEMPLATE;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use crate::{RepxConfig, RepxError, FLAKE_TEMPLATE, RESET, BOLD, CYAN};
+use crate::atomic_file::write_atomic;
+use crate::source_state::commit_time;
 use tera::Context;
 
+/// Profiles Cargo defines without any `[profile.*]` table
+const BUILTIN_PROFILES: &[&str] = &["dev", "release"];
+
+/// Built-in profiles of `cargo test` and `cargo bench`, which repx can't build with
+///
+/// nixpkgs' build hook passes `--profile <buildType>` and installs from
+/// `target/<buildType>`, but Cargo builds these two into `target/debug` and
+/// `target/release`, so no `buildType` selects them.
+const TEST_PROFILES: &[&str] = &["test", "bench"];
+
+/// Resolve a profile name to the name Cargo expects and the directory it builds into
+///
+/// `debug` is accepted as an alias of `dev`, whose output lands in `target/debug`; any
+/// other profile, custom ones included, gets a directory named after itself.
+pub fn cargo_profile_names(profile: &str) -> (&str, &str) {
+    match profile {
+        "dev" | "debug" => ("dev", "debug"),
+        other => (other, other),
+    }
+}
+
+/// Check that a Cargo profile is either built in or defined in the workspace manifest
+async fn validate_cargo_profile(workspace_manifest: &Path, profile: &str) -> Result<()> {
+    let (cargo_profile, _) = cargo_profile_names(profile);
+    if BUILTIN_PROFILES.contains(&cargo_profile) {
+        return Ok(());
+    }
+    if TEST_PROFILES.contains(&cargo_profile) {
+        return Err(RepxError::ConfigInvalid(format!(
+            "Cargo profile '{}' is only used by cargo {}; build with dev or release, or a custom profile that inherits from it",
+            cargo_profile, cargo_profile
+        )).into());
+    }
+
+    let manifest: toml::Value = toml::from_str(&tokio::fs::read_to_string(workspace_manifest).await?)?;
+    let defined = manifest
+        .get("profile")
+        .and_then(|profiles| profiles.get(cargo_profile))
+        .is_some();
+    if !defined {
+        return Err(RepxError::ConfigInvalid(format!(
+            "Cargo profile '{}' is not defined in {}",
+            cargo_profile,
+            workspace_manifest.display()
+        )).into());
+    }
+    Ok(())
+}
+
+/// Check that a cargo hash override is an SRI hash, as `cargoHash` expects
+fn validate_cargo_hash(hash: &str) -> Result<()> {
+    let valid = hash
+        .strip_prefix("sha256-")
+        .is_some_and(|digest| !digest.is_empty() && digest.chars().all(|c| c.is_ascii_alphanumeric() || "+/=".contains(c)));
+    if !valid {
+        return Err(RepxError::ConfigInvalid(format!(
+            "Invalid cargo hash '{}': expected an SRI hash such as 'sha256-AAAA...='",
+            hash
+        )).into());
+    }
+    Ok(())
+}
+
+/// Whether a flake URL pins an exact revision or content hash
+///
+/// Accepts `github:`/`gitlab:`/`sourcehut:` shorthands with a full commit hash as the
+/// ref, and any URL carrying a `rev=<commit>` or `narHash=` parameter.
+pub fn is_pinned_flake_url(url: &str) -> bool {
+    let is_commit = |s: &str| s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit());
+    let (base, query) = url.split_once('?').unwrap_or((url, ""));
+
+    let pinned_by_query = query.split('&').any(|param| match param.split_once('=') {
+        Some(("rev", rev)) => is_commit(rev),
+        Some(("narHash", hash)) => !hash.is_empty(),
+        _ => false,
+    });
+    if pinned_by_query {
+        return true;
+    }
+
+    ["github:", "gitlab:", "sourcehut:"].iter().any(|scheme| {
+        base.strip_prefix(scheme)
+            .and_then(|path| path.split('/').nth(2))
+            .is_some_and(is_commit)
+    })
+}
+
+/// Inputs of the generated flake, by name
+fn flake_inputs(config: &RepxConfig) -> [(&'static str, &String); 3] {
+    [
+        ("nixpkgs", &config.nixpkgs_url),
+        ("rust-overlay", &config.rust_overlay_url),
+        ("flake-utils", &config.flake_utils_url),
+    ]
+}
+
+/// Whether any flake input is fetched with git, e.g. `git+https://...`
+pub fn uses_git_inputs(config: &RepxConfig) -> bool {
+    flake_inputs(config)
+        .iter()
+        .any(|(_, url)| url.starts_with("git+") || url.starts_with("git:"))
+}
+
+/// Check that every flake input is pinned, as required when no flake.lock is generated
+pub fn validate_pinned_inputs(config: &RepxConfig) -> Result<()> {
+    let unpinned: Vec<String> = flake_inputs(config)
+        .iter()
+        .filter(|(_, url)| !is_pinned_flake_url(url))
+        .map(|(name, url)| format!("{} ({})", name, url))
+        .collect();
+    if !unpinned.is_empty() {
+        return Err(RepxError::ConfigInvalid(format!(
+            "--no-flake-lock requires every flake input to be pinned to a commit or narHash, but these are not: {}",
+            unpinned.join(", ")
+        )).into());
+    }
+    Ok(())
+}
+
+/// Split an image reference into its name and tag, defaulting the tag to `latest`
+///
+/// A `:` before the last `/` belongs to a registry port, not the tag.
+pub fn split_image_ref(image_ref: &str) -> Result<(String, String)> {
+    let name_start = image_ref.rfind('/').map_or(0, |i| i + 1);
+    let (name, tag) = match image_ref[name_start..].rfind(':') {
+        Some(i) => (&image_ref[..name_start + i], &image_ref[name_start + i + 1..]),
+        None => (image_ref, "latest"),
+    };
+    if name.is_empty() || tag.is_empty() {
+        return Err(RepxError::ConfigInvalid(format!("Invalid image reference '{}': expected name[:tag]", image_ref)).into());
+    }
+    Ok((name.to_string(), tag.to_string()))
+}
+
+/// Targets the built-in template has packages for, named as its `packages.<system>` attributes
+pub const AVAILABLE_TARGETS: &[&str] = &[
+    "x86_64-linux-gnu",
+    "aarch64-linux-gnu",
+    "x86_64-linux-musl",
+    "aarch64-linux-musl",
+    "riscv64-linux-gnu",        // RISC-V 64 (experimental)
+    "armv7-linux-gnueabihf",    // ARMv7 hard-float (experimental)
+    "powerpc64le-linux-gnu",    // POWER little-endian (experimental)
+    "wasm32-wasi",              // WebAssembly (WASI)
+    "wasm32-unknown-unknown",   // WebAssembly (bare, e.g. for wasm-bindgen)
+    "x86_64-w64-mingw32",       // Windows GNU
+    "x86_64-pc-windows-msvc", // Windows MSVC
+    "aarch64-w64-mingw32",      // Windows ARM GNU (experimental)
+    "x86_64-apple-darwin",      // macOS Intel
+    "aarch64-apple-darwin",     // macOS Apple Silicon
+];
+
+/// Template variables the built-in template renders when set in `template_vars`
+const TEMPLATE_HOOKS: &[&str] = &["extra_overlays", "extra_native_build_inputs", "pre_build_hook"];
+
+/// How the flake sets `SOURCE_DATE_EPOCH`: `git` for the commit time, a fixed timestamp, or empty to leave it
+///
+/// The commit time is read by the flake itself (`self.lastModified`), so flake.nix doesn't
+/// change, and drift, with every commit.
+fn source_date_epoch_context(config: &RepxConfig, commit_time: Option<u64>) -> String {
+    match (config.no_source_date_epoch, config.source_date_epoch, commit_time) {
+        (true, ..) => String::new(),
+        (false, Some(epoch), _) => epoch.to_string(),
+        (false, None, Some(_)) => "git".to_string(),
+        (false, None, None) => String::new(),
+    }
+}
+
 /// Generate a flake.nix file for the Rust project
-pub async fn generate_flake_file(
-    flake_path: &Path,
-    extra_packages: &[String],
-    rust_channel: &str,
-    rust_version: &str,
-    nixpkgs_url: &str,
-) -> Result<String> {
-    let metadata = MetadataCommand::new().exec()?;
-    let package = metadata.packages
+pub async fn generate_flake_file(flake_path: &Path, config: &RepxConfig) -> Result<String> {
+    let content = render_flake(config).await?;
+    write_atomic(flake_path, &content).await?;
+    Ok(content)
+}
+
+/// Render the flake.nix a build of `config` would write to `.repx/`, without any Docker
+///
+/// Reads the project's Cargo metadata and git HEAD on the host; nothing is written.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), repx_lib::RepxError> {
+/// let config = repx_lib::RepxConfig { project: "path/to/project".to_string(), ..Default::default() };
+/// let flake = repx_lib::generate::flake(&config).await?;
+/// assert!(flake.contains("packages"));
+/// # Ok(())
+/// # }
+/// ```
+pub async fn flake(config: &RepxConfig) -> Result<String, RepxError> {
+    Ok(render_flake(config).await?)
+}
+
+/// The workspace member to build: the one named `name`, or the only one if there is no choice
+fn select_package<'a>(metadata: &'a Metadata, name: Option<&str>) -> Result<&'a Package> {
+    let members: Vec<&Package> = metadata
+        .packages
         .iter()
-        .find(|p| p.id.repr == metadata.workspace_members[0].repr)
-        .ok_or_else(|| anyhow::anyhow!("Could not find package in metadata"))?;
+        .filter(|p| metadata.workspace_members.contains(&p.id))
+        .collect();
+    let names = || members.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(", ");
+    match (name, members.as_slice()) {
+        (Some(name), _) => members.iter().copied().find(|p| p.name.as_str() == name).ok_or_else(|| {
+            RepxError::ConfigInvalid(format!("No package '{}' in the workspace; its members are: {}", name, names())).into()
+        }),
+        (None, [package]) => Ok(package),
+        (None, []) => Err(anyhow::anyhow!("Could not find package in metadata")),
+        (None, _) => Err(RepxError::ConfigInvalid(format!(
+            "The workspace has several members; pick one with --package (package in repx.toml): {}",
+            names()
+        ))
+        .into()),
+    }
+}
+
+async fn render_flake(config: &RepxConfig) -> Result<String> {
+    let metadata = MetadataCommand::new()
+        .manifest_path(config.manifest_path())
+        .exec()?;
+    let package = select_package(&metadata, config.package.as_deref())?;
+
+    if let Some(hash) = &config.cargo_hash {
+        validate_cargo_hash(hash)?;
+        if config.vendor {
+            return Err(RepxError::ConfigInvalid("--cargo-hash can't be combined with --vendor, which builds from .repx/vendor".to_string()).into());
+        }
+    }
+    if config.no_flake_lock {
+        validate_pinned_inputs(config)?;
+    }
+    validate_cargo_profile(metadata.workspace_root.join("Cargo.toml").as_std_path(), &config.profile).await?;
+    let (cargo_profile, profile_dir) = cargo_profile_names(&config.profile);
 
     let mut tera = Tera::default();
     tera.add_raw_template("flake.nix", FLAKE_TEMPLATE)?;
@@ -27,20 +244,59 @@ pub async fn generate_flake_file(
     let mut context = Context::new();
     context.insert("package_name", &package.name);
     context.insert("package_version", &package.version.to_string());
-    context.insert("extra_packages", &extra_packages);
-    context.insert("rust_channel", rust_channel);
-    context.insert("rust_version", rust_version);
-    context.insert("nixpkgs_url", nixpkgs_url);
+    // Only passed to cargo when there is a choice, so single-crate projects build as before
+    let cargo_package = (metadata.workspace_members.len() > 1).then_some(package.name.as_str());
+    context.insert("cargo_package", &cargo_package);
+    context.insert("extra_packages", &config.extra);
+    context.insert("rust_channel", &config.rust_channel);
+    context.insert("rust_version", &config.rust_version);
+    let rust_matrix: Vec<serde_json::Value> = config
+        .rust_matrix()?
+        .iter()
+        .map(|t| serde_json::json!({ "suffix": t.attr_suffix(), "channel": t.channel, "version": t.version }))
+        .collect();
+    context.insert("rust_matrix", &rust_matrix);
+    let target_toolchains: Vec<serde_json::Value> = config
+        .target_toolchains()?
+        .iter()
+        .map(|(target, t)| serde_json::json!({ "target": target, "channel": t.channel, "version": t.version }))
+        .collect();
+    context.insert("target_toolchains", &target_toolchains);
+    context.insert("nixpkgs_url", &config.nixpkgs_url);
+    context.insert("rust_overlay_url", &config.rust_overlay_url);
+    context.insert("flake_utils_url", &config.flake_utils_url);
+    context.insert("cargo_profile", cargo_profile);
+    context.insert("profile_dir", profile_dir);
+    context.insert("cargo_hash", &config.cargo_hash);
+    context.insert("vendored", &config.vendor);
+    context.insert("target_dir_name", config.target_dir_root());
+    context.insert("static_link", &config.static_link);
+    let commit_time = commit_time(Path::new(&config.project)).await;
+    context.insert("source_date_epoch", &source_date_epoch_context(config, commit_time));
+    let image = config.image_output.as_deref().map(split_image_ref).transpose()?;
+    context.insert("image_name", &image.as_ref().map(|(name, _)| name));
+    context.insert("image_tag", &image.as_ref().map(|(_, tag)| tag));
+    for slot in TEMPLATE_HOOKS {
+        context.insert(*slot, "");
+    }
+    for (name, value) in &config.template_vars {
+        if context.contains_key(name) && !TEMPLATE_HOOKS.contains(&name.as_str()) {
+            return Err(RepxError::ConfigInvalid(format!(
+                "template_vars.{} would override a variable set by repx", name
+            )).into());
+        }
+        context.insert(name, value);
+    }
+
+    if config.dump_context {
+        println!("{}{}Template context:{}", BOLD, CYAN, RESET);
+        println!("{}", serde_json::to_string_pretty(&context.clone().into_json())?);
+    }
 
     let rendered = tera.render("flake.nix", &context)?;
 
     // Normalize line endings to Unix-style (LF only) to ensure compatibility with Nix in Linux containers
-    let normalized_content = rendered.replace("\r\n", "\n").replace("\r", "\n");
-
-    let mut file = File::create(flake_path).await?;
-    file.write_all(normalized_content.as_bytes()).await?;
-
-    Ok(normalized_content)
+    Ok(rendered.replace("\r\n", "\n").replace("\r", "\n"))
 }
 
 #[cfg(test)]
@@ -52,17 +308,11 @@ mod tests {
     fn test_flake_generation_without_musl_override() {
         // Test that flake generation works without musl version override
         let temp_file = NamedTempFile::new().expect("Failed to create temp file");
-        let extra_packages = vec![];
+        let config = RepxConfig::default();
         
         let rt = tokio::runtime::Runtime::new().unwrap();
         let content = rt.block_on(async {
-            generate_flake_file(
-                temp_file.path(),
-                &extra_packages,
-                "stable",
-                "latest",
-                "github:NixOS/nixpkgs/nixos-unstable",
-            ).await.unwrap()
+            generate_flake_file(temp_file.path(), &config).await.unwrap()
         });
 
         // Check that no musl overlay is included
@@ -71,4 +321,311 @@ mod tests {
         assert!(content.contains("x86_64-linux-musl"));
         assert!(content.contains("aarch64-linux-musl"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_flake_generation_includes_additional_linux_targets() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(async {
+            generate_flake_file(temp_file.path(), &RepxConfig::default()).await.unwrap()
+        });
+
+        for (target, triple) in [
+            ("riscv64-linux-gnu", "riscv64gc-unknown-linux-gnu"),
+            ("armv7-linux-gnueabihf", "armv7-unknown-linux-gnueabihf"),
+            ("powerpc64le-linux-gnu", "powerpc64le-unknown-linux-gnu"),
+        ] {
+            assert!(content.contains(&format!("\"{}\" = buildFor", target)), "missing package for {}", target);
+            assert!(content.contains(&format!("targetTriple == \"{}\"", triple)), "missing cross set for {}", triple);
+        }
+    }
+
+    #[test]
+    fn test_flake_generation_with_dev_profile() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let config = RepxConfig { profile: "debug".to_string(), ..RepxConfig::default() };
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(async {
+            generate_flake_file(temp_file.path(), &config).await.unwrap()
+        });
+
+        assert!(content.contains("buildType = \"debug\";"));
+        assert!(content.contains("--profile dev"));
+        assert_eq!(cargo_profile_names("dist"), ("dist", "dist"));
+    }
+
+    #[test]
+    fn test_every_builder_gets_the_same_profile() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        // buildRustPackage's hook turns buildType "debug" into Cargo's dev profile
+        for (profile, build_type, cargo_profile) in [("release", "release", "release"), ("dev", "debug", "dev"), ("debug", "debug", "dev")] {
+            let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+            let config = RepxConfig { profile: profile.to_string(), ..RepxConfig::default() };
+            let content = rt.block_on(async {
+                generate_flake_file(temp_file.path(), &config).await.unwrap()
+            });
+
+            assert_eq!(content.matches("buildType = ").count(), 1);
+            assert!(content.contains(&format!("buildType = \"{}\";", build_type)), "buildType for {}", profile);
+            // The wasm and MSVC builders run cargo themselves
+            assert_eq!(content.matches("--profile ").count(), 2);
+            assert!(content.contains(&format!("cargo build --profile {} ", cargo_profile)), "wasm profile for {}", profile);
+            assert!(content.contains(&format!("--profile {} \\\n", cargo_profile)), "MSVC profile for {}", profile);
+        }
+    }
+
+    #[test]
+    fn test_flake_generation_rejects_test_profiles() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        for profile in ["test", "bench"] {
+            let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+            let config = RepxConfig { profile: profile.to_string(), ..RepxConfig::default() };
+            let result = rt.block_on(async {
+                generate_flake_file(temp_file.path(), &config).await
+            });
+
+            assert!(result.unwrap_err().to_string().contains(&format!("only used by cargo {}", profile)));
+        }
+    }
+
+    #[test]
+    fn test_flake_generation_rejects_unknown_profile() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let config = RepxConfig { profile: "does-not-exist".to_string(), ..RepxConfig::default() };
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let result = rt.block_on(async {
+            generate_flake_file(temp_file.path(), &config).await
+        });
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_flake_generation_vendors_from_cargo_lock_by_default() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(async {
+            generate_flake_file(temp_file.path(), &RepxConfig::default()).await.unwrap()
+        });
+
+        assert!(content.contains("lockFile = ../Cargo.lock;"));
+        assert!(!content.contains("cargoHash"));
+    }
+
+    #[test]
+    fn test_flake_generation_with_cargo_hash_override() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let hash = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+        let config = RepxConfig { cargo_hash: Some(hash.to_string()), ..RepxConfig::default() };
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(async {
+            generate_flake_file(temp_file.path(), &config).await.unwrap()
+        });
+
+        assert!(content.contains(&format!("cargoHash = \"{}\";", hash)));
+        assert!(!content.contains("lockFile = ../Cargo.lock;"));
+
+        let invalid = RepxConfig { cargo_hash: Some("abc".to_string()), ..RepxConfig::default() };
+        assert!(rt.block_on(generate_flake_file(temp_file.path(), &invalid)).is_err());
+    }
+
+    #[test]
+    fn test_flake_generation_with_vendored_sources() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let config = RepxConfig { vendor: true, ..RepxConfig::default() };
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(async {
+            generate_flake_file(temp_file.path(), &config).await.unwrap()
+        });
+
+        assert!(content.contains("cargoDeps = ./vendor;"));
+        assert!(!content.contains("lockFile = ../Cargo.lock;"));
+
+        let conflicting = RepxConfig { cargo_hash: Some("sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_string()), ..config };
+        assert!(rt.block_on(generate_flake_file(temp_file.path(), &conflicting)).is_err());
+    }
+
+    #[test]
+    fn test_pinned_flake_urls() {
+        let rev = "0123456789abcdef0123456789abcdef01234567";
+        assert!(is_pinned_flake_url(&format!("github:NixOS/nixpkgs/{}", rev)));
+        assert!(is_pinned_flake_url(&format!("git+https://github.com/numtide/flake-utils?rev={}", rev)));
+        assert!(is_pinned_flake_url("https://example.com/src.tar.gz?narHash=sha256-abc="));
+        assert!(!is_pinned_flake_url("github:NixOS/nixpkgs/nixos-unstable"));
+        assert!(!is_pinned_flake_url("github:oxalica/rust-overlay"));
+        assert!(!is_pinned_flake_url("github:NixOS/nixpkgs?rev=abc"));
+
+        // The defaults follow branches, so they can't be used without a lock file
+        let config = RepxConfig { no_flake_lock: true, ..RepxConfig::default() };
+        assert!(validate_pinned_inputs(&config).is_err());
+
+        assert!(!uses_git_inputs(&config));
+        let config = RepxConfig { flake_utils_url: format!("git+https://github.com/numtide/flake-utils?rev={}", rev), ..config };
+        assert!(uses_git_inputs(&config));
+    }
+
+    #[test]
+    fn test_split_image_ref() {
+        assert_eq!(split_image_ref("myapp").unwrap(), ("myapp".to_string(), "latest".to_string()));
+        assert_eq!(split_image_ref("ghcr.io/me/myapp:1.2").unwrap(), ("ghcr.io/me/myapp".to_string(), "1.2".to_string()));
+        assert_eq!(split_image_ref("localhost:5000/myapp").unwrap(), ("localhost:5000/myapp".to_string(), "latest".to_string()));
+        assert!(split_image_ref("myapp:").is_err());
+    }
+
+    #[test]
+    fn test_flake_generation_with_image_output() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let config = RepxConfig { image_output: Some("myapp:1.0".to_string()), ..RepxConfig::default() };
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(async {
+            generate_flake_file(temp_file.path(), &config).await.unwrap()
+        });
+
+        assert!(content.contains("dockerTools.buildLayeredImage"));
+        assert!(content.contains("tag = \"1.0\";"));
+        assert!(content.contains("// imagePackages"));
+    }
+
+    #[test]
+    fn test_flake_generation_with_template_vars() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let mut config = RepxConfig::default();
+        config.template_vars.insert("extra_overlays".to_string(), "(final: prev: { })".to_string());
+        config.template_vars.insert("extra_native_build_inputs".to_string(), "pkgs.protobuf".to_string());
+        config.template_vars.insert("pre_build_hook".to_string(), "export PROTOC=protoc".to_string());
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(async {
+            generate_flake_file(temp_file.path(), &config).await.unwrap()
+        });
+
+        assert!(content.contains("overlays = [ rust-overlay.overlays.default (final: prev: { }) ];"));
+        assert!(content.contains("++ [ pkgs.protobuf ]"));
+        assert!(content.contains("export PROTOC=protoc"));
+        assert!(content.contains("runHook preBuild"));
+
+        // Without hooks the template is unchanged
+        let plain = rt.block_on(generate_flake_file(temp_file.path(), &RepxConfig::default())).unwrap();
+        assert!(!plain.contains("preBuild"));
+
+        config.template_vars.insert("package_name".to_string(), "other".to_string());
+        assert!(rt.block_on(generate_flake_file(temp_file.path(), &config)).is_err());
+    }
+
+    #[test]
+    fn test_flake_generation_with_source_date_epoch() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let config = RepxConfig { source_date_epoch: Some(1700000000), ..RepxConfig::default() };
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(generate_flake_file(temp_file.path(), &config)).unwrap();
+        assert!(content.contains("sourceDateEpoch = \"1700000000\";"));
+        assert_eq!(content.matches("SOURCE_DATE_EPOCH = sourceDateEpoch;").count(), 3);
+
+        let config = RepxConfig { no_source_date_epoch: true, ..RepxConfig::default() };
+        let content = rt.block_on(generate_flake_file(temp_file.path(), &config)).unwrap();
+        assert!(!content.contains("SOURCE_DATE_EPOCH"));
+
+        // The commit time is left to the flake, so the generated text is the same for every commit
+        assert_eq!(source_date_epoch_context(&RepxConfig::default(), Some(1700000000)), "git");
+        assert_eq!(source_date_epoch_context(&RepxConfig::default(), None), "");
+    }
+
+    #[test]
+    fn test_flake_renders_without_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let flake_path = dir.path().join("flake.nix");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        let rendered = rt.block_on(flake(&RepxConfig::default())).unwrap();
+        assert!(!flake_path.exists());
+        let written = rt.block_on(generate_flake_file(&flake_path, &RepxConfig::default())).unwrap();
+        assert_eq!(rendered, written);
+        assert!(AVAILABLE_TARGETS.iter().all(|target| rendered.contains(&format!("\"{}\"", target))));
+    }
+
+    #[test]
+    fn test_flake_generation_selects_workspace_member() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "[workspace]\nmembers = [\"app\", \"tool\"]\n").unwrap();
+        for (name, version) in [("app", "1.2.0"), ("tool", "0.3.0")] {
+            std::fs::create_dir_all(dir.path().join(name).join("src")).unwrap();
+            std::fs::write(
+                dir.path().join(name).join("Cargo.toml"),
+                format!("[package]\nname = \"{}\"\nversion = \"{}\"\nedition = \"2021\"\n", name, version),
+            )
+            .unwrap();
+            std::fs::write(dir.path().join(name).join("src/main.rs"), "fn main() {}\n").unwrap();
+        }
+        let config = RepxConfig { project: dir.path().display().to_string(), ..RepxConfig::default() };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        let err = rt.block_on(flake(&config)).unwrap_err().to_string();
+        assert!(err.contains("app, tool"), "{}", err);
+        let config = RepxConfig { package: Some("tool".to_string()), ..config };
+        let rendered = rt.block_on(flake(&config)).unwrap();
+        assert!(rendered.contains("pname = \"tool\";"));
+        assert!(rendered.contains("version = \"0.3.0\";"));
+        assert!(rendered.contains("cargoBuildFlags = [ \"--package\" \"tool\" ];"));
+        let config = RepxConfig { package: Some("missing".to_string()), ..config };
+        assert!(rt.block_on(flake(&config)).is_err());
+
+        // A single crate builds as before, without --package
+        assert!(!rt.block_on(flake(&RepxConfig::default())).unwrap().contains("cargoBuildFlags"));
+    }
+
+    #[test]
+    fn test_flake_generation_with_static_link() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let config = RepxConfig { static_link: true, ..RepxConfig::default() };
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(async {
+            generate_flake_file(temp_file.path(), &config).await.unwrap()
+        });
+        assert!(content.contains("if staticGlibc then \"-C target-feature=+crt-static\""));
+        assert!(content.contains("targetPkgs.glibc.static"));
+
+        let dynamic = rt.block_on(generate_flake_file(temp_file.path(), &RepxConfig::default())).unwrap();
+        assert!(dynamic.contains("staticGlibc = false;"));
+    }
+
+    #[test]
+    fn test_flake_generation_with_rust_matrix() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let rust_versions = vec!["1.75.0".to_string(), "nightly".to_string()];
+        let config = RepxConfig { rust_versions, ..RepxConfig::default() };
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(generate_flake_file(temp_file.path(), &config)).unwrap();
+        assert!(content.contains("\"1_75_0\" = rust-bin: rust-bin.stable.\"1.75.0\";"));
+        assert!(content.contains("\"nightly\" = rust-bin: rust-bin.nightly.\"latest\";"));
+        assert!(content.contains("++ toolchainOverlays;"));
+
+        let plain = rt.block_on(generate_flake_file(temp_file.path(), &RepxConfig::default())).unwrap();
+        assert!(!plain.contains("outputsFor"));
+    }
+
+    #[test]
+    fn test_flake_generation_with_target_toolchains() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let mut config = RepxConfig::default();
+        config.target_toolchains.insert(
+            "x86_64-linux-musl".to_string(),
+            crate::ToolchainOverride { channel: Some("nightly".to_string()), version: Some("2024-05-01".to_string()) },
+        );
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let content = rt.block_on(generate_flake_file(temp_file.path(), &config)).unwrap();
+        assert!(content.contains("\"x86_64-linux-musl\" = rust-bin: rust-bin.nightly.\"2024-05-01\";"));
+        assert!(content.contains("defaultOutputs.packages // targetPackages;"));
+        assert!(!content.contains("matrixPackages"));
+    }
+}
@@ -1,10 +1,13 @@
 use anyhow::Result;
 use bollard::Docker;
-use crate::execute_command::execute_command;
+use crate::execute_command::{execute_command, CommandOutput, ExecSettings};
 
 /// Generate flake.lock file inside the container
-pub async fn generate_flake_lock(docker: &Docker, container_id: &str) -> Result<String> {
-    let cmd = "cd .repx && nix --extra-experimental-features 'nix-command flakes' flake lock";
-    let output = execute_command(docker, container_id, cmd).await?;
+///
+/// Offline, Nix resolves the inputs from what is already in the store.
+pub async fn generate_flake_lock(docker: &Docker, container_id: &str, settings: &ExecSettings, offline: bool) -> Result<CommandOutput> {
+    let offline_option = if offline { " --offline" } else { "" };
+    let cmd = format!("cd .repx && nix --extra-experimental-features 'nix-command flakes' flake lock{}", offline_option);
+    let output = execute_command(docker, container_id, &cmd, settings).await?;
     Ok(output)
 }
\ No newline at end of file
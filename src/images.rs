@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tokio::process::Command;
+use crate::RepxError;
+
+/// A Nix image that works as the build image, with what to know when choosing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecommendedImage {
+    pub image: &'static str,
+    /// Rough compressed download size
+    pub size: &'static str,
+    /// Whether the tag always refers to the same Nix version
+    pub pinned: bool,
+    /// Whether flakes are enabled in the image's nix.conf; repx enables them per command either way
+    pub flakes_enabled: bool,
+    pub notes: &'static str,
+}
+
+/// Images known to work with repx, pinned ones first
+pub const RECOMMENDED_IMAGES: &[RecommendedImage] = &[
+    RecommendedImage {
+        image: "nixos/nix:2.24.0",
+        size: "~180 MB",
+        pinned: true,
+        flakes_enabled: false,
+        notes: "Official image at a fixed Nix release; the reproducible choice for CI",
+    },
+    RecommendedImage {
+        image: "nixos/nix:2.18.1",
+        size: "~170 MB",
+        pinned: true,
+        flakes_enabled: false,
+        notes: "Older Nix release, for projects whose flake.lock or inputs need it",
+    },
+    RecommendedImage {
+        image: "nixos/nix:latest",
+        size: "~180 MB",
+        pinned: false,
+        flakes_enabled: false,
+        notes: "Follows the newest Nix release, so builds can change when it moves",
+    },
+    RecommendedImage {
+        image: "nixpkgs/nix-flakes:latest",
+        size: "~150 MB",
+        pinned: false,
+        flakes_enabled: true,
+        notes: "Built from nixpkgs with flakes on by default; floating, like nixos/nix:latest",
+    },
+];
+
+/// Repository whose tags `list_image_tags` can query
+pub const TAGGED_REPOSITORY: &str = "nixos/nix";
+
+/// A tag of the Nix image on Docker Hub
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ImageTag {
+    pub name: String,
+    /// Compressed size summed over all platforms, if Docker Hub reports it
+    #[serde(rename = "full_size")]
+    pub size_bytes: Option<u64>,
+    pub last_updated: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TagPage {
+    results: Vec<ImageTag>,
+}
+
+/// Parse a page of Docker Hub's tag listing
+fn parse_tags(json: &str) -> Result<Vec<ImageTag>> {
+    Ok(serde_json::from_str::<TagPage>(json)?.results)
+}
+
+/// The most recently updated tags of `nixos/nix` on Docker Hub, newest first
+///
+/// Queries the Docker Hub API with `curl`, so it needs network access but no Docker daemon.
+pub async fn list_image_tags(limit: usize) -> Result<Vec<ImageTag>, RepxError> {
+    Ok(fetch_tags(limit).await?)
+}
+
+async fn fetch_tags(limit: usize) -> Result<Vec<ImageTag>> {
+    let url = format!(
+        "https://hub.docker.com/v2/repositories/{}/tags?page_size={}&ordering=last_updated",
+        TAGGED_REPOSITORY,
+        limit.clamp(1, 100)
+    );
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", &url])
+        .output()
+        .await
+        .context("Could not run curl; is it installed?")?;
+    if !output.status.success() {
+        anyhow::bail!("Could not list the tags of {}: {}", TAGGED_REPOSITORY, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    parse_tags(&String::from_utf8_lossy(&output.stdout))
+        .with_context(|| format!("Unexpected response listing the tags of {}", TAGGED_REPOSITORY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tags() {
+        let json = r#"{"count": 2, "next": null, "results": [
+            {"name": "2.24.14", "full_size": 188743680, "last_updated": "2025-03-01T10:00:00Z", "images": []},
+            {"name": "latest", "full_size": null}
+        ]}"#;
+        let tags = parse_tags(json).unwrap();
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].name, "2.24.14");
+        assert_eq!(tags[0].size_bytes, Some(188743680));
+        assert_eq!(tags[1].size_bytes, None);
+        assert_eq!(tags[1].last_updated, None);
+        assert!(parse_tags("{\"message\": \"not found\"}").is_err());
+    }
+}
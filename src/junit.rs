@@ -0,0 +1,102 @@
+use anyhow::Result;
+use std::path::Path;
+use crate::BuildReport;
+
+/// Escape text for an XML attribute or element
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than whitespace aren't allowed in XML 1.0
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// JUnit XML for a build, with each of `builds` as a test case
+///
+/// A build fails if it is among the report's failed targets, passes if it ran, and is
+/// skipped if the build stopped before reaching it.
+pub fn junit_xml(report: &BuildReport, builds: &[String]) -> String {
+    let error = report.error.as_deref().unwrap_or("Build failed");
+    let mut cases = String::new();
+    let (mut failures, mut skipped) = (0, 0);
+    for name in builds {
+        let phase = report.phases.iter().find(|p| p.name == format!("build:{}", name));
+        let seconds = phase.map_or(0.0, |p| p.seconds);
+        cases.push_str(&format!(
+            "    <testcase name=\"{}\" classname=\"repx.build\" time=\"{:.1}\"",
+            escape(name),
+            seconds
+        ));
+        if report.failed_targets.contains(name) {
+            failures += 1;
+            cases.push_str(&format!(
+                ">\n      <failure message=\"Build failed for {}\">{}</failure>\n    </testcase>\n",
+                escape(name),
+                escape(error)
+            ));
+        } else if phase.is_none() && !report.success {
+            skipped += 1;
+            cases.push_str(&format!(">\n      <skipped message=\"Not built: {}\"/>\n    </testcase>\n", escape(error)));
+        } else {
+            cases.push_str("/>\n");
+        }
+    }
+    let total: f64 = report.phases.iter().map(|p| p.seconds).sum();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n  <testsuite name=\"repx\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.1}\">\n{}  </testsuite>\n</testsuites>\n",
+        builds.len(),
+        failures,
+        skipped,
+        total,
+        cases
+    )
+}
+
+/// Write the JUnit XML for a build to `path`
+pub async fn write_junit(report: &BuildReport, builds: &[String], path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, junit_xml(report, builds)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PhaseTiming;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_junit_xml() {
+        let builds: Vec<String> = ["x86_64-linux-gnu", "aarch64-linux-gnu", "x86_64-w64-mingw32"].iter().map(|t| t.to_string()).collect();
+        let mut report = BuildReport::new("abc", PathBuf::from("build-abc.log"), &["x86_64-linux-gnu", "aarch64-linux-gnu", "x86_64-w64-mingw32"]);
+        report.phases = vec![
+            PhaseTiming { name: "image_pull".to_string(), seconds: 2.0 },
+            PhaseTiming { name: "build:x86_64-linux-gnu".to_string(), seconds: 30.0 },
+            PhaseTiming { name: "build:aarch64-linux-gnu".to_string(), seconds: 45.5 },
+        ];
+        report.failed_targets = vec!["aarch64-linux-gnu".to_string()];
+        report.error = Some("Build failed for target(s): aarch64-linux-gnu <exit 1>".to_string());
+
+        let xml = junit_xml(&report, &builds);
+        assert!(xml.contains("<testsuite name=\"repx\" tests=\"3\" failures=\"1\" skipped=\"1\" time=\"77.5\">"));
+        assert!(xml.contains("<testcase name=\"x86_64-linux-gnu\" classname=\"repx.build\" time=\"30.0\"/>"));
+        assert!(xml.contains("<failure message=\"Build failed for aarch64-linux-gnu\">Build failed for target(s): aarch64-linux-gnu &lt;exit 1&gt;</failure>"));
+        assert!(xml.contains("<testcase name=\"x86_64-w64-mingw32\" classname=\"repx.build\" time=\"0.0\">\n      <skipped"));
+
+        report.success = true;
+        report.failed_targets.clear();
+        let xml = junit_xml(&report, &builds);
+        assert!(xml.contains("failures=\"0\" skipped=\"0\""));
+    }
+}
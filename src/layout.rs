@@ -0,0 +1,59 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use crate::report::ArtifactInfo;
+
+/// Directory under `<target_dir>/repx` that the flat layout copies executables to
+pub const FLAT_DIR: &str = "flat";
+
+/// Name of an executable in the flat layout: its own name with the build's name before the extension
+///
+/// `bin/myapp.exe` of `x86_64-w64-mingw32` becomes `myapp-x86_64-w64-mingw32.exe`; a matrix
+/// build such as `x86_64-linux-gnu@1.75.0` is suffixed `-x86_64-linux-gnu-1.75.0`.
+pub fn flat_name(path: &str, build: &str) -> String {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if matches!(extension, "exe" | "wasm") => (stem, format!(".{}", extension)),
+        _ => (file_name, String::new()),
+    };
+    format!("{}-{}{}", stem, build.replace('@', "-"), extension)
+}
+
+/// Copy the executables of a build from its artifact dir into `flat_dir`, returning the files written
+pub fn flatten_artifacts(artifact_dir: &Path, build: &str, artifacts: &[ArtifactInfo], flat_dir: &Path) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(flat_dir)?;
+    let mut written = Vec::new();
+    for artifact in artifacts.iter().filter(|a| a.is_executable()) {
+        let destination = flat_dir.join(flat_name(&artifact.path, build));
+        std::fs::copy(artifact_dir.join(&artifact.path), &destination)?;
+        written.push(destination);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::collect_artifacts;
+
+    #[test]
+    fn test_flatten_artifacts() {
+        assert_eq!(flat_name("bin/myapp", "x86_64-linux-gnu"), "myapp-x86_64-linux-gnu");
+        assert_eq!(flat_name("bin/myapp.exe", "x86_64-w64-mingw32"), "myapp-x86_64-w64-mingw32.exe");
+        assert_eq!(flat_name("lib/module.wasm", "wasm32-wasi"), "module-wasm32-wasi.wasm");
+        assert_eq!(flat_name("bin/tool.v2", "x86_64-linux-gnu@1.75.0"), "tool.v2-x86_64-linux-gnu-1.75.0");
+
+        let dir = tempfile::tempdir().unwrap();
+        let artifact_dir = dir.path().join("x86_64-linux-gnu");
+        std::fs::create_dir_all(artifact_dir.join("bin")).unwrap();
+        std::fs::create_dir_all(artifact_dir.join("share")).unwrap();
+        std::fs::write(artifact_dir.join("bin/server"), "server").unwrap();
+        std::fs::write(artifact_dir.join("bin/client"), "client").unwrap();
+        std::fs::write(artifact_dir.join("share/README"), "docs").unwrap();
+
+        let artifacts = collect_artifacts(&artifact_dir).unwrap();
+        let flat_dir = dir.path().join(FLAT_DIR);
+        let written = flatten_artifacts(&artifact_dir, "x86_64-linux-gnu", &artifacts, &flat_dir).unwrap();
+        assert_eq!(written, [flat_dir.join("client-x86_64-linux-gnu"), flat_dir.join("server-x86_64-linux-gnu")]);
+        assert_eq!(std::fs::read_to_string(&written[1]).unwrap(), "server");
+    }
+}
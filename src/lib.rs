@@ -1,8 +1,9 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use bollard::Docker;
 use serde::Serialize;
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
 
 mod generate_flake;
 mod generate_lock;
@@ -13,6 +14,37 @@ mod build_integration;
 mod logging;
 mod config;
 mod file_comparison;
+mod explain_failure;
+mod cache;
+mod report;
+mod output_buffer;
+mod source_state;
+mod error;
+mod binfmt;
+mod secrets;
+mod disk_space;
+mod status;
+mod audit;
+mod build_diff;
+mod vendor;
+mod serve;
+mod artifact_processor;
+mod signing;
+mod builder;
+mod bench;
+mod shutdown;
+mod remote_source;
+mod junit;
+mod cargo_config;
+mod diagnostics;
+mod sarif;
+mod images;
+mod dev_shell;
+mod atomic_file;
+mod layout;
+mod flake_outputs;
+mod exec;
+mod verify;
 
 pub mod build_script {
     //! This module provides integration for build.rs scripts.
@@ -22,17 +54,67 @@ pub mod build_script {
     pub use crate::build_integration::run_build;
 }
 
-pub use logging::BuildLogger;
-pub use config::RepxConfig;
+pub mod generate {
+    //! Flake generation on its own, without Docker or a build.
+    //!
+    //! Use this to vendor the generated flake into other Nix tooling, or to test its output.
+
+    pub use crate::generate_flake::{flake, AVAILABLE_TARGETS};
+    pub use crate::ExtraInput;
+}
 
-use generate_flake::generate_flake_file;
+pub use logging::{list_build_logs, log_tail, read_build_log, BuildLogger};
+pub use config::{env_overrides, parse_meta, upgrade_config_text, PartialRepxConfig, RepxConfig, ToolchainOverride, CONFIG_VERSION};
+pub use cache::{cache_clean, cache_stats, remove_store_cache, CacheStats, DEFAULT_STORE_VOLUME};
+pub use container_utils::{reap_idle_containers, ContainerRuntime, DockerEndpoint, RUNTIME_ENV};
+pub use execute_command::CommandOutput;
+pub use file_comparison::DriftStatus;
+pub use output_buffer::OutputLimit;
+pub use source_state::SourceState;
+pub use error::{DockerProblem, RepxError};
+pub use execute_command::{CommandError, ProgressRate};
+pub use report::{ArtifactInfo, BuildReport, DryRunSummary, MatrixResult, PhaseTiming, ReferenceComparison, ReferenceStatus};
+pub use build_diff::{diff_builds, ArtifactChange, BuildDiff, ConfigChange};
+pub use status::BuildState;
+pub use serve::{serve, ServeRoots};
+pub use artifact_processor::{Artifact, ArtifactProcessor, ChecksumProcessor, StripProcessor};
+pub use signing::{SignBackend, SignProcessor};
+pub use audit::{AuditFinding, AuditReport, Severity};
+pub use builder::{ProgressSink, RepxBuilder};
+pub use bench::{run_bench, BenchResult, DEFAULT_BENCH_VOLUME};
+pub use shutdown::ShutdownSignal;
+pub use remote_source::{fetch_source, RemoteSource};
+pub use junit::junit_xml;
+pub use diagnostics::{parse_diagnostics, Diagnostic};
+pub use sarif::sarif_json;
+pub use dev_shell::dev_shell;
+pub use flake_outputs::flake_packages;
+pub use exec::exec_in_container;
+pub use verify::{verify_reproducible, TargetVerification, VerifyResult};
+pub use images::{list_image_tags, ImageTag, RecommendedImage, RECOMMENDED_IMAGES, TAGGED_REPOSITORY};
+
+use generate_flake::uses_git_inputs;
 use generate_lock::generate_flake_lock;
-use execute_build::execute_nix_build;
-use container_utils::{setup_container, cleanup_container};
-use execute_command::execute_command;
+use execute_build::{artifact_dirs, check_flake_syntax, execute_flake_check, execute_nix_build, prefetch_nix_build, supports_image, supports_static_linking, validate_nix_args, IMAGE_FILE_NAME};
+use container_utils::{chown_command, container_name_for, validate_container_name, copy_source_command, resolve_owner, runtime_host_config, probe_nix_daemon, pull_build_image, setup_container, cleanup_container, load_image};
+use execute_command::{execute_command, init_trace_script, ExecSettings};
+use explain_failure::print_failure_explanation;
 use file_comparison::{check_flake_changes, check_lock_changes};
+use atomic_file::{remove_stale_temp_files, temp_path_for, write_atomic, STALE_TEMP_AGE};
+use layout::{flatten_artifacts, FLAT_DIR};
+use source_state::{commit_time, detect_source_state};
+use cargo_config::check_cargo_config;
+use binfmt::check_binfmt_support;
+use secrets::{nix_access_token_values, nix_access_tokens_env, read_secret_file, read_secrets_env, SECRETS_ENV_FILE};
+use status::StatusFile;
+use artifact_processor::process_artifacts;
+use build_diff::compare_with_reference;
+use vendor::vendor_dependencies;
+use audit::run_audit;
+use disk_space::{check_host_space, check_store_space};
+use shutdown::ShutdownListener;
 
-pub const FLAKE_TEMPLATE: &'static str = include_str!("../templates/flake.nix.tera");
+pub const FLAKE_TEMPLATE: &str = include_str!("../templates/flake.nix.tera");
 
 // ANSI color codes for terminal output
 pub const RESET: &str = "\x1b[0m";
@@ -51,24 +133,93 @@ pub struct ExtraInput {
     pub url: String,
 }
 
+/// .gitignore files written by earlier versions, replaced like any other generated preset
+const LEGACY_GITIGNORES: &[&str] = &[
+    "# Ignore log files and directories\nlogs/\n*.log\n",
+    "# Ignore log files and directories\nlogs/\n*.log\nstatus.json\n",
+    "# Generated by repx: commit everything in .repx\n",
+];
+
+/// Contents of the .repx/.gitignore for a `metadata_gitignore` preset
+fn gitignore_preset(preset: &str) -> Result<&'static str, RepxError> {
+    match preset {
+        "ignore-logs-only" => Ok("# Ignore log files and directories\nlogs/\n*.log\nstatus.json\nsecrets.env\n"),
+        "ignore-all" => Ok("# Generated by repx: ignore everything in .repx\n*\n"),
+        "ignore-nothing" => Ok("# Generated by repx: commit everything in .repx but local secrets\nsecrets.env\n"),
+        other => Err(RepxError::ConfigInvalid(format!(
+            "Unknown metadata_gitignore preset '{}'; expected ignore-logs-only, ignore-all or ignore-nothing",
+            other
+        ))),
+    }
+}
+
+/// File in `.repx` holding the merged configuration of the latest build
+const EFFECTIVE_CONFIG_FILE: &str = "effective-config.toml";
+
 /// Generate a .gitignore file for the .repx directory
-async fn generate_gitignore(metadata_dir: &Path) -> Result<()> {
+///
+/// A .gitignore that doesn't match any generated preset was customized, so it is left alone.
+async fn generate_gitignore(metadata_dir: &Path, preset: &str) -> Result<()> {
     let gitignore_path = metadata_dir.join(".gitignore");
+    let gitignore_content = gitignore_preset(preset)?;
 
-    // Only create if it doesn't exist
-    if !gitignore_path.exists() {
-        let gitignore_content = r#"# Ignore log files and directories
-logs/
-*.log
-"#;
-
-        tokio::fs::write(&gitignore_path, gitignore_content).await?;
+    if let Ok(existing) = tokio::fs::read_to_string(&gitignore_path).await {
+        let generated = ["ignore-logs-only", "ignore-all", "ignore-nothing"]
+            .iter()
+            .filter_map(|p| gitignore_preset(p).ok())
+            .chain(LEGACY_GITIGNORES.iter().copied())
+            .any(|content| content == existing);
+        if !generated || existing == gitignore_content {
+            return Ok(());
+        }
     }
 
+    tokio::fs::write(&gitignore_path, gitignore_content).await?;
     Ok(())
 }
 
+/// Connect to the local Docker daemon and check that it responds
+///
+/// Creating the client doesn't touch the socket, so the ping is what surfaces a daemon
+/// that is down or out of reach, as the first and clearest error of a build.
+pub(crate) async fn connect_docker(config: &RepxConfig) -> Result<Docker, RepxError> {
+    let runtime = config.container_runtime()?;
+    let endpoint = match &config.docker_host {
+        Some(host) => Some(DockerEndpoint::parse(host)?),
+        None => runtime.default_socket().map(|socket| DockerEndpoint::Socket(socket.display().to_string())),
+    };
+    let client = match &endpoint {
+        Some(DockerEndpoint::Socket(socket)) => Docker::connect_with_socket(socket, 120, bollard::API_DEFAULT_VERSION),
+        Some(DockerEndpoint::Http(address)) => Docker::connect_with_http(address, 120, bollard::API_DEFAULT_VERSION),
+        None => Docker::connect_with_local_defaults(),
+    };
+    let connected = match client {
+        Ok(docker) => docker.ping().await.map(|_| docker),
+        Err(e) => Err(e),
+    };
+    match connected {
+        Ok(docker) => Ok(docker),
+        Err(source) => {
+            let problem = DockerProblem::classify(&source, runtime_cli_installed(runtime).await);
+            Err(RepxError::DockerUnavailable { problem, source })
+        }
+    }
+}
+
+/// Whether the runtime's CLI is on the PATH, telling a stopped daemon from a missing install
+async fn runtime_cli_installed(runtime: ContainerRuntime) -> bool {
+    tokio::process::Command::new(runtime.cli())
+        .arg("--version")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .await
+        .is_ok_and(|status| status.success())
+}
+
 /// Build a Rust project with Nix inside Docker
+///
+/// Kept for existing callers; `RepxBuilder` offers the same with named setters.
 pub async fn build_with_nix(
     nix_image: &str,
     project_path: &str,
@@ -77,114 +228,761 @@ pub async fn build_with_nix(
     rust_channel: &str,
     rust_version: &str,
     nixpkgs_url: &str,
+) -> Result<BuildReport, RepxError> {
+    let mut builder = RepxBuilder::new(project_path)
+        .image(nix_image)
+        .targets(targets.iter().copied())
+        .rust_channel(rust_channel)
+        .rust_version(rust_version)
+        .nixpkgs_url(nixpkgs_url);
+    for package in extra_packages {
+        builder = builder.extra_package(package);
+    }
+    builder.build().await
+}
+
+/// Build a Rust project with Nix inside Docker using a full configuration
+///
+/// If `summary_json` is set, the report is written there whether or not the build succeeds.
+/// Progress is tracked in `.repx/status.json` for supervising processes.
+/// `processors` run on every artifact of a successful build, in order.
+pub async fn build_with_nix_using(
+    config: &RepxConfig,
+    targets: &[&str],
+    processors: &[Box<dyn ArtifactProcessor>],
+) -> Result<BuildReport, RepxError> {
+    run_build(config, targets, processors, None, None, false).await
+}
+
+/// Like `build_with_nix_using`, but returns the report even when the build fails
+///
+/// The report then has `success: false` and the `error`, as written to `summary_json`.
+pub async fn build_with_nix_report(
+    config: &RepxConfig,
+    targets: &[&str],
+    processors: &[Box<dyn ArtifactProcessor>],
+) -> (BuildReport, Result<(), RepxError>) {
+    run_build_reporting(config, targets, processors, None, None, false).await
+}
+
+/// Pull the image, lock the flake and fetch the inputs for `targets` into the Nix store, without building
+///
+/// Everything fetched into the container's store is lost with the container, so this uses
+/// `nix_store_cache`, or `DEFAULT_STORE_VOLUME` if none is set; later builds reuse it with
+/// the same `--nix-store-cache`.
+pub async fn prefetch_with_nix(config: &RepxConfig, targets: &[&str]) -> Result<BuildReport, RepxError> {
+    let config = RepxConfig {
+        nix_store_cache: Some(config.nix_store_cache.clone().unwrap_or_else(|| DEFAULT_STORE_VOLUME.to_string())),
+        image_output: None,
+        compare_with: None,
+        junit: None,
+        sarif: None,
+        ..config.clone()
+    };
+    run_build(&config, targets, &[], None, None, true).await
+}
+
+/// Report what building `targets` would take: the derivations Nix would build and the paths it would fetch
+///
+/// Runs `nix build --dry-run` for each target in the container; the results are in the report's `dry_run`.
+pub async fn dry_run_with_nix(config: &RepxConfig, targets: &[&str]) -> Result<BuildReport, RepxError> {
+    let config = RepxConfig { image_output: None, compare_with: None, junit: None, sarif: None, ..config.clone() };
+    run_build(&config, targets, &[], None, None, true).await
+}
+
+/// Run a build, with the Docker client to use (else the local daemon) and a sink for its progress
+///
+/// With `prefetch`, everything up to the build itself runs, then the inputs are fetched and
+/// each target is evaluated with `nix build --dry-run` instead.
+pub(crate) async fn run_build(
+    config: &RepxConfig,
+    targets: &[&str],
+    processors: &[Box<dyn ArtifactProcessor>],
+    docker: Option<Docker>,
+    progress_sink: Option<&(dyn Fn(BuildState) + Send + Sync)>,
+    prefetch: bool,
+) -> Result<BuildReport, RepxError> {
+    let (report, result) = run_build_reporting(config, targets, processors, docker, progress_sink, prefetch).await;
+    result?;
+    Ok(report)
+}
+
+/// `run_build`, returning the report along with the result so it isn't lost when the build fails
+async fn run_build_reporting(
+    config: &RepxConfig,
+    targets: &[&str],
+    processors: &[Box<dyn ArtifactProcessor>],
+    docker: Option<Docker>,
+    progress_sink: Option<&(dyn Fn(BuildState) + Send + Sync)>,
+    prefetch: bool,
+) -> (BuildReport, Result<(), RepxError>) {
+    let mut report = BuildReport::new("", PathBuf::new(), targets);
+    let result = run_build_recording(config, targets, processors, docker, progress_sink, prefetch, &mut report).await;
+    (report, result.map_err(RepxError::from))
+}
+
+/// The body of `run_build`, filling in `report` as it goes
+async fn run_build_recording(
+    config: &RepxConfig,
+    targets: &[&str],
+    processors: &[Box<dyn ArtifactProcessor>],
+    docker: Option<Docker>,
+    progress_sink: Option<&(dyn Fn(BuildState) + Send + Sync)>,
+    prefetch: bool,
+    report: &mut BuildReport,
+) -> Result<()> {
+    if let Some(sink) = progress_sink {
+        sink(BuildState::Building);
+    }
+    let status = if Path::new(&config.project).is_dir() {
+        Some(StatusFile::start(&Path::new(&config.project).join(".repx"), targets).await?)
+    } else {
+        None
+    };
+    let result = run_build_with_nix(config, targets, processors, docker, prefetch, report).await;
+    let result = match result {
+        Ok(()) if config.fail_on_warning && !report.warnings.is_empty() => {
+            Err(RepxError::WarningsAsErrors { warnings: report.warnings.clone() }.into())
+        }
+        other => other,
+    };
+
+    report.success = result.is_ok();
+    if let Err(e) = &result {
+        report.error = Some(format!("{:#}", e));
+        if let Some(RepxError::BuildFailed { targets } | RepxError::EmptyTargets { targets }) = e.downcast_ref::<RepxError>() {
+            report.failed_targets = targets.clone();
+        }
+    }
+    let mut manifests = Vec::new();
+    if !report.build_id.is_empty() {
+        // Kept next to the log for `repx diff-builds`
+        let build_dir = Path::new(&config.project).join(".repx");
+        let report_path = BuildReport::path_for(&build_dir, &report.build_id);
+        report.write_json(&report_path).await?;
+        manifests.push(report_path);
+    }
+    if let Some(summary_path) = &config.summary_json {
+        report.write_json(Path::new(summary_path)).await?;
+        println!("{}{}Wrote build summary to {}{}", BOLD, BLUE, summary_path, RESET);
+        manifests.push(PathBuf::from(summary_path));
+    }
+    if let Some(junit_path) = &config.junit {
+        let builds = match artifact_dirs(targets, config) {
+            Ok(dirs) => dirs.into_iter().map(|(name, _)| name).collect(),
+            Err(_) => report.targets.clone(),
+        };
+        junit::write_junit(report, &builds, Path::new(junit_path)).await?;
+        println!("{}{}Wrote JUnit report to {}{}", BOLD, BLUE, junit_path, RESET);
+    }
+    if let Some(sarif_path) = &config.sarif {
+        sarif::write_sarif(report, Path::new(sarif_path)).await?;
+        println!("{}{}Wrote SARIF report to {}{}", BOLD, BLUE, sarif_path, RESET);
+    }
+    // The reports list the artifacts with their checksums, so processors such as signing cover them too
+    let result = match result {
+        Ok(()) => manifests
+            .iter()
+            .try_for_each(|path| {
+                processors.iter().try_for_each(|p| {
+                    p.process_manifest(path)
+                        .with_context(|| format!("Artifact processor {} failed on {}", p.name(), path.display()))
+                })
+            }),
+        Err(e) => Err(e),
+    };
+    if let Some(status) = status {
+        status.finish(report).await?;
+    }
+    if let Some(sink) = progress_sink {
+        sink(if result.is_ok() { BuildState::Idle } else { BuildState::Failed });
+    }
+
+    result
+}
+
+/// Compare every recorded artifact with the reference set, failing if any file differs or is missing
+async fn compare_artifacts_with_reference(
+    reference_dir: &Path,
+    targets: &[&str],
+    config: &RepxConfig,
+    logger: &BuildLogger,
+    report: &mut BuildReport,
+) -> Result<()> {
+    println!("\n{}{}Comparing artifacts with {}{}", BOLD, CYAN, reference_dir.display(), RESET);
+    logger.log(&format!("Comparing artifacts with {}", reference_dir.display())).await?;
+
+    // The reference mirrors the artifacts directory, so strip that to find each target's counterpart
+    for (name, artifact_dir) in artifact_dirs(targets, config)? {
+        let relative_dir = Path::new(&artifact_dir).strip_prefix(config.artifacts_dir())?;
+        let artifacts = report.artifacts.get(&name).map(Vec::as_slice).unwrap_or_default();
+        report.reference.extend(compare_with_reference(&name, artifacts, &reference_dir.join(relative_dir))?);
+    }
+
+    let mut mismatched = Vec::new();
+    for comparison in &report.reference {
+        let file = format!("{}/{}", comparison.target, comparison.path);
+        let (color, status) = match comparison.status {
+            ReferenceStatus::Match => (GREEN, "match"),
+            ReferenceStatus::Mismatch => (RED, "MISMATCH"),
+            ReferenceStatus::MissingFromReference => (RED, "not in reference"),
+            ReferenceStatus::MissingFromBuild => (RED, "not built"),
+        };
+        println!("   - {}: {}{}{}", file, color, status, RESET);
+        logger.log(&format!("Reference comparison {}: {}", file, status)).await?;
+        if comparison.status != ReferenceStatus::Match {
+            mismatched.push(file);
+        }
+    }
+    if !mismatched.is_empty() {
+        return Err(RepxError::ReferenceMismatch { files: mismatched }.into());
+    }
+    println!("{}{}All {} artifact(s) match the reference{}", BOLD, GREEN, report.reference.len(), RESET);
+    Ok(())
+}
+
+/// Revision nixpkgs is locked to in a flake.lock, if it records one
+async fn locked_nixpkgs_rev(flake_lock_path: &Path) -> Option<String> {
+    let lock: serde_json::Value = serde_json::from_str(&tokio::fs::read_to_string(flake_lock_path).await.ok()?).ok()?;
+    lock["nodes"]["nixpkgs"]["locked"]["rev"].as_str().map(String::from)
+}
+
+async fn run_build_with_nix(
+    config: &RepxConfig,
+    targets: &[&str],
+    processors: &[Box<dyn ArtifactProcessor>],
+    docker: Option<Docker>,
+    prefetch: bool,
+    report: &mut BuildReport,
 ) -> Result<()> {
-    let docker = Docker::connect_with_local_defaults()?;
-    let abs_project_path = PathBuf::from(project_path).canonicalize()?;
+    let nix_image = config.image.as_str();
+    let rust_channel = config.rust_channel.as_str();
+    let rust_version = config.rust_version.as_str();
+    let nixpkgs_url = config.nixpkgs_url.as_str();
+
+    config.validate_target_dir()?;
+    config.validate_manifest_path()?;
+    config.validate_output_attr_template()?;
+    config.validate_build_command()?;
+    config.validate_layout()?;
+    config.validate_docker_host()?;
+    config.validate_meta()?;
+    let progress = config.progress_rate()?;
+    validate_nix_args(&config.nix_args)?;
+    runtime_host_config(&config.container_runtime_args)?;
+    if let Some(name) = &config.container_name {
+        validate_container_name(name)?;
+        if config.deterministic_name {
+            return Err(RepxError::ConfigInvalid("--container-name can't be combined with --deterministic-name".to_string()).into());
+        }
+    }
+    let rust_matrix = config.rust_matrix()?;
+    if !rust_matrix.is_empty() && config.image_output.is_some() {
+        return Err(RepxError::ConfigInvalid("--image-output can't be combined with --rust-versions".to_string()).into());
+    }
+    if let Some(reference_dir) = config.compare_with.as_deref().filter(|dir| !Path::new(dir).is_dir()) {
+        return Err(RepxError::ConfigInvalid(format!("Reference directory {} doesn't exist", reference_dir)).into());
+    }
+    let audit_deny = config.audit_deny.as_deref().map(str::parse::<Severity>).transpose()?;
+    if config.offline && (config.audit || audit_deny.is_some()) {
+        return Err(RepxError::ConfigInvalid("--audit fetches the advisory database, so it can't be combined with --offline".to_string()).into());
+    }
+    let owner = resolve_owner(config.user.as_deref())?;
+    if config.vendor && targets.contains(&"x86_64-pc-windows-msvc") {
+        return Err(RepxError::ConfigInvalid("--vendor isn't supported for x86_64-pc-windows-msvc, which fetches crates with cargo-xwin".to_string()).into());
+    }
+    if config.static_link {
+        let unsupported: Vec<&str> = targets.iter().copied().filter(|t| !supports_static_linking(t)).collect();
+        if !unsupported.is_empty() {
+            return Err(RepxError::ConfigInvalid(format!(
+                "Static linking isn't supported for {}; use a musl target or the x86_64-w64-mingw32-static target instead",
+                unsupported.join(", ")
+            )).into());
+        }
+    }
+    let docker = match docker {
+        Some(docker) => docker,
+        None => connect_docker(config).await?,
+    };
+    let abs_project_path = PathBuf::from(&config.project).canonicalize()?;
+
+    // A build from uncommitted changes can't be reproduced from any commit
+    let source_state = detect_source_state(&abs_project_path).await;
+    if let SourceState::Dirty { changed_files } = source_state {
+        if config.strict && !config.allow_dirty {
+            return Err(anyhow::anyhow!(
+                "Refusing to build: the project has {} uncommitted change(s). Commit them or pass --allow-dirty.",
+                changed_files
+            ));
+        }
+        if !config.allow_dirty {
+            println!("{}{}WARNING:{} The project has {} uncommitted change(s); this build isn't tied to a commit and can't be reproduced from version control.", BOLD, RED, RESET, changed_files);
+            println!("   Commit your changes, or pass --allow-dirty to silence this warning.");
+            report.warnings.push(format!("The project has {} uncommitted change(s)", changed_files));
+        }
+    }
+    // The project's .cargo/config.toml is part of the source the flake builds
+    for conflict in check_cargo_config(&abs_project_path).await {
+        println!("{}{}WARNING:{} {}", BOLD, YELLOW, RESET, conflict);
+        report.warnings.push(conflict);
+    }
     let metadata_dir = abs_project_path.join(".repx");
     if !metadata_dir.exists() {
         tokio::fs::create_dir_all(&metadata_dir).await?;
     }
+    // A build killed mid-write leaves its temp files behind; the real files are still whole
+    for stale in remove_stale_temp_files(&metadata_dir, STALE_TEMP_AGE).await? {
+        println!("{}{}Removed stale temp file {}{}", BOLD, CYAN, stale.display(), RESET);
+    }
+    // Running out of space mid-build leaves a half-populated store behind, so check up front
+    if config.min_free_space_gb > 0 {
+        check_host_space(&metadata_dir, config.min_free_space_gb)?;
+    }
 
     // Generate .gitignore for the .repx directory
-    generate_gitignore(&metadata_dir).await?;
+    generate_gitignore(&metadata_dir, &config.metadata_gitignore).await?;
 
     // Initialize logger
-    let logger = BuildLogger::new(&metadata_dir).await?;
+    let output_limit = OutputLimit::new(config.max_output_mb, config.max_output_lines);
+    let logger = BuildLogger::new_with_meta(&metadata_dir, &config.meta).await?.with_output_limit(output_limit);
     println!("{}{}Logging to {}{}", BOLD, BLUE, logger.log_file().display(), RESET);
+    report.build_id = logger.build_id().to_string();
+    report.log_file = logger.log_file().to_path_buf();
+    report.git_commit = source_state.revision();
+    report.meta = config.meta.clone();
+    report.source_date_epoch = match (config.no_source_date_epoch, config.source_date_epoch) {
+        (true, _) => None,
+        (false, Some(epoch)) => Some(epoch),
+        (false, None) => commit_time(&abs_project_path).await,
+    };
 
     // Log build configuration
-    let mut config = HashMap::new();
-    config.insert("Docker Image".to_string(), nix_image.to_string());
-    config.insert("Project Path".to_string(), abs_project_path.display().to_string());
-    config.insert("Targets".to_string(), targets.join(", "));
-    config.insert("Rust Channel".to_string(), rust_channel.to_string());
-    config.insert("Rust Version".to_string(), rust_version.to_string());
-    config.insert("nixpkgs URL".to_string(), nixpkgs_url.to_string());
-    config.insert("Build ID".to_string(), logger.build_id().to_string());
+    let mut build_config = BTreeMap::new();
+    build_config.insert("Docker Image".to_string(), nix_image.to_string());
+    build_config.insert("Project Path".to_string(), abs_project_path.display().to_string());
+    build_config.insert("Targets".to_string(), targets.join(", "));
+    build_config.insert("Rust Channel".to_string(), rust_channel.to_string());
+    build_config.insert("Rust Version".to_string(), rust_version.to_string());
+    if !rust_matrix.is_empty() {
+        build_config.insert("Rust Versions".to_string(), config.rust_versions.join(", "));
+    }
+    build_config.insert("nixpkgs URL".to_string(), nixpkgs_url.to_string());
+    build_config.insert("Cargo Profile".to_string(), config.profile.clone());
+    let lock_mode = if config.no_flake_lock { "skipped" } else if config.verify_lock { "verified" } else { "generated" };
+    build_config.insert("Flake Lock".to_string(), lock_mode.to_string());
+    build_config.insert("Build ID".to_string(), logger.build_id().to_string());
+    build_config.insert("Git Commit".to_string(), report.git_commit.clone().unwrap_or_else(|| "unknown".to_string()));
+    if let Some(epoch) = report.source_date_epoch {
+        build_config.insert("Source Date Epoch".to_string(), epoch.to_string());
+    }
 
-    logger.log_build_config(&config).await?;
+    logger.log_build_config(&build_config).await?;
+    logger.log_effective_config(config).await?;
+    report.config = build_config;
+    // Lets others rebuild with exactly these settings: `repx build -c .repx/effective-config.toml`
+    write_atomic(&metadata_dir.join(EFFECTIVE_CONFIG_FILE), config.effective_toml(targets)?).await?;
+
+    // Foreign-architecture builds need qemu registered on the Docker host
+    let missing_binfmt = check_binfmt_support(&docker, targets).await;
+    if !missing_binfmt.is_empty() {
+        logger.log(&format!("Warning: qemu-aarch64 binfmt handler not registered; needed by {}", missing_binfmt.join(", "))).await?;
+        report.warnings.push(format!("qemu-aarch64 isn't registered with binfmt_misc; needed by {}", missing_binfmt.join(", ")));
+    }
 
     // Always generate flake.nix and compare with existing one
     let flake_path = metadata_dir.join("flake.nix");
-    let temp_flake_path = metadata_dir.join("flake.nix.new");
     
+    let flake_started = Instant::now();
     logger.log("Generating flake.nix file").await?;
-    let generated_content = generate_flake_file(&temp_flake_path, &extra_packages, rust_channel, rust_version, nixpkgs_url).await?;
+    let generated_content = generate_flake::flake(config).await?;
     
     // Compare with existing flake.nix and warn if different
-    check_flake_changes(&temp_flake_path, &flake_path, &generated_content).await?;
+    report.flake_status = check_flake_changes(&flake_path, &generated_content, config.exact_flake_compare, config.show_diff, &mut report.warnings).await?;
     
     // If no existing flake.nix or it's different, use the generated one
     if !flake_path.exists() || tokio::fs::read_to_string(&flake_path).await?.replace("\r\n", "\n").replace("\r", "\n") != generated_content.replace("\r\n", "\n").replace("\r", "\n") {
-        tokio::fs::rename(&temp_flake_path, &flake_path).await?;
+        write_atomic(&flake_path, &generated_content).await?;
         logger.log(&format!("Updated flake.nix at {}", flake_path.display())).await?;
     } else {
-        // Remove temp file if not needed
-        let _ = tokio::fs::remove_file(&temp_flake_path).await;
         logger.log(&format!("Using existing flake.nix at {}", flake_path.display())).await?;
     }
+    report.record_phase("flake_generation", flake_started);
+
+    let pull_started = Instant::now();
+    pull_build_image(&docker, config).await?;
+    report.record_phase("image_pull", pull_started);
+
+    // Secrets are read before the container exists, so a bad token file can't leave one behind
+    let mut exec_settings = ExecSettings {
+        log: Some(logger.writer()),
+        output_limit,
+        max_log_bytes: config.max_log_size_mb.map(|mb| mb.saturating_mul(1024 * 1024)),
+        timeout: config.command_timeout_secs.map(Duration::from_secs),
+        progress,
+        // Keeps cargo inside the container away from the developer's own target dir
+        env: vec![format!("CARGO_TARGET_DIR=/app/{}", config.target_dir)],
+        ..ExecSettings::default()
+    };
+    if let Some(path) = &config.flake_token_file {
+        let secret = read_secret_file(path).await?;
+        exec_settings.secret_env.push(nix_access_tokens_env(&secret));
+        exec_settings.redact.extend(nix_access_token_values(&secret));
+    }
+    let secrets_env_path = metadata_dir.join(SECRETS_ENV_FILE);
+    if secrets_env_path.exists() {
+        let secrets = read_secrets_env(&secrets_env_path).await?;
+        logger.log(&format!("Injecting {} secret(s) from {}", secrets.len(), secrets_env_path.display())).await?;
+        exec_settings.secret_env.extend(secrets);
+    }
 
     // Set up the Docker container
     logger.log("Setting up Docker container").await?;
-    let container = setup_container(&docker, nix_image, &abs_project_path, &metadata_dir).await?;
-    logger.log(&format!("Created container with ID: {}", container.id)).await?;
-
-    // Configure git safe directory inside the container
-    // This is crucial to run before any nix commands that might access .git history for flake inputs
-    logger.log("Configuring git safe directory in container").await?;
-    let git_config_cmd = "git config --global --add safe.directory /app";
-    match execute_command(&docker, &container.id, git_config_cmd).await {
-        Ok(output) => {
-            logger.log_command(git_config_cmd, &output).await?;
-        }
-        Err(e) => {
+    let setup_started = Instant::now();
+    let container_name = match &config.container_name {
+        Some(name) => name.clone(),
+        None => container_name_for(&abs_project_path, targets, config.deterministic_name),
+    };
+    // Signals are handled from here until the container is gone and the images are loaded.
+    // Setup isn't interrupted, as an abandoned create could leave a container nobody removes;
+    // a signal that arrives meanwhile is honoured once it finishes.
+    let mut shutdown = ShutdownListener::install();
+    let announce = |signal: ShutdownSignal| {
+        println!("\n{}{}Received {}, stopping the build...{}", BOLD, YELLOW, signal, RESET);
+        signal
+    };
+    let container = setup_container(&docker, config, &abs_project_path, &metadata_dir, &container_name).await?;
+    report.record_phase("container_setup", setup_started);
+
+    // Run the in-container steps, making sure the container is cleaned up however they end
+    let in_container = async {
+        logger.log(&format!("Created container {} with ID: {}", container.name, container.id)).await?;
+        // The replay instructions name the container, so the trace script is only started now
+        if config.trace_commands {
+            let trace_script = metadata_dir.join("build-script.sh");
+            init_trace_script(&trace_script, &container.name).await?;
+            println!("{}{}Recording executed commands to {}{}", BOLD, BLUE, trace_script.display(), RESET);
+            logger.log(&format!("Recording executed commands to {}", trace_script.display())).await?;
+            exec_settings.trace_script = Some(trace_script);
+        }
+        // Parsing takes a moment, while a broken template would otherwise only fail after locking and fetching
+        if config.no_flake_check {
+            logger.log("Skipping the flake.nix syntax check (--no-flake-check)").await?;
+        } else {
+            check_flake_syntax(&docker, &container.id, &logger, &exec_settings, &generated_content).await?;
+        }
+        if config.min_free_space_gb > 0 && !check_store_space(&docker, &container.id, config.min_free_space_gb, &exec_settings).await? {
+            println!("{}{}WARNING:{} Couldn't determine the free space in the container's /nix", BOLD, YELLOW, RESET);
+            logger.log("Couldn't determine the free space in the container's /nix").await?;
+            report.warnings.push("Couldn't determine the free space in the container's /nix".to_string());
+        }
+        if config.nix_daemon {
+            if probe_nix_daemon(&docker, &container.id, &exec_settings).await {
+                logger.log("Using the Nix daemon (multi-user mode)").await?;
+                exec_settings.env.push("NIX_REMOTE=daemon".to_string());
+            } else {
+                println!("{}{}WARNING:{} The Nix daemon isn't available in {}; falling back to single-user mode", BOLD, YELLOW, RESET, nix_image);
+                logger.log("Nix daemon unavailable; falling back to single-user mode").await?;
+                report.warnings.push("The Nix daemon isn't available; fell back to single-user mode".to_string());
+            }
+        }
+        if config.readonly_source {
+            logger.log("Copying the read-only source into the container").await?;
+            execute_command(&docker, &container.id, &copy_source_command(config), &exec_settings).await?;
+        }
+
+        // Configure git safe directory inside the container
+        // This is crucial to run before any nix commands that might access .git history for flake inputs:
+        // the flake in /app/.repx is read through git when the project is a repository
+        let needs_git_config = uses_git_inputs(config) || abs_project_path.join(".git").exists();
+        if config.no_git_config {
+            logger.log("Skipping git safe.directory configuration (--no-git-config)").await?;
+        } else if !needs_git_config {
+            logger.log("Skipping git safe.directory configuration: neither the project nor any flake input uses git").await?;
+        } else if let Err(e) = async {
+            logger.log("Configuring git safe directory in container").await?;
+            let git_config_cmd = "git config --global --add safe.directory /app";
+            // Output is streamed to the build log by execute_command
+            execute_command(&docker, &container.id, git_config_cmd, &exec_settings).await
+        }
+        .await
+        {
             // Log the error but attempt to continue; some images might not have git or this might not be strictly necessary if not using git-based flake inputs directly from /app
             logger.log(&format!("Warning: Failed to set git safe.directory: {}. This might cause issues if your flake relies on git history from the source directory.", e)).await?;
             println!("{}{}Warning:{} Failed to set git safe.directory in container. Build might proceed if git history isn't needed for local flake inputs.", BOLD, YELLOW, RESET);
+            report.warnings.push("Failed to set git safe.directory in the container".to_string());
+        }
+
+        // Generate Cargo.lock if needed
+        let cargo_lock_path = abs_project_path.join("Cargo.lock");
+        let cargo_lock_exists = tokio::fs::metadata(&cargo_lock_path).await.is_ok();
+        if !cargo_lock_exists && config.locked {
+            return Err(RepxError::ConfigInvalid(
+                "--locked requires a Cargo.lock; run cargo generate-lockfile and commit the result".to_string()
+            ).into());
+        }
+        if config.locked {
+            // Resolving with --locked fails if Cargo.toml needs a different lock
+            logger.log("Checking that Cargo.lock is up to date (--locked)").await?;
+            let offline_option = if config.offline { " --offline" } else { "" };
+            let check_cmd = format!("cargo metadata --locked{} --format-version 1 > /dev/null", offline_option);
+            if let Err(e) = execute_command(&docker, &container.id, &check_cmd, &exec_settings).await {
+                logger.log(&format!("Cargo.lock check failed: {}", e)).await?;
+                return Err(RepxError::ConfigInvalid(
+                    "Cargo.lock is out of date with Cargo.toml; update it with cargo update and commit the result, or drop --locked".to_string()
+                ).into());
+            }
+        }
+        if !cargo_lock_exists {
+            println!("{}{}Cargo.lock not found, generating it...{}", BOLD, YELLOW, RESET);
+            logger.log("Cargo.lock not found, generating it...").await?;
+            if config.cargo_hash.is_some() {
+                // The hash was computed for some other lock file, so it almost certainly won't match
+                println!("{}{}Warning:{} --cargo-hash is set but Cargo.lock is being regenerated; the hash will likely mismatch. Commit Cargo.lock or drop the override.", BOLD, YELLOW, RESET);
+                logger.log("Warning: cargo hash override set while regenerating Cargo.lock").await?;
+            }
+            let cmd = "cargo generate-lockfile";
+            execute_command(&docker, &container.id, cmd, &exec_settings).await?;
+
+            if config.readonly_source {
+                if config.write_cargo_lock {
+                    // The host writes the lock, so the container never touches the source tree
+                    let mut cp_cmd = "cp Cargo.lock .repx/Cargo.lock.generated".to_string();
+                    if let Some((uid, gid)) = owner {
+                        cp_cmd.push_str(&format!(" && chown {}:{} .repx/Cargo.lock.generated", uid, gid));
+                    }
+                    execute_command(&docker, &container.id, &cp_cmd, &exec_settings).await?;
+                    tokio::fs::rename(metadata_dir.join("Cargo.lock.generated"), &cargo_lock_path).await?;
+                    println!("{}{}Wrote the generated Cargo.lock to the project{}", BOLD, GREEN, RESET);
+                    logger.log("Wrote the generated Cargo.lock back to the project").await?;
+                } else {
+                    println!("{}{}Note:{} The generated Cargo.lock was only used inside the container. Pass --write-cargo-lock to keep it.", BOLD, YELLOW, RESET);
+                    logger.log("Generated Cargo.lock kept inside the container (read-only source)").await?;
+                }
+            }
         }
-    }
 
-    // Generate Cargo.lock if needed
-    let cargo_lock_path = abs_project_path.join("Cargo.lock");
-    let cargo_lock_exists = tokio::fs::metadata(&cargo_lock_path).await.is_ok();
-    if !cargo_lock_exists {
-        println!("{}{}Cargo.lock not found, generating it...{}", BOLD, YELLOW, RESET);
-        logger.log("Cargo.lock not found, generating it...").await?;
-        let cmd = "cargo generate-lockfile";
-        let output = execute_command(&docker, &container.id, cmd).await?;
-        logger.log_command(cmd, &output).await?;
+        if config.no_flake_lock {
+            if config.verify_lock {
+                return Err(RepxError::ConfigInvalid("--verify-lock can't be combined with --no-flake-lock, which doesn't use a flake.lock".to_string()).into());
+            }
+            // Inputs were validated as pinned when the flake was generated
+            report.lock_status = DriftStatus::Skipped;
+            println!("{}{}Skipping flake.lock generation; relying on pinned input URLs{}", BOLD, CYAN, RESET);
+            logger.log("Skipping flake.lock generation; relying on pinned input URLs").await?;
+        } else {
+            // Generate flake.lock and compare with existing one
+            let flake_lock_path = metadata_dir.join("flake.lock");
+            // A unique name, so concurrent builds don't share a backup and a crash leaves only a stale temp file
+            let backup_lock_path = temp_path_for(&flake_lock_path);
+
+            logger.log("Generating flake.lock file").await?;
+
+            if config.verify_lock && !flake_lock_path.exists() {
+                return Err(anyhow::anyhow!(
+                    "--verify-lock requires a committed flake.lock at {}",
+                    flake_lock_path.display()
+                ));
+            }
+
+            // Back up the existing lock for comparison if it exists
+            if flake_lock_path.exists() {
+                write_atomic(&backup_lock_path, tokio::fs::read(&flake_lock_path).await?).await?;
+            }
+
+            let lock_started = Instant::now();
+            generate_flake_lock(&docker, &container.id, &exec_settings, config.offline).await?;
+            report.record_phase("lock_generation", lock_started);
+
+            // Check if the lock file changed and warn if so
+            if flake_lock_path.exists() && backup_lock_path.exists() {
+                report.lock_status = check_lock_changes(&backup_lock_path, &flake_lock_path, config.show_diff, &mut report.warnings).await?;
+
+                if config.verify_lock && report.lock_status == DriftStatus::Drifted {
+                    // Put the committed lock back; it stays authoritative
+                    write_atomic(&flake_lock_path, tokio::fs::read(&backup_lock_path).await?).await?;
+                    let _ = tokio::fs::remove_file(&backup_lock_path).await;
+                    logger.log("flake.lock verification failed: the committed lock is stale").await?;
+                    return Err(anyhow::anyhow!(
+                        "{} is out of date with the flake inputs. Run a build without --verify-lock to update it, then commit the result.",
+                        flake_lock_path.display()
+                    ));
+                }
+
+                // Clean up the backup
+                let _ = tokio::fs::remove_file(&backup_lock_path).await;
+            }
+
+            println!("{}{}Generated/updated flake.lock{}", BOLD, GREEN, RESET);
+            report.nixpkgs_rev = locked_nixpkgs_rev(&flake_lock_path).await;
+            if let Some(rev) = &report.nixpkgs_rev {
+                println!("{}{}nixpkgs revision:{} {}", BOLD, CYAN, RESET, rev);
+                logger.log(&format!("nixpkgs revision: {}", rev)).await?;
+                report.config.insert("nixpkgs Revision".to_string(), rev.clone());
+            }
+        }
+
+        if config.vendor {
+            let vendor_started = Instant::now();
+            vendor_dependencies(&docker, &container.id, &logger, &exec_settings, config.offline).await?;
+            report.record_phase("vendor", vendor_started);
+        }
+
+        // Audit the locked dependencies before spending time on the build
+        if config.audit || audit_deny.is_some() {
+            logger.log("Auditing Cargo.lock with cargo-audit").await?;
+            let audit_started = Instant::now();
+            let audit = run_audit(&docker, &container.id, &exec_settings, audit_deny).await?;
+            report.record_phase("audit", audit_started);
+            logger.log(&format!("cargo-audit found {} vulnerabilit(y/ies)", audit.findings.len())).await?;
+            report.warnings.extend(audit.findings.iter().map(|f| format!("{} {}: {} ({})", f.package, f.version, f.title, f.id)));
+            let denied: Vec<String> = audit.denied().iter().map(|f| format!("{} ({} {})", f.id, f.package, f.version)).collect();
+            report.audit = Some(audit);
+            if !denied.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Refusing to build: cargo-audit found vulnerabilities at or above the --audit-deny threshold: {}",
+                    denied.join(", ")
+                ));
+            }
+        }
+
+        if prefetch {
+            return prefetch_nix_build(&docker, &container.id, targets, &logger, &exec_settings, config, report).await;
+        }
+
+        // Execute the Nix build
+        if config.builds_targets() {
+            logger.log(&format!("Starting build for targets: {}", targets.join(", "))).await?;
+            execute_nix_build(&docker, &container.id, targets, &logger, &exec_settings, config, report).await?;
+        }
+        if config.runs_flake_check() {
+            execute_flake_check(&docker, &container.id, &logger, &exec_settings, config, report).await?;
+        }
+        Ok(())
+    };
+    // A signal abandons the step in progress and takes the same cleanup path as a failed build
+    let mut interrupted = shutdown.pending().map(announce);
+    let build_result: Result<()> = match interrupted {
+        Some(signal) => {
+            drop(in_container);
+            Err(RepxError::Interrupted { signal }.into())
+        }
+        None => tokio::select! {
+            result = in_container => result,
+            signal = shutdown.recv() => {
+                interrupted = Some(announce(signal));
+                Err(RepxError::Interrupted { signal }.into())
+            }
+        },
+    };
+
+    // Hand the files written into the project back to the requested user, whether or not the build succeeded;
+    // a signal only cuts this short, as the container is removed regardless
+    if let (Some((uid, gid)), None) = (owner, interrupted) {
+        let chown = async {
+            logger.log(&format!("Changing owner of files written into the project to {}:{}", uid, gid)).await?;
+            execute_command(&docker, &container.id, &chown_command(config, (uid, gid)), &exec_settings).await
+        };
+        let chown_result = tokio::select! {
+            result = chown => Some(result),
+            signal = shutdown.recv() => {
+                interrupted = Some(announce(signal));
+                None
+            }
+        };
+        if let Some(Err(e)) = chown_result {
+            println!("{}{}WARNING:{} Could not change the owner of the build output to {}:{}: {}", BOLD, YELLOW, RESET, uid, gid, e);
+            report.warnings.push(format!("Could not change the owner of the build output to {}:{}", uid, gid));
+        }
     }
 
-    // Always generate flake.lock and compare with existing one
-    let flake_lock_path = metadata_dir.join("flake.lock");
-    let temp_lock_path = metadata_dir.join("flake.lock.new");
-    
-    logger.log("Generating flake.lock file").await?;
-    
-    // Copy existing lock to temp location for comparison if it exists
-    if flake_lock_path.exists() {
-        tokio::fs::copy(&flake_lock_path, &temp_lock_path).await?;
+    // Clean up, exactly once and without interruption
+    let logged = logger.log("Cleaning up container").await;
+    let cleaned = cleanup_container(&docker, &container.id).await;
+    if let Some(signal) = interrupted {
+        logger.log(&format!("Received {}, stopping the build", signal)).await?;
     }
-    
-    let output = generate_flake_lock(&docker, &container.id).await?;
-    logger.log_command("nix flake lock", &output).await?;
-    
-    // Check if the lock file changed and warn if so
-    if flake_lock_path.exists() && temp_lock_path.exists() {
-        check_lock_changes(&temp_lock_path, &flake_lock_path).await?;
-        // Clean up temp lock file
-        let _ = tokio::fs::remove_file(&temp_lock_path).await;
+    logged?;
+    cleaned?;
+    if interrupted.is_none() {
+        interrupted = shutdown.pending().map(announce);
     }
-    
-    println!("{}{}Generated/updated flake.lock{}", BOLD, GREEN, RESET);
 
-    // Execute the Nix build
-    logger.log(&format!("Starting build for targets: {}", targets.join(", "))).await?;
-    let build_result = execute_nix_build(&docker, &container.id, targets, &logger).await;
+    // Post-process the artifacts and record them, so later builds can be compared with `repx diff-builds`,
+    // then load the built images into the local daemon so they can be run right away
+    let finish = async {
+        let builds = artifact_dirs(targets, config)?;
+        // Executables of earlier builds must not linger among this build's
+        let flat_dir = abs_project_path.join(config.artifacts_dir()).join(FLAT_DIR);
+        if config.layout == "flat" && flat_dir.exists() {
+            tokio::fs::remove_dir_all(&flat_dir).await?;
+        }
+        for (name, artifact_dir) in &builds {
+            let artifact_dir = abs_project_path.join(artifact_dir);
+            if artifact_dir.is_dir() {
+                for processor in processors {
+                    logger.log(&format!("Running artifact processor {} for {}", processor.name(), name)).await?;
+                }
+                let artifacts = process_artifacts(&artifact_dir, name, processors)?;
+                if config.layout == "flat" {
+                    for path in flatten_artifacts(&artifact_dir, name, &artifacts, &flat_dir)? {
+                        logger.log(&format!("Copied {} executable to {}", name, path.display())).await?;
+                    }
+                }
+                report.artifacts.insert(name.clone(), artifacts);
+            }
+        }
+        // A flake output that builds but is empty usually means a misconfigured package
+        let names: Vec<String> = builds.into_iter().map(|(name, _)| name).collect();
+        let empty = if config.builds_targets() { report.builds_without_artifacts(&names) } else { Vec::new() };
+        if !empty.is_empty() {
+            if config.strict_targets {
+                logger.log(&format!("Target(s) produced no artifacts: {}", empty.join(", "))).await?;
+                return Err(RepxError::EmptyTargets { targets: empty }.into());
+            }
+            let warning = format!("Target(s) built but produced no artifacts: {}", empty.join(", "));
+            println!("{}{}WARNING:{} {}", BOLD, YELLOW, RESET, warning);
+            logger.log(&warning).await?;
+            report.warnings.push(warning);
+        }
+        if let Some(reference_dir) = &config.compare_with {
+            compare_artifacts_with_reference(Path::new(reference_dir), targets, config, &logger, report).await?;
+        }
 
-    // Clean up
-    logger.log("Cleaning up container").await?;
-    cleanup_container(&docker, &container.id).await?;
+        if let Some(image_ref) = &config.image_output {
+            for target in targets.iter().filter(|t| supports_image(t)) {
+                let tarball = abs_project_path
+                    .join(config.artifacts_dir())
+                    .join(target)
+                    .join(IMAGE_FILE_NAME);
+                load_image(&docker, &tarball).await?;
+                println!("{}{}Loaded image {} for {}{}", BOLD, GREEN, image_ref, target, RESET);
+                logger.log(&format!("Loaded image {} for {} from {}", image_ref, target, tarball.display())).await?;
+                report.images.push(tarball);
+            }
+        }
+        Ok(())
+    };
+    let build_result = match (build_result, interrupted) {
+        (_, Some(signal)) => Err(RepxError::Interrupted { signal }.into()),
+        (Ok(()), None) if prefetch => Ok(()),
+        (Ok(()), None) => tokio::select! {
+            result = finish => result,
+            signal = shutdown.recv() => {
+                announce(signal);
+                logger.log(&format!("Received {}, stopping the build", signal)).await?;
+                Err(RepxError::Interrupted { signal }.into())
+            }
+        },
+        (Err(e), None) => Err(e),
+    };
+
+    if let Err(e) = &build_result {
+        if config.explain_failure {
+            if let Some(command_error) = e.downcast_ref::<CommandError>() {
+                print_failure_explanation(&command_error.output.labeled());
+            }
+        }
+    }
 
     // Log build completion
     let success = build_result.is_ok();
@@ -193,4 +991,52 @@ pub async fn build_with_nix(
 
     // Return the build result
     build_result
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_locked_nixpkgs_rev() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = dir.path().join("flake.lock");
+        tokio::fs::write(&lock, r#"{"nodes": {"nixpkgs": {"locked": {"owner": "NixOS", "rev": "abc123", "type": "github"}}, "root": {}}}"#).await.unwrap();
+        assert_eq!(locked_nixpkgs_rev(&lock).await.as_deref(), Some("abc123"));
+        assert_eq!(locked_nixpkgs_rev(&dir.path().join("missing.lock")).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_build_report_kept_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = RepxConfig {
+            project: dir.path().display().to_string(),
+            layout: "sideways".to_string(),
+            ..RepxConfig::default()
+        };
+        let (report, result) = build_with_nix_report(&config, &["x86_64-linux-gnu"], &[]).await;
+        assert!(matches!(result, Err(RepxError::ConfigInvalid(_))));
+        assert!(!report.success);
+        assert_eq!(report.targets, ["x86_64-linux-gnu"]);
+        assert!(report.error.unwrap().contains("Unknown layout"));
+    }
+
+    #[tokio::test]
+    async fn test_gitignore_presets_respect_custom_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let gitignore = dir.path().join(".gitignore");
+
+        generate_gitignore(dir.path(), "ignore-logs-only").await.unwrap();
+        assert!(tokio::fs::read_to_string(&gitignore).await.unwrap().contains("logs/"));
+
+        // Switching presets replaces a generated file
+        generate_gitignore(dir.path(), "ignore-all").await.unwrap();
+        assert!(tokio::fs::read_to_string(&gitignore).await.unwrap().ends_with("*\n"));
+
+        // A customized file is kept
+        tokio::fs::write(&gitignore, "logs/\nflake.lock\n").await.unwrap();
+        generate_gitignore(dir.path(), "ignore-nothing").await.unwrap();
+        assert_eq!(tokio::fs::read_to_string(&gitignore).await.unwrap(), "logs/\nflake.lock\n");
+
+        assert!(generate_gitignore(dir.path(), "ignore-some").await.is_err());
+    }
+}
@@ -1,34 +1,59 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::collections::BTreeMap;
 use tokio::fs::{File, OpenOptions};
 use tokio::io::AsyncWriteExt;
 use std::sync::{Arc, Mutex};
 use uuid::Uuid;
+use crate::config::RepxConfig;
+use crate::error::RepxError;
+use crate::execute_command::CommandOutput;
+use crate::output_buffer::{OutputBuffer, OutputLimit};
 
 /// Represents a build logger
+///
+/// All entries, including the command output streamed through `writer`, go through one
+/// append-mode handle behind an async mutex, so entries logged concurrently, e.g. by
+/// parallel artifact copies, are written whole and in order.
 pub struct BuildLogger {
     log_file: PathBuf,
     build_id: String,
-    log_buffer: Arc<Mutex<String>>,
+    file: Arc<tokio::sync::Mutex<File>>,
+    /// Most recent entries, bounded so verbose builds can't exhaust memory
+    log_buffer: Arc<Mutex<OutputBuffer>>,
+    /// Held while the build runs so other builds' log rotation leaves this log alone
+    _lock: Option<LogLock>,
 }
 
 impl BuildLogger {
     /// Create a new build logger with a unique ID
     pub async fn new(build_dir: &Path) -> Result<Self> {
+        Self::new_with_meta(build_dir, &BTreeMap::new()).await
+    }
+
+    /// Create a new build logger, recording `meta` in the log header as `Meta: key=value` lines
+    pub async fn new_with_meta(build_dir: &Path, meta: &BTreeMap<String, String>) -> Result<Self> {
         // Generate a unique build ID using UUID v4
         let build_id = Uuid::new_v4().to_string();
         
         // Create logs directory if it doesn't exist
         let logs_dir = build_dir.join("logs");
         tokio::fs::create_dir_all(&logs_dir).await?;
+
+        // Logs of earlier builds are only read occasionally, so keep them compressed
+        compress_old_logs(&logs_dir).await?;
         
         // Create log file path with build ID
         let log_file = logs_dir.join(format!("build-{}.log", build_id));
         
-        // Create and initialize the log file
-        let mut file = File::create(&log_file).await?;
+        // Create and initialize the log file; commands stream their output into it too, so append
+        let mut file = OpenOptions::new().create(true).append(true).open(&log_file).await?;
+        let lock = try_lock_log(&log_file)?;
         
         // Write initial log header
         let timestamp = SystemTime::now()
@@ -36,22 +61,35 @@ impl BuildLogger {
             .unwrap_or_default()
             .as_secs();
         
+        let meta_lines: String = meta.iter().map(|(key, value)| format!("Meta: {}={}\n", key, value)).collect();
         let header = format!(
             "=== repx Log ===\n\
              Build ID: {}\n\
              Timestamp: {}\n\
+             {}\
              =====================\n\n",
-            build_id, timestamp
+            build_id, timestamp, meta_lines
         );
         
         file.write_all(header.as_bytes()).await?;
+        file.flush().await?;
         
         Ok(Self {
             log_file,
             build_id,
-            log_buffer: Arc::new(Mutex::new(String::new())),
+            file: Arc::new(tokio::sync::Mutex::new(file)),
+            log_buffer: Arc::new(Mutex::new(OutputBuffer::new(OutputLimit::default()))),
+            _lock: lock,
         })
     }
+
+    /// Bound the in-memory copy of the log; the log file always gets every entry
+    pub fn with_output_limit(self, limit: OutputLimit) -> Self {
+        Self {
+            log_buffer: Arc::new(Mutex::new(OutputBuffer::new(limit))),
+            ..self
+        }
+    }
     
     /// Get the build ID
     pub fn build_id(&self) -> &str {
@@ -63,6 +101,16 @@ impl BuildLogger {
         &self.log_file
     }
     
+    /// A handle for streaming command output into the log through the same locked file
+    pub fn writer(&self) -> LogWriter {
+        LogWriter { file: self.file.clone() }
+    }
+
+    /// Most recent log entries kept in memory
+    pub fn recent_entries(&self) -> String {
+        self.log_buffer.lock().unwrap().contents()
+    }
+
     /// Log a message with timestamp
     pub async fn log(&self, message: &str) -> Result<()> {
         let timestamp = SystemTime::now()
@@ -72,37 +120,31 @@ impl BuildLogger {
         
         let log_entry = format!("[{}] {}\n", timestamp, message);
         
-        // Add to buffer
-        {
-            let mut buffer = self.log_buffer.lock().unwrap();
-            buffer.push_str(&log_entry);
-        }
-        
-        // Write to file
-        let mut file = OpenOptions::new()
-            .append(true)
-            .open(&self.log_file)
-            .await?;
+        // Held across the buffer and the file so both see entries in the same order
+        let mut file = self.file.lock().await;
+        self.log_buffer.lock().unwrap().push_str(&log_entry);
         
+        // Flushed before the lock is released, so command output streamed into the log can't overtake it
         file.write_all(log_entry.as_bytes()).await?;
+        file.flush().await?;
         
         Ok(())
     }
     
-    /// Log a command execution with its output
-    pub async fn log_command(&self, command: &str, output: &str) -> Result<()> {
+    /// Log a command execution with its output, labeling stdout and stderr
+    pub async fn log_command(&self, command: &str, output: &CommandOutput) -> Result<()> {
         let log_entry = format!(
             "Command: {}\nOutput:\n{}\n{}\n",
             command,
-            output,
+            output.labeled(),
             "-".repeat(80)
         );
         
         self.log(&log_entry).await
     }
     
-    /// Log build configuration
-    pub async fn log_build_config(&self, config: &HashMap<String, String>) -> Result<()> {
+    /// Log build configuration, sorted by key so logs of different builds can be diffed
+    pub async fn log_build_config(&self, config: &BTreeMap<String, String>) -> Result<()> {
         let mut config_str = String::from("Build Configuration:\n");
         
         for (key, value) in config {
@@ -112,6 +154,14 @@ impl BuildLogger {
         self.log(&config_str).await
     }
     
+    /// Log every setting the build runs with, after merging defaults, repx.toml and the command line
+    ///
+    /// Written as TOML in the field order of `RepxConfig`, so it can be copied into a repx.toml.
+    pub async fn log_effective_config(&self, config: &RepxConfig) -> Result<()> {
+        let toml = toml::to_string_pretty(config)?;
+        self.log(&format!("Effective Configuration:\n{}", toml)).await
+    }
+
     /// Log build completion
     pub async fn log_build_completion(&self, success: bool) -> Result<()> {
         let status = if success { "SUCCESS" } else { "FAILURE" };
@@ -126,21 +176,297 @@ impl BuildLogger {
     }
     
     /// Flush remaining logs to disk
+    ///
+    /// Entries are written as they are logged, so this only syncs the file.
     pub async fn flush(&self) -> Result<()> {
-        let buffer = {
-            let buffer = self.log_buffer.lock().unwrap();
-            buffer.clone()
+        let mut file = self.file.lock().await;
+        file.flush().await?;
+        file.sync_all().await?;
+        Ok(())
+    }
+}
+
+/// Appends to a build log through its `BuildLogger`'s locked handle
+///
+/// Used for command output, which is written as it arrives rather than as one entry.
+#[derive(Clone)]
+pub struct LogWriter {
+    file: Arc<tokio::sync::Mutex<File>>,
+}
+
+impl std::fmt::Debug for LogWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogWriter").finish_non_exhaustive()
+    }
+}
+
+impl LogWriter {
+    /// Append `text` in one write under the lock, so concurrent writers can't split it
+    pub async fn append(&self, text: &str) -> Result<()> {
+        let mut file = self.file.lock().await;
+        file.write_all(text.as_bytes()).await?;
+        file.flush().await?;
+        Ok(())
+    }
+
+    /// Current size of the log file
+    pub async fn size(&self) -> Result<u64> {
+        Ok(self.file.lock().await.metadata().await?.len())
+    }
+}
+
+/// Logs modified this recently may belong to a build that is still starting up
+const ROTATION_MIN_AGE: Duration = Duration::from_secs(60);
+
+#[cfg(unix)]
+type LogLock = nix::fcntl::Flock<std::fs::File>;
+#[cfg(not(unix))]
+type LogLock = ();
+
+/// Take an exclusive lock on a log, or `None` if a running build holds it
+#[cfg(unix)]
+fn try_lock_log(path: &Path) -> std::io::Result<Option<LogLock>> {
+    let file = std::fs::File::open(path)?;
+    match nix::fcntl::Flock::lock(file, nix::fcntl::FlockArg::LockExclusiveNonblock) {
+        Ok(lock) => Ok(Some(lock)),
+        Err((_, nix::errno::Errno::EWOULDBLOCK)) => Ok(None),
+        Err((_, errno)) => Err(errno.into()),
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock_log(_path: &Path) -> std::io::Result<Option<LogLock>> {
+    Ok(Some(()))
+}
+
+/// Gzip the uncompressed logs of finished builds in `logs_dir` to `build-<id>.log.gz`
+///
+/// Logs still locked by a running build, or written to within `ROTATION_MIN_AGE`, are
+/// left as they are. Another build may rotate the same logs concurrently, so a log that
+/// disappears underneath us counts as already rotated.
+async fn compress_old_logs(logs_dir: &Path) -> Result<()> {
+    let mut entries = tokio::fs::read_dir(logs_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let is_plain_log = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("build-") && name.ends_with(".log"));
+        if !is_plain_log {
+            continue;
+        }
+        match compress_log(&path).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// Gzip one build log unless it is still in use
+async fn compress_log(path: &Path) -> std::io::Result<()> {
+    let modified = tokio::fs::metadata(path).await?.modified()?;
+    let recent = SystemTime::now()
+        .duration_since(modified)
+        .map_or(true, |age| age < ROTATION_MIN_AGE);
+    if recent {
+        return Ok(());
+    }
+    // Keep the lock until the plain log is gone, so concurrent rotations skip it
+    let Some(_lock) = try_lock_log(path)? else {
+        return Ok(());
+    };
+    let contents = tokio::fs::read(path).await?;
+    let compressed = tokio::task::spawn_blocking(move || -> std::io::Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&contents)?;
+        encoder.finish()
+    })
+    .await
+    .map_err(std::io::Error::other)??;
+    let compressed_path = path.with_extension("log.gz");
+    tokio::fs::write(&compressed_path, compressed).await?;
+    // Keep the build's time so logs still sort by when they were written
+    std::fs::File::options().write(true).open(&compressed_path)?.set_modified(modified)?;
+    tokio::fs::remove_file(path).await
+}
+
+/// Build IDs of the logs in `build_dir`, most recent first
+pub async fn list_build_logs(build_dir: &Path) -> Result<Vec<(String, PathBuf)>, RepxError> {
+    Ok(build_logs(build_dir).await?)
+}
+
+async fn build_logs(build_dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let logs_dir = build_dir.join("logs");
+    if !logs_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut logs = Vec::new();
+    let mut entries = tokio::fs::read_dir(&logs_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
         };
-        
-        if !buffer.is_empty() {
-            let mut file = OpenOptions::new()
-                .append(true)
-                .open(&self.log_file)
-                .await?;
-            
-            file.write_all(buffer.as_bytes()).await?;
+        let build_id = name
+            .strip_prefix("build-")
+            .and_then(|rest| rest.strip_suffix(".log.gz").or_else(|| rest.strip_suffix(".log")));
+        if let Some(build_id) = build_id {
+            let modified = entry.metadata().await?.modified()?;
+            logs.push((modified, build_id.to_string(), path));
         }
-        
-        Ok(())
     }
-} 
\ No newline at end of file
+    logs.sort_by_key(|(modified, ..)| std::cmp::Reverse(*modified));
+    Ok(logs.into_iter().map(|(_, build_id, path)| (build_id, path)).collect())
+}
+
+/// Read a build log, decompressing it if it was rotated; the most recent build if no ID is given
+pub async fn read_build_log(build_dir: &Path, build_id: Option<&str>) -> Result<String, RepxError> {
+    Ok(read_log(build_dir, build_id).await?)
+}
+
+async fn read_log(build_dir: &Path, build_id: Option<&str>) -> Result<String> {
+    let logs = build_logs(build_dir).await?;
+    let (_, path) = match build_id {
+        Some(id) => logs
+            .into_iter()
+            .find(|(log_id, _)| log_id == id)
+            .ok_or_else(|| anyhow!("No build log with ID {} in {}", id, build_dir.join("logs").display()))?,
+        None => logs
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("No build logs in {}", build_dir.join("logs").display()))?,
+    };
+    let data = tokio::fs::read(&path).await?;
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        let mut contents = String::new();
+        GzDecoder::new(data.as_slice()).read_to_string(&mut contents)?;
+        Ok(contents)
+    } else {
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+}
+
+/// The last `lines` lines of a log, and how many lines were left out before them
+pub fn log_tail(log: &str, lines: usize) -> (usize, &str) {
+    let total = log.lines().count();
+    if total <= lines {
+        return (0, log);
+    }
+    let skip = total - lines;
+    let start = log.split_inclusive('\n').take(skip).map(str::len).sum();
+    (skip, &log[start..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execute_command::LogStream;
+
+    #[tokio::test]
+    async fn test_old_logs_are_compressed_and_readable() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = BuildLogger::new(dir.path()).await.unwrap();
+        first.log("first build").await.unwrap();
+        let first_id = first.build_id().to_string();
+        let first_log = first.log_file().to_path_buf();
+        drop(first);
+        let finished_at = SystemTime::now() - ROTATION_MIN_AGE * 2;
+        std::fs::File::options().write(true).open(&first_log).unwrap().set_modified(finished_at).unwrap();
+
+        let meta = BTreeMap::from([("ci_run".to_string(), "42".to_string())]);
+        let second = BuildLogger::new_with_meta(dir.path(), &meta).await.unwrap();
+        assert!(dir.path().join(format!("logs/build-{}.log.gz", first_id)).exists());
+        assert!(std::fs::read_to_string(second.log_file()).unwrap().contains("\nMeta: ci_run=42\n====="));
+
+        let contents = read_build_log(dir.path(), Some(&first_id)).await.unwrap();
+        assert!(contents.contains("first build"));
+        assert!(read_build_log(dir.path(), Some("missing")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_logs_of_running_builds_are_not_rotated() {
+        let dir = tempfile::tempdir().unwrap();
+        let running = BuildLogger::new(dir.path()).await.unwrap();
+        let backdated = SystemTime::now() - ROTATION_MIN_AGE * 2;
+        std::fs::File::options().write(true).open(running.log_file()).unwrap().set_modified(backdated).unwrap();
+
+        let recent = BuildLogger::new(dir.path()).await.unwrap();
+        drop(recent);
+        BuildLogger::new(dir.path()).await.unwrap();
+        assert!(running.log_file().exists());
+        assert_eq!(list_build_logs(dir.path()).await.unwrap().len(), 3);
+        assert!(!dir.path().join("logs").read_dir().unwrap().any(|entry| {
+            entry.unwrap().path().extension().is_some_and(|ext| ext == "gz")
+        }));
+
+        // A log another build already rotated surfaces as NotFound, which rotation skips
+        let gone = compress_log(&dir.path().join("logs/build-gone.log")).await.unwrap_err();
+        assert_eq!(gone.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_log_entries_are_whole_and_ordered() {
+        let dir = tempfile::tempdir().unwrap();
+        let logger = Arc::new(BuildLogger::new(dir.path()).await.unwrap());
+        let padding = "x".repeat(2000);
+        let mut tasks: Vec<_> = (0..16)
+            .map(|task| {
+                let (logger, padding) = (logger.clone(), padding.clone());
+                tokio::spawn(async move {
+                    for entry in 0..50 {
+                        logger.log(&format!("task {} entry {} {}", task, entry, padding)).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        // Commands streaming their output at the same time, in chunks that split lines
+        tasks.extend((0..8).map(|command| {
+            let (writer, padding) = (logger.writer(), padding.clone());
+            tokio::spawn(async move {
+                let output: String = (0..50).map(|line| format!("command {} line {} {}\n", command, line, padding)).collect();
+                let mut stream = LogStream::start(&writer, &format!("command {}", command), None).await.unwrap();
+                for chunk in output.as_bytes().chunks(777) {
+                    stream.write(command % 2, std::str::from_utf8(chunk).unwrap()).await.unwrap();
+                }
+                stream.finish(Some(0)).await.unwrap();
+            })
+        }));
+        for task in tasks {
+            task.await.unwrap();
+        }
+        logger.flush().await.unwrap();
+
+        let contents = tokio::fs::read_to_string(logger.log_file()).await.unwrap();
+        let mut next_line = [0; 8];
+        for line in contents.lines().filter(|line| line.starts_with("[stdout] ") || line.starts_with("[stderr] ")) {
+            let words: Vec<&str> = line.split(' ').collect();
+            assert_eq!(words.len(), 6, "torn output line: {:.80}", line);
+            assert_eq!(words[5], padding);
+            let (command, output_line): (usize, usize) = (words[2].parse().unwrap(), words[4].parse().unwrap());
+            assert_eq!(output_line, next_line[command], "command {} output out of order", command);
+            next_line[command] += 1;
+        }
+        assert_eq!(next_line, [50; 8]);
+
+        let mut next_entry = [0; 16];
+        for line in contents.lines().filter(|line| line.starts_with('[') && line.contains("] task ")) {
+            let words: Vec<&str> = line.split(' ').collect();
+            assert_eq!(words.len(), 6, "torn log line: {:.80}", line);
+            assert_eq!(words[5], padding);
+            let (task, entry): (usize, usize) = (words[2].parse().unwrap(), words[4].parse().unwrap());
+            assert_eq!(entry, next_entry[task], "task {} logged out of order", task);
+            next_entry[task] += 1;
+        }
+        assert_eq!(next_entry, [50; 16]);
+        assert!(logger.recent_entries().ends_with(&format!("{}\n", padding)));
+    }
+
+    #[test]
+    fn test_log_tail() {
+        let log = "one\ntwo\nthree\n";
+        assert_eq!(log_tail(log, 2), (1, "two\nthree\n"));
+        assert_eq!(log_tail(log, 3), (0, log));
+        assert_eq!(log_tail("", 5), (0, ""));
+    }
+}
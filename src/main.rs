@@ -1,20 +1,38 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use cargo_metadata::MetadataCommand;
-use repx_lib::{build_with_nix, RepxConfig, RESET, BOLD, GREEN, RED, YELLOW, CYAN, MAGENTA};
-use std::path::Path;
+use repx_lib::generate::AVAILABLE_TARGETS;
+use repx_lib::{env_overrides, exec_in_container, verify_reproducible, flake_packages, parse_meta, DockerProblem, dry_run_with_nix, fetch_source, prefetch_with_nix, DryRunSummary, run_bench, DEFAULT_BENCH_VOLUME, diff_builds, ArtifactProcessor, ChecksumProcessor, SignProcessor, StripProcessor, serve, ServeRoots, upgrade_config_text, list_image_tags, dev_shell, RECOMMENDED_IMAGES, TAGGED_REPOSITORY, ArtifactInfo, BuildReport, CONFIG_VERSION, build_with_nix_report, cache_clean, cache_stats, reap_idle_containers, remove_store_cache, list_build_logs, log_tail, read_build_log, PartialRepxConfig, RepxConfig, RepxError, DEFAULT_STORE_VOLUME, RESET, BOLD, GREEN, RED, YELLOW, CYAN, MAGENTA};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs;
 
+/// Lines from the end of the build log printed by --dump-logs-on-failure
+const DUMP_LOG_LINES: usize = 200;
+
 #[derive(Parser)]
 #[command(name = "repx", about = "Cargo subcommand for Nix-based Rust builds")]
+#[allow(clippy::large_enum_variant)] // Parsed once at startup, so the Build variant's size doesn't matter
 enum Cli {
     Build {
         #[arg(short, long, help = "Path location to your Cargo.toml or project root.")]
         project: Option<String>,
+        #[arg(long, help = "Path to a Cargo.toml; the project root is its directory")]
+        manifest_path: Option<String>,
+        #[arg(long, value_name = "NAME", help = "Workspace member to build, required when the workspace has several")]
+        package: Option<String>,
+        #[arg(long, value_name = "URL|TARBALL", conflicts_with_all = ["project", "manifest_path"], help = "Build sources fetched from a tarball URL, a local tarball or a git URL (git+<url>#<rev>) instead of a local project")]
+        source: Option<String>,
+        #[arg(long, value_name = "HEX", requires = "source", help = "Expected SHA-256 of the --source tarball")]
+        source_sha256: Option<String>,
         #[arg(short, long, help = "Pin nix docker image to a specific version.")]
         image: Option<String>,
         #[arg(short, long, help = "Comma-separated list of targets to build for. If not specified, builds for host target.")]
         targets: Option<String>,
+        #[arg(long, conflicts_with = "targets", help = "Build the targets of a named group from repx.toml")]
+        target_group: Option<String>,
         #[arg(long, help = "List all available targets and exit")]
         list_targets: bool,
         #[arg(long, value_delimiter = ',', help = "Extra packages to install with nix.")]
@@ -25,8 +43,160 @@ enum Cli {
         rust_version: Option<String>,
         #[arg(long, help = "nixpkgs URL/commit to use for reproducible builds")]
         nixpkgs_url: Option<String>,
-        #[arg(short = 'c', long, help = "Path to repx.toml configuration file")]
+        #[arg(long, help = "rust-overlay flake URL to use instead of the latest revision")]
+        rust_overlay_url: Option<String>,
+        #[arg(long, help = "flake-utils flake URL to use instead of the latest revision")]
+        flake_utils_url: Option<String>,
+        #[arg(long, value_name = "VERSIONS", value_delimiter = ',', help = "Build every target with each of these Rust versions (e.g. 1.75.0,1.80.0,stable); artifacts go to <target_dir>/repx/<version>/<target>")]
+        rust_versions: Vec<String>,
+        #[arg(long, value_name = "ATTR", help = "Flake attribute to build per target, with {target} for the target (default {target})")]
+        output_attr_template: Option<String>,
+        #[arg(long, value_name = "CMD", help = "What to run for the targets: build (default) builds each one, check runs nix flake check instead")]
+        build_command: Option<String>,
+        #[arg(long, help = "Also run nix flake check after building the targets")]
+        check: bool,
+        #[arg(long, help = "Skip generating flake.lock; every flake input URL must be pinned to a commit")]
+        no_flake_lock: bool,
+        #[arg(long, help = "Don't configure git safe.directory in the container (by default only done when the flake reads git)")]
+        no_git_config: bool,
+        #[arg(long, help = "Don't parse the generated flake.nix before the build (skips the early syntax check)")]
+        no_flake_check: bool,
+        #[arg(long, conflicts_with = "no_flake_lock", help = "Fail if the committed flake.lock is stale instead of updating it (for CI)")]
+        verify_lock: bool,
+        #[arg(long, help = "Cargo profile to build with: release, dev, or a custom profile from Cargo.toml")]
+        profile: Option<String>,
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the project")]
         config: Option<String>,
+        #[arg(long, help = "SRI hash (sha256-...) of the vendored dependencies, instead of vendoring from Cargo.lock")]
+        cargo_hash: Option<String>,
+        #[arg(long, value_name = "REF", help = "Also build a container image of the result, tagged REF (name[:tag]), and load it into Docker")]
+        image_output: Option<String>,
+        #[arg(long, help = "Megabytes of each command's output to keep in memory (the log file gets all of it)")]
+        max_output_mb: Option<usize>,
+        #[arg(long, help = "Lines of each command's output to keep in memory")]
+        max_output_lines: Option<usize>,
+        #[arg(long, value_name = "MB", help = "Cap the build log at MB megabytes; further command output is left out with a marker")]
+        max_log_size: Option<u64>,
+        #[arg(long, value_name = "PRESET", help = "How often command progress is redrawn: fast, default or slow (or set REPX_PROGRESS)")]
+        progress: Option<String>,
+        #[arg(long, value_name = "MS", help = "Milliseconds between progress redraws, overriding the preset")]
+        progress_interval_ms: Option<u64>,
+        #[arg(long, value_name = "N", help = "Copied store paths between redraws of the copied-paths counter, overriding the preset")]
+        progress_path_step: Option<usize>,
+        #[arg(long, value_name = "PATH", help = "Read registry credentials (username:token) for pulling the Nix image from PATH")]
+        registry_token_file: Option<String>,
+        #[arg(long, value_name = "PATH", help = "Read Nix access tokens (host=token) for private flake inputs from PATH")]
+        flake_token_file: Option<String>,
+        #[arg(long, value_name = "DIR", help = "Cargo target directory inside the container, relative to the project (artifacts go to DIR/repx)")]
+        target_dir: Option<String>,
+        #[arg(long, value_name = "LAYOUT", help = "Artifact layout: nested (default) keeps each target's tree, flat also copies the executables to DIR/repx/flat/<name>-<target>")]
+        layout: Option<String>,
+        #[arg(long, value_name = "PRESET", help = "What .repx/.gitignore ignores: ignore-logs-only, ignore-all or ignore-nothing")]
+        metadata_gitignore: Option<String>,
+        #[arg(long = "nix-arg", value_name = "ARG", allow_hyphen_values = true, help = "Extra argument for nix build, repeatable (e.g. --nix-arg=--keep-going); for power users")]
+        nix_args: Vec<String>,
+        #[arg(long = "meta", value_name = "KEY=VALUE", help = "Metadata to record with the build, repeatable (e.g. --meta ci_run=1234); kept in the log header and build report")]
+        meta: Vec<String>,
+        #[arg(long = "container-runtime-arg", value_name = "ARG", allow_hyphen_values = true, help = "Extra docker run option for the build container, repeatable (e.g. --container-runtime-arg=--device=/dev/fuse)")]
+        container_runtime_args: Vec<String>,
+        #[arg(long, value_name = "docker|podman", help = "Container runtime to build with (default: REPX_RUNTIME, else docker)")]
+        runtime: Option<String>,
+        #[arg(long, value_name = "URL", help = "Docker API to connect to instead of the local default: unix:///path, tcp://host:port, http://host:port or a socket path")]
+        docker_host: Option<String>,
+        #[arg(long, help = "Don't run the build container privileged, for rootless runtimes; Nix then needs --nix-arg=--option --nix-arg=sandbox --nix-arg=false")]
+        no_privileged: bool,
+        #[arg(long, help = "Treat any text change in flake.nix as drift, including comments and whitespace")]
+        exact_flake_compare: bool,
+        #[arg(long, conflicts_with = "no_diff", help = "Print a diff of flake.nix and flake.lock when they drift (the default)")]
+        show_diff: bool,
+        #[arg(long, help = "Only report that flake.nix or flake.lock drifted, without printing the diff")]
+        no_diff: bool,
+        #[arg(long, help = "Print the variables passed to the flake template as JSON")]
+        dump_context: bool,
+        #[arg(long = "static", help = "Link statically: crt-static for glibc targets (musl targets are always static)")]
+        static_link: bool,
+        #[arg(long, value_name = "UNIX_TS", conflicts_with = "no_source_date_epoch", help = "SOURCE_DATE_EPOCH for the build (defaults to the commit time of git HEAD)")]
+        source_date_epoch: Option<u64>,
+        #[arg(long, help = "Don't set SOURCE_DATE_EPOCH from git; Nix's default of 1980-01-01 applies")]
+        no_source_date_epoch: bool,
+        #[arg(long, help = "Record every store path in each target's runtime closure in the build report (slower)")]
+        record_closure: bool,
+        #[arg(long, value_name = "UID:GID", num_args = 0..=1, default_missing_value = "host", help = "Give files written into the project to UID:GID after the build (just --user: the current user)")]
+        user: Option<String>,
+        #[arg(long, help = "Strip symbols from ELF artifacts on the host after the build")]
+        strip: bool,
+        #[arg(long, help = "Write a <file>.sha256 checksum next to each artifact")]
+        checksums: bool,
+        #[arg(long, value_name = "BACKEND", help = "Sign artifacts and the build report with minisign or cosign")]
+        sign: Option<String>,
+        #[arg(long, value_name = "PATH", help = "Secret key file for --sign (required for minisign; cosign signs keyless without it)")]
+        sign_key_file: Option<String>,
+        #[arg(long, help = "Fail if Cargo.lock is missing or out of date instead of regenerating it")]
+        locked: bool,
+        #[arg(long, help = "Audit Cargo.lock with cargo-audit before building and include the findings in the report")]
+        audit: bool,
+        #[arg(long, value_name = "SEVERITY", help = "Fail the build on vulnerabilities of this severity or worse: low, medium, high or critical (implies --audit)")]
+        audit_deny: Option<String>,
+        #[arg(long, help = "Mount the project read-only and build from a copy, so the source tree is never modified")]
+        readonly_source: bool,
+        #[arg(long, help = "With --readonly-source, write a generated Cargo.lock back to the project")]
+        write_cargo_lock: bool,
+        #[arg(long, value_name = "SECS", help = "Abort any single command in the build container that runs longer than SECS seconds")]
+        command_timeout: Option<u64>,
+        #[arg(long, value_name = "PATH", help = "Also write the build report as JSON to PATH, even if the build fails")]
+        summary_json: Option<String>,
+        #[arg(long, value_name = "PATH", help = "Also write a JUnit XML report to PATH, with each target as a test case")]
+        junit: Option<String>,
+        #[arg(long, value_name = "PATH", help = "Also write the Rust compiler errors of a failed build to PATH as SARIF, for code scanning")]
+        sarif: Option<String>,
+        #[arg(long, help = "Number of targets to copy artifacts for concurrently")]
+        copy_jobs: Option<usize>,
+        #[arg(short, long, value_name = "N", help = "Build up to N targets concurrently (default 1)")]
+        jobs: Option<usize>,
+        #[arg(long, value_name = "N", help = "Retry an interrupted image pull up to N times, keeping completed layers (default 3)")]
+        pull_retries: Option<u32>,
+        #[arg(long, value_name = "N", help = "Retry a failed artifact copy up to N times, with a short backoff (default 2)")]
+        copy_retries: Option<u32>,
+        #[arg(long, value_name = "N", help = "Build targets that failed again, up to N more times, after the other targets (default 0)")]
+        retry_failed_targets: Option<u32>,
+        #[arg(long, value_name = "DIR", help = "Check that every artifact matches the file at the same path in DIR (laid out like target/repx) by SHA-256")]
+        compare_with: Option<String>,
+        #[arg(long, value_name = "GB", help = "Refuse to build with less free disk space than this on the host or in the container's /nix (default 5, 0 disables)")]
+        min_free_space_gb: Option<u64>,
+        #[arg(long, help = "Build a working tree with uncommitted changes without warning")]
+        allow_dirty: bool,
+        #[arg(long, help = "Refuse to build when reproducibility can't be guaranteed (e.g. uncommitted changes)")]
+        strict: bool,
+        #[arg(long, help = "Fail the build if any target builds but produces no artifacts")]
+        strict_targets: bool,
+        #[arg(long, help = "Fail the build if it emits any warning, such as flake drift or a dirty tree")]
+        fail_on_warning: bool,
+        #[arg(long, help = "Vendor crate sources into .repx/vendor and build against them, without fetching crates")]
+        vendor: bool,
+        #[arg(long, help = "Run the build container without network access (needs a warm --nix-store-cache)")]
+        offline: bool,
+        #[arg(long, help = "Run the Nix daemon in the build container (multi-user mode), falling back to single-user if the image lacks it")]
+        nix_daemon: bool,
+        #[arg(long, help = "Named Docker volume, or host directory if a path (e.g. /var/cache/repx-nix), to persist the Nix store in between builds")]
+        nix_store_cache: Option<String>,
+        #[arg(long, help = "Name the container after the project path and targets instead of a timestamp")]
+        deterministic_name: bool,
+        #[arg(long, value_name = "NAME", help = "Name the build container NAME, e.g. for docker exec or docker logs during the build")]
+        container_name: Option<String>,
+        #[arg(long, help = "Record every command run in the container to .repx/build-script.sh")]
+        trace_commands: bool,
+        #[arg(long, help = "Suggest targeted fixes when a build fails with a known error")]
+        explain_failure: bool,
+        #[arg(long, conflicts_with = "no_dump_logs_on_failure", help = "Print the end of the build log to stderr if the build fails (on by default when CI is set)")]
+        dump_logs_on_failure: bool,
+        #[arg(long, help = "Don't print the build log when the build fails, even in CI")]
+        no_dump_logs_on_failure: bool,
+        #[arg(long, conflicts_with = "stdout", help = "Run nix build --dry-run for each target and report what would be built or fetched, without building")]
+        dry_run_nix: bool,
+        #[arg(long, help = "Write the single executable of a one-target build to stdout; status goes to stderr")]
+        stdout: bool,
+        #[arg(long, default_value = "text", value_parser = ["text", "json"], conflicts_with_all = ["stdout", "dry_run_nix"], help = "Print the build report as JSON on stdout instead of the status output, which goes to stderr")]
+        format: String,
     },
     #[command(about = "Initialize a new repx.toml configuration file")]
     Init {
@@ -37,23 +207,146 @@ enum Cli {
     Clean {
         #[arg(short, long, default_value = ".", help = "Path location to your project root.")]
         project: String,
+        #[arg(long, help = "Target directory to remove (defaults to target_dir from repx.toml)")]
+        target_dir: Option<String>,
+        #[arg(long, help = "Also remove the Nix store cache, the nix_store_cache volume or directory from repx.toml, else repx-nix-store")]
+        cache: bool,
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the project")]
+        config: Option<String>,
+    },
+    #[command(about = "Inspect or garbage-collect the persistent Nix store cache")]
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+        #[arg(long, global = true, help = "Cache volume to operate on (defaults to nix_store_cache from repx.toml)")]
+        volume: Option<String>,
+        #[arg(short = 'c', long, global = true, help = "Path to repx.toml configuration file; skips the search up from the project")]
+        config: Option<String>,
+    },
+    #[command(about = "Pull the image, lock the flake and fetch inputs into the Nix store cache without building")]
+    Prefetch {
+        #[arg(short, long, default_value = ".", help = "Path location to your project root.")]
+        project: String,
+        #[arg(short, long, help = "Comma-separated list of targets to prefetch (defaults to targets from repx.toml, else the host target)")]
+        targets: Option<String>,
+        #[arg(long, help = "Nix store cache volume to fill (defaults to nix_store_cache from repx.toml, else repx-nix-store)")]
+        nix_store_cache: Option<String>,
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the project")]
+        config: Option<String>,
+    },
+    #[command(about = "Open a nix develop shell with the build's toolchain and extra packages")]
+    Shell {
+        #[arg(short, long, default_value = ".", help = "Path location to your project root.")]
+        project: String,
+        #[arg(help = "Dev shell to enter, e.g. x86_64-linux-musl (defaults to the native shell)")]
+        shell: Option<String>,
+        #[arg(long, help = "Use the host's Nix instead of a container from the build image")]
+        host: bool,
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the project")]
+        config: Option<String>,
+    },
+    #[command(about = "Run a command in /app of a build container with the generated flake, e.g. repx exec -- nix eval ./.repx#...")]
+    Exec {
+        #[arg(short, long, default_value = ".", help = "Path location to your project root.")]
+        project: String,
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the project")]
+        config: Option<String>,
+        #[arg(last = true, required = true, value_name = "COMMAND", help = "Command to run; a single argument is run by sh as is, several are passed as separate words")]
+        command: Vec<String>,
+    },
+    #[command(about = "Time a cold build and a warm build with a Nix store cache, per phase")]
+    Bench {
+        #[arg(short, long, default_value = ".", help = "Path location to your project root.")]
+        project: String,
+        #[arg(short, long, help = "Comma-separated list of targets to build (defaults to targets from repx.toml, else the host target)")]
+        targets: Option<String>,
+        #[arg(long, default_value = DEFAULT_BENCH_VOLUME, help = "Nix store cache volume to benchmark with; removed before and after")]
+        volume: String,
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the project")]
+        config: Option<String>,
+    },
+    #[command(about = "Build twice in fresh containers and check that every artifact is bit-for-bit identical")]
+    Verify {
+        #[arg(short, long, default_value = ".", help = "Path location to your project root.")]
+        project: String,
+        #[arg(short, long, help = "Comma-separated list of targets to build (defaults to targets from repx.toml, else the host target)")]
+        targets: Option<String>,
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the project")]
+        config: Option<String>,
+    },
+    #[command(about = "Remove repx containers left behind by interrupted builds")]
+    Reap {
+        #[arg(long, default_value_t = 24, help = "Only remove containers idle for more than this many hours")]
+        idle_hours: u64,
+        #[arg(long, value_name = "URL", help = "Docker API to connect to instead of the one from repx.toml or the local default")]
+        docker_host: Option<String>,
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the current directory")]
+        config: Option<String>,
+    },
+    #[command(about = "List the build targets, or with --installed the ones the project's generated flake exposes")]
+    Targets {
+        #[arg(long, help = "Run nix flake show on .repx/flake.nix in the build image and list the packages it actually exposes")]
+        installed: bool,
+        #[arg(short, long, default_value = ".", help = "Path location to your project root.")]
+        project: String,
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the project")]
+        config: Option<String>,
+    },
+    #[command(about = "List recommended Nix images for --image, optionally with the tags available on Docker Hub")]
+    ListImages {
+        #[arg(long, help = "Also query Docker Hub for the most recent tags of nixos/nix")]
+        tags: bool,
+        #[arg(long, default_value_t = 20, help = "Number of tags to list with --tags")]
+        limit: usize,
+    },
+    #[command(about = "Migrate repx.toml to the current config format, keeping comments")]
+    UpgradeConfig {
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the current directory")]
+        config: Option<String>,
+    },
+    #[command(about = "Show the log of a previous build, decompressing rotated logs")]
+    Logs {
+        #[arg(help = "Build ID to show (defaults to the most recent build)")]
+        build_id: Option<String>,
+        #[arg(short, long, default_value = ".", help = "Path location to your project root.")]
+        project: String,
+        #[arg(short, long, help = "List the available build logs instead")]
+        list: bool,
+    },
+    #[command(about = "Compare the targets, artifacts and settings of two previous builds")]
+    DiffBuilds {
+        #[arg(help = "ID of the earlier build")]
+        old: String,
+        #[arg(help = "ID of the later build")]
+        new: String,
+        #[arg(short, long, default_value = ".", help = "Path location to your project root.")]
+        project: String,
+    },
+    #[command(about = "Serve build logs, reports and artifacts read-only over HTTP")]
+    Serve {
+        #[arg(short, long, default_value = ".", help = "Path location to your project root.")]
+        project: String,
+        #[arg(long, default_value_t = 8080, help = "Port to listen on")]
+        port: u16,
+        #[arg(long, default_value = "127.0.0.1", help = "Address to listen on; use 0.0.0.0 to serve other machines")]
+        host: String,
+        #[arg(short = 'c', long, help = "Path to repx.toml configuration file; skips the search up from the project")]
+        config: Option<String>,
     },
     #[command(about = "Print the repx version")]
     Release,
 }
 
-// Available targets based on the flake template
-const AVAILABLE_TARGETS: &[&str] = &[
-    "x86_64-linux-gnu",
-    "aarch64-linux-gnu",
-    "x86_64-linux-musl",
-    "aarch64-linux-musl",
-    "x86_64-w64-mingw32",       // Windows GNU
-    "x86_64-pc-windows-msvc", // Windows MSVC
-    "aarch64-w64-mingw32",      // Windows ARM GNU (experimental)
-    "x86_64-apple-darwin",      // macOS Intel
-    "aarch64-apple-darwin",     // macOS Apple Silicon
-];
+#[derive(Subcommand)]
+enum CacheAction {
+    #[command(about = "Show the size and number of store paths in the cache")]
+    Stats,
+    #[command(about = "Garbage-collect the cache")]
+    Clean {
+        #[arg(long, help = "Keep profile generations newer than this many days")]
+        keep_days: Option<u32>,
+    },
+}
 
 fn get_host_target() -> &'static str {
     #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
@@ -64,6 +357,18 @@ fn get_host_target() -> &'static str {
     {
         "aarch64-linux-gnu"
     }
+    #[cfg(all(target_arch = "riscv64", target_os = "linux"))]
+    {
+        "riscv64-linux-gnu"
+    }
+    #[cfg(all(target_arch = "arm", target_os = "linux"))]
+    {
+        "armv7-linux-gnueabihf"
+    }
+    #[cfg(all(target_arch = "powerpc64", target_endian = "little", target_os = "linux"))]
+    {
+        "powerpc64le-linux-gnu"
+    }
     #[cfg(all(target_arch = "x86_64", target_os = "windows"))]
     {
         "x86_64-w64-mingw32" // Default to GNU for Windows host
@@ -79,6 +384,9 @@ fn get_host_target() -> &'static str {
     #[cfg(not(any(
         all(target_arch = "x86_64", target_os = "linux"),
         all(target_arch = "aarch64", target_os = "linux"),
+        all(target_arch = "riscv64", target_os = "linux"),
+        all(target_arch = "arm", target_os = "linux"),
+        all(target_arch = "powerpc64", target_endian = "little", target_os = "linux"),
         all(target_arch = "x86_64", target_os = "windows"),
         all(target_arch = "x86_64", target_os = "macos"),
         all(target_arch = "aarch64", target_os = "macos")
@@ -88,22 +396,30 @@ fn get_host_target() -> &'static str {
     }
 }
 
+fn target_description(target: &str) -> &'static str {
+    match target {
+        "x86_64-linux-gnu" => "Linux x86_64 (GNU libc, dynamic)",
+        "aarch64-linux-gnu" => "Linux ARM64/AArch64 (GNU libc, dynamic)",
+        "x86_64-linux-musl" => "Linux x86_64 (musl libc, static)",
+        "aarch64-linux-musl" => "Linux ARM64/AArch64 (musl libc, static)",
+        "riscv64-linux-gnu" => "Linux RISC-V 64 (GNU libc, dynamic, experimental)",
+        "armv7-linux-gnueabihf" => "Linux ARMv7 hard-float (GNU libc, dynamic, experimental)",
+        "powerpc64le-linux-gnu" => "Linux POWER little-endian (GNU libc, dynamic, experimental)",
+        "wasm32-wasi" => "WebAssembly with WASI (.wasm module)",
+        "wasm32-unknown-unknown" => "WebAssembly, no OS (.wasm module; use wasm-bindgen-cli for JS glue)",
+        "x86_64-w64-mingw32" => "Windows x86_64 (MinGW-w64/GNU)",
+        "x86_64-pc-windows-msvc" => "Windows x86_64 (MSVC toolchain)",
+        "aarch64-w64-mingw32" => "Windows ARM64 (MinGW-w64/GNU, experimental)",
+        "x86_64-apple-darwin" => "macOS x86_64 (Intel)",
+        "aarch64-apple-darwin" => "macOS ARM64 (Apple Silicon)",
+        _ => "Unknown target",
+    }
+}
+
 fn print_available_targets() {
     println!("{}{}Available targets:{}", BOLD, CYAN, RESET);
     for target in AVAILABLE_TARGETS {
-        let description = match *target {
-            "x86_64-linux-gnu" => "Linux x86_64 (GNU libc, dynamic)",
-            "aarch64-linux-gnu" => "Linux ARM64/AArch64 (GNU libc, dynamic)",
-            "x86_64-linux-musl" => "Linux x86_64 (musl libc, static)",
-            "aarch64-linux-musl" => "Linux ARM64/AArch64 (musl libc, static)",
-            "x86_64-w64-mingw32" => "Windows x86_64 (MinGW-w64/GNU)",
-            "x86_64-pc-windows-msvc" => "Windows x86_64 (MSVC toolchain)",
-            "aarch64-w64-mingw32" => "Windows ARM64 (MinGW-w64/GNU, experimental)",
-            "x86_64-apple-darwin" => "macOS x86_64 (Intel)",
-            "aarch64-apple-darwin" => "macOS ARM64 (Apple Silicon)",
-            _ => "Unknown target",
-        };
-        println!("   - {}: {}", target, description);
+        println!("   - {}: {}", target, target_description(target));
     }
 }
 
@@ -120,60 +436,212 @@ fn print_version() -> Result<()> {
     Ok(())
 }
 
-async fn load_config(config_path: Option<String>) -> Result<RepxConfig> {
-    let config_file = config_path.as_deref().unwrap_or(RepxConfig::default_config_path());
-    
-    if Path::new(config_file).exists() {
-        println!("{}{}Loading configuration from:{} {}", BOLD, CYAN, RESET, config_file);
-        RepxConfig::from_file(config_file).await
-    } else if config_path.is_some() {
-        // If a specific config file was requested but doesn't exist, that's an error
-        return Err(anyhow::anyhow!("Configuration file '{}' not found", config_file));
+/// Load the `-c` config file, or else the nearest repx.toml from `project` up to its workspace root
+///
+/// The `REPX_*` environment variables are layered over it; the command line goes on top.
+async fn load_config(config_path: Option<String>, project: &str) -> Result<RepxConfig> {
+    let config_file = match &config_path {
+        Some(path) => PathBuf::from(path),
+        None => match RepxConfig::discover_config_path(Path::new(project)) {
+            Some(path) => path,
+            // Use default configuration if no config file exists
+            None => return Ok(RepxConfig::default().merge(env_overrides())),
+        },
+    };
+
+    if config_file.exists() {
+        println!("{}{}Loading configuration from:{} {}", BOLD, CYAN, RESET, config_file.display());
+        let config = RepxConfig::from_file(&config_file).await?;
+        if config.is_newer_than_supported() {
+            println!("{}{}WARNING:{} {} has config version {}, but this repx only understands up to {}; newer settings may be ignored. Upgrade repx.",
+                BOLD, YELLOW, RESET, config_file.display(), config.version, CONFIG_VERSION);
+        } else if config.version < CONFIG_VERSION {
+            println!("{}{}Note:{} {} uses an older config format; run `repx upgrade-config` to migrate it.", BOLD, CYAN, RESET, config_file.display());
+        }
+        Ok(config.merge(env_overrides()))
     } else {
-        // Use default configuration if no config file exists
-        Ok(RepxConfig::default())
+        // If a specific config file was requested but doesn't exist, that's an error
+        Err(anyhow::anyhow!("Configuration file '{}' not found", config_file.display()))
     }
 }
 
-fn merge_config_with_args(mut config: RepxConfig, args: &Cli) -> RepxConfig {
-    if let Cli::Build { 
-        project, image, targets, extra, rust_channel, rust_version, nixpkgs_url, .. 
-    } = args {
+/// Whether two paths point at the same location, comparing canonical forms when they exist
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Settings given on the command line, as overrides for repx.toml
+fn cli_overrides(args: &Cli) -> Result<PartialRepxConfig> {
+    let Cli::Build { 
+        project, manifest_path, package, image, targets, extra, rust_channel, rust_version, rust_versions, nixpkgs_url, rust_overlay_url, flake_utils_url, output_attr_template, build_command, check, no_flake_lock, no_git_config, no_flake_check, verify_lock, profile, cargo_hash, image_output, max_output_mb, max_output_lines, max_log_size, progress, progress_interval_ms, progress_path_step, registry_token_file, flake_token_file, target_dir, layout, metadata_gitignore, nix_args, meta, container_runtime_args, runtime, docker_host, no_privileged, exact_flake_compare, show_diff, no_diff, dump_context, static_link, source_date_epoch, no_source_date_epoch, record_closure, user, strip, checksums, sign, sign_key_file, locked, audit, audit_deny, readonly_source, write_cargo_lock, command_timeout, summary_json, junit, sarif, copy_jobs, jobs, pull_retries, copy_retries, retry_failed_targets, compare_with, min_free_space_gb, allow_dirty, strict, strict_targets, fail_on_warning, vendor, offline, nix_daemon, nix_store_cache, deterministic_name, container_name, trace_commands, explain_failure, dump_logs_on_failure, no_dump_logs_on_failure, .. 
+    } = args else {
+        return Ok(PartialRepxConfig::default());
+    };
+    let mut overrides = PartialRepxConfig {
+        project: project.clone(),
+        image: image.clone(),
+        targets: targets.clone(),
+        extra: extra.clone(),
+        rust_channel: rust_channel.clone(),
+        rust_version: rust_version.clone(),
+        rust_versions: (!rust_versions.is_empty()).then(|| rust_versions.clone()),
+        nixpkgs_url: nixpkgs_url.clone(),
+        rust_overlay_url: rust_overlay_url.clone(),
+        flake_utils_url: flake_utils_url.clone(),
+        no_flake_lock: no_flake_lock.then_some(true),
+        no_git_config: no_git_config.then_some(true),
+        no_flake_check: no_flake_check.then_some(true),
+        verify_lock: verify_lock.then_some(true),
+        profile: profile.clone(),
+        cargo_hash: cargo_hash.clone(),
+        max_output_mb: *max_output_mb,
+        max_output_lines: *max_output_lines,
+        max_log_size_mb: *max_log_size,
+        progress: progress.clone(),
+        progress_interval_ms: *progress_interval_ms,
+        progress_path_step: *progress_path_step,
+        registry_token_file: registry_token_file.clone(),
+        flake_token_file: flake_token_file.clone(),
+        output_attr_template: output_attr_template.clone(),
+        build_command: build_command.clone(),
+        flake_check: check.then_some(true),
+        target_dir: target_dir.clone(),
+        layout: layout.clone(),
+        metadata_gitignore: metadata_gitignore.clone(),
+        nix_args: (!nix_args.is_empty()).then(|| nix_args.clone()),
+        meta: (!meta.is_empty())
+            .then(|| meta.iter().map(|entry| parse_meta(entry)).collect::<Result<BTreeMap<_, _>, _>>())
+            .transpose()?,
+        container_runtime_args: (!container_runtime_args.is_empty()).then(|| container_runtime_args.clone()),
+        runtime: runtime.clone(),
+        docker_host: docker_host.clone(),
+        privileged: no_privileged.then_some(false),
+        exact_flake_compare: exact_flake_compare.then_some(true),
+        show_diff: if *show_diff { Some(true) } else { no_diff.then_some(false) },
+        dump_context: dump_context.then_some(true),
+        static_link: static_link.then_some(true),
+        source_date_epoch: *source_date_epoch,
+        no_source_date_epoch: no_source_date_epoch.then_some(true),
+        record_closure: record_closure.then_some(true),
+        user: user.clone(),
+        strip: strip.then_some(true),
+        checksums: checksums.then_some(true),
+        sign: sign.clone(),
+        sign_key_file: sign_key_file.clone(),
+        locked: locked.then_some(true),
+        audit: audit.then_some(true),
+        audit_deny: audit_deny.clone(),
+        readonly_source: readonly_source.then_some(true),
+        write_cargo_lock: write_cargo_lock.then_some(true),
+        command_timeout_secs: *command_timeout,
+        image_output: image_output.clone(),
+        summary_json: summary_json.clone(),
+        junit: junit.clone(),
+        sarif: sarif.clone(),
+        copy_jobs: *copy_jobs,
+        jobs: *jobs,
+        pull_retries: *pull_retries,
+        copy_retries: *copy_retries,
+        retry_failed_targets: *retry_failed_targets,
+        compare_with: compare_with.clone(),
+        min_free_space_gb: *min_free_space_gb,
+        allow_dirty: allow_dirty.then_some(true),
+        strict: strict.then_some(true),
+        strict_targets: strict_targets.then_some(true),
+        fail_on_warning: fail_on_warning.then_some(true),
+        vendor: vendor.then_some(true),
+        offline: offline.then_some(true),
+        nix_daemon: nix_daemon.then_some(true),
+        nix_store_cache: nix_store_cache.clone(),
+        deterministic_name: deterministic_name.then_some(true),
+        container_name: container_name.clone(),
+        package: package.clone(),
+        trace_commands: trace_commands.then_some(true),
+        explain_failure: explain_failure.then_some(true),
+        dump_logs_on_failure: if *dump_logs_on_failure { Some(true) } else { no_dump_logs_on_failure.then_some(false) },
+        ..PartialRepxConfig::default()
+    };
+    if let Some(ref mp) = manifest_path {
+        let manifest = Path::new(mp);
+        let root = match manifest.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
         if let Some(ref p) = project {
-            config.project = p.clone();
-        }
-        if let Some(ref i) = image {
-            config.image = i.clone();
-        }
-        if let Some(ref t) = targets {
-            config.targets = Some(t.clone());
+            if !same_path(Path::new(p), root) {
+                return Err(anyhow::anyhow!(
+                    "--project '{}' conflicts with --manifest-path '{}', whose project root is '{}'",
+                    p, mp, root.display()
+                ));
+            }
         }
-        if let Some(ref e) = extra {
-            config.extra = e.clone();
+        overrides.project = Some(root.display().to_string());
+        overrides.manifest_path = Some(mp.clone());
+    }
+    Ok(overrides)
+}
+
+fn merge_config_with_args(config: RepxConfig, args: &Cli) -> Result<RepxConfig> {
+    let mut config = config.merge(cli_overrides(args)?);
+    if let Cli::Build { targets, target_group, .. } = args {
+        if let Some(ref g) = target_group {
+            config.targets = Some(config.target_group(g)?);
         }
-        if let Some(ref rc) = rust_channel {
-            config.rust_channel = rc.clone();
+        if config.targets.is_none() && config.groups.contains_key("default") {
+            config.targets = Some(config.target_group("default")?);
         }
-        if let Some(ref rv) = rust_version {
-            config.rust_version = rv.clone();
+        // Targets picked on the command line are built as asked; ones from repx.toml are filtered for this host
+        if targets.is_none() && target_group.is_none() && !config.target_hosts.is_empty() {
+            if let Some(configured) = config.targets.clone() {
+                let configured: Vec<&str> = configured.split(',').collect();
+                let (host_os, host_arch) = (std::env::consts::OS, std::env::consts::ARCH);
+                let (kept, skipped) = config.targets_for_host(&configured, host_os, host_arch)?;
+                for (target, reason) in &skipped {
+                    println!("{}{}INFO:{} Skipping {} on this {}-{} host: {}", BOLD, CYAN, RESET, target, host_os, host_arch, reason);
+                }
+                if kept.is_empty() {
+                    return Err(RepxError::ConfigInvalid(format!(
+                        "None of the configured targets are built on a {}-{} host; pass --targets to build one anyway",
+                        host_os, host_arch
+                    )).into());
+                }
+                config.targets = Some(kept.join(","));
+            }
         }
-        if let Some(ref nu) = nixpkgs_url {
-            config.nixpkgs_url = nu.clone();
+    }
+    Ok(config)
+}
+
+/// Print the end of the most recent build log to stderr, so it survives in CI console output
+async fn dump_build_log(project: &str) {
+    let repx_dir = Path::new(project).join(".repx");
+    match read_build_log(&repx_dir, None).await {
+        Ok(log) => {
+            let (omitted, tail) = log_tail(&log, DUMP_LOG_LINES);
+            eprintln!("\n{}{}Build log:{}", BOLD, YELLOW, RESET);
+            if omitted > 0 {
+                eprintln!("... {} earlier line(s) omitted; the full log is shown by repx logs ...", omitted);
+            }
+            eprint!("{}", tail);
         }
+        Err(e) => eprintln!("{}{}Could not read the build log:{} {}", BOLD, YELLOW, RESET, e),
     }
-    config
 }
 
-async fn clean_directories(project_path: &str) -> Result<()> {
+async fn clean_directories(project_path: &str, target_dir_name: &str) -> Result<()> {
     let project = Path::new(project_path);
-    let target_dir = project.join("target");
+    let target_dir = project.join(target_dir_name);
+    let target_label = format!("{}/", target_dir_name.trim_end_matches('/'));
     let repx_dir = project.join(".repx");
     
     let mut cleaned = Vec::new();
     
     if target_dir.exists() {
         fs::remove_dir_all(&target_dir).await?;
-        cleaned.push("target/");
+        cleaned.push(target_label.as_str());
     }
     
     if repx_dir.exists() {
@@ -190,6 +658,327 @@ async fn clean_directories(project_path: &str) -> Result<()> {
     Ok(())
 }
 
+fn format_bytes(bytes: u64) -> String {
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    if mb < 1.0 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else if mb >= 1024.0 {
+        format!("{:.2} GB", mb / 1024.0)
+    } else {
+        format!("{:.1} MB", mb)
+    }
+}
+
+async fn run_cache_command(action: &CacheAction, volume: Option<String>, config_path: Option<String>) -> Result<()> {
+    let config = load_config(config_path, ".").await?;
+    let volume = volume
+        .or(config.nix_store_cache.clone())
+        .unwrap_or_else(|| DEFAULT_STORE_VOLUME.to_string());
+
+    match action {
+        CacheAction::Stats => {
+            let stats = cache_stats(&config, &volume).await?;
+            println!("{}{}Nix store cache:{} {}", BOLD, CYAN, RESET, stats.volume);
+            println!("   - Store paths: {}", stats.store_paths);
+            println!("   - Total size: {}", format_bytes(stats.total_bytes));
+        }
+        CacheAction::Clean { keep_days } => {
+            let output = cache_clean(&config, &volume, *keep_days).await?;
+            if let Some(summary) = output.lines().rev().find(|line| line.contains("freed")) {
+                println!("{}{}Cleaned cache {}:{} {}", BOLD, GREEN, volume, RESET, summary.trim());
+            } else {
+                println!("{}{}Cleaned cache:{} {}", BOLD, GREEN, RESET, volume);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Targets for subcommands other than build: `--targets`, else those from repx.toml, else the host target
+fn resolve_targets(targets: Option<&str>, config: &RepxConfig) -> Result<String> {
+    let target_string = targets
+        .map(str::to_string)
+        .or_else(|| config.targets.clone())
+        .unwrap_or_else(|| get_host_target().to_string());
+    if let Some(unknown) = target_string.split(',').map(str::trim).find(|t| !AVAILABLE_TARGETS.contains(t)) {
+        return Err(anyhow::anyhow!("Invalid target: {}", unknown));
+    }
+    Ok(target_string)
+}
+
+async fn prefetch(project: &str, targets: Option<&str>, nix_store_cache: Option<String>, config_path: Option<String>) -> Result<()> {
+    let config = load_config(config_path, project).await?;
+    let config = RepxConfig { project: project.to_string(), nix_store_cache: nix_store_cache.or(config.nix_store_cache.clone()), ..config };
+    let target_string = resolve_targets(targets, &config)?;
+    let targets: Vec<&str> = target_string.split(',').map(str::trim).collect();
+
+    let volume = config.nix_store_cache.clone().unwrap_or_else(|| DEFAULT_STORE_VOLUME.to_string());
+    println!("{}{}Prefetching {} into store cache volume {}{}", BOLD, CYAN, targets.join(", "), volume, RESET);
+    prefetch_with_nix(&config, &targets).await?;
+    println!("{}{}Build with --nix-store-cache {} to reuse the prefetched store{}", BOLD, GREEN, volume, RESET);
+    Ok(())
+}
+
+async fn run_verify(project: &str, targets: Option<&str>, config_path: Option<String>) -> Result<()> {
+    let config = RepxConfig { project: project.to_string(), ..load_config(config_path, project).await? };
+    let target_string = resolve_targets(targets, &config)?;
+    let targets: Vec<&str> = target_string.split(',').map(str::trim).collect();
+
+    println!("{}{}Verifying that {} build reproducibly{}", BOLD, CYAN, targets.join(", "), RESET);
+    let result = verify_reproducible(&config, &targets).await?;
+    println!("\n{}{}Reproducibility report{} (builds {} and {}):", BOLD, CYAN, RESET, result.first.build_id, result.second.build_id);
+    for target in result.targets() {
+        if target.mismatched.is_empty() {
+            println!("   - {}: {}reproducible{} ({} files identical)", target.target, GREEN, RESET, target.files);
+        } else {
+            println!("   - {}: {}NOT reproducible{} ({} of {} files differ)", target.target, RED, RESET, target.mismatched.len(), target.files);
+            for path in &target.mismatched {
+                println!("       {}", path);
+            }
+        }
+    }
+    if !result.is_reproducible() {
+        println!("\n{}{}Troubleshooting tips:{}", BOLD, YELLOW, RESET);
+        println!("   - Common causes are embedded timestamps, absolute build paths and unordered iteration in build scripts");
+        println!("   - repx diff-builds {} {} compares the two builds; diffoscope shows how the files differ", result.first.build_id, result.second.build_id);
+        let files = result.mismatches.iter().map(|m| format!("{}/{}", m.target, m.path)).collect();
+        return Err(RepxError::NotReproducible { files }.into());
+    }
+    println!("\n{}{}All artifacts are bit-for-bit identical{}", BOLD, GREEN, RESET);
+    Ok(())
+}
+
+async fn run_benchmark(project: &str, targets: Option<&str>, volume: &str, config_path: Option<String>) -> Result<()> {
+    let config = RepxConfig { project: project.to_string(), ..load_config(config_path, project).await? };
+    let target_string = resolve_targets(targets, &config)?;
+    let targets: Vec<&str> = target_string.split(',').map(str::trim).collect();
+
+    println!("{}{}Benchmarking {} with store cache volume {}{}", BOLD, CYAN, targets.join(", "), volume, RESET);
+    let result = run_bench(&config, &targets, volume).await?;
+    println!("\n{}{}Cold vs. warm build:{}", BOLD, GREEN, RESET);
+    print!("{}", result.table());
+    Ok(())
+}
+
+async fn reap_containers(idle_hours: u64, docker_host: Option<String>, config_path: Option<String>) -> Result<()> {
+    let config = load_config(config_path, ".").await?;
+    let config = RepxConfig { docker_host: docker_host.or(config.docker_host.clone()), ..config };
+    config.validate_docker_host()?;
+    let reaped = reap_idle_containers(&config, Duration::from_secs(idle_hours * 3600)).await?;
+    if reaped.is_empty() {
+        println!("{}{}No containers idle for more than {} hours.{}", BOLD, GREEN, idle_hours, RESET);
+    }
+    for name in reaped {
+        println!("{}{}Removed idle container:{} {}", BOLD, GREEN, RESET, name);
+    }
+    Ok(())
+}
+
+async fn list_targets(project: &str, installed: bool, config_path: Option<String>) -> Result<()> {
+    if !installed {
+        print_available_targets();
+        return Ok(());
+    }
+    let config = RepxConfig { project: project.to_string(), ..load_config(config_path, project).await? };
+    let packages = flake_packages(&config).await?;
+
+    println!("\n{}{}Packages exposed by .repx/flake.nix:{}", BOLD, CYAN, RESET);
+    for (system, names) in &packages {
+        println!("  {}:", system);
+        if names.is_empty() {
+            println!("     - (none)");
+        }
+        for name in names {
+            if AVAILABLE_TARGETS.contains(&name.as_str()) {
+                println!("     - {}: {}", name, target_description(name));
+            } else {
+                println!("     - {} (not a repx target)", name);
+            }
+        }
+    }
+    let missing: Vec<&str> = AVAILABLE_TARGETS
+        .iter()
+        .copied()
+        .filter(|target| !packages.values().any(|names| names.iter().any(|name| name == target)))
+        .collect();
+    if !missing.is_empty() {
+        println!("{}{}Not exposed on any system:{} {}", BOLD, YELLOW, RESET, missing.join(", "));
+    }
+    Ok(())
+}
+
+async fn list_images(tags: bool, limit: usize) -> Result<()> {
+    let configured = RepxConfig::default().image;
+    println!("{}{}Recommended Nix images:{}", BOLD, CYAN, RESET);
+    println!("  {:<28} {:<9} {:<8} {:<7} NOTES", "IMAGE", "SIZE", "PINNED", "FLAKES");
+    for image in RECOMMENDED_IMAGES {
+        let default = if image.image == configured { " (default)" } else { "" };
+        println!(
+            "  {:<28} {:<9} {:<8} {:<7} {}{}",
+            image.image,
+            image.size,
+            if image.pinned { "yes" } else { "no" },
+            if image.flakes_enabled { "on" } else { "off" },
+            image.notes,
+            default
+        );
+    }
+    println!("Pick a pinned image for reproducible builds, e.g. `repx build --image {}` or `image` in repx.toml.", RECOMMENDED_IMAGES[0].image);
+    println!("repx enables flakes for its own commands, so images with flakes off work too.");
+
+    if tags {
+        println!("\n{}{}Recent tags of {}:{}", BOLD, CYAN, TAGGED_REPOSITORY, RESET);
+        for tag in list_image_tags(limit).await? {
+            let size = tag.size_bytes.map(format_bytes).unwrap_or_default();
+            let updated = tag.last_updated.as_deref().and_then(|t| t.get(..10)).unwrap_or("");
+            println!("  {}:{:<20} {:<10} {}", TAGGED_REPOSITORY, tag.name, size, updated);
+        }
+    }
+    Ok(())
+}
+
+/// Migrate the `-c` config file, or else the nearest repx.toml, found as `repx build` finds it
+async fn upgrade_config(config_path: Option<&str>) -> Result<()> {
+    let config_file = match config_path {
+        Some(path) => path.to_string(),
+        None => RepxConfig::discover_config_path(Path::new("."))
+            .map_or_else(|| RepxConfig::default_config_path().to_string(), |path| path.display().to_string()),
+    };
+    let config_file = config_file.as_str();
+    let text = fs::read_to_string(config_file).await
+        .map_err(|e| anyhow::anyhow!("Could not read {}: {}", config_file, e))?;
+    let (upgraded, from) = upgrade_config_text(&text)?;
+
+    if from == CONFIG_VERSION {
+        println!("{}{}{} is already at config version {}{}", BOLD, GREEN, config_file, CONFIG_VERSION, RESET);
+        return Ok(());
+    }
+    fs::write(config_file, upgraded).await?;
+    println!("{}{}Upgraded {}:{} version {} -> {}", BOLD, GREEN, config_file, RESET, from, CONFIG_VERSION);
+    Ok(())
+}
+
+async fn show_logs(project: &str, build_id: Option<&str>, list: bool) -> Result<()> {
+    let repx_dir = Path::new(project).join(".repx");
+
+    if list {
+        let logs = list_build_logs(&repx_dir).await?;
+        if logs.is_empty() {
+            println!("{}{}No build logs found.{}", BOLD, YELLOW, RESET);
+        }
+        for (build_id, path) in logs {
+            // The report next to the log has the build's metadata, if it got far enough to write one
+            let meta = match BuildReport::read(&repx_dir, &build_id).await {
+                Ok(report) => report.meta.iter().map(|(key, value)| format!("  {}={}", key, value)).collect(),
+                Err(_) => String::new(),
+            };
+            println!("{}  {}{}", build_id, path.display(), meta);
+        }
+        return Ok(());
+    }
+
+    print!("{}", read_build_log(&repx_dir, build_id).await?);
+    Ok(())
+}
+
+/// Print what each target would build and fetch, with the derivations to build by name
+fn print_dry_run(summaries: &[DryRunSummary]) {
+    println!("\n{}{}Dry run:{}", BOLD, CYAN, RESET);
+    for summary in summaries {
+        let download = summary.download_mib.map(|mib| format!(" ({:.1} MiB download)", mib)).unwrap_or_default();
+        println!("   - {}: {} to build, {} to fetch{}", summary.target, summary.to_build.len(), summary.to_fetch.len(), download);
+        for path in &summary.to_build {
+            // Drop the /nix/store/<hash>- prefix
+            let name = path.rsplit('/').next().and_then(|name| name.split_once('-')).map_or(path.as_str(), |(_, name)| name);
+            println!("       {}", name);
+        }
+    }
+}
+
+/// Built-in artifact processors enabled in the config, in the order they must run
+fn artifact_processors(config: &RepxConfig) -> Result<Vec<Box<dyn ArtifactProcessor>>, RepxError> {
+    let mut processors: Vec<Box<dyn ArtifactProcessor>> = Vec::new();
+    if config.strip {
+        processors.push(Box::new(StripProcessor::default()));
+    }
+    if config.checksums {
+        processors.push(Box::new(ChecksumProcessor));
+    }
+    if let Some(backend) = &config.sign {
+        processors.push(Box::new(SignProcessor::new(backend.parse()?, config.sign_key_file.as_deref())?));
+    }
+    Ok(processors)
+}
+
+async fn show_build_diff(project: &str, old_id: &str, new_id: &str) -> Result<()> {
+    let repx_dir = Path::new(project).join(".repx");
+    let old = BuildReport::read(&repx_dir, old_id).await?;
+    let new = BuildReport::read(&repx_dir, new_id).await?;
+    let diff = diff_builds(&old, &new);
+    let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+
+    println!("{}{}Comparing builds {} and {}{}", BOLD, CYAN, old_id, new_id, RESET);
+    println!("   - Status: {} -> {}",
+        if old.success { "success" } else { "failed" },
+        if new.success { "success" } else { "failed" });
+
+    println!("\n{}Settings:{}", BOLD, RESET);
+    if diff.config.is_empty() {
+        println!("   unchanged");
+    }
+    for change in &diff.config {
+        println!("   - {}: {} -> {}", change.key, show(&change.old), show(&change.new));
+    }
+
+    println!("\n{}Targets:{}", BOLD, RESET);
+    if diff.added_targets.is_empty() && diff.removed_targets.is_empty() {
+        println!("   unchanged ({})", new.targets.join(", "));
+    }
+    for target in &diff.removed_targets {
+        println!("   {}- {}{}", RED, target, RESET);
+    }
+    for target in &diff.added_targets {
+        println!("   {}+ {}{}", GREEN, target, RESET);
+    }
+
+    println!("\n{}Artifacts:{}", BOLD, RESET);
+    if old.artifacts.is_empty() || new.artifacts.is_empty() {
+        println!("   {}not recorded for both builds{}", YELLOW, RESET);
+    } else if diff.artifacts.is_empty() {
+        println!("   identical");
+    }
+    for change in &diff.artifacts {
+        let size = |info: &Option<ArtifactInfo>| info.as_ref().map_or("-".to_string(), |i| format_bytes(i.size));
+        let delta = change.size_delta();
+        let sign = if delta < 0 { "-" } else { "+" };
+        let note = match (&change.old, &change.new) {
+            (Some(_), Some(_)) => "checksum changed",
+            (None, _) => "added",
+            (_, None) => "removed",
+        };
+        println!(
+            "   - {}/{}: {} -> {} ({}{}), {}",
+            change.target, change.path, size(&change.old), size(&change.new), sign, format_bytes(delta.unsigned_abs()), note
+        );
+    }
+    Ok(())
+}
+
+/// Point stdout at stderr, returning a handle to the original stdout
+#[cfg(unix)]
+fn redirect_stdout_to_stderr() -> Result<std::fs::File> {
+    std::io::stdout().flush()?;
+    let original = nix::unistd::dup(std::io::stdout())?;
+    nix::unistd::dup2_stdout(std::io::stderr())?;
+    Ok(std::fs::File::from(original))
+}
+
+#[cfg(not(unix))]
+fn redirect_stdout_to_stderr() -> Result<std::fs::File> {
+    Err(anyhow::anyhow!("--stdout and --format json are only supported on Unix hosts"))
+}
+
 async fn init_config(force: bool) -> Result<()> {
     let config_path = RepxConfig::default_config_path();
     
@@ -213,31 +1002,46 @@ async fn main() -> Result<()> {
     let cli = Cli::parse();
     
     match &cli {
-        Cli::Build { list_targets, config, .. } => {
+        Cli::Build { list_targets, config, project, manifest_path, stdout, dry_run_nix, format, source, source_sha256, .. } => {
             if *list_targets {
                 print_available_targets();
                 return Ok(());
             }
 
             // Load configuration from file if it exists
-            let base_config = load_config(config.clone()).await?;
+            let search_from = match (project, manifest_path) {
+                (Some(project), _) => project.clone(),
+                (None, Some(manifest)) => match Path::new(manifest).parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir.display().to_string(),
+                    _ => ".".to_string(),
+                },
+                (None, None) => ".".to_string(),
+            };
+            let base_config = load_config(config.clone(), &search_from).await?;
             
             // Merge with command line arguments
-            let final_config = merge_config_with_args(base_config, &cli);
+            let mut final_config = merge_config_with_args(base_config, &cli)?;
+
+            // Fetch remote sources into .repx/sources and build them as the project
+            if let Some(source) = source {
+                let project = fetch_source(source, source_sha256.as_deref(), Path::new(".repx/sources")).await?;
+                final_config.project = project.display().to_string();
+            }
 
             let project_path = Path::new(&final_config.project);
             if !project_path.exists() {
                 eprintln!("{}{}ERROR:{} Project path '{}' does not exist", BOLD, RED, RESET, final_config.project);
                 return Err(anyhow::anyhow!("Invalid project path"));
             }
-            let cargo_path = project_path.join("Cargo.toml");
+            final_config.validate_manifest_path()?;
+            let cargo_path = final_config.manifest_path();
             if !cargo_path.exists() {
-                eprintln!("{}{}ERROR:{} No Cargo.toml found in '{}' - is this a Rust project?", BOLD, RED, RESET, final_config.project);
+                eprintln!("{}{}ERROR:{} No Cargo manifest found at '{}' - is this a Rust project?", BOLD, RED, RESET, cargo_path.display());
                 return Err(anyhow::anyhow!("Missing Cargo.toml"));
             }
             
             // Determine targets to build
-            let target_string = match final_config.targets {
+            let target_string = match final_config.targets.clone() {
                 Some(t) => t,
                 None => {
                     let host_target = get_host_target();
@@ -255,50 +1059,241 @@ async fn main() -> Result<()> {
                 }
             }
 
+            // In pipe mode stdout carries only the executable, so everything printed goes to stderr
+            let mut piped_stdout = None;
+            if *stdout {
+                if t.len() != 1 || !final_config.rust_versions.is_empty() || final_config.image_output.is_some() {
+                    eprintln!("{}{}ERROR:{} --stdout builds exactly one target, without --rust-versions or --image-output", BOLD, RED, RESET);
+                    return Err(anyhow::anyhow!("--stdout needs a single target"));
+                }
+                piped_stdout = Some(redirect_stdout_to_stderr()?);
+            }
+            // Likewise with --format json, where stdout carries only the report
+            let json_stdout = if format == "json" { Some(redirect_stdout_to_stderr()?) } else { None };
+
             println!("{}{}Configuration:{}", BOLD, CYAN, RESET);
             println!("   - Project: {}", final_config.project);
             println!("   - Docker Image: {}", final_config.image);
-            println!("   - Rust: {} {}", final_config.rust_channel, final_config.rust_version);
+            if final_config.rust_versions.is_empty() {
+                println!("   - Rust: {} {}", final_config.rust_channel, final_config.rust_version);
+            } else {
+                println!("   - Rust: {} (matrix)", final_config.rust_versions.join(", "));
+            }
+            for (target, toolchain) in &final_config.target_toolchains()? {
+                println!("   - Rust for {}: {} {}", target, toolchain.channel, toolchain.version);
+            }
             println!("   - nixpkgs: {}", final_config.nixpkgs_url);
+            if final_config.no_flake_lock {
+                println!("   - flake.lock: skipped (pinned inputs only)");
+            }
+            println!("   - Cargo profile: {}", final_config.profile);
             println!("   - Targets: {:?}", t);
             println!("   - Extra packages: {}", if final_config.extra.is_empty() { "none".to_string() } else {
                 final_config.extra.join(", ")
             });
 
+            if *dry_run_nix {
+                println!("\n{}{}Evaluating the build with Nix inside Docker...{}", BOLD, MAGENTA, RESET);
+                let report = dry_run_with_nix(&final_config, &t).await?;
+                print_dry_run(&report.dry_run);
+                return Ok(());
+            }
+
             println!("\n{}{}Building project with Nix inside Docker...{}", BOLD, MAGENTA, RESET);
 
-            let build_result = build_with_nix(&final_config.image, &final_config.project, &t, final_config.extra, &final_config.rust_channel, &final_config.rust_version, &final_config.nixpkgs_url).await;
+            let processors = artifact_processors(&final_config)?;
+            let (report, build_result) = build_with_nix_report(&final_config, &t, &processors).await;
+            if let Some(mut out) = json_stdout {
+                serde_json::to_writer_pretty(&mut out, &report)?;
+                writeln!(out)?;
+                out.flush()?;
+            }
+            let build_result = build_result.map(|()| report);
 
             match build_result {
-                Ok(_) => {
+                Ok(report) => {
                     println!("\n{}{}Build completed successfully!{}", BOLD, GREEN, RESET);
-                    let target_path = Path::new(&final_config.project).join("target/repx");
+                    if report.has_drift() {
+                        println!("{}{}NOTE:{} flake.nix or flake.lock drifted from the existing files (flake: {:?}, lock: {:?})",
+                            BOLD, YELLOW, RESET, report.flake_status, report.lock_status);
+                    }
+                    if let Some(rev) = &report.nixpkgs_rev {
+                        println!("{}{}nixpkgs revision:{} {}", BOLD, CYAN, RESET, rev);
+                    }
+                    if !report.phases.is_empty() {
+                        println!("{}{}Phase timings:{}", BOLD, CYAN, RESET);
+                        for phase in &report.phases {
+                            println!("   - {}: {:.1}s", phase.name, phase.seconds);
+                        }
+                    }
+                    if let Some(passed) = report.flake_check {
+                        println!("{}{}nix flake check:{} {}", BOLD, CYAN, RESET, if passed { "passed" } else { "failed" });
+                    }
+                    if let Some(audit) = &report.audit {
+                        println!("{}{}Audit:{} {} known vulnerabilit(y/ies) in Cargo.lock", BOLD, CYAN, RESET, audit.findings.len());
+                    }
+                    let artifacts_dir = final_config.artifacts_dir();
+                    let target_path = Path::new(&final_config.project).join(&artifacts_dir);
 
                     if target_path.exists() {
                         println!("{}{}Build artifacts are available in:{}", BOLD, CYAN, RESET);
-                        println!("   - target/repx/ directory");
-                    } else {
-                        println!("\n{}{}WARNING:{} No build artifacts found in target/repx", BOLD, YELLOW, RESET);
+                        println!("   - {}/ directory", artifacts_dir);
+                    } else if final_config.builds_targets() {
+                        println!("\n{}{}WARNING:{} No build artifacts found in {}", BOLD, YELLOW, RESET, artifacts_dir);
                         println!("This could indicate that all builds failed or no artifacts were produced");
                     }
+                    if let Some(mut out) = piped_stdout {
+                        let (target, executable) = report.single_executable()?;
+                        let path = target_path.join(target).join(&executable.path);
+                        println!("{}{}Writing to stdout:{} {}", BOLD, CYAN, RESET, path.display());
+                        out.write_all(&fs::read(&path).await?)?;
+                        out.flush()?;
+                    }
                     Ok(())
                 },
                 Err(e) => {
+                    if final_config.dump_logs_on_failure {
+                        dump_build_log(&final_config.project).await;
+                    }
                     eprintln!("\n{}{}Build failed:{} {}", BOLD, RED, RESET, e);
                     eprintln!("{}{}Troubleshooting tips:{}", BOLD, YELLOW, RESET);
-                    eprintln!("   - Make sure Docker is running and your user has permission to access it");
-                    eprintln!("   - Try running with the --image flag to use a different Nix image");
-                    eprintln!("   - Check the error details above for more information");
-                    eprintln!("   - Use --list-targets to see all available build targets");
-                    Err(e)
+                    match &e {
+                        RepxError::DockerUnavailable { problem, .. } => match problem {
+                            DockerProblem::NotInstalled => {
+                                eprintln!("   - Install Docker (https://docs.docker.com/get-docker/) or Docker Desktop, then start it");
+                            }
+                            DockerProblem::NotRunning => {
+                                eprintln!("   - Start the daemon, e.g. with sudo systemctl start docker, or open Docker Desktop");
+                                eprintln!("   - If it runs elsewhere, point DOCKER_HOST at it");
+                                eprintln!("   - With Podman, start its API socket (systemctl --user start podman.socket) and pass --runtime podman");
+                            }
+                            DockerProblem::PermissionDenied => {
+                                eprintln!("   - Add your user to the docker group (sudo usermod -aG docker $USER) and log in again");
+                                eprintln!("   - Or use rootless Docker and set DOCKER_HOST to its socket");
+                            }
+                            DockerProblem::Unreachable => {
+                                eprintln!("   - Make sure Docker is running and your user has permission to access it");
+                                eprintln!("   - Check DOCKER_HOST, --docker-host or REPX_RUNTIME if set");
+                            }
+                        },
+                        RepxError::ImagePullFailed { .. } => {
+                            eprintln!("   - The registry couldn't be reached; check your network connection and proxy settings");
+                            eprintln!("   - Layers that finished are kept, so running the build again resumes the pull");
+                            eprintln!("   - Raise --pull-retries on flaky networks");
+                        }
+                        RepxError::ImageNotFound { .. } => {
+                            eprintln!("   - Check the image name and tag, or use the --image flag to pick a different Nix image");
+                            eprintln!("   - For a private image, pass the registry credentials with --registry-token-file");
+                        }
+                        RepxError::ConfigInvalid(_) => {
+                            eprintln!("   - Check repx.toml and the command-line options");
+                        }
+                        RepxError::FlakeInvalid { .. } => {
+                            eprintln!("   - Check the template_vars, extra packages and package name the flake was rendered from");
+                            eprintln!("   - Rerun with --dump-context to see the variables passed to the template");
+                        }
+                        RepxError::Interrupted { signal } => {
+                            eprintln!("   - The build container was removed; run the build again to resume");
+                            std::process::exit(signal.exit_code());
+                        }
+                        RepxError::ReferenceMismatch { .. } => {
+                            eprintln!("   - The build isn't bit-identical to the reference; compare the rebuild's settings with the reference build's");
+                            eprintln!("   - The per-file results are in the build report's reference field");
+                        }
+                        RepxError::EmptyTargets { .. } => {
+                            eprintln!("   - The flake output built but contained no files; check the package's installPhase and output_attr_template");
+                            eprintln!("   - Drop --strict-targets to let such targets pass with a warning");
+                        }
+                        RepxError::InsufficientDiskSpace { .. } => {
+                            eprintln!("   - Free up disk space, e.g. with docker system prune or repx cache clean");
+                            eprintln!("   - Or lower the threshold with --min-free-space-gb (0 disables the check)");
+                        }
+                        RepxError::WarningsAsErrors { warnings } => {
+                            for warning in warnings {
+                                eprintln!("   - {}", warning);
+                            }
+                            eprintln!("   - Fix the warnings above, or drop --fail-on-warning to let them pass");
+                        }
+                        _ => {
+                            eprintln!("   - Check the error details above for more information");
+                            eprintln!("   - Use --list-targets to see all available build targets");
+                            if !final_config.explain_failure {
+                                eprintln!("   - Re-run with --explain-failure for suggestions based on the error output");
+                            }
+                        }
+                    }
+                    Err(e.into())
                 }
             }
         },
         Cli::Init { force } => {
             init_config(*force).await
         },
-        Cli::Clean { project } => {
-            clean_directories(project).await
+        Cli::Clean { project, target_dir, cache, config } => {
+            // The config is only needed for what wasn't given on the command line
+            let loaded = match (target_dir, cache) {
+                (Some(_), false) => None,
+                _ => Some(load_config(config.clone(), project).await?),
+            };
+            let target_dir = target_dir.clone().or_else(|| loaded.as_ref().map(|c| c.target_dir.clone())).unwrap_or_default();
+            clean_directories(project, &target_dir).await?;
+            if let Some(config) = loaded.filter(|_| *cache) {
+                let store_cache = config.nix_store_cache.clone().unwrap_or_else(|| DEFAULT_STORE_VOLUME.to_string());
+                if remove_store_cache(&config, &store_cache).await? {
+                    println!("{}{}Removed Nix store cache:{} {}", BOLD, GREEN, RESET, store_cache);
+                } else {
+                    println!("{}{}No Nix store cache named {} to remove.{}", BOLD, YELLOW, store_cache, RESET);
+                }
+            }
+            Ok(())
+        },
+        Cli::Cache { action, volume, config } => {
+            run_cache_command(action, volume.clone(), config.clone()).await
+        },
+        Cli::Prefetch { project, targets, nix_store_cache, config } => {
+            prefetch(project, targets.as_deref(), nix_store_cache.clone(), config.clone()).await
+        },
+        Cli::Shell { project, shell, host, config } => {
+            let config = RepxConfig { project: project.clone(), ..load_config(config.clone(), project).await? };
+            let code = dev_shell(&config, shell.as_deref(), *host).await?;
+            std::process::exit(code);
+        },
+        Cli::Exec { project, config, command } => {
+            let config = RepxConfig { project: project.clone(), ..load_config(config.clone(), project).await? };
+            let code = exec_in_container(&config, command).await?;
+            std::process::exit(code);
+        },
+        Cli::Verify { project, targets, config } => {
+            run_verify(project, targets.as_deref(), config.clone()).await
+        },
+        Cli::Bench { project, targets, volume, config } => {
+            run_benchmark(project, targets.as_deref(), volume, config.clone()).await
+        },
+        Cli::Reap { idle_hours, docker_host, config } => {
+            reap_containers(*idle_hours, docker_host.clone(), config.clone()).await
+        },
+        Cli::Targets { installed, project, config } => {
+            list_targets(project, *installed, config.clone()).await
+        },
+        Cli::ListImages { tags, limit } => {
+            list_images(*tags, *limit).await
+        },
+        Cli::UpgradeConfig { config } => {
+            upgrade_config(config.as_deref()).await
+        },
+        Cli::Logs { build_id, project, list } => {
+            show_logs(project, build_id.as_deref(), *list).await
+        },
+        Cli::DiffBuilds { old, new, project } => {
+            show_build_diff(project, old, new).await
+        },
+        Cli::Serve { project, port, host, config } => {
+            let config = load_config(config.clone(), project).await?;
+            let roots = ServeRoots {
+                metadata_dir: Path::new(project).join(".repx"),
+                artifacts_dir: Path::new(project).join(config.artifacts_dir()),
+            };
+            Ok(serve(roots, &format!("{}:{}", host, port)).await?)
         },
         Cli::Release => {
             print_version()
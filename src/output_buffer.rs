@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+/// How much command output is kept in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputLimit {
+    pub max_bytes: usize,
+    pub max_lines: usize,
+}
+
+impl OutputLimit {
+    /// Build a limit from the `max_output_mb` / `max_output_lines` config values
+    pub fn new(max_mb: usize, max_lines: usize) -> Self {
+        Self {
+            max_bytes: max_mb.saturating_mul(1024 * 1024),
+            max_lines,
+        }
+    }
+}
+
+impl Default for OutputLimit {
+    fn default() -> Self {
+        Self::new(16, 100_000)
+    }
+}
+
+/// Keeps the most recent output up to an `OutputLimit`, dropping the oldest lines first
+#[derive(Debug, Clone)]
+pub struct OutputBuffer {
+    /// Lines including their trailing newline; only the last one may be partial
+    lines: VecDeque<String>,
+    bytes: usize,
+    dropped_bytes: usize,
+    limit: OutputLimit,
+}
+
+impl OutputBuffer {
+    pub fn new(limit: OutputLimit) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            bytes: 0,
+            dropped_bytes: 0,
+            limit,
+        }
+    }
+
+    /// Append output, evicting the oldest lines if the limit is exceeded
+    pub fn push_str(&mut self, text: &str) {
+        for piece in text.split_inclusive('\n') {
+            match self.lines.back_mut() {
+                Some(last) if !last.ends_with('\n') => last.push_str(piece),
+                _ => self.lines.push_back(piece.to_string()),
+            }
+            self.bytes += piece.len();
+        }
+        self.enforce_limit();
+    }
+
+    fn enforce_limit(&mut self) {
+        while self.lines.len() > self.limit.max_lines
+            || (self.bytes > self.limit.max_bytes && self.lines.len() > 1)
+        {
+            let line = self.lines.pop_front().unwrap_or_default();
+            self.bytes -= line.len();
+            self.dropped_bytes += line.len();
+        }
+
+        // A single line longer than the whole budget keeps only its end
+        if self.bytes > self.limit.max_bytes {
+            if let Some(line) = self.lines.front_mut() {
+                let mut cut = self.bytes - self.limit.max_bytes;
+                while !line.is_char_boundary(cut) {
+                    cut += 1;
+                }
+                line.drain(..cut);
+                self.bytes -= cut;
+                self.dropped_bytes += cut;
+            }
+        }
+    }
+
+    /// Bytes evicted so far
+    pub fn dropped_bytes(&self) -> usize {
+        self.dropped_bytes
+    }
+
+    /// The retained output
+    pub fn contents(&self) -> String {
+        let mut contents = String::with_capacity(self.bytes);
+        for line in &self.lines {
+            contents.push_str(line);
+        }
+        contents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_buffer_keeps_most_recent_lines() {
+        let mut buffer = OutputBuffer::new(OutputLimit { max_bytes: 1024, max_lines: 2 });
+        buffer.push_str("one\ntw");
+        buffer.push_str("o\nthree\nerror: four");
+        assert_eq!(buffer.contents(), "three\nerror: four");
+        assert_eq!(buffer.dropped_bytes(), "one\ntwo\n".len());
+
+        let mut buffer = OutputBuffer::new(OutputLimit { max_bytes: 8, max_lines: 100 });
+        buffer.push_str("short\n");
+        buffer.push_str("a very long line\n");
+        assert_eq!(buffer.contents(), "ng line\n");
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use crate::{RepxError, BOLD, CYAN, RESET};
+
+/// Where the sources of a `--source` build come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteSource {
+    /// A git repository, at a revision if given after `#`
+    Git { url: String, rev: Option<String> },
+    /// A tarball to download over HTTP(S)
+    TarballUrl(String),
+    /// A tarball on the local filesystem
+    Tarball(PathBuf),
+}
+
+impl RemoteSource {
+    /// Parse a `--source` value: `git+<url>[#rev]` or a URL ending in `.git` is cloned,
+    /// any other `http(s)://` URL is downloaded as a tarball, and anything else is a local tarball
+    pub fn parse(source: &str) -> Result<Self, RepxError> {
+        let (location, rev) = match source.split_once('#') {
+            Some((location, rev)) if !rev.is_empty() => (location, Some(rev.to_string())),
+            _ => (source, None),
+        };
+        // git would take a revision starting with `-` for an option
+        if rev.as_deref().is_some_and(|rev| rev.starts_with('-')) {
+            return Err(RepxError::ConfigInvalid(format!("Invalid git revision in {}: revisions can't start with '-'", source)));
+        }
+        if let Some(url) = location.strip_prefix("git+") {
+            return Ok(RemoteSource::Git { url: url.to_string(), rev });
+        }
+        if location.ends_with(".git") || location.starts_with("git@") || location.starts_with("ssh://") {
+            return Ok(RemoteSource::Git { url: location.to_string(), rev });
+        }
+        if rev.is_some() {
+            return Err(RepxError::ConfigInvalid(format!("Only git sources take a #revision: {}", source)));
+        }
+        if source.starts_with("https://") || source.starts_with("http://") {
+            return Ok(RemoteSource::TarballUrl(source.to_string()));
+        }
+        let path = PathBuf::from(source);
+        if !path.is_file() {
+            return Err(RepxError::ConfigInvalid(format!(
+                "Source {} is neither a URL nor a tarball on disk",
+                source
+            )));
+        }
+        Ok(RemoteSource::Tarball(path))
+    }
+}
+
+/// Run a host program, failing with its stderr if it exits unsuccessfully
+async fn run(program: &str, args: &[&std::ffi::OsStr]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("Could not run {}; is it installed?", program))?;
+    if !output.status.success() {
+        anyhow::bail!("{} exited with {}: {}", program, output.status, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Check a file against an expected SHA-256, given as hex
+fn verify_sha256(path: &Path, expected: &str) -> Result<()> {
+    let expected = expected.trim().to_ascii_lowercase();
+    if expected.len() != 64 || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(RepxError::ConfigInvalid(format!("Invalid SHA-256 '{}'; expected 64 hex digits", expected)).into());
+    }
+    let actual = format!("{:x}", Sha256::digest(std::fs::read(path)?));
+    if actual != expected {
+        anyhow::bail!("SHA-256 mismatch for {}: expected {}, got {}", path.display(), expected, actual);
+    }
+    Ok(())
+}
+
+/// The directory a tarball was extracted to: its only top-level directory, as in most release tarballs
+fn source_root(extracted: &Path) -> Result<PathBuf> {
+    let entries: Vec<PathBuf> = std::fs::read_dir(extracted)?.map(|entry| entry.map(|e| e.path())).collect::<Result<_, _>>()?;
+    match entries.as_slice() {
+        [only] if only.is_dir() => Ok(only.clone()),
+        _ => Ok(extracted.to_path_buf()),
+    }
+}
+
+/// Fetch `source` into a scratch directory under `scratch_root`, returning the project directory to build
+///
+/// Tarballs are checked against `sha256` when given; git sources are pinned by their revision instead.
+/// The scratch directory is named after a hash of the source, and replaced if it exists.
+pub async fn fetch_source(source: &str, sha256: Option<&str>, scratch_root: &Path) -> Result<PathBuf, RepxError> {
+    Ok(fetch(source, sha256, scratch_root).await?)
+}
+
+async fn fetch(source: &str, sha256: Option<&str>, scratch_root: &Path) -> Result<PathBuf> {
+    let remote = RemoteSource::parse(source)?;
+    if sha256.is_some() && matches!(remote, RemoteSource::Git { .. }) {
+        return Err(RepxError::ConfigInvalid("--source-sha256 only applies to tarballs; pin git sources with #<rev>".to_string()).into());
+    }
+
+    tokio::fs::create_dir_all(scratch_root).await?;
+    // Keep fetched sources out of the enclosing repository's status
+    tokio::fs::write(scratch_root.join(".gitignore"), "*\n").await?;
+    let digest = format!("{:x}", Sha256::digest(source.as_bytes()));
+    let scratch = scratch_root.join(&digest[..16]);
+    if scratch.exists() {
+        tokio::fs::remove_dir_all(&scratch).await?;
+    }
+
+    println!("{}{}Fetching source:{} {}", BOLD, CYAN, RESET, source);
+    let project = match &remote {
+        RemoteSource::Git { url, rev } => {
+            let mut args: Vec<&std::ffi::OsStr> = vec!["clone".as_ref(), "--quiet".as_ref()];
+            if rev.is_none() {
+                args.push("--depth=1".as_ref());
+            }
+            // After `--`, a URL starting with `-` can't pass for an option such as --upload-pack
+            args.extend(["--".as_ref(), url.as_ref(), scratch.as_os_str()]);
+            run("git", &args).await?;
+            if let Some(rev) = rev {
+                run("git", &["-C".as_ref(), scratch.as_os_str(), "checkout".as_ref(), "--quiet".as_ref(), rev.as_ref(), "--".as_ref()]).await?;
+            }
+            scratch
+        }
+        RemoteSource::TarballUrl(_) | RemoteSource::Tarball(_) => {
+            let tarball = match &remote {
+                RemoteSource::TarballUrl(url) => {
+                    let download = scratch_root.join(format!("{}.tar", &digest[..16]));
+                    run("curl", &["--fail".as_ref(), "--silent".as_ref(), "--show-error".as_ref(), "--location".as_ref(), "--output".as_ref(), download.as_os_str(), url.as_ref()]).await?;
+                    download
+                }
+                _ => PathBuf::from(source),
+            };
+            if let Some(expected) = sha256 {
+                verify_sha256(&tarball, expected)?;
+            }
+            tokio::fs::create_dir_all(&scratch).await?;
+            run("tar", &["-xf".as_ref(), tarball.as_os_str(), "-C".as_ref(), scratch.as_os_str()]).await?;
+            if matches!(remote, RemoteSource::TarballUrl(_)) {
+                tokio::fs::remove_file(&tarball).await?;
+            }
+            source_root(&scratch)?
+        }
+    };
+
+    if !project.join("Cargo.toml").is_file() {
+        return Err(RepxError::ConfigInvalid(format!("Source {} has no Cargo.toml at its root", source)).into());
+    }
+    println!("{}{}Source extracted to:{} {}", BOLD, CYAN, RESET, project.display());
+    Ok(project)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote_source() {
+        assert_eq!(
+            RemoteSource::parse("git+https://example.com/demo#v1.0.0").unwrap(),
+            RemoteSource::Git { url: "https://example.com/demo".to_string(), rev: Some("v1.0.0".to_string()) }
+        );
+        assert_eq!(
+            RemoteSource::parse("https://example.com/demo.git").unwrap(),
+            RemoteSource::Git { url: "https://example.com/demo.git".to_string(), rev: None }
+        );
+        assert_eq!(
+            RemoteSource::parse("https://example.com/demo-1.0.0.tar.gz").unwrap(),
+            RemoteSource::TarballUrl("https://example.com/demo-1.0.0.tar.gz".to_string())
+        );
+        assert!(RemoteSource::parse("https://example.com/demo.tar.gz#v1").is_err());
+        assert!(RemoteSource::parse("/does/not/exist.tar.gz").is_err());
+        assert!(RemoteSource::parse("git+https://example.com/demo#--upload-pack=touch").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_local_tarball() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = dir.path().join("demo-1.0.0");
+        std::fs::create_dir(&package).unwrap();
+        std::fs::write(package.join("Cargo.toml"), "[package]\nname = \"demo\"\n").unwrap();
+        let tarball = dir.path().join("demo-1.0.0.tar.gz");
+        let status = std::process::Command::new("tar")
+            .arg("-czf").arg(&tarball).arg("-C").arg(dir.path()).arg("demo-1.0.0")
+            .status().unwrap();
+        assert!(status.success());
+        let source = tarball.to_str().unwrap();
+        let scratch = dir.path().join("sources");
+
+        let sha256 = format!("{:x}", Sha256::digest(std::fs::read(&tarball).unwrap()));
+        let project = fetch_source(source, Some(&sha256), &scratch).await.unwrap();
+        assert!(project.ends_with("demo-1.0.0"));
+        assert!(project.join("Cargo.toml").is_file());
+
+        assert!(fetch_source(source, Some(&"0".repeat(64)), &scratch).await.is_err());
+        assert!(fetch_source(source, Some("abc"), &scratch).await.is_err());
+    }
+}
@@ -0,0 +1,327 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::audit::AuditReport;
+use crate::diagnostics::Diagnostic;
+use crate::file_comparison::DriftStatus;
+use crate::RepxError;
+
+/// A file copied out of the Nix store for a target
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactInfo {
+    /// Path relative to the target's artifact directory
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl ArtifactInfo {
+    /// Whether this is an executable, i.e. under `bin/`, or a `.wasm` module
+    pub fn is_executable(&self) -> bool {
+        self.path.starts_with("bin/") || self.path.ends_with(".wasm")
+    }
+}
+
+/// Outcome of one target built with one toolchain of the `rust_versions` matrix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatrixResult {
+    pub rust_version: String,
+    pub target: String,
+    /// Whether the target built and its artifacts were copied
+    pub success: bool,
+}
+
+/// What `nix build --dry-run` reported for one target
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DryRunSummary {
+    pub target: String,
+    /// Derivations that would be built locally
+    pub to_build: Vec<String>,
+    /// Store paths that would be fetched from a binary cache
+    pub to_fetch: Vec<String>,
+    /// Size of the fetched paths, as reported by Nix
+    pub download_mib: Option<f64>,
+}
+
+/// How a built artifact compares with the reference set of `compare_with`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceStatus {
+    /// Same SHA-256 as the reference file
+    Match,
+    Mismatch,
+    /// Built, but the reference has no such file
+    MissingFromReference,
+    /// In the reference, but not built
+    MissingFromBuild,
+}
+
+/// One file compared against the reference set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferenceComparison {
+    pub target: String,
+    /// Path relative to the target's artifact directory
+    pub path: String,
+    pub status: ReferenceStatus,
+}
+
+/// How long one phase of a build took
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    /// Phase name, e.g. `image_pull` or `build:x86_64-linux-gnu`
+    pub name: String,
+    pub seconds: f64,
+}
+
+impl PhaseTiming {
+    /// Time from `started` until now, to a tenth of a second
+    pub fn since(name: &str, started: Instant) -> Self {
+        let seconds = (started.elapsed().as_secs_f64() * 10.0).round() / 10.0;
+        PhaseTiming { name: name.to_string(), seconds }
+    }
+}
+
+/// Structured result of a build, for library callers and machine-readable output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildReport {
+    /// Unique ID of the build, matching its log file; empty if the build failed before logging started
+    pub build_id: String,
+    /// Log file with every command executed and its output
+    pub log_file: PathBuf,
+    /// Targets that were requested
+    pub targets: Vec<String>,
+    /// Whether every step of the build succeeded
+    pub success: bool,
+    /// Targets that failed to build or copy
+    pub failed_targets: Vec<String>,
+    /// Targets that built only when retried, with `retry_failed_targets`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retried_targets: Vec<String>,
+    /// Why the build failed, if it did
+    pub error: Option<String>,
+    /// Commit the sources were built from, `dirty` for uncommitted changes, or none outside git
+    pub git_commit: Option<String>,
+    /// nixpkgs revision pinned in flake.lock, if a lock was used
+    #[serde(default)]
+    pub nixpkgs_rev: Option<String>,
+    /// `SOURCE_DATE_EPOCH` the artifacts were built with, if one was set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_date_epoch: Option<u64>,
+    /// Metadata attached with `--meta` or `[meta]` in repx.toml, e.g. the CI run that built it
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+    /// Settings the build ran with, such as the Rust version and nixpkgs revision
+    #[serde(default)]
+    pub config: BTreeMap<String, String>,
+    /// Whether the generated flake.nix matched the existing one
+    pub flake_status: DriftStatus,
+    /// Whether the regenerated flake.lock matched the existing one
+    pub lock_status: DriftStatus,
+    /// Container image tarballs built with `image_output`
+    pub images: Vec<PathBuf>,
+    /// Store paths in the runtime closure of each target, with `record_closure`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub closures: BTreeMap<String, Vec<String>>,
+    /// Vulnerabilities found in Cargo.lock, with `audit`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audit: Option<AuditReport>,
+    /// Whether `nix flake check` passed, if it ran
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flake_check: Option<bool>,
+    /// Time spent in each phase of the build, in the order the phases ran
+    #[serde(default)]
+    pub phases: Vec<PhaseTiming>,
+    /// Result of each target with each toolchain, with `rust_versions`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matrix: Vec<MatrixResult>,
+    /// What each target would build and fetch, from a dry run or prefetch
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dry_run: Vec<DryRunSummary>,
+    /// Every artifact compared with the reference set, with `compare_with`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reference: Vec<ReferenceComparison>,
+    /// Rust compiler errors from the targets that failed to build
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Warnings emitted during the build, such as flake drift
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// Files copied for each target, with their sizes and checksums
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub artifacts: BTreeMap<String, Vec<ArtifactInfo>>,
+}
+
+impl BuildReport {
+    /// Create a report for a build that hasn't compared any files yet
+    pub fn new(build_id: &str, log_file: PathBuf, targets: &[&str]) -> Self {
+        Self {
+            build_id: build_id.to_string(),
+            log_file,
+            targets: targets.iter().map(|t| t.to_string()).collect(),
+            success: false,
+            failed_targets: Vec::new(),
+            retried_targets: Vec::new(),
+            error: None,
+            git_commit: None,
+            nixpkgs_rev: None,
+            source_date_epoch: None,
+            meta: BTreeMap::new(),
+            flake_check: None,
+            config: BTreeMap::new(),
+            flake_status: DriftStatus::Created,
+            lock_status: DriftStatus::Created,
+            images: Vec::new(),
+            closures: BTreeMap::new(),
+            audit: None,
+            phases: Vec::new(),
+            matrix: Vec::new(),
+            dry_run: Vec::new(),
+            reference: Vec::new(),
+            diagnostics: Vec::new(),
+            warnings: Vec::new(),
+            artifacts: BTreeMap::new(),
+        }
+    }
+
+    /// Whether the existing flake.nix or flake.lock drifted from what was generated
+    pub fn has_drift(&self) -> bool {
+        self.flake_status == DriftStatus::Drifted || self.lock_status == DriftStatus::Drifted
+    }
+
+    /// The one executable or `.wasm` module the build produced, with its target
+    ///
+    /// Used to stream a single-target build to stdout; zero or several candidates are an error.
+    pub fn single_executable(&self) -> Result<(&str, &ArtifactInfo), RepxError> {
+        let candidates: Vec<(&str, &ArtifactInfo)> = self
+            .artifacts
+            .iter()
+            .flat_map(|(target, artifacts)| artifacts.iter().map(move |a| (target.as_str(), a)))
+            .filter(|(_, a)| a.is_executable())
+            .collect();
+        match candidates.as_slice() {
+            [single] => Ok(*single),
+            [] => Err(RepxError::ConfigInvalid("--stdout needs an executable, but the build produced none".to_string())),
+            several => Err(RepxError::ConfigInvalid(format!(
+                "--stdout needs a single executable, but the build produced {}: {}",
+                several.len(),
+                several.iter().map(|(_, a)| a.path.as_str()).collect::<Vec<_>>().join(", ")
+            ))),
+        }
+    }
+
+    /// Those of `builds` that have no recorded artifacts, e.g. because their result was empty
+    pub fn builds_without_artifacts(&self, builds: &[String]) -> Vec<String> {
+        builds
+            .iter()
+            .filter(|name| self.artifacts.get(*name).is_none_or(|artifacts| artifacts.is_empty()))
+            .cloned()
+            .collect()
+    }
+
+    /// Write the report as pretty-printed JSON
+    pub async fn write_json(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(path, json + "\n").await?;
+        Ok(())
+    }
+
+    /// Record that a phase which began at `started` has just finished
+    pub fn record_phase(&mut self, name: &str, started: Instant) {
+        self.phases.push(PhaseTiming::since(name, started));
+    }
+
+    /// Where the report of a build is kept, next to its log in `.repx/logs`
+    pub fn path_for(build_dir: &Path, build_id: &str) -> PathBuf {
+        build_dir.join("logs").join(format!("build-{}.json", build_id))
+    }
+
+    /// Read the report kept for a past build
+    pub async fn read(build_dir: &Path, build_id: &str) -> Result<Self> {
+        let path = Self::path_for(build_dir, build_id);
+        let json = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| anyhow::anyhow!("No build report for {} at {}: {}", build_id, path.display(), e))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+/// Size and checksum of the file at `path`, named relative to `root`
+pub fn artifact_info(path: &Path, root: &Path) -> Result<ArtifactInfo> {
+    let data = std::fs::read(path)?;
+    Ok(ArtifactInfo {
+        path: path.strip_prefix(root)?.to_string_lossy().replace('\\', "/"),
+        size: data.len() as u64,
+        sha256: format!("{:x}", Sha256::digest(&data)),
+    })
+}
+
+/// Size and checksum of every file under a target's artifact directory, sorted by path
+pub fn collect_artifacts(dir: &Path) -> Result<Vec<ArtifactInfo>> {
+    let mut artifacts = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            artifacts.push(artifact_info(&path, dir)?);
+        }
+    }
+    artifacts.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(artifacts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_failed_report_written_as_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ci/summary.json");
+        let mut report = BuildReport::new("abc", PathBuf::from("build-abc.log"), &["x86_64-linux-gnu"]);
+        report.failed_targets = vec!["x86_64-linux-gnu".to_string()];
+        report.error = Some("Build failed for target(s): x86_64-linux-gnu".to_string());
+        report.write_json(&path).await.unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["success"], false);
+        assert_eq!(json["failed_targets"][0], "x86_64-linux-gnu");
+        assert_eq!(json["error"], "Build failed for target(s): x86_64-linux-gnu");
+    }
+
+    #[test]
+    fn test_single_executable() {
+        let artifact = |path: &str| ArtifactInfo { path: path.to_string(), size: 1, sha256: String::new() };
+        let mut report = BuildReport::new("abc", PathBuf::from("build-abc.log"), &["x86_64-linux-musl"]);
+        assert!(report.single_executable().is_err());
+
+        report.artifacts.insert("x86_64-linux-musl".to_string(), vec![artifact("bin/app"), artifact("share/doc/README")]);
+        let (target, executable) = report.single_executable().unwrap();
+        assert_eq!((target, executable.path.as_str()), ("x86_64-linux-musl", "bin/app"));
+
+        report.artifacts.get_mut("x86_64-linux-musl").unwrap().push(artifact("bin/helper"));
+        assert!(report.single_executable().is_err());
+    }
+
+    #[test]
+    fn test_builds_without_artifacts() {
+        let builds: Vec<String> = ["x86_64-linux-musl", "aarch64-linux-gnu", "wasm32-wasi"].iter().map(|t| t.to_string()).collect();
+        let mut report = BuildReport::new("abc", PathBuf::from("build-abc.log"), &["x86_64-linux-musl", "aarch64-linux-gnu", "wasm32-wasi"]);
+        report.artifacts.insert(
+            "x86_64-linux-musl".to_string(),
+            vec![ArtifactInfo { path: "bin/app".to_string(), size: 1, sha256: String::new() }],
+        );
+        report.artifacts.insert("aarch64-linux-gnu".to_string(), Vec::new());
+        assert_eq!(report.builds_without_artifacts(&builds), ["aarch64-linux-gnu", "wasm32-wasi"]);
+    }
+}
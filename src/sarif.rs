@@ -0,0 +1,116 @@
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::path::Path;
+use crate::BuildReport;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Rule ID for a rustc error without an error code
+const GENERIC_RULE: &str = "rustc";
+
+/// A SARIF 2.1.0 log with one result per rustc error in the report's diagnostics
+///
+/// Error codes become rule IDs, linked to their explanation in the rustc error index.
+/// Locations are relative to the project, resolved against `%SRCROOT%`.
+pub fn sarif_json(report: &BuildReport) -> Value {
+    let rule_ids: BTreeSet<&str> = report
+        .diagnostics
+        .iter()
+        .map(|d| d.code.as_deref().unwrap_or(GENERIC_RULE))
+        .collect();
+    let rules: Vec<Value> = rule_ids
+        .iter()
+        .map(|id| match *id {
+            GENERIC_RULE => json!({ "id": id, "shortDescription": { "text": "rustc error" } }),
+            code => json!({
+                "id": code,
+                "shortDescription": { "text": format!("rustc error {}", code) },
+                "helpUri": format!("https://doc.rust-lang.org/error_codes/{}.html", code),
+            }),
+        })
+        .collect();
+
+    let results: Vec<Value> = report
+        .diagnostics
+        .iter()
+        .map(|d| {
+            let mut result = json!({
+                "ruleId": d.code.as_deref().unwrap_or(GENERIC_RULE),
+                "level": "error",
+                "message": { "text": format!("{} ({})", d.message, d.target) },
+                "properties": { "target": d.target, "rendered": d.rendered },
+            });
+            if let Some(file) = &d.file {
+                let mut region = json!({ "startLine": d.line.unwrap_or(1) });
+                if let Some(column) = d.column {
+                    region["startColumn"] = json!(column);
+                }
+                result["locations"] = json!([{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": file, "uriBaseId": "%SRCROOT%" },
+                        "region": region,
+                    }
+                }]);
+            }
+            result
+        })
+        .collect();
+
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "repx",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+/// Write the SARIF log for a build to `path`
+pub async fn write_sarif(report: &BuildReport, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, serde_json::to_string_pretty(&sarif_json(report))?).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::parse_diagnostics;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_sarif_json() {
+        let mut report = BuildReport::new("abc", PathBuf::from("build-abc.log"), &["x86_64-linux-gnu"]);
+        report.diagnostics = parse_diagnostics(
+            "x86_64-linux-gnu",
+            "error[E0308]: mismatched types\n --> src/main.rs:4:18\n\nerror: expected `;`\n",
+        );
+
+        let sarif = sarif_json(&report);
+        assert_eq!(sarif["version"], "2.1.0");
+        let run = &sarif["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"].as_array().unwrap().len(), 2);
+        assert_eq!(run["tool"]["driver"]["rules"][0]["helpUri"], "https://doc.rust-lang.org/error_codes/E0308.html");
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["ruleId"], "E0308");
+        assert_eq!(results[0]["message"]["text"], "mismatched types (x86_64-linux-gnu)");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/main.rs");
+        assert_eq!(location["region"]["startLine"], 4);
+        assert_eq!(location["region"]["startColumn"], 18);
+        assert_eq!(results[1]["ruleId"], "rustc");
+        assert!(results[1].get("locations").is_none());
+    }
+}
@@ -0,0 +1,136 @@
+use bollard::auth::DockerCredentials;
+use std::path::Path;
+use crate::{RepxError, RESET, BOLD, YELLOW};
+
+/// Local secrets injected into every container command, relative to the `.repx` directory
+pub const SECRETS_ENV_FILE: &str = "secrets.env";
+
+/// Read a secret from a file, trimming the trailing newline
+///
+/// Errors name the file but never include its contents.
+pub async fn read_secret_file(path: &str) -> Result<String, RepxError> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| RepxError::ConfigInvalid(format!("Could not read secret file {}: {}", path, e)))?;
+    let secret = contents.trim().to_string();
+    if secret.is_empty() {
+        return Err(RepxError::ConfigInvalid(format!("Secret file {} is empty", path)));
+    }
+    warn_if_shared(Path::new(path));
+    Ok(secret)
+}
+
+/// Warn when other users on the machine can read a secret file
+#[cfg(unix)]
+pub(crate) fn warn_if_shared(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            println!(
+                "{}{}WARNING:{} {} is readable by other users; restrict it with chmod 600",
+                BOLD, YELLOW, RESET, path.display()
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn warn_if_shared(_path: &Path) {}
+
+/// Read `KEY=VALUE` pairs from a secrets env file, such as `.repx/secrets.env`
+///
+/// Blank lines and `#` comments are skipped, and values may be quoted. Errors name the
+/// file and line but never include its contents.
+pub async fn read_secrets_env(path: &Path) -> Result<Vec<String>, RepxError> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| RepxError::ConfigInvalid(format!("Could not read secrets file {}: {}", path.display(), e)))?;
+    warn_if_shared(path);
+
+    let mut env = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let valid_key = |key: &str| {
+            !key.is_empty()
+                && !key.starts_with(|c: char| c.is_ascii_digit())
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        let Some((key, value)) = line.split_once('=').filter(|(key, _)| valid_key(key.trim())) else {
+            return Err(RepxError::ConfigInvalid(format!(
+                "{} line {}: expected KEY=VALUE",
+                path.display(),
+                number + 1
+            )));
+        };
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+            .unwrap_or(value);
+        env.push(format!("{}={}", key.trim(), value));
+    }
+    Ok(env)
+}
+
+/// Registry credentials from a `username:token` secret, or a bare registry token
+pub fn registry_credentials(secret: &str) -> DockerCredentials {
+    match secret.split_once(':') {
+        Some((username, password)) => DockerCredentials {
+            username: Some(username.to_string()),
+            password: Some(password.to_string()),
+            ..Default::default()
+        },
+        None => DockerCredentials {
+            registrytoken: Some(secret.to_string()),
+            ..Default::default()
+        },
+    }
+}
+
+/// `NIX_CONFIG` entry granting Nix access to private flake inputs
+///
+/// The secret holds `host=token` pairs, separated by whitespace or newlines.
+pub fn nix_access_tokens_env(secret: &str) -> String {
+    format!("NIX_CONFIG=access-tokens = {}", secret.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// The tokens of the `host=token` pairs in a flake token secret, to redact each on its own
+pub fn nix_access_token_values(secret: &str) -> Vec<String> {
+    secret
+        .split_whitespace()
+        .map(|pair| pair.split_once('=').map_or(pair, |(_, token)| token).to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_secrets_read_from_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        tokio::fs::write(&path, "github.com=ghp_abc\ngitlab.com=glpat_def\n").await.unwrap();
+        let secret = read_secret_file(path.to_str().unwrap()).await.unwrap();
+        assert_eq!(nix_access_tokens_env(&secret), "NIX_CONFIG=access-tokens = github.com=ghp_abc gitlab.com=glpat_def");
+        assert_eq!(nix_access_token_values(&secret), ["ghp_abc", "glpat_def"]);
+
+        let credentials = registry_credentials("me:hunter2");
+        assert_eq!(credentials.username.as_deref(), Some("me"));
+        assert_eq!(credentials.password.as_deref(), Some("hunter2"));
+
+        let secrets_env = dir.path().join("secrets.env");
+        tokio::fs::write(&secrets_env, "# local only\nexport CARGO_REGISTRY_TOKEN=\"abc123\"\n\nCACHE_KEY = xyz\n").await.unwrap();
+        assert_eq!(read_secrets_env(&secrets_env).await.unwrap(), ["CARGO_REGISTRY_TOKEN=abc123", "CACHE_KEY=xyz"]);
+        tokio::fs::write(&secrets_env, "do-not-print-me\n").await.unwrap();
+        assert!(!read_secrets_env(&secrets_env).await.unwrap_err().to_string().contains("do-not-print-me"));
+
+        tokio::fs::write(&path, "do-not-print-me").await.unwrap();
+        let missing = read_secret_file(dir.path().join("missing").to_str().unwrap()).await.unwrap_err();
+        assert!(!missing.to_string().contains("do-not-print-me"));
+    }
+}
@@ -0,0 +1,289 @@
+use anyhow::Result;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::{Component, Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use crate::report::{collect_artifacts, ArtifactInfo};
+use crate::{list_build_logs, read_build_log, BuildReport, RepxError, RESET, BOLD, GREEN, YELLOW};
+
+/// Largest request head accepted; requests have no body
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// What `repx serve` exposes, all read-only
+#[derive(Debug, Clone)]
+pub struct ServeRoots {
+    /// The project's `.repx` directory
+    pub metadata_dir: PathBuf,
+    /// The directory artifacts are copied to, `<target_dir>/repx`
+    pub artifacts_dir: PathBuf,
+}
+
+/// Entry of `GET /api/builds`
+#[derive(Debug, Serialize)]
+struct BuildEntry {
+    build_id: String,
+    /// Whether `GET /api/builds/<id>` has a report for the build
+    has_report: bool,
+}
+
+/// A response, before it is written out
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(value: &impl Serialize) -> Self {
+        let body = serde_json::to_vec_pretty(value).unwrap_or_default();
+        Self { status: 200, content_type: "application/json", body }
+    }
+
+    fn text(status: u16, body: impl Into<String>) -> Self {
+        Self { status, content_type: "text/plain; charset=utf-8", body: body.into().into_bytes() }
+    }
+
+    fn not_found() -> Self {
+        Self::text(404, "Not found\n")
+    }
+}
+
+/// Serve build metadata, logs and artifacts over HTTP until the process is stopped
+///
+/// Endpoints:
+/// - `GET /api/builds`: builds from the log index, most recent first
+/// - `GET /api/builds/<id>`: the build's report
+/// - `GET /api/builds/<id>/log`: the build's log, decompressed
+/// - `GET /api/artifacts`: every artifact with its size and SHA-256
+/// - `GET /artifacts/<path>`: an artifact, and `/artifacts/<path>.sha256` its checksum
+/// - `GET /repx/<path>`: a file from `.repx`, such as `flake.nix` or `status.json`
+pub async fn serve(roots: ServeRoots, address: &str) -> Result<(), RepxError> {
+    Ok(run_server(roots, address).await?)
+}
+
+async fn run_server(roots: ServeRoots, address: &str) -> Result<()> {
+    let listener = TcpListener::bind(address).await?;
+    println!("{}{}Serving build artifacts and logs on http://{}{}", BOLD, GREEN, listener.local_addr()?, RESET);
+    serve_on(listener, roots).await
+}
+
+async fn serve_on(listener: TcpListener, roots: ServeRoots) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let roots = roots.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &roots).await {
+                println!("{}{}WARNING:{} Failed to answer a request: {}", BOLD, YELLOW, RESET, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, roots: &ServeRoots) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_BYTES {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split_whitespace();
+    let (method, target) = (request_line.next().unwrap_or_default(), request_line.next().unwrap_or_default());
+    let response = match method {
+        "GET" | "HEAD" => route(roots, target.split('?').next().unwrap_or_default()).await,
+        _ => Response::text(405, "Only GET and HEAD are supported\n"),
+    };
+
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status, reason, response.content_type, response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    if method != "HEAD" {
+        stream.write_all(&response.body).await?;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn route(roots: &ServeRoots, path: &str) -> Response {
+    let Some(path) = percent_decode(path) else {
+        return Response::text(400, "Invalid path\n");
+    };
+    let result = match path.trim_end_matches('/') {
+        "" => Ok(Response::text(200, "repx build server: see /api/builds and /api/artifacts\n")),
+        "/api/builds" => list_builds(roots).await,
+        "/api/artifacts" => list_artifacts(roots).await,
+        other => {
+            if let Some(rest) = other.strip_prefix("/api/builds/") {
+                build_endpoint(roots, rest).await
+            } else if let Some(rest) = other.strip_prefix("/artifacts/") {
+                artifact_endpoint(roots, rest).await
+            } else if let Some(rest) = other.strip_prefix("/repx/").filter(|rest| is_served_metadata(rest)) {
+                Ok(serve_file(&roots.metadata_dir, rest).await)
+            } else {
+                Ok(Response::not_found())
+            }
+        }
+    };
+    result.unwrap_or_else(|e| Response::text(500, format!("{}\n", e)))
+}
+
+async fn list_builds(roots: &ServeRoots) -> Result<Response> {
+    let builds: Vec<BuildEntry> = list_build_logs(&roots.metadata_dir)
+        .await?
+        .into_iter()
+        .map(|(build_id, _)| BuildEntry {
+            has_report: BuildReport::path_for(&roots.metadata_dir, &build_id).exists(),
+            build_id,
+        })
+        .collect();
+    Ok(Response::json(&builds))
+}
+
+async fn build_endpoint(roots: &ServeRoots, rest: &str) -> Result<Response> {
+    let (build_id, log) = match rest.strip_suffix("/log") {
+        Some(build_id) => (build_id, true),
+        None => (rest, false),
+    };
+    if build_id.is_empty() || build_id.contains(['/', '\\']) || build_id.contains("..") {
+        return Ok(Response::not_found());
+    }
+    if log {
+        return Ok(match read_build_log(&roots.metadata_dir, Some(build_id)).await {
+            Ok(contents) => Response::text(200, contents),
+            Err(_) => Response::not_found(),
+        });
+    }
+    Ok(match BuildReport::read(&roots.metadata_dir, build_id).await {
+        Ok(report) => Response::json(&report),
+        Err(_) => Response::not_found(),
+    })
+}
+
+async fn list_artifacts(roots: &ServeRoots) -> Result<Response> {
+    if !roots.artifacts_dir.is_dir() {
+        return Ok(Response::json(&Vec::<ArtifactInfo>::new()));
+    }
+    let dir = roots.artifacts_dir.clone();
+    let artifacts = tokio::task::spawn_blocking(move || collect_artifacts(&dir)).await??;
+    Ok(Response::json(&artifacts))
+}
+
+async fn artifact_endpoint(roots: &ServeRoots, rest: &str) -> Result<Response> {
+    if let Some(artifact) = rest.strip_suffix(".sha256") {
+        if let Some(path) = resolve(&roots.artifacts_dir, artifact).filter(|p| p.is_file()) {
+            let data = tokio::fs::read(&path).await?;
+            let name = Path::new(artifact).file_name().unwrap_or_default().to_string_lossy();
+            return Ok(Response::text(200, format!("{}  {}\n", sha256_hex(&data), name)));
+        }
+    }
+    Ok(serve_file(&roots.artifacts_dir, rest).await)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+async fn serve_file(root: &Path, relative: &str) -> Response {
+    let Some(path) = resolve(root, relative).filter(|p| p.is_file()) else {
+        return Response::not_found();
+    };
+    match tokio::fs::read(&path).await {
+        Ok(body) => Response { status: 200, content_type: "application/octet-stream", body },
+        Err(_) => Response::not_found(),
+    }
+}
+
+/// Whether `relative` is one of the `.repx` files served under `/repx/`
+///
+/// An allowlist rather than the whole directory, which also holds `secrets.env` and
+/// whatever else users keep there.
+fn is_served_metadata(relative: &str) -> bool {
+    const FILES: &[&str] = &["flake.nix", "flake.lock", "status.json", "effective-config.toml"];
+    let is_report = relative
+        .strip_prefix("logs/build-")
+        .and_then(|rest| rest.strip_suffix(".json"))
+        .is_some_and(|build_id| !build_id.is_empty() && !build_id.contains(['/', '\\']));
+    FILES.contains(&relative) || is_report
+}
+
+/// Path of `relative` under `root`, refusing anything that would leave it
+fn resolve(root: &Path, relative: &str) -> Option<PathBuf> {
+    let relative = Path::new(relative);
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    // Symlinks could still point outside, so compare the canonical paths
+    let path = root.join(relative).canonicalize().ok()?;
+    path.starts_with(root.canonicalize().ok()?).then_some(path)
+}
+
+/// Decode `%XX` escapes in a request path
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(address: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_serve_artifacts_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let roots = ServeRoots { metadata_dir: dir.path().join(".repx"), artifacts_dir: dir.path().join("target/repx") };
+        std::fs::create_dir_all(roots.artifacts_dir.join("x86_64-linux-gnu/bin")).unwrap();
+        std::fs::write(roots.artifacts_dir.join("x86_64-linux-gnu/bin/app"), "binary").unwrap();
+        std::fs::create_dir_all(&roots.metadata_dir).unwrap();
+        std::fs::write(dir.path().join("secret"), "private").unwrap();
+        std::fs::write(roots.metadata_dir.join("flake.lock"), "{}").unwrap();
+        std::fs::write(roots.metadata_dir.join("secrets.env"), "TOKEN=private").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_on(listener, roots));
+
+        let artifact = get(address, "/artifacts/x86_64-linux-gnu/bin/app").await;
+        assert!(artifact.starts_with("HTTP/1.1 200") && artifact.ends_with("binary"));
+        assert!(get(address, "/artifacts/x86_64-linux-gnu/bin/app.sha256").await.contains(&sha256_hex(b"binary")));
+        assert!(get(address, "/api/artifacts").await.contains("\"path\": \"x86_64-linux-gnu/bin/app\""));
+        assert!(get(address, "/api/builds").await.contains("[]"));
+        assert!(get(address, "/artifacts/../../secret").await.starts_with("HTTP/1.1 404"));
+        assert!(get(address, "/artifacts/%2e%2e/%2e%2e/secret").await.starts_with("HTTP/1.1 404"));
+        assert!(get(address, "/repx/flake.lock").await.starts_with("HTTP/1.1 200"));
+        assert!(get(address, "/repx/secrets.env").await.starts_with("HTTP/1.1 404"));
+    }
+}
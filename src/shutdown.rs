@@ -0,0 +1,115 @@
+use futures_util::FutureExt;
+use std::fmt;
+
+/// Signal that asked repx to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    /// SIGINT, e.g. Ctrl-C
+    Interrupt,
+    /// SIGTERM, e.g. from `docker stop` or a CI runner; a console close or shutdown on Windows
+    Terminate,
+}
+
+impl ShutdownSignal {
+    /// Conventional exit code of a process ended by this signal: 128 + the signal number
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ShutdownSignal::Interrupt => 130,
+            ShutdownSignal::Terminate => 143,
+        }
+    }
+}
+
+impl fmt::Display for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownSignal::Interrupt => write!(f, "SIGINT"),
+            ShutdownSignal::Terminate => write!(f, "SIGTERM"),
+        }
+    }
+}
+
+/// Handlers for the signals that ask repx to stop
+///
+/// Tokio never uninstalls a signal handler, so once a listener exists SIGINT and SIGTERM
+/// no longer end the process; whoever installs one has to keep checking it until it is
+/// safe to exit.
+pub struct ShutdownListener {
+    #[cfg(unix)]
+    signals: Option<(tokio::signal::unix::Signal, tokio::signal::unix::Signal)>,
+    #[cfg(windows)]
+    signals: Option<(tokio::signal::windows::CtrlC, tokio::signal::windows::CtrlClose, tokio::signal::windows::CtrlShutdown)>,
+}
+
+impl ShutdownListener {
+    /// Install the handlers; a listener that couldn't install them never reports a signal
+    pub fn install() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let signals = match (signal(SignalKind::interrupt()), signal(SignalKind::terminate())) {
+                (Ok(interrupt), Ok(terminate)) => Some((interrupt, terminate)),
+                _ => None,
+            };
+            Self { signals }
+        }
+        #[cfg(windows)]
+        {
+            use tokio::signal::windows::{ctrl_c, ctrl_close, ctrl_shutdown};
+            let signals = match (ctrl_c(), ctrl_close(), ctrl_shutdown()) {
+                (Ok(interrupt), Ok(close), Ok(shutdown)) => Some((interrupt, close, shutdown)),
+                _ => None,
+            };
+            Self { signals }
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            Self {}
+        }
+    }
+
+    /// Wait until repx is asked to stop
+    pub async fn recv(&mut self) -> ShutdownSignal {
+        #[cfg(unix)]
+        if let Some((interrupt, terminate)) = &mut self.signals {
+            return tokio::select! {
+                _ = interrupt.recv() => ShutdownSignal::Interrupt,
+                _ = terminate.recv() => ShutdownSignal::Terminate,
+            };
+        }
+        #[cfg(windows)]
+        if let Some((interrupt, close, shutdown)) = &mut self.signals {
+            return tokio::select! {
+                _ = interrupt.recv() => ShutdownSignal::Interrupt,
+                _ = close.recv() => ShutdownSignal::Terminate,
+                _ = shutdown.recv() => ShutdownSignal::Terminate,
+            };
+        }
+        std::future::pending().await
+    }
+
+    /// A signal received since the listener was last checked, without waiting for one
+    pub fn pending(&mut self) -> Option<ShutdownSignal> {
+        self.recv().now_or_never()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_sigterm_is_caught() {
+        let mut listener = ShutdownListener::install();
+        assert_eq!(listener.pending(), None);
+        let status = std::process::Command::new("kill").args(["-TERM", &std::process::id().to_string()]).status().unwrap();
+        assert!(status.success());
+
+        // Caught even though nothing was waiting when it arrived
+        let signal = tokio::time::timeout(std::time::Duration::from_secs(5), listener.recv()).await.unwrap();
+        assert_eq!(signal, ShutdownSignal::Terminate);
+        assert_eq!(signal.exit_code(), 143);
+        assert_eq!(signal.to_string(), "SIGTERM");
+    }
+}
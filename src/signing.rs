@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use crate::artifact_processor::{Artifact, ArtifactProcessor};
+use crate::secrets::warn_if_shared;
+use crate::RepxError;
+
+/// Tool used to sign artifacts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignBackend {
+    /// `minisign` with a secret key file, writing `<file>.minisig`
+    Minisign,
+    /// `cosign sign-blob`, with a key file or keyless through OIDC, writing `<file>.sig`
+    Cosign,
+}
+
+impl FromStr for SignBackend {
+    type Err = RepxError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "minisign" => Ok(SignBackend::Minisign),
+            "cosign" => Ok(SignBackend::Cosign),
+            other => Err(RepxError::ConfigInvalid(format!(
+                "Unknown signing backend '{}'; expected minisign or cosign",
+                other
+            ))),
+        }
+    }
+}
+
+/// Signs every artifact, and the build report, with minisign or cosign on the host
+///
+/// Only the key's path is ever passed around; the key itself is read by the signing tool,
+/// which prompts for its password (cosign also reads `COSIGN_PASSWORD`).
+#[derive(Debug, Clone)]
+pub struct SignProcessor {
+    backend: SignBackend,
+    key_file: Option<PathBuf>,
+}
+
+impl SignProcessor {
+    /// Check the key file exists; minisign needs one, cosign signs keyless without
+    pub fn new(backend: SignBackend, key_file: Option<&str>) -> Result<Self, RepxError> {
+        let key_file = key_file.map(PathBuf::from);
+        match &key_file {
+            Some(path) if !path.is_file() => {
+                return Err(RepxError::ConfigInvalid(format!("Signing key file {} does not exist", path.display())));
+            }
+            Some(path) => warn_if_shared(path),
+            None if backend == SignBackend::Minisign => {
+                return Err(RepxError::ConfigInvalid("--sign minisign needs a secret key, set with --sign-key-file".to_string()));
+            }
+            None => {}
+        }
+        Ok(Self { backend, key_file })
+    }
+
+    /// Sign `path`, returning the signature file written next to it
+    pub fn sign(&self, path: &Path) -> Result<PathBuf> {
+        let with_suffix = |suffix: &str| {
+            let mut signature = path.as_os_str().to_owned();
+            signature.push(suffix);
+            PathBuf::from(signature)
+        };
+        let (program, signature, mut command) = match self.backend {
+            SignBackend::Minisign => {
+                let signature = with_suffix(".minisig");
+                let mut command = Command::new("minisign");
+                command.arg("-S").arg("-s").args(&self.key_file).arg("-m").arg(path).arg("-x").arg(&signature);
+                ("minisign", signature, command)
+            }
+            SignBackend::Cosign => {
+                let signature = with_suffix(".sig");
+                let mut command = Command::new("cosign");
+                command.args(["sign-blob", "--yes"]);
+                match &self.key_file {
+                    Some(key) => command.arg("--key").arg(key),
+                    // Keyless signatures are verified against the certificate Fulcio issued
+                    None => command.arg("--output-certificate").arg(with_suffix(".pem")),
+                };
+                command.arg("--output-signature").arg(&signature).arg(path);
+                ("cosign", signature, command)
+            }
+        };
+        let status = command.status().with_context(|| format!("Could not run {}; is it installed?", program))?;
+        if !status.success() {
+            anyhow::bail!("{} exited with {} while signing {}", program, status, path.display());
+        }
+        Ok(signature)
+    }
+}
+
+impl ArtifactProcessor for SignProcessor {
+    fn name(&self) -> &str {
+        "sign"
+    }
+
+    fn process(&self, artifact: &Artifact) -> Result<()> {
+        self.sign(artifact.path).map(|_| ())
+    }
+
+    fn process_manifest(&self, path: &Path) -> Result<()> {
+        self.sign(path).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_processor_requires_key_for_minisign() {
+        assert!("gpg".parse::<SignBackend>().is_err());
+        assert!(SignProcessor::new(SignBackend::Minisign, None).is_err());
+        assert!(SignProcessor::new(SignBackend::Minisign, Some("/nonexistent/repx.key")).is_err());
+        assert!(SignProcessor::new(SignBackend::Cosign, None).is_ok());
+
+        let dir = tempfile::tempdir().unwrap();
+        let key = dir.path().join("repx.key");
+        std::fs::write(&key, "untrusted comment: minisign encrypted secret key\n").unwrap();
+        assert!(SignProcessor::new(SignBackend::Minisign, key.to_str()).is_ok());
+    }
+}
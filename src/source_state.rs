@@ -0,0 +1,67 @@
+use std::path::Path;
+use tokio::process::Command;
+
+/// Git state of the project sources a build was made from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceState {
+    /// Every tracked and untracked file matches this commit
+    Clean { commit: String },
+    /// The working tree has uncommitted changes
+    Dirty { changed_files: usize },
+    /// The project isn't in a git repository, or git isn't installed
+    Unknown,
+}
+
+impl SourceState {
+    /// Value recorded in the build report: the commit hash, or `dirty`
+    pub fn revision(&self) -> Option<String> {
+        match self {
+            SourceState::Clean { commit } => Some(commit.clone()),
+            SourceState::Dirty { .. } => Some("dirty".to_string()),
+            SourceState::Unknown => None,
+        }
+    }
+}
+
+/// Count changed files in `git status --porcelain` output
+fn count_changes(porcelain: &str) -> usize {
+    porcelain.lines().filter(|line| !line.trim().is_empty()).count()
+}
+
+/// Run git on the host in the project directory, returning stdout on success
+async fn git(project_path: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(project_path)
+        .args(args)
+        .output()
+        .await
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Commit time of the project's git HEAD as a Unix timestamp, if it is in a repository with commits
+pub async fn commit_time(project_path: &Path) -> Option<u64> {
+    git(project_path, &["log", "-1", "--format=%ct"]).await?.trim().parse().ok()
+}
+
+/// Inspect the project's working tree with the host's git
+pub async fn detect_source_state(project_path: &Path) -> SourceState {
+    // Only the project directory matters, minus the files repx writes itself
+    let Some(status) = git(project_path, &["status", "--porcelain", "--", ".", ":(exclude).repx"]).await else {
+        return SourceState::Unknown;
+    };
+    let changed_files = count_changes(&status);
+    if changed_files > 0 {
+        return SourceState::Dirty { changed_files };
+    }
+
+    // A fresh repository without commits has no HEAD to tie the build to
+    match git(project_path, &["rev-parse", "HEAD"]).await {
+        Some(commit) => SourceState::Clean { commit: commit.trim().to_string() },
+        None => SourceState::Unknown,
+    }
+}
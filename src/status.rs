@@ -0,0 +1,113 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use crate::BuildReport;
+use crate::atomic_file::write_atomic;
+
+/// How often `updated_at` is refreshed while a build runs
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What repx is doing, as seen by a supervising process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildState {
+    Idle,
+    Building,
+    Failed,
+}
+
+/// Contents of `.repx/status.json`
+#[derive(Debug, Clone, Serialize)]
+struct Status<'a> {
+    state: BuildState,
+    pid: u32,
+    /// Unix time of the last update; refreshed periodically while building
+    updated_at: u64,
+    /// Targets of the build in progress
+    targets: &'a [&'a str],
+    /// Report of the last finished build
+    last_build: Option<&'a BuildReport>,
+}
+
+/// Keeps `.repx/status.json` up to date for the duration of a build
+pub struct StatusFile {
+    path: PathBuf,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+async fn write_status(path: &Path, status: &Status<'_>) -> Result<()> {
+    // Readers poll this file, so they must never see a partial one
+    write_atomic(path, serde_json::to_string_pretty(status)? + "\n").await
+}
+
+impl StatusFile {
+    /// Record that a build of `targets` started, and keep the timestamp fresh until it finishes
+    pub async fn start(metadata_dir: &Path, targets: &[&str]) -> Result<Self> {
+        tokio::fs::create_dir_all(metadata_dir).await?;
+        let path = metadata_dir.join("status.json");
+        let status = Status { state: BuildState::Building, pid: std::process::id(), updated_at: now(), targets, last_build: None };
+        write_status(&path, &status).await?;
+
+        let heartbeat_path = path.clone();
+        let owned_targets: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+        let heartbeat = tokio::spawn(async move {
+            let targets: Vec<&str> = owned_targets.iter().map(String::as_str).collect();
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let status = Status { state: BuildState::Building, pid: std::process::id(), updated_at: now(), targets: &targets, last_build: None };
+                let _ = write_status(&heartbeat_path, &status).await;
+            }
+        });
+
+        Ok(Self { path, heartbeat: Some(heartbeat) })
+    }
+
+    /// Record the finished build: `idle` if it succeeded, `failed` otherwise
+    pub async fn finish(mut self, report: &BuildReport) -> Result<()> {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.abort();
+            let _ = heartbeat.await;
+        }
+        let state = if report.success { BuildState::Idle } else { BuildState::Failed };
+        let status = Status { state, pid: std::process::id(), updated_at: now(), targets: &[], last_build: Some(report) };
+        write_status(&self.path, &status).await
+    }
+}
+
+impl Drop for StatusFile {
+    fn drop(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_status_file_tracks_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let status = StatusFile::start(dir.path(), &["x86_64-linux-gnu"]).await.unwrap();
+        let read = || serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(dir.path().join("status.json")).unwrap()).unwrap();
+        assert_eq!(read()["state"], "building");
+        assert_eq!(read()["targets"][0], "x86_64-linux-gnu");
+
+        let report = BuildReport::new("abc", PathBuf::from("build-abc.log"), &["x86_64-linux-gnu"]);
+        status.finish(&report).await.unwrap();
+        assert_eq!(read()["state"], "failed");
+        assert_eq!(read()["last_build"]["build_id"], "abc");
+    }
+}
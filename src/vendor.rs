@@ -0,0 +1,48 @@
+use anyhow::Result;
+use bollard::Docker;
+use crate::execute_command::{execute_command, ExecSettings};
+use crate::{BuildLogger, RepxError, RESET, BOLD, GREEN, YELLOW};
+
+/// Where crate sources are vendored, relative to the project root
+pub const VENDOR_DIR: &str = ".repx/vendor";
+
+/// Vendor every locked crate into `VENDOR_DIR`, in the layout the Nix cargo setup hook expects
+///
+/// `cargo vendor` prints the source replacement config; the directory is swapped for the
+/// `@vendor@` placeholder, which Nix substitutes with the vendored sources in the build.
+/// Cargo.lock is kept alongside so Nix can check the sources match the lock file.
+fn vendor_command() -> String {
+    format!(
+        "set -e; rm -rf {dir}; \
+         cargo vendor --locked {dir} > .repx/vendor-config.toml; \
+         mkdir -p {dir}/.cargo; \
+         sed 's|^directory = .*|directory = \"@vendor@\"|' .repx/vendor-config.toml > {dir}/.cargo/config.toml; \
+         rm .repx/vendor-config.toml; \
+         cp Cargo.lock {dir}/Cargo.lock",
+        dir = VENDOR_DIR
+    )
+}
+
+/// Vendor the crate sources unless `VENDOR_DIR` already matches Cargo.lock
+///
+/// Vendoring needs network access, so an offline build fails if the sources are stale.
+pub async fn vendor_dependencies(docker: &Docker, container_id: &str, logger: &BuildLogger, settings: &ExecSettings, offline: bool) -> Result<()> {
+    let up_to_date_cmd = format!("cmp -s Cargo.lock {}/Cargo.lock", VENDOR_DIR);
+    if execute_command(docker, container_id, &up_to_date_cmd, settings).await.is_ok() {
+        println!("{}{}Vendored crate sources are up to date{}", BOLD, GREEN, RESET);
+        logger.log(&format!("Vendored crate sources in {} match Cargo.lock", VENDOR_DIR)).await?;
+        return Ok(());
+    }
+    if offline {
+        return Err(RepxError::ConfigInvalid(format!(
+            "{} is missing or doesn't match Cargo.lock; run a build with --vendor but without --offline to vendor the crates first",
+            VENDOR_DIR
+        )).into());
+    }
+
+    println!("{}{}Vendoring crate sources into {}...{}", BOLD, YELLOW, VENDOR_DIR, RESET);
+    logger.log(&format!("Vendoring crate sources into {}", VENDOR_DIR)).await?;
+    execute_command(docker, container_id, &vendor_command(), settings).await?;
+    println!("{}{}Vendored crate sources into {}{}", BOLD, GREEN, VENDOR_DIR, RESET);
+    Ok(())
+}
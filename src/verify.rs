@@ -0,0 +1,113 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::build_diff::{diff_builds, ArtifactChange};
+use crate::{build_with_nix_using, BuildReport, RepxConfig, RepxError, BOLD, MAGENTA, RESET};
+
+/// Two builds of the same sources, compared artifact by artifact
+#[derive(Debug, Clone)]
+pub struct VerifyResult {
+    pub first: BuildReport,
+    pub second: BuildReport,
+    /// Artifacts whose SHA-256 differs between the builds, or that only one build produced
+    pub mismatches: Vec<ArtifactChange>,
+}
+
+/// How one target fared in `VerifyResult`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetVerification {
+    pub target: String,
+    /// Files either build produced for the target
+    pub files: usize,
+    /// Paths, relative to the target's artifact directory, that differ
+    pub mismatched: Vec<String>,
+}
+
+impl VerifyResult {
+    /// Whether every artifact of every target was bit-for-bit identical
+    pub fn is_reproducible(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    /// Files compared and files that differ, per target
+    pub fn targets(&self) -> Vec<TargetVerification> {
+        let mut paths: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for (target, files) in self.first.artifacts.iter().chain(&self.second.artifacts) {
+            paths.entry(target).or_default().extend(files.iter().map(|f| f.path.as_str()));
+        }
+        paths
+            .into_iter()
+            .map(|(target, files)| TargetVerification {
+                target: target.to_string(),
+                files: files.len(),
+                mismatched: self.mismatches.iter().filter(|c| c.target == target).map(|c| c.path.clone()).collect(),
+            })
+            .collect()
+    }
+}
+
+/// Artifacts that differ between two builds, including those of targets only one of them copied
+fn compare_builds(first: &BuildReport, second: &BuildReport) -> Vec<ArtifactChange> {
+    let mut mismatches = diff_builds(first, second).artifacts;
+    for (built, other, built_first) in [(first, second, true), (second, first, false)] {
+        for (target, files) in built.artifacts.iter().filter(|(target, _)| !other.artifacts.contains_key(*target)) {
+            for file in files {
+                let (old, new) = if built_first { (Some(file.clone()), None) } else { (None, Some(file.clone())) };
+                mismatches.push(ArtifactChange { target: target.clone(), path: file.path.clone(), old, new });
+            }
+        }
+    }
+    mismatches
+}
+
+/// Build the project twice, each in a fresh container with an empty Nix store, and compare the artifacts
+///
+/// The store cache is left out, since Nix would otherwise reuse the first build's result
+/// instead of building again. Artifacts of a target that only one build copied count as
+/// mismatches.
+pub async fn verify_reproducible(config: &RepxConfig, targets: &[&str]) -> Result<VerifyResult, RepxError> {
+    let config = RepxConfig {
+        nix_store_cache: None,
+        summary_json: None,
+        junit: None,
+        sarif: None,
+        fail_on_warning: false,
+        ..config.clone()
+    };
+
+    let mut builds = Vec::new();
+    for run in ["first", "second"] {
+        println!("\n{}{}Verify: {} build{}", BOLD, MAGENTA, run, RESET);
+        builds.push(build_with_nix_using(&config, targets, &[]).await?);
+    }
+    let second = builds.pop().expect("second build");
+    let first = builds.pop().expect("first build");
+    let mismatches = compare_builds(&first, &second);
+    Ok(VerifyResult { first, second, mismatches })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArtifactInfo;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_verify_targets() {
+        let artifact = |path: &str, sha256: &str| ArtifactInfo { path: path.to_string(), size: 1, sha256: sha256.to_string() };
+        let mut first = BuildReport::new("a", PathBuf::from("build-a.log"), &["x86_64-linux-gnu", "aarch64-linux-gnu"]);
+        first.artifacts.insert("x86_64-linux-gnu".to_string(), vec![artifact("bin/demo", "aa"), artifact("share/demo.1", "bb")]);
+        first.artifacts.insert("aarch64-linux-gnu".to_string(), vec![artifact("bin/demo", "cc")]);
+        let mut second = first.clone();
+        second.artifacts.insert("x86_64-linux-gnu".to_string(), vec![artifact("bin/demo", "ab"), artifact("share/demo.1", "bb")]);
+        second.artifacts.insert("wasm32-wasi".to_string(), vec![artifact("demo.wasm", "dd")]);
+
+        let mismatches = compare_builds(&first, &second);
+        let result = VerifyResult { first, second, mismatches };
+        assert!(!result.is_reproducible());
+        let targets = result.targets();
+        assert_eq!(targets[0], TargetVerification { target: "aarch64-linux-gnu".to_string(), files: 1, mismatched: vec![] });
+        assert_eq!(targets[1].mismatched, ["demo.wasm"]);
+        assert_eq!(targets[2].files, 2);
+        assert_eq!(targets[2].mismatched, ["bin/demo"]);
+        assert!(result.mismatches[1].old.is_none());
+    }
+}